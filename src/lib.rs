@@ -5,771 +5,7180 @@
 //  Created by Gioele Cantoni on 05/11/25.
 //
 
+// FFI entry points receive raw C pointers by design; each one checks for NULL before dereferencing
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
 use std::ffi::{CStr, CString};
-use std::os::raw::c_char;
+use std::os::raw::{c_char, c_void};
+use std::panic::{self, AssertUnwindSafe};
 use std::ptr;
-use std::sync::{Mutex, OnceLock};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
 
-use rmcp::transport::{SseClientTransport, StreamableHttpClientTransport};
-use rmcp::{ServiceExt, RoleClient};
-use rmcp::model::{ClientInfo, ClientCapabilities, Implementation};
-use serde_json;
+use rmcp::transport::{SseClientTransport, StreamableHttpClientTransport, TokioChildProcess};
+use rmcp::transport::streamable_http_client::{AuthRequiredError, SseError, StreamableHttpClient, StreamableHttpError, StreamableHttpPostResponse};
+use rmcp::transport::sse_client::{SseClient, SseTransportError};
+use rmcp::transport::common::http_header::{EVENT_STREAM_MIME_TYPE, HEADER_LAST_EVENT_ID, HEADER_SESSION_ID, JSON_MIME_TYPE};
+use futures::stream::BoxStream;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
+use rmcp::{ClientHandler, Peer, ServiceExt, RoleClient};
+use rmcp::service::{NotificationContext, RequestContext};
+use rmcp::model::{ClientInfo, ClientCapabilities, ClientJsonRpcMessage, ClientRequest, GetExtensions, Implementation, PingRequest, ProgressNotificationParam, ServerJsonRpcMessage, ServerResult};
+use rmcp::model::{CreateMessageRequestMethod, CreateMessageRequestParam, CreateMessageResult};
+use rmcp::model::{ListRootsResult, ProtocolVersion, ResourceUpdatedNotificationParam, Root, RootsCapabilities};
+use rmcp::model::{LoggingLevel, LoggingMessageNotificationParam, SetLevelRequestParam};
+use rmcp::model::{ArgumentInfo, CompleteRequestParam, PaginatedRequestParam, Reference, Tool};
+use rmcp::model::{CallToolRequestParam, CallToolResult, CancelledNotification, CancelledNotificationMethod, CancelledNotificationParam, ProgressToken, RequestId};
+use rmcp::service::PeerRequestOptions;
+use rmcp::ErrorData as McpError;
 
-// Global client instance - one client per process
-static GLOBAL_CLIENT: OnceLock<Mutex<Option<McpClient>>> = OnceLock::new();
+// Global client instances keyed by connection id - several servers can be connected at once
+// This map is the single source of truth for live connections; the McpClient* arguments the
+// FFI functions take are unused and only kept so the C signatures stay the same
+// Clients are shared so a request never holds the map lock while it waits for the server
+// Any thread may call into a client at once: requests share its runtime and transport, and rmcp
+// routes each response to its caller by JSON-RPC request id, so calls never see each other's results
+static GLOBAL_CLIENTS: OnceLock<Mutex<HashMap<String, Arc<McpClient>>>> = OnceLock::new();
 
-/// Extract error message from JSON error response
-/// Returns the error message string if found, or the original JSON if not found
-fn extract_error_message(json_str: &str) -> String {
-    match serde_json::from_str::<serde_json::Value>(json_str) {
-        Ok(json) => {
-            if let Some(error) = json.get("error").and_then(|e| e.as_str()) {
-                error.to_string()
-            } else {
-                json_str.to_string()
+// Connection id used when the caller does not name a connection
+const DEFAULT_CONNECTION_ID: &str = "default";
+
+/// Get the global connection map, creating it on first use
+fn global_clients() -> &'static Mutex<HashMap<String, Arc<McpClient>>> {
+    GLOBAL_CLIENTS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Look up a connection, releasing the map lock before the caller starts its request
+/// Requests on other connections, and on the same one, can then run at the same time
+fn get_client(connection_id: &str) -> Option<Arc<McpClient>> {
+    global_clients().lock_or_recover().get(connection_id).cloned()
+}
+
+// Whether tool calls wait for a connect in progress on their connection instead of failing as not connected
+static LAZY_CONNECT: AtomicBool = AtomicBool::new(false);
+
+// Connects in progress per connection id, and a condvar signalled whenever one of them finishes
+static PENDING_CONNECTS: OnceLock<(Mutex<HashMap<String, usize>>, std::sync::Condvar)> = OnceLock::new();
+
+/// Get the in-progress connect counts, creating them on first use
+fn pending_connects() -> &'static (Mutex<HashMap<String, usize>>, std::sync::Condvar) {
+    PENDING_CONNECTS.get_or_init(|| (Mutex::new(HashMap::new()), std::sync::Condvar::new()))
+}
+
+/// Marks a connect to a connection id as in progress until dropped
+struct PendingConnect(String);
+
+impl PendingConnect {
+    fn new(connection_id: &str) -> Self {
+        *pending_connects().0.lock_or_recover().entry(connection_id.to_string()).or_insert(0) += 1;
+        PendingConnect(connection_id.to_string())
+    }
+}
+
+impl Drop for PendingConnect {
+    fn drop(&mut self) {
+        let (pending, finished) = pending_connects();
+        let mut pending = pending.lock_or_recover();
+        if let Some(count) = pending.get_mut(&self.0) {
+            *count -= 1;
+            if *count == 0 {
+                pending.remove(&self.0);
             }
         }
-        Err(_) => json_str.to_string(),
+        finished.notify_all();
     }
 }
 
-/// Initialize the MCP library
-/// Returns 0 on success, non-zero on error
-#[no_mangle]
-pub extern "C" fn mcp_init() -> i32 {
-    0
+/// Look up a connection for a tool call
+/// With mcp_set_lazy enabled and a connect to it in progress, waits up to the connect timeout
+/// for that connect to finish; connections that are not being connected are looked up at once
+/// Then waits while the connection is paused, see wait_while_paused
+/// kind: What the caller is about to send, listed by mcp_queue_json while it waits
+/// Returns the not connected error JSON, or a cancelled error if mcp_clear_queue was called while waiting
+fn get_client_or_wait(connection_id: &str, kind: &str) -> Result<Arc<McpClient>, String> {
+    let client = wait_for_connect(connection_id, kind)?;
+    wait_while_paused(connection_id, kind)?;
+    Ok(client)
 }
 
-/// Free a string allocated by the MCP library
-#[no_mangle]
-pub extern "C" fn mcp_free_string(s: *mut c_char) {
-    if s.is_null() {
-        return;
-    }
-    unsafe {
-        let _ = CString::from_raw(s);
-    }
+/// Look up a connection for a list or read request, waiting while it is paused, see wait_while_paused
+fn get_unpaused_client(connection_id: &str, kind: &str) -> Result<Arc<McpClient>, String> {
+    let client = get_client(connection_id).ok_or_else(|| not_connected_error(connection_id))?;
+    wait_while_paused(connection_id, kind)?;
+    Ok(client)
 }
 
-/// Extract error message from JSON error response
-/// Returns the error message string if found, or NULL if no error
-#[no_mangle]
-pub extern "C" fn mcp_extract_error_message(json_str: *const c_char) -> *mut c_char {
-    if json_str.is_null() {
-        return std::ptr::null_mut();
+// Connections paused with mcp_pause, and whether their requests wait for mcp_resume rather than fail;
+// changed and read under the pending connects lock so waits on its condition variable see every change
+static PAUSED_CONNECTIONS: Mutex<BTreeMap<String, bool>> = Mutex::new(BTreeMap::new());
+
+/// Error JSON returned for a request to a connection paused with requests failing
+fn paused_error(connection_id: &str) -> String {
+    error_json_code(ERROR_PAUSED, &format!("Connection '{}' is paused. Call mcp_resume() first", connection_id))
+}
+
+/// Wait until a connection paused with mcp_pause is resumed, up to its request timeout
+/// The time waited is not deducted from the request timeout the request is sent with after resuming
+/// Connections paused with requests failing, and connections that are not paused, return at once
+/// Returns the paused error JSON, a timeout, not connected or cancelled error if the wait did not end in a resume
+fn wait_while_paused(connection_id: &str, kind: &str) -> Result<(), String> {
+    let (pending, finished) = pending_connects();
+    let mut pending = pending.lock_or_recover();
+    match PAUSED_CONNECTIONS.lock_or_recover().get(connection_id) {
+        None => return Ok(()),
+        Some(false) => return Err(paused_error(connection_id)),
+        Some(true) => {}
     }
 
-    let json_string = unsafe {
-        match CStr::from_ptr(json_str).to_str() {
-            Ok(s) => s,
-            Err(_) => return std::ptr::null_mut(),
+    let timeout_ms = request_timeout_ms(connection_id);
+    let deadline = (timeout_ms > 0).then(|| std::time::Instant::now() + Duration::from_millis(timeout_ms as u64));
+    let clears = QUEUE_CLEARS.load(Ordering::Relaxed);
+    let _queued = TrackedRequest::start(connection_id, kind, true);
+    loop {
+        match PAUSED_CONNECTIONS.lock_or_recover().get(connection_id) {
+            None => return Ok(()),
+            Some(false) => return Err(paused_error(connection_id)),
+            Some(true) => {}
         }
-    };
-
-    // Check if this is an error JSON
-    match serde_json::from_str::<serde_json::Value>(json_string) {
-        Ok(json) => {
-            if let Some(error) = json.get("error").and_then(|e| e.as_str()) {
-                // Found an error message, return it
-                match CString::new(error) {
-                    Ok(c_str) => c_str.into_raw(),
-                    Err(_) => std::ptr::null_mut(),
+        if get_client(connection_id).is_none() {
+            return Err(not_connected_error(connection_id));
+        }
+        // Logged after releasing the lock, which every connect and request on any connection takes
+        if QUEUE_CLEARS.load(Ordering::Relaxed) != clears {
+            drop(pending);
+            log_event(LOG_WARN, format_args!("[{}] queued {} cleared", connection_id, kind));
+            return Err(error_json_code(ERROR_CANCELLED, "cancelled"));
+        }
+        pending = match deadline {
+            None => finished.wait(pending).unwrap_or_else(|e| e.into_inner()),
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+                if remaining.is_zero() {
+                    drop(pending);
+                    log_event(LOG_WARN, format_args!("[{}] {} timed out while paused", connection_id, kind));
+                    return Err(error_json_code(ERROR_TIMEOUT, &format!("Timed out waiting for connection '{}' to resume", connection_id)));
                 }
-            } else {
-                // No error field found
-                std::ptr::null_mut()
+                finished.wait_timeout(pending, remaining).unwrap_or_else(|e| e.into_inner()).0
             }
-        }
-        Err(_) => std::ptr::null_mut(),
+        };
     }
 }
 
-/// Parse tools JSON and extract structured data for virtual table
-/// Returns number of tools found, or 0 on error
-#[no_mangle]
-pub extern "C" fn mcp_parse_tools_json(json_str: *const c_char) -> usize {
-    if json_str.is_null() {
-        return 0;
+/// Wait for a connect in progress when mcp_set_lazy is enabled, see get_client_or_wait
+fn wait_for_connect(connection_id: &str, kind: &str) -> Result<Arc<McpClient>, String> {
+    if let Some(client) = get_client(connection_id) {
+        return Ok(client);
+    }
+    if !LAZY_CONNECT.load(Ordering::Relaxed) {
+        return Err(not_connected_error(connection_id));
     }
 
-    let json_string = unsafe {
-        match CStr::from_ptr(json_str).to_str() {
-            Ok(s) => s,
-            Err(_) => return 0,
+    let (pending, finished) = pending_connects();
+    let timeout_ms = connect_timeout_ms(None);
+    let deadline = (timeout_ms > 0).then(|| std::time::Instant::now() + Duration::from_millis(timeout_ms as u64));
+    let mut pending = pending.lock_or_recover();
+    // Read under the lock, which mcp_clear_queue also holds, so a clear is either seen here or wakes the wait
+    let clears = QUEUE_CLEARS.load(Ordering::Relaxed);
+    let _queued = TrackedRequest::start(connection_id, kind, true);
+    loop {
+        // Connects store their client before they stop being pending, so checking under the lock misses none
+        if let Some(client) = get_client(connection_id) {
+            return Ok(client);
         }
-    };
-
-    // Parse JSON using serde_json
-    match serde_json::from_str::<serde_json::Value>(json_string) {
-        Ok(json) => {
-            if let Some(tools) = json.get("tools").and_then(|v| v.as_array()) {
-                tools.len()
-            } else {
-                0
+        if !pending.contains_key(connection_id) {
+            return Err(not_connected_error(connection_id));
+        }
+        if QUEUE_CLEARS.load(Ordering::Relaxed) != clears {
+            drop(pending);
+            log_event(LOG_WARN, format_args!("[{}] queued {} cleared", connection_id, kind));
+            return Err(error_json_code(ERROR_CANCELLED, "cancelled"));
+        }
+        pending = match deadline {
+            None => finished.wait(pending).unwrap_or_else(|e| e.into_inner()),
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+                if remaining.is_zero() {
+                    return Err(not_connected_error(connection_id));
+                }
+                finished.wait_timeout(pending, remaining).unwrap_or_else(|e| e.into_inner()).0
             }
+        };
+    }
+}
+
+// Times mcp_clear_queue was called, so requests waiting for a connect know they were cleared
+static QUEUE_CLEARS: AtomicU64 = AtomicU64::new(0);
+
+/// Resolve an optional connection id coming from C
+/// Returns the "default" id for NULL, or the error JSON string if the id is not valid UTF-8
+fn resolve_connection_id(connection_id: *const c_char) -> Result<String, String> {
+    if connection_id.is_null() {
+        return Ok(DEFAULT_CONNECTION_ID.to_string());
+    }
+    unsafe {
+        match CStr::from_ptr(connection_id).to_str() {
+            Ok(s) => Ok(s.to_string()),
+            Err(_) => Err(invalid_params_json("Invalid connection id")),
         }
-        Err(_) => 0,
     }
 }
 
-/// Extract tool data by index for virtual table
-/// Returns allocated string that must be freed, or NULL if index out of bounds
-#[no_mangle]
-pub extern "C" fn mcp_get_tool_field(
-    json_str: *const c_char,
-    tool_index: usize,
-    field_name: *const c_char,
-) -> *mut c_char {
-    if json_str.is_null() || field_name.is_null() {
-        return ptr::null_mut();
+/// Error JSON returned when no client is stored under the given connection id
+fn not_connected_error(connection_id: &str) -> String {
+    if connection_id == DEFAULT_CONNECTION_ID {
+        error_json_code(ERROR_NOT_CONNECTED, "Not connected. Call mcp_connect() first")
+    } else {
+        error_json_code(ERROR_NOT_CONNECTED, &format!("Not connected: no connection named '{}'. Call mcp_connect() first", connection_id))
     }
+}
 
-    let json_string = unsafe {
-        match CStr::from_ptr(json_str).to_str() {
-            Ok(s) => s,
-            Err(_) => return ptr::null_mut(),
-        }
-    };
+// Timeout applied to every MCP request in milliseconds, 0 means wait forever
+static REQUEST_TIMEOUT_MS: AtomicU32 = AtomicU32::new(0);
 
-    let field = unsafe {
-        match CStr::from_ptr(field_name).to_str() {
-            Ok(s) => s,
-            Err(_) => return ptr::null_mut(),
-        }
-    };
+// Timeout for opening a transport and completing the initialize handshake in milliseconds,
+// 0 means connecting uses the request timeout instead
+static CONNECT_TIMEOUT_MS: AtomicU32 = AtomicU32::new(0);
 
-    // Parse JSON using serde_json
-    match serde_json::from_str::<serde_json::Value>(json_string) {
-        Ok(json) => {
-            // Check if this is a streaming result (single tool object) or batch result (tools array)
-            let tool = if let Some(tools) = json.get("tools").and_then(|v| v.as_array()) {
-                // Batch result: {"tools": [...]}, get tool by index
-                tools.get(tool_index)
-            } else if tool_index == 0 {
-                // Streaming result: single tool object, only valid for index 0
-                Some(&json)
-            } else {
-                None
-            };
+// Attempts at connecting, including the first, when the connect timeout expires before the initialize
+// handshake completes, and the delay before the second attempt (doubled on each following attempt)
+static INITIALIZE_MAX_ATTEMPTS: AtomicU32 = AtomicU32::new(1);
+static INITIALIZE_BACKOFF_MS: AtomicU32 = AtomicU32::new(0);
 
-            if let Some(tool) = tool {
-                let value = match field {
-                    "name" => tool.get("name"),
-                    "title" => tool.get("title"),
-                    "description" => tool.get("description"),
-                    "inputSchema" => tool.get("inputSchema"),
-                    "outputSchema" => tool.get("outputSchema"),
-                    "annotations" => tool.get("annotations"),
-                    _ => None,
-                };
+// Auto-reconnect settings: how many times to re-establish a dropped connection and retry a request,
+// the delay before the first retry (doubled on each attempt), and whether tool calls are retried
+static AUTO_RECONNECT_MAX_RETRIES: AtomicU32 = AtomicU32::new(0);
+static AUTO_RECONNECT_BASE_DELAY_MS: AtomicU32 = AtomicU32::new(0);
+static AUTO_RECONNECT_RETRY_TOOL_CALLS: AtomicBool = AtomicBool::new(false);
 
-                if let Some(v) = value {
-                    let result = if v.is_string() {
-                        v.as_str().unwrap_or("").to_string()
-                    } else {
-                        // For complex objects, serialize to JSON
-                        serde_json::to_string(v).unwrap_or_else(|_| "".to_string())
-                    };
+// Retry settings for transient transport errors on a live connection: attempts per request including
+// the first, and the delay before the second attempt (doubled on each following attempt)
+static RETRY_MAX_ATTEMPTS: AtomicU32 = AtomicU32::new(1);
+static RETRY_BACKOFF_MS: AtomicU32 = AtomicU32::new(0);
 
-                    return match CString::new(result) {
-                        Ok(c_str) => c_str.into_raw(),
-                        Err(_) => ptr::null_mut(),
-                    };
-                }
-            }
-        }
-        Err(_) => {}
+// Interval of keep-alive pings on idle connections in milliseconds, 0 means off; a watch channel so
+// the keep-alive task of every connection wakes up when it changes
+static KEEPALIVE_INTERVAL_MS: OnceLock<tokio::sync::watch::Sender<u32>> = OnceLock::new();
+
+// When each connection last got a response, used by the keep-alive task to tell whether it is idle
+static CONNECTION_ACTIVITY: OnceLock<Mutex<HashMap<String, std::time::Instant>>> = OnceLock::new();
+
+/// Get the keep-alive interval channel, creating it on first use
+fn keepalive_interval() -> &'static tokio::sync::watch::Sender<u32> {
+    KEEPALIVE_INTERVAL_MS.get_or_init(|| tokio::sync::watch::Sender::new(0))
+}
+
+/// Get the last activity map, creating it on first use
+fn connection_activity() -> &'static Mutex<HashMap<String, std::time::Instant>> {
+    CONNECTION_ACTIVITY.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// Whether tool arguments are checked against the tool's input schema before a call is sent
+static VALIDATE_ARGS: AtomicBool = AtomicBool::new(false);
+
+// Whether the structured content of tool results is checked against the tool's output schema
+static VALIDATE_RESULTS: AtomicBool = AtomicBool::new(false);
+
+// Whether JSON returned to SQL functions is indented for reading, rather than compact
+static PRETTY_JSON: AtomicBool = AtomicBool::new(false);
+
+// Whether tool call results include the JSON-RPC id the call was sent with
+static INCLUDE_REQUEST_ID: AtomicBool = AtomicBool::new(false);
+
+// Whether resource_link items in tool results are read and replaced by the resource contents
+static RESOLVE_RESOURCES: AtomicBool = AtomicBool::new(false);
+// Limits on the links resolved for one tool result and on the text and base64 data they add
+const MAX_RESOLVED_RESOURCES: usize = 16;
+const MAX_RESOLVED_RESOURCE_BYTES: usize = 8 * 1024 * 1024;
+
+tokio::task_local! {
+    // Id of the last tools/call request sent by the future this is scoped to, see with_sent_request_id
+    static SENT_REQUEST_ID: std::cell::RefCell<Option<RequestId>>;
+}
+
+/// Run a future that sends tool calls, returning the id the last one went out with when
+/// mcp_set_include_request_id is enabled
+async fn with_sent_request_id<T>(future: impl std::future::Future<Output = T>) -> (T, Option<RequestId>) {
+    if !INCLUDE_REQUEST_ID.load(Ordering::Relaxed) {
+        return (future.await, None);
     }
+    SENT_REQUEST_ID.scope(std::cell::RefCell::new(None), async {
+        let output = future.await;
+        (output, SENT_REQUEST_ID.with(|id| id.borrow_mut().take()))
+    }).await
+}
 
-    // Return empty string if field not found
-    match CString::new("") {
-        Ok(c_str) => c_str.into_raw(),
-        Err(_) => ptr::null_mut(),
+/// Add the id a request was sent with to its {"result": ...} or {"error": ...} JSON
+fn insert_request_id(json: &mut serde_json::Value, request_id: Option<RequestId>) {
+    if let (Some(object), Some(request_id)) = (json.as_object_mut(), request_id) {
+        object.insert("id".to_string(), serde_json::to_value(request_id).unwrap_or_default());
     }
 }
 
-/// Parse call tool result JSON and extract text content  
-/// Returns number of text results found, or 0 on error
-#[no_mangle]
-pub extern "C" fn mcp_parse_call_result_json(json_str: *const c_char) -> usize {
-    if json_str.is_null() {
-        return 0;
+/// Add the output schema mismatch found by validate_tool_result next to a tool call's result
+fn insert_validation_error(json: &mut serde_json::Value, validation_error: Option<serde_json::Value>) {
+    if let (Some(object), Some(validation_error)) = (json.as_object_mut(), validation_error) {
+        object.insert("validation_error".to_string(), validation_error);
     }
+}
 
-    let json_string = unsafe {
-        match CStr::from_ptr(json_str).to_str() {
-            Ok(s) => s,
-            Err(_) => return 0,
-        }
-    };
+// How long a cached tool list is served before it is fetched again in milliseconds, 0 means until
+// the server sends tools/list_changed
+static TOOLS_CACHE_TTL_MS: AtomicU32 = AtomicU32::new(0);
 
-    // Parse JSON using serde_json
-    match serde_json::from_str::<serde_json::Value>(json_string) {
-        Ok(json) => {
-            // Try both direct content and nested result.content
-            let content_array = json.get("content").and_then(|v| v.as_array())
-                .or_else(|| json.get("result").and_then(|r| r.get("content").and_then(|v| v.as_array())));
-                
-            if let Some(content) = content_array {
-                content.len()
-            } else {
-                0
-            }
-        }
-        Err(_) => 0,
+// Most pages fetched by one list request before giving up on a server that keeps returning a
+// nextCursor, 0 means no limit
+static MAX_LIST_PAGES: AtomicU32 = AtomicU32::new(100);
+
+// Largest response accepted from a server in bytes, 0 means no limit
+// Bodies are counted as they arrive, so an oversized one is dropped before it is buffered in full
+static MAX_RESPONSE_BYTES: AtomicU32 = AtomicU32::new(64 * 1024 * 1024);
+
+/// Get the response size limit, or None if there is none
+fn max_response_bytes() -> Option<u64> {
+    match MAX_RESPONSE_BYTES.load(Ordering::Relaxed) {
+        0 => None,
+        limit => Some(limit as u64),
     }
 }
 
-/// Extract call result text by index for virtual table
-/// Returns allocated string that must be freed, or NULL if index out of bounds
-#[no_mangle]
-pub extern "C" fn mcp_get_call_result_text(
-    json_str: *const c_char,
-    content_index: usize,
-) -> *mut c_char {
-    if json_str.is_null() {
-        return ptr::null_mut();
-    }
+// Whether invalid UTF-8 in server responses is replaced with U+FFFD instead of failing the response
+static LOSSY_UTF8: AtomicBool = AtomicBool::new(false);
 
-    let json_string = unsafe {
-        match CStr::from_ptr(json_str).to_str() {
-            Ok(s) => s,
-            Err(_) => return ptr::null_mut(),
-        }
-    };
+/// Replaces invalid UTF-8 in a byte stream with U+FFFD, keeping sequences split across chunks whole
+#[derive(Default)]
+struct LossyUtf8Decoder {
+    // Start of a sequence cut off at the end of the last chunk
+    pending: Vec<u8>,
+}
 
-    // Parse JSON using serde_json
-    match serde_json::from_str::<serde_json::Value>(json_string) {
-        Ok(json) => {
-            // Try both direct content and nested result.content
-            let content_array = json.get("content").and_then(|v| v.as_array())
-                .or_else(|| json.get("result").and_then(|r| r.get("content").and_then(|v| v.as_array())));
-                
-            if let Some(content) = content_array {
-                if let Some(item) = content.get(content_index) {
-                    if let Some(text) = item.get("text").and_then(|v| v.as_str()) {
-                        return match CString::new(text) {
-                            Ok(c_str) => c_str.into_raw(),
-                            Err(_) => ptr::null_mut(),
-                        };
+impl LossyUtf8Decoder {
+    /// Decode the next chunk, holding back an incomplete sequence at its end for the next one
+    fn decode(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut input = std::mem::take(&mut self.pending);
+        input.extend_from_slice(chunk);
+        let mut output = Vec::with_capacity(input.len());
+        let mut rest = &input[..];
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    output.extend_from_slice(valid.as_bytes());
+                    return output;
+                }
+                Err(e) => {
+                    let (valid, invalid) = rest.split_at(e.valid_up_to());
+                    output.extend_from_slice(valid);
+                    match e.error_len() {
+                        Some(len) => {
+                            output.extend_from_slice("\u{FFFD}".as_bytes());
+                            rest = &invalid[len..];
+                        }
+                        None => {
+                            self.pending = invalid.to_vec();
+                            return output;
+                        }
                     }
                 }
             }
         }
-        Err(_) => {}
     }
 
-    // Return empty string if not found
-    match CString::new("") {
-        Ok(c_str) => c_str.into_raw(),
-        Err(_) => ptr::null_mut(),
+    /// End the stream, replacing a sequence it was cut off in
+    fn finish(&mut self) -> Vec<u8> {
+        if std::mem::take(&mut self.pending).is_empty() {
+            Vec::new()
+        } else {
+            "\u{FFFD}".as_bytes().to_vec()
+        }
     }
 }
 
-type RunningClient = rmcp::service::RunningService<RoleClient, ClientInfo>;
+/// Replace invalid UTF-8 in a whole response body when mcp_set_lossy_utf8 is enabled
+fn lossy_utf8_body(body: Vec<u8>) -> Vec<u8> {
+    if !LOSSY_UTF8.load(Ordering::Relaxed) || std::str::from_utf8(&body).is_ok() {
+        return body;
+    }
+    String::from_utf8_lossy(&body).into_owned().into_bytes()
+}
 
-/// Opaque handle for MCP client
-pub struct McpClient {
-    runtime: tokio::runtime::Runtime,
-    service: Arc<TokioMutex<Option<RunningClient>>>,
-    server_url: Mutex<Option<String>>,
+/// Output of a stdio server read through LossyUtf8Decoder, owning the child so it is killed when dropped
+struct LossyUtf8Stdout {
+    stdout: tokio::process::ChildStdout,
+    _child: tokio::process::Child,
+    decoder: LossyUtf8Decoder,
+    decoded: Vec<u8>,
+    position: usize,
 }
 
-/// Create a new MCP client
-/// Returns NULL on error
-#[no_mangle]
-pub extern "C" fn mcp_client_new() -> *mut McpClient {
-    match tokio::runtime::Runtime::new() {
-        Ok(runtime) => {
-            let client = Box::new(McpClient {
-                runtime,
-                service: Arc::new(TokioMutex::new(None)),
-                server_url: Mutex::new(None),
-            });
-            Box::into_raw(client)
+impl tokio::io::AsyncRead for LossyUtf8Stdout {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        let this = &mut *self;
+        while this.position == this.decoded.len() {
+            let mut raw = [0u8; 8192];
+            let mut raw_buf = tokio::io::ReadBuf::new(&mut raw);
+            std::task::ready!(std::pin::Pin::new(&mut this.stdout).poll_read(cx, &mut raw_buf))?;
+            this.decoded = match raw_buf.filled() {
+                [] => this.decoder.finish(),
+                chunk => this.decoder.decode(chunk),
+            };
+            this.position = 0;
+            if raw_buf.filled().is_empty() && this.decoded.is_empty() {
+                return std::task::Poll::Ready(Ok(()));
+            }
         }
-        Err(_) => ptr::null_mut(),
+        let count = buf.remaining().min(this.decoded.len() - this.position);
+        buf.put_slice(&this.decoded[this.position..this.position + count]);
+        this.position += count;
+        std::task::Poll::Ready(Ok(()))
     }
 }
 
-/// Free an MCP client
-#[no_mangle]
-pub extern "C" fn mcp_client_free(client: *mut McpClient) {
-    if client.is_null() {
-        return;
+/// Build the error returned for a response over the size limit
+fn response_too_large(limit: u64) -> McpError {
+    McpError::new(
+        rmcp::model::ErrorCode(ERROR_RESPONSE_TOO_LARGE),
+        format!("Response too large: over {} bytes. Raise the limit with mcp_set_max_response_bytes()", limit),
+        None,
+    )
+}
+
+/// Build the error returned for an HTTP 429 answer, carrying the delay the server asked for in retry_after_ms
+fn rate_limited(retry_after_ms: Option<u64>) -> McpError {
+    let message = match retry_after_ms {
+        Some(ms) => format!("Rate limited by the server, retry after {} ms", ms),
+        None => "Rate limited by the server".to_string(),
+    };
+    McpError::new(
+        rmcp::model::ErrorCode(ERROR_RATE_LIMITED),
+        message,
+        retry_after_ms.map(|ms| serde_json::json!({ "retry_after_ms": ms })),
+    )
+}
+
+/// Get the delay a rate limited request asked for, None for other errors or when the server gave no Retry-After
+fn retry_after_ms(error: &rmcp::ServiceError) -> Option<u64> {
+    match error {
+        rmcp::ServiceError::McpError(e) if e.code.0 == ERROR_RATE_LIMITED => e.data.as_ref()?.get("retry_after_ms")?.as_u64(),
+        _ => None,
     }
-    unsafe {
-        let _ = Box::from_raw(client);
+}
+
+/// Parse a Retry-After header, given in seconds or as an HTTP date, into milliseconds from now
+fn parse_retry_after(value: &str) -> Option<u64> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(seconds.saturating_mul(1000));
     }
+    let at = parse_http_date(value)?;
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).ok()?.as_secs();
+    Some(at.saturating_sub(now).saturating_mul(1000))
 }
 
-/// Connect to an MCP server with optional custom headers
-/// server_url: URL of the MCP server (e.g., "http://localhost:8931/sse")
-/// headers_json: Optional JSON string with custom headers (e.g., '{"Authorization": "Bearer token", "X-MCP-Readonly": "true"}'), can be NULL
-/// legacy_sse: 1 to use SSE transport (legacy), 0 to use streamable HTTP transport (default)
-/// Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
-#[no_mangle]
-pub extern "C" fn mcp_connect(
-    _client_ptr: *mut McpClient,
-    server_url: *const c_char,
-    headers_json: *const c_char,
-    legacy_sse: i32
-) -> *mut c_char {
-    if server_url.is_null() {
-        let error = r#"{"error": "Invalid arguments"}"#;
-        return CString::new(error).unwrap_or_default().into_raw();
+/// Parse an HTTP date such as "Sun, 06 Nov 1994 08:49:37 GMT" into seconds since the Unix epoch
+fn parse_http_date(value: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    let [_, day, month, year, time, "GMT"] = parts[..] else {
+        return None;
+    };
+    let day: u64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as u64 + 1;
+    let year: u64 = year.parse().ok()?;
+    let mut fields = time.split(':').map(|field| field.parse::<u64>().ok());
+    let (hour, minute, second) = (fields.next()??, fields.next()??, fields.next()??);
+    if year < 1970 || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
     }
 
-    let server_url_str = unsafe {
-        match CStr::from_ptr(server_url).to_str() {
-            Ok(s) => s.to_string(),
-            Err(_) => {
-                let error = r#"{"error": "Invalid server URL"}"#;
-                return CString::new(error).unwrap_or_default().into_raw();
+    // Days since the epoch of a civil date, with years starting in March so leap days come last
+    let (year, month) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Check that a result fits the response size limit, counting its JSON text without building it
+fn check_response_size(result: &impl serde::Serialize) -> Result<(), McpError> {
+    struct Counter {
+        written: u64,
+        limit: u64,
+    }
+
+    impl std::io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.written += buf.len() as u64;
+            if self.written > self.limit {
+                return Err(std::io::Error::other("response too large"));
             }
+            Ok(buf.len())
         }
-    };
 
-    // Parse optional headers_json (can be NULL or a JSON object)
-    let headers_map: Option<std::collections::HashMap<String, String>> = if headers_json.is_null() {
-        None
-    } else {
-        unsafe {
-            match CStr::from_ptr(headers_json).to_str() {
-                Ok(json_str) => {
-                    // Try to parse as JSON object
-                    match serde_json::from_str::<std::collections::HashMap<String, String>>(json_str) {
-                        Ok(map) => {
-                            Some(map)
-                        },
-                        Err(_e) => {
-                            let error = r#"{"error": "Invalid headers JSON format. Expected: {\"Header-Name\": \"value\"}"}"#;
-                            return CString::new(error).unwrap_or_default().into_raw();
-                        }
-                    }
-                }
-                Err(_) => {
-                    let error = r#"{"error": "Invalid headers string"}"#;
-                    return CString::new(error).unwrap_or_default().into_raw();
-                }
-            }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
         }
-    };
+    }
 
-    // Create a new McpClient with runtime
-    let new_client = McpClient {
-        runtime: match tokio::runtime::Runtime::new() {
-            Ok(r) => r,
-            Err(e) => {
-                let error = format!(r#"{{"error": "Failed to create runtime: {}"}}"#, e);
-                return CString::new(error).unwrap_or_default().into_raw();
-            }
-        },
-        service: Arc::new(TokioMutex::new(None)),
-        server_url: Mutex::new(None),
+    let Some(limit) = max_response_bytes() else {
+        return Ok(());
     };
+    serde_json::to_writer(Counter { written: 0, limit }, result).map_err(|_| response_too_large(limit))
+}
 
-    let use_sse = legacy_sse != 0;
+// Request timeouts of connections whose config set timeout_ms, or that were given one with
+// mcp_set_connection_timeout_ms, used instead of REQUEST_TIMEOUT_MS
+// Kept outside the connection map like the cancellation signals, so a lookup never holds a client
+static CONNECTION_TIMEOUTS: OnceLock<Mutex<HashMap<String, u32>>> = OnceLock::new();
 
-    let (result, maybe_service) = if use_sse {
-        // Use SSE transport (legacy) with optional custom headers
-        new_client.runtime.block_on(async {
-            // Create HTTP client with optional custom headers
-            let mut client_builder = reqwest::Client::builder();
-            if let Some(ref headers_map) = headers_map {
-                use reqwest::header::{HeaderMap, HeaderValue, HeaderName};
-                let mut headers = HeaderMap::new();
-
-                for (key, value) in headers_map {
-                    match (HeaderName::from_bytes(key.as_bytes()), HeaderValue::from_str(value)) {
-                        (Ok(header_name), Ok(header_value)) => {
-                            headers.insert(header_name, header_value);
-                        }
-                        _ => {
-                            let error = format!(r#"{{"error": "Invalid header format: {}: {}"}}"#, key, value);
-                            return (error, None);
-                        }
-                    }
-                }
+/// Get the per-connection timeout map, creating it on first use
+fn connection_timeouts() -> &'static Mutex<HashMap<String, u32>> {
+    CONNECTION_TIMEOUTS.get_or_init(|| Mutex::new(HashMap::new()))
+}
 
-                client_builder = client_builder.default_headers(headers);
-            }
+/// Request timeout of a connection in milliseconds: its own if its config set one, the global one otherwise
+fn request_timeout_ms(connection_id: &str) -> u32 {
+    match connection_timeouts().lock_or_recover().get(connection_id) {
+        Some(ms) => *ms,
+        None => REQUEST_TIMEOUT_MS.load(Ordering::Relaxed),
+    }
+}
 
-            let http_client = match client_builder.build() {
-                Ok(c) => c,
-                Err(e) => {
-                    let error = format!(r#"{{"error": "Failed to create HTTP client: {}"}}"#, e);
-                    return (error, None);
-                }
-            };
+/// Run a request future of a connection under its request timeout
+/// Returns the error JSON string if the request did not complete in time
+async fn with_request_timeout<F: std::future::Future>(connection_id: &str, request: F) -> Result<F::Output, String> {
+    with_timeout_ms(request_timeout_ms(connection_id), request).await
+}
 
-            // Build SSE transport with custom HTTP client
-            let sse_config = rmcp::transport::sse_client::SseClientConfig {
-                sse_endpoint: server_url_str.clone().into(),
-                ..Default::default()
-            };
+/// Run a future under a timeout in milliseconds, 0 waits forever
+/// Returns the error JSON string if the future did not complete in time
+async fn with_timeout_ms<F: std::future::Future>(timeout_ms: u32, request: F) -> Result<F::Output, String> {
+    match timeout_ms {
+        0 => Ok(request.await),
+        ms => tokio::time::timeout(Duration::from_millis(ms as u64), request)
+            .await
+            .map_err(|_| {
+                log_event(LOG_WARN, format_args!("request timed out after {} ms", ms));
+                error_json_code(ERROR_TIMEOUT, &format!("Request timed out after {} ms", ms))
+            }),
+    }
+}
+
+// Cancellation signals keyed by connection id, kept outside the connection map so a signal exists
+// even for a request that is still connecting
+static CANCEL_SIGNALS: OnceLock<Mutex<HashMap<String, Arc<tokio::sync::Notify>>>> = OnceLock::new();
+
+/// Get the cancellation signal of a connection, creating it on first use
+fn cancel_signal(connection_id: &str) -> Arc<tokio::sync::Notify> {
+    CANCEL_SIGNALS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock_or_recover()
+        .entry(connection_id.to_string())
+        .or_default()
+        .clone()
+}
+
+/// Run a request of a connection under the request timeout, stopping early if mcp_cancel is called for it
+/// Returns the request output, or the error JSON string if it timed out or was cancelled
+async fn run_request<F: std::future::Future>(connection_id: &str, request: F) -> Result<F::Output, String> {
+    run_cancellable(connection_id, with_request_timeout(connection_id, request)).await?
+}
+
+/// Run a future of a connection until it completes or mcp_cancel is called for the connection
+/// Returns the future output, or the error JSON string if it was cancelled
+async fn run_cancellable<F: std::future::Future>(connection_id: &str, request: F) -> Result<F::Output, String> {
+    let signal = cancel_signal(connection_id);
+    // Created before the request starts so a cancel arriving at any point while it runs is seen
+    let cancelled = signal.notified();
+    tokio::select! {
+        result = request => Ok(result),
+        _ = cancelled => {
+            log_event(LOG_WARN, format_args!("[{}] request cancelled", connection_id));
+            Err(error_json_code(ERROR_CANCELLED, "cancelled"))
+        }
+    }
+}
+
+/// Cancel the requests currently running on a connection
+/// The waiting calls return an error with code -32003 and servers are notified of cancelled tool calls
+/// connection_id: Connection whose requests to cancel, can be NULL for the "default" connection
+#[no_mangle]
+pub extern "C" fn mcp_cancel(connection_id: *const c_char) {
+    ffi_guard(|_| (), || {
+        if let Ok(connection_id) = resolve_connection_id(connection_id) {
+            cancel_signal(&connection_id).notify_waiters();
+        }
+    })
+}
+
+/// Sends notifications/cancelled for a request if it is dropped before its response arrives
+struct CancelOnDrop {
+    peer: Option<Peer<RoleClient>>,
+    request_id: RequestId,
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        let (Some(peer), Ok(runtime)) = (self.peer.take(), tokio::runtime::Handle::try_current()) else {
+            return;
+        };
+        let notification = CancelledNotification {
+            params: CancelledNotificationParam {
+                request_id: self.request_id.clone(),
+                reason: Some("Cancelled by the client".to_string()),
+            },
+            method: CancelledNotificationMethod,
+            extensions: Default::default(),
+        };
+        runtime.spawn(async move {
+            let _ = peer.send_notification(notification.into()).await;
+        });
+    }
+}
+
+/// Call a tool, telling the server to stop if the caller gives up on it (mcp_cancel or a timeout)
+async fn call_tool_cancellable(peer: &Peer<RoleClient>, param: CallToolRequestParam) -> Result<CallToolResult, rmcp::ServiceError> {
+    call_tool_with_progress(peer, param, None, None).await
+}
+
+/// HTTP headers sent with one request on top of the connection's headers, carried in the request's
+/// extensions so the streamable HTTP client can add them to the POST; other transports ignore them
+#[derive(Clone)]
+struct RequestHeaders(reqwest::header::HeaderMap);
+
+impl RequestHeaders {
+    /// Build the headers of a request, keeping repeated headers as separate values
+    fn new(headers: &[(String, String)]) -> Result<Self, String> {
+        let mut header_map = reqwest::header::HeaderMap::new();
+        for (name, value) in &without_user_agent(headers) {
+            match (reqwest::header::HeaderName::from_bytes(name.as_bytes()), reqwest::header::HeaderValue::from_str(value)) {
+                (Ok(name), Ok(value)) => header_map.append(name, value),
+                _ => return Err(invalid_params_json(&format!("Invalid header format: {}: {}", name, value))),
+            };
+        }
+        Ok(RequestHeaders(header_map))
+    }
+}
+
+/// Call a tool like call_tool_cancellable, sending the progress the server reports for it to a tool stream
+/// progress_stream: Connection id the call is sent on and the stream its progress goes to, or None to only queue it
+/// headers: HTTP headers sent with this call only, or None
+async fn call_tool_with_progress(
+    peer: &Peer<RoleClient>,
+    param: CallToolRequestParam,
+    progress_stream: Option<(&str, &Arc<ToolStream>)>,
+    headers: Option<RequestHeaders>,
+) -> Result<CallToolResult, rmcp::ServiceError> {
+    if !supports_tools(peer) {
+        return Err(tools_unsupported());
+    }
+    let mut request = rmcp::model::Request::new(param);
+    if let Some(headers) = headers {
+        request.extensions.insert(headers);
+    }
+    let request = ClientRequest::CallToolRequest(request);
+    let handle = peer.send_cancellable_request(request, PeerRequestOptions::no_options()).await?;
+    let _ = SENT_REQUEST_ID.try_with(|id| *id.borrow_mut() = Some(handle.id.clone()));
+    let mut guard = CancelOnDrop {
+        peer: Some(handle.peer.clone()),
+        request_id: handle.id.clone(),
+    };
+    let _progress = progress_stream.map(|(connection_id, stream)| {
+        ToolStreamProgress::register((connection_id.to_string(), handle.progress_token.clone()), stream.clone())
+    });
+
+    let result = handle.await_response().await;
+    guard.peer = None;
+    match result? {
+        ServerResult::CallToolResult(result) => {
+            check_response_size(&result).map_err(rmcp::ServiceError::McpError)?;
+            Ok(result)
+        }
+        _ => Err(rmcp::ServiceError::UnexpectedResponse),
+    }
+}
+
+/// Check tool arguments against the tool's input schema when mcp_set_validate_args is enabled
+/// Returns None if the arguments are valid or cannot be checked, or the error naming the offending field
+async fn validate_tool_arguments(
+    cache: &ToolListCache,
+    peer: &Peer<RoleClient>,
+    name: &str,
+    arguments: Option<&serde_json::Map<String, serde_json::Value>>,
+) -> Option<serde_json::Value> {
+    if !VALIDATE_ARGS.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    // Unknown tools and schemas the validator cannot compile are left for the server to reject
+    let validator = tool_schema_validator(cache, peer, name, false).await?;
+    let arguments = serde_json::Value::Object(arguments.cloned().unwrap_or_default());
+    let error = validator.iter_errors(&arguments).next()?;
+
+    Some(serde_json::json!({
+        "error": {
+            "code": rmcp::model::ErrorCode::INVALID_PARAMS.0,
+            "message": format!("Invalid arguments for tool '{}': {}", name, error),
+            "data": { "field": schema_error_field(&error) },
+        }
+    }))
+}
+
+/// Check a tool result's structured content against the tool's output schema when mcp_set_validate_results is enabled
+/// Returns None if the result matches or cannot be checked, or the mismatch naming the offending field
+async fn validate_tool_result(
+    cache: &ToolListCache,
+    peer: &Peer<RoleClient>,
+    name: &str,
+    result: &CallToolResult,
+) -> Option<serde_json::Value> {
+    if !VALIDATE_RESULTS.load(Ordering::Relaxed) || result.is_error == Some(true) {
+        return None;
+    }
+
+    // Tools without an output schema and schemas the validator cannot compile are not checked
+    let validator = tool_schema_validator(cache, peer, name, true).await?;
+    let Some(structured_content) = &result.structured_content else {
+        return Some(serde_json::json!({
+            "message": format!("Tool '{}' declares an output schema but returned no structuredContent", name),
+            "field": null,
+        }));
+    };
+    let error = validator.iter_errors(structured_content).next()?;
+
+    Some(serde_json::json!({
+        "message": format!("Result of tool '{}' does not match its output schema: {}", name, error),
+        "field": schema_error_field(&error),
+    }))
+}
+
+/// Validator of a tool's input schema, or of its output schema if output is true, compiled once per fetched tool list
+/// Returns None for unknown tools, tools without an output schema and schemas the validator cannot compile
+async fn tool_schema_validator(
+    cache: &ToolListCache,
+    peer: &Peer<RoleClient>,
+    name: &str,
+    output: bool,
+) -> Option<Arc<jsonschema::Validator>> {
+    let (tools, validators) = cached_tool_list(cache, peer).await.ok()?;
+    let mut validators = validators.lock_or_recover();
+    validators.entry((name.to_string(), output)).or_insert_with(|| {
+        let tool = tools.iter().find(|tool| tool.name == name)?;
+        let schema = if output { tool.output_schema.clone()? } else { tool.input_schema.clone() };
+        jsonschema::validator_for(&serde_json::Value::Object((*schema).clone())).ok().map(Arc::new)
+    }).clone()
+}
+
+/// Field a schema validation error names: the missing property, or the path of the invalid value
+fn schema_error_field(error: &jsonschema::ValidationError) -> Option<String> {
+    match error.kind() {
+        jsonschema::error::ValidationErrorKind::Required { property } => property.as_str().map(str::to_string),
+        _ => Some(error.instance_path().as_str().trim_start_matches('/').to_string()).filter(|field| !field.is_empty()),
+    }
+}
+
+// Tool filter set with mcp_set_tool_filter, checked before every tool call is sent on any connection
+static TOOL_FILTER: Mutex<Option<ToolFilter>> = Mutex::new(None);
+
+/// Tool name patterns that may and may not be called, where * matches any run of characters
+/// Without an allow list every tool that is not denied may be called; deny patterns always win
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ToolFilter {
+    allow: Option<Vec<String>>,
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+impl ToolFilter {
+    /// Whether the filter lets a tool be called
+    fn allows(&self, name: &str) -> bool {
+        let matches = |patterns: &[String]| patterns.iter().any(|pattern| wildcard_match(pattern, name));
+        !matches(&self.deny) && self.allow.as_deref().is_none_or(matches)
+    }
+}
+
+/// Match a name against a pattern in which * stands for any run of characters, including none
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// Check a tool call against the filter set with mcp_set_tool_filter
+/// Returns None if the tool may be called, or the permission error to return instead of calling it
+fn tool_filter_error(name: &str) -> Option<serde_json::Value> {
+    if TOOL_FILTER.lock_or_recover().as_ref().is_none_or(|filter| filter.allows(name)) {
+        return None;
+    }
+    log_event(LOG_WARN, format_args!("tool call '{}' blocked by the tool filter", name));
+    Some(serde_json::json!({
+        "error": {
+            "code": ERROR_PERMISSION_DENIED,
+            "message": format!("Tool '{}' is not allowed by the tool filter", name),
+        }
+    }))
+}
+
+/// Allow or deny tool calls by name on every connection, whatever tools the servers advertise
+/// filter_json: JSON object such as '{"allow": ["search", "fs_*"], "deny": ["fs_delete"]}', or NULL to allow every tool
+/// Returns NULL on success, error string on failure (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_set_tool_filter(filter_json: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error, || {
+        let filter = if filter_json.is_null() {
+            None
+        } else {
+            let filter_json = unsafe { CStr::from_ptr(filter_json) }.to_string_lossy();
+            match serde_json::from_str::<ToolFilter>(&filter_json) {
+                Ok(filter) => Some(filter),
+                Err(e) => return to_c_string(format!("Invalid tool filter: {}", e)).into_raw(),
+            }
+        };
+
+        *TOOL_FILTER.lock_or_recover() = filter;
+        ptr::null_mut()
+    })
+}
+
+// Default arguments per tool name set with mcp_set_tool_defaults, merged under the arguments of every call
+static TOOL_DEFAULTS: Mutex<BTreeMap<String, serde_json::Map<String, serde_json::Value>>> = Mutex::new(BTreeMap::new());
+
+/// Merge the defaults set with mcp_set_tool_defaults under a tool call's arguments, the call's own values winning
+/// Arguments that are neither an object nor null are returned unchanged, for validation or the server to reject
+fn with_tool_defaults(name: &str, arguments: serde_json::Value) -> serde_json::Value {
+    let Some(defaults) = TOOL_DEFAULTS.lock_or_recover().get(name).cloned() else {
+        return arguments;
+    };
+    match arguments {
+        serde_json::Value::Null => serde_json::Value::Object(defaults),
+        arguments @ serde_json::Value::Object(_) => {
+            let mut merged = serde_json::Value::Object(defaults);
+            merge_json(&mut merged, arguments);
+            merged
+        }
+        arguments => arguments,
+    }
+}
+
+/// Deep merge a JSON value into another: objects are merged key by key, anything else replaces the value
+fn merge_json(base: &mut serde_json::Value, value: serde_json::Value) {
+    match (base, value) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(value)) => {
+            for (key, value) in value {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_json(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, value) => *base = value,
+    }
+}
+
+/// Set the default arguments of a tool, merged under the arguments of every call to it on any connection
+/// Nested objects are merged key by key; the caller's values win over the defaults
+/// tool_name: Name of the tool
+/// defaults_json: JSON object of default arguments, or NULL to clear the tool's defaults
+/// Returns NULL on success, error string on failure (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_set_tool_defaults(tool_name: *const c_char, defaults_json: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error, || {
+        let tool_name = match (!tool_name.is_null()).then(|| unsafe { CStr::from_ptr(tool_name) }.to_str()) {
+            Some(Ok(name)) if !name.is_empty() => name.to_string(),
+            _ => return to_c_string("Invalid tool name".to_string()).into_raw(),
+        };
+
+        if defaults_json.is_null() {
+            TOOL_DEFAULTS.lock_or_recover().remove(&tool_name);
+            return ptr::null_mut();
+        }
+        let defaults_json = unsafe { CStr::from_ptr(defaults_json) }.to_string_lossy();
+        match serde_json::from_str::<serde_json::Value>(&defaults_json) {
+            Ok(serde_json::Value::Object(defaults)) => {
+                TOOL_DEFAULTS.lock_or_recover().insert(tool_name, defaults);
+                ptr::null_mut()
+            }
+            Ok(_) => to_c_string("Invalid tool defaults: expected a JSON object".to_string()).into_raw(),
+            Err(e) => to_c_string(format!("Invalid tool defaults: {}", e)).into_raw(),
+        }
+    })
+}
+
+/// Get the server's tools, listing them if they are not cached yet or the cached list has expired
+async fn cached_tools(cache: &ToolListCache, peer: &Peer<RoleClient>) -> Result<Arc<Vec<Tool>>, rmcp::ServiceError> {
+    cached_tool_list(cache, peer).await.map(|(tools, _)| tools)
+}
+
+/// Get the server's tools as cached_tools does, with the schema validators compiled from that list
+async fn cached_tool_list(
+    cache: &ToolListCache,
+    peer: &Peer<RoleClient>,
+) -> Result<(Arc<Vec<Tool>>, Arc<SchemaValidators>), rmcp::ServiceError> {
+    let ttl_ms = TOOLS_CACHE_TTL_MS.load(Ordering::Relaxed);
+    if let Some((fetched_at, tools, validators)) = cache.lock_or_recover().clone() {
+        if ttl_ms == 0 || fetched_at.elapsed() < Duration::from_millis(ttl_ms as u64) {
+            return Ok((tools, validators));
+        }
+    }
+
+    let tools = Arc::new(list_all_tools(peer).await?);
+    let validators = Arc::new(SchemaValidators::default());
+    *cache.lock_or_recover() = Some((std::time::Instant::now(), tools.clone(), validators.clone()));
+    Ok((tools, validators))
+}
+
+/// Fetch every page of a list request, following nextCursor up to the configured page limit
+/// what: Plural name of the listed items for the error message, e.g. "tools"
+async fn list_all_pages<T, F, Fut>(what: &str, mut fetch_page: F) -> Result<Vec<T>, rmcp::ServiceError>
+where
+    F: FnMut(Option<PaginatedRequestParam>) -> Fut,
+    Fut: std::future::Future<Output = Result<(Vec<T>, Option<String>), rmcp::ServiceError>>,
+{
+    let max_pages = MAX_LIST_PAGES.load(Ordering::Relaxed);
+    let mut items = Vec::new();
+    let mut cursor = None;
+    let mut pages = 0;
+    loop {
+        let (page, next_cursor) = fetch_page(cursor.map(|cursor| PaginatedRequestParam { cursor: Some(cursor) })).await?;
+        items.extend(page);
+        pages += 1;
+        cursor = match next_cursor {
+            Some(next_cursor) => Some(next_cursor),
+            None => return Ok(items),
+        };
+        if max_pages != 0 && pages >= max_pages {
+            let message = format!(
+                "Server still had more {1} after the page limit of {0}. Raise the limit with mcp_set_max_list_pages()",
+                max_pages, what
+            );
+            return Err(rmcp::ServiceError::McpError(McpError::internal_error(message, None)));
+        }
+    }
+}
+
+/// List every tool of a server, see list_all_pages
+async fn list_all_tools(peer: &Peer<RoleClient>) -> Result<Vec<Tool>, rmcp::ServiceError> {
+    if !supports_tools(peer) {
+        return Err(tools_unsupported());
+    }
+    list_all_pages("tools", |cursor| async move {
+        peer.list_tools(cursor).await.map(|result| (result.tools, result.next_cursor))
+    }).await
+}
+
+/// Serialize a tool list in the format returned by mcp_list_tools_json
+fn tools_list_json(tools: &[Tool]) -> String {
+    let tools_json: Vec<serde_json::Value> = tools
+        .iter()
+        .map(|tool| {
+            serde_json::json!({
+                "name": tool.name,
+                "description": tool.description,
+                "inputSchema": tool.input_schema
+            })
+        })
+        .collect();
+
+    match serde_json::to_string(&serde_json::json!({
+        "tools": tools_json
+    })) {
+        Ok(json) => json,
+        Err(e) => error_json_code(rmcp::model::ErrorCode::INTERNAL_ERROR.0, &format!("Serialization failed: {}", e)),
+    }
+}
+
+/// Convert a tool list to a JSON array of the tool names, leaving out the schemas
+fn tool_names_json(tools: &[Tool]) -> String {
+    let names: Vec<&str> = tools.iter().map(|tool| tool.name.as_ref()).collect();
+    serde_json::to_string(&names)
+        .unwrap_or_else(|e| error_json_code(rmcp::model::ErrorCode::INTERNAL_ERROR.0, &format!("Serialization failed: {}", e)))
+}
+
+// Log levels passed to the log callback
+const LOG_ERROR: i32 = 0;
+const LOG_WARN: i32 = 1;
+const LOG_INFO: i32 = 2;
+const LOG_DEBUG: i32 = 3;
+
+/// Callback the host registers to receive log events
+/// level: 0 = error, 1 = warning, 2 = info, 3 = debug
+pub type McpLogCallback = extern "C" fn(level: i32, message: *const c_char);
+
+static LOG_CALLBACK: Mutex<Option<McpLogCallback>> = Mutex::new(None);
+
+/// Register a callback receiving connect attempts, requests, timeouts and dropped notifications
+/// callback: Function called with a level and message, or NULL to stop logging
+#[no_mangle]
+pub extern "C" fn mcp_set_log_callback(callback: Option<McpLogCallback>) {
+    ffi_guard(|_| (), || {
+        *LOG_CALLBACK.lock_or_recover() = callback;
+    })
+}
+
+/// Send an event to the log callback, if one is registered
+/// The callback runs after the lock is released, so it may call back into the library
+fn log_event(level: i32, message: impl std::fmt::Display) {
+    let callback = *LOG_CALLBACK.lock_or_recover();
+    if let Some(callback) = callback {
+        let message = to_c_string(message.to_string());
+        callback(level, message.as_ptr());
+    }
+}
+
+// Request counters reported by mcp_metrics_json, across all connections
+static REQUESTS_TOTAL: AtomicU64 = AtomicU64::new(0);
+static REQUESTS_IN_FLIGHT: AtomicU64 = AtomicU64::new(0);
+static LAST_LATENCY_MS: AtomicU64 = AtomicU64::new(0);
+static RECONNECTS: AtomicU64 = AtomicU64::new(0);
+
+/// Counts a request as in flight until it is dropped, so timed out and cancelled requests are not left counted
+struct InFlightRequest {
+    _tracked: TrackedRequest,
+}
+
+impl InFlightRequest {
+    fn start(connection_id: &str, request: &str) -> Self {
+        REQUESTS_TOTAL.fetch_add(1, Ordering::Relaxed);
+        REQUESTS_IN_FLIGHT.fetch_add(1, Ordering::Relaxed);
+        InFlightRequest { _tracked: TrackedRequest::start(connection_id, request, false) }
+    }
+}
+
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        REQUESTS_IN_FLIGHT.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Requests listed by mcp_queue_json, keyed by the order they started in
+static TRACKED_REQUESTS: OnceLock<Mutex<std::collections::BTreeMap<u64, QueuedRequest>>> = OnceLock::new();
+static NEXT_TRACKED_REQUEST: AtomicU64 = AtomicU64::new(0);
+
+/// A request waiting for its connection or waiting for the server's answer
+struct QueuedRequest {
+    connection_id: String,
+    kind: String,
+    started: std::time::Instant,
+    queued: bool,
+}
+
+/// Get the tracked requests, creating the map on first use
+fn tracked_requests() -> &'static Mutex<std::collections::BTreeMap<u64, QueuedRequest>> {
+    TRACKED_REQUESTS.get_or_init(|| Mutex::new(std::collections::BTreeMap::new()))
+}
+
+/// Lists a request in mcp_queue_json until dropped
+struct TrackedRequest(u64);
+
+impl TrackedRequest {
+    /// queued: True while the request waits for a connect and has not been sent
+    fn start(connection_id: &str, kind: &str, queued: bool) -> Self {
+        let id = NEXT_TRACKED_REQUEST.fetch_add(1, Ordering::Relaxed);
+        tracked_requests().lock_or_recover().insert(id, QueuedRequest {
+            connection_id: connection_id.to_string(),
+            kind: kind.to_string(),
+            started: std::time::Instant::now(),
+            queued,
+        });
+        TrackedRequest(id)
+    }
+}
+
+impl Drop for TrackedRequest {
+    fn drop(&mut self) {
+        tracked_requests().lock_or_recover().remove(&self.0);
+    }
+}
+
+// Latencies reported by mcp_timing_json, per MCP method and per tool called with tools/call
+static TIMINGS: OnceLock<Mutex<Timings>> = OnceLock::new();
+
+/// Latencies kept for percentiles per method or tool, the oldest dropped first
+const TIMING_SAMPLES: usize = 1000;
+
+#[derive(Default)]
+struct Timings {
+    methods: std::collections::BTreeMap<String, MethodTiming>,
+    tools: std::collections::BTreeMap<String, MethodTiming>,
+}
+
+/// Latency statistics of one method or tool, over every request for count, min, max and avg
+#[derive(Default)]
+struct MethodTiming {
+    count: u64,
+    min_ms: u64,
+    max_ms: u64,
+    total_ms: u64,
+    recent_ms: std::collections::VecDeque<u64>,
+}
+
+impl MethodTiming {
+    fn record(&mut self, elapsed_ms: u64) {
+        self.min_ms = if self.count == 0 { elapsed_ms } else { self.min_ms.min(elapsed_ms) };
+        self.max_ms = self.max_ms.max(elapsed_ms);
+        self.count += 1;
+        self.total_ms = self.total_ms.saturating_add(elapsed_ms);
+        if self.recent_ms.len() == TIMING_SAMPLES {
+            self.recent_ms.pop_front();
+        }
+        self.recent_ms.push_back(elapsed_ms);
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let mut recent: Vec<u64> = self.recent_ms.iter().copied().collect();
+        recent.sort_unstable();
+        let p95 = recent.get((recent.len() * 95).div_ceil(100).saturating_sub(1)).copied().unwrap_or(0);
+        serde_json::json!({
+            "count": self.count,
+            "min_ms": self.min_ms,
+            "max_ms": self.max_ms,
+            "avg_ms": self.total_ms as f64 / self.count.max(1) as f64,
+            "p95_ms": p95,
+        })
+    }
+}
+
+/// Record how long a request took, under the MCP method of its traced name
+/// Tool calls are also recorded under the tool name, so one slow tool stands out from the others
+fn record_timing(request: &str, elapsed_ms: u64) {
+    let (kind, argument) = match request.split_once(' ') {
+        Some((kind, argument)) => (kind, Some(argument.trim_matches('\''))),
+        None => (request, None),
+    };
+    let method = match kind {
+        "PingRequest" | "keep-alive" => "ping",
+        "SetLevelRequest" => "logging/setLevel",
+        "ListToolsRequest" => "tools/list",
+        "CallToolRequest" => "tools/call",
+        "ListResourcesRequest" => "resources/list",
+        "ListResourceTemplatesRequest" => "resources/templates/list",
+        "ReadResourceRequest" => "resources/read",
+        "SubscribeRequest" => "resources/subscribe",
+        "UnsubscribeRequest" => "resources/unsubscribe",
+        "ListPromptsRequest" => "prompts/list",
+        "GetPromptRequest" => "prompts/get",
+        "CompleteRequest" => "completion/complete",
+        // Requests sent with mcp_raw_request_json are traced as "request '<method>'"
+        "request" => argument.unwrap_or(kind),
+        _ => kind,
+    };
+
+    let mut timings = TIMINGS.get_or_init(Mutex::default).lock_or_recover();
+    timings.methods.entry(method.to_string()).or_default().record(elapsed_ms);
+    if let ("tools/call", Some(tool)) = (method, argument) {
+        timings.tools.entry(tool.to_string()).or_default().record(elapsed_ms);
+    }
+}
+
+/// Run a request, logging when it is sent and how long the server took to answer
+async fn traced<T, E: std::fmt::Display>(
+    connection_id: &str,
+    request: &str,
+    future: impl std::future::Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    log_event(LOG_DEBUG, format_args!("[{}] sending {}", connection_id, request));
+    let in_flight = InFlightRequest::start(connection_id, request);
+    let started = std::time::Instant::now();
+    let result = future.await;
+    let elapsed_ms = started.elapsed().as_millis();
+    LAST_LATENCY_MS.store(elapsed_ms as u64, Ordering::Relaxed);
+    record_timing(request, elapsed_ms as u64);
+    drop(in_flight);
+    if result.is_ok() {
+        connection_activity().lock_or_recover().insert(connection_id.to_string(), std::time::Instant::now());
+    }
+    match &result {
+        Ok(_) => log_event(LOG_INFO, format_args!("[{}] received response to {} in {}ms", connection_id, request, elapsed_ms)),
+        Err(e) => log_event(LOG_ERROR, format_args!("[{}] {} failed after {}ms: {}", connection_id, request, elapsed_ms, e)),
+    }
+    result
+}
+
+// Codes of errors raised by the extension itself, in the JSON-RPC range reserved for implementation-defined
+// server errors; errors answered by the server keep the server's code
+const ERROR_TRANSPORT: i32 = -32000;
+const ERROR_NOT_CONNECTED: i32 = -32001;
+const ERROR_TIMEOUT: i32 = -32002;
+const ERROR_CANCELLED: i32 = -32003;
+const ERROR_PERMISSION_DENIED: i32 = -32004;
+const ERROR_RESPONSE_TOO_LARGE: i32 = -32005;
+const ERROR_RATE_LIMITED: i32 = -32006;
+const ERROR_PAUSED: i32 = -32007;
+// Server did not declare the capability a request needs, so the request was not sent
+const ERROR_UNSUPPORTED: i32 = -32099;
+
+/// Build a JSON error response for a transport-level failure
+/// The message is escaped so quotes and control characters from servers stay valid JSON
+fn error_json(message: &str) -> String {
+    error_json_code(ERROR_TRANSPORT, message)
+}
+
+/// Build a JSON error response with a JSON-RPC error code
+fn error_json_code(code: i32, message: &str) -> String {
+    serde_json::json!({ "error": { "code": code, "message": message } }).to_string()
+}
+
+/// Build a JSON error response for arguments rejected before anything is sent
+fn invalid_params_json(message: &str) -> String {
+    error_json_code(rmcp::model::ErrorCode::INVALID_PARAMS.0, message)
+}
+
+/// Build a JSON error response for a failed request, keeping the code and data of errors answered by the server
+/// context: Prefix for errors that did not come from the server, e.g. "Failed to list tools"
+fn service_error_json(context: &str, error: &rmcp::ServiceError) -> String {
+    serde_json::json!({ "error": service_error_value(context, error) }).to_string()
+}
+
+/// Build the error object of a failed request, see service_error_json
+fn service_error_value(context: &str, error: &rmcp::ServiceError) -> serde_json::Value {
+    let code = match error {
+        rmcp::ServiceError::McpError(e) => return serde_json::to_value(e).unwrap_or_default(),
+        rmcp::ServiceError::Timeout { .. } => ERROR_TIMEOUT,
+        rmcp::ServiceError::Cancelled { .. } => ERROR_CANCELLED,
+        _ => ERROR_TRANSPORT,
+    };
+    serde_json::json!({ "code": code, "message": format!("{}: {}", context, error) })
+}
+
+/// Get the message of an error response, for {"error": {"message": ...}} as well as {"error": "..."}
+fn error_message(json: &serde_json::Value) -> Option<&str> {
+    let error = json.get("error")?;
+    error.get("message").unwrap_or(error).as_str()
+}
+
+/// Extract error message from JSON error response
+/// Returns the error message string if found, or the original JSON if not found
+fn extract_error_message(json_str: &str) -> String {
+    match serde_json::from_str::<serde_json::Value>(json_str) {
+        Ok(json) => {
+            if let Some(error) = error_message(&json) {
+                error.to_string()
+            } else {
+                json_str.to_string()
+            }
+        }
+        Err(_) => json_str.to_string(),
+    }
+}
+
+/// Convert a string to a C string, dropping interior NUL bytes instead of failing
+/// Server output can contain NUL bytes, which C strings cannot represent
+fn to_c_string(s: impl Into<String>) -> CString {
+    CString::new(s.into()).unwrap_or_else(|e| {
+        let mut bytes = e.into_vec();
+        bytes.retain(|&b| b != 0);
+        CString::new(bytes).unwrap_or_default()
+    })
+}
+
+/// Run the body of an FFI entry point, calling on_panic with the panic message if it panics
+/// Unwinding across extern "C" into SQLite is undefined behavior, so every exported function goes through here
+fn ffi_guard<T>(on_panic: impl FnOnce(String) -> T, body: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        log_event(LOG_ERROR, format_args!("recovered from panic: {}", message));
+        on_panic(message)
+    })
+}
+
+/// Lock a mutex even if a panic poisoned it, clearing the poison
+/// The state behind every lock stays consistent across a panic, so one failed call must not
+/// make every later call on the same lock panic until the process restarts
+trait LockOrRecover<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T> LockOrRecover<T> for Mutex<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(|poisoned| {
+            self.clear_poison();
+            poisoned.into_inner()
+        })
+    }
+}
+
+/// Panic handler for FFI functions that return a plain error string
+fn panic_error(message: String) -> *mut c_char {
+    to_c_string(format!("Internal error: {}", message)).into_raw()
+}
+
+/// Panic handler for FFI functions that return a JSON string
+fn panic_error_json(message: String) -> *mut c_char {
+    to_c_string(error_json_code(rmcp::model::ErrorCode::INTERNAL_ERROR.0, &format!("Internal error: {}", message))).into_raw()
+}
+
+/// Initialize the MCP library
+/// Returns 0 on success, non-zero on error
+#[no_mangle]
+pub extern "C" fn mcp_init() -> i32 {
+    0
+}
+
+/// Set the timeout applied to every MCP request, and to connecting unless mcp_set_connect_timeout_ms set one
+/// timeout_ms: Milliseconds to wait before giving up, 0 to wait forever (default)
+#[no_mangle]
+pub extern "C" fn mcp_set_timeout_ms(timeout_ms: u32) {
+    REQUEST_TIMEOUT_MS.store(timeout_ms, Ordering::Relaxed);
+}
+
+/// Set the request timeout of one connection, used instead of the global one from mcp_set_timeout_ms
+/// timeout_ms: Milliseconds to wait before giving up, 0 to wait forever, or -1 to use the global timeout again
+/// connection_id: Connection to set the timeout of, can be NULL for the "default" connection
+/// Returns NULL on success, error string on failure (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_set_connection_timeout_ms(timeout_ms: i64, connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error, || {
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(extract_error_message(&error)).into_raw(),
+        };
+        let timeout_ms = match timeout_ms {
+            -1 => None,
+            ms => match u32::try_from(ms) {
+                Ok(ms) => Some(ms),
+                Err(_) => return to_c_string("Timeout must be -1 or between 0 and 4294967295 milliseconds").into_raw(),
+            },
+        };
+        if get_client(&connection_id).is_none() {
+            return to_c_string(extract_error_message(&not_connected_error(&connection_id))).into_raw();
+        }
+
+        match timeout_ms {
+            Some(ms) => connection_timeouts().lock_or_recover().insert(connection_id, ms),
+            None => connection_timeouts().lock_or_recover().remove(&connection_id),
+        };
+        ptr::null_mut()
+    })
+}
+
+/// Set the timeout for opening a transport and completing the initialize handshake, including reconnects
+/// timeout_ms: Milliseconds to wait before giving up, 0 to use the request timeout (default)
+#[no_mangle]
+pub extern "C" fn mcp_set_connect_timeout_ms(timeout_ms: u32) {
+    CONNECT_TIMEOUT_MS.store(timeout_ms, Ordering::Relaxed);
+}
+
+/// Retry connecting when the connect timeout expires before the initialize handshake completes, with exponential backoff
+/// max_attempts: Attempts per connect including the first, 0 or 1 to disable (default)
+/// backoff_ms: Delay before the second attempt, doubled on each following attempt
+/// Connects failing for other reasons, such as a refused connection, are not retried
+#[no_mangle]
+pub extern "C" fn mcp_set_initialize_retry(max_attempts: u32, backoff_ms: u32) {
+    INITIALIZE_MAX_ATTEMPTS.store(max_attempts.max(1), Ordering::Relaxed);
+    INITIALIZE_BACKOFF_MS.store(backoff_ms, Ordering::Relaxed);
+}
+
+/// Enable re-establishing dropped connections, retrying the failed request with exponential backoff
+/// max_retries: Reconnect attempts per request, 0 to disable (default)
+/// base_delay_ms: Delay before the first attempt, doubled on each following attempt
+/// retry_tool_calls: 1 to also retry tool calls, which may run a non-idempotent tool twice, 0 to only retry read-only requests
+#[no_mangle]
+pub extern "C" fn mcp_set_auto_reconnect(max_retries: u32, base_delay_ms: u32, retry_tool_calls: i32) {
+    AUTO_RECONNECT_MAX_RETRIES.store(max_retries, Ordering::Relaxed);
+    AUTO_RECONNECT_BASE_DELAY_MS.store(base_delay_ms, Ordering::Relaxed);
+    AUTO_RECONNECT_RETRY_TOOL_CALLS.store(retry_tool_calls != 0, Ordering::Relaxed);
+}
+
+/// Retry requests that fail with a transient transport error, such as a reset connection, with exponential backoff
+/// max_attempts: Attempts per request including the first, 0 or 1 to disable (default)
+/// backoff_ms: Delay before the second attempt, doubled on each following attempt
+/// Tool calls are only retried when made with mcp_call_tool_idempotent_json
+#[no_mangle]
+pub extern "C" fn mcp_set_retry(max_attempts: u32, backoff_ms: u32) {
+    RETRY_MAX_ATTEMPTS.store(max_attempts.max(1), Ordering::Relaxed);
+    RETRY_BACKOFF_MS.store(backoff_ms, Ordering::Relaxed);
+}
+
+/// Check tool arguments against the tool's input schema before calling it, failing invalid calls locally
+/// enabled: 1 to validate, 0 to send arguments unchecked (default)
+#[no_mangle]
+pub extern "C" fn mcp_set_validate_args(enabled: i32) {
+    VALIDATE_ARGS.store(enabled != 0, Ordering::Relaxed);
+}
+
+/// Check the structured content of tool results against the tool's output schema, flagging mismatches
+/// enabled: 1 to validate, 0 to return results unchecked (default)
+/// A mismatching result is still returned, with a "validation_error" next to it
+#[no_mangle]
+pub extern "C" fn mcp_set_validate_results(enabled: i32) {
+    VALIDATE_RESULTS.store(enabled != 0, Ordering::Relaxed);
+}
+
+/// Read the resources that tool results link to and return their contents in place of the links
+/// enabled: 1 to resolve resource_link items into embedded resources, 0 to return the links as they are (default)
+#[no_mangle]
+pub extern "C" fn mcp_set_resolve_resources(enabled: i32) {
+    RESOLVE_RESOURCES.store(enabled != 0, Ordering::Relaxed);
+}
+
+/// Indent the JSON returned by SQL functions, to make it readable in the SQLite shell
+/// enabled: 1 to indent, 0 to return compact JSON (default)
+#[no_mangle]
+pub extern "C" fn mcp_set_pretty(enabled: i32) {
+    PRETTY_JSON.store(enabled != 0, Ordering::Relaxed);
+}
+
+/// Indent a JSON result of a SQL function when mcp_set_pretty is enabled
+/// json: Result text of the function
+/// Returns: The indented JSON, or NULL if pretty output is off or the text is not a JSON object or array
+/// (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_pretty_json(json: *const c_char) -> *mut c_char {
+    ffi_guard(|_| ptr::null_mut(), || {
+        if json.is_null() || !PRETTY_JSON.load(Ordering::Relaxed) {
+            return ptr::null_mut();
+        }
+        let Ok(json) = unsafe { CStr::from_ptr(json) }.to_str() else {
+            return ptr::null_mut();
+        };
+        match serde_json::from_str::<serde_json::Value>(json) {
+            Ok(value @ (serde_json::Value::Object(_) | serde_json::Value::Array(_))) => match serde_json::to_string_pretty(&value) {
+                Ok(pretty) => to_c_string(pretty).into_raw(),
+                Err(_) => ptr::null_mut(),
+            },
+            _ => ptr::null_mut(),
+        }
+    })
+}
+
+/// Include the JSON-RPC id a tool call was sent with in its result, as {"id": ..., "result": ...}
+/// enabled: 1 to include the id, 0 to leave it out (default)
+#[no_mangle]
+pub extern "C" fn mcp_set_include_request_id(enabled: i32) {
+    INCLUDE_REQUEST_ID.store(enabled != 0, Ordering::Relaxed);
+}
+
+/// Let tool calls wait for a connect in progress on their connection instead of failing as not connected
+/// enabled: 1 to wait up to the connect timeout, 0 to fail at once (default)
+#[no_mangle]
+pub extern "C" fn mcp_set_lazy(enabled: i32) {
+    LAZY_CONNECT.store(enabled != 0, Ordering::Relaxed);
+}
+
+/// Set how long mcp_list_tools_json_cached serves a cached tool list before fetching it again
+/// ttl_ms: Lifetime in milliseconds, 0 keeps the list until the server reports that it changed (default)
+#[no_mangle]
+pub extern "C" fn mcp_set_tools_cache_ttl_ms(ttl_ms: u32) {
+    TOOLS_CACHE_TTL_MS.store(ttl_ms, Ordering::Relaxed);
+}
+
+/// Set how many pages a list request fetches before failing, guarding against servers that never stop paginating
+/// max_pages: Page limit, 0 to follow nextCursor without a limit (default 100)
+#[no_mangle]
+pub extern "C" fn mcp_set_max_list_pages(max_pages: u32) {
+    MAX_LIST_PAGES.store(max_pages, Ordering::Relaxed);
+}
+
+/// Set the largest response accepted from a server, guarding the host against runaway tool results
+/// max_bytes: Limit in bytes, 0 for no limit (default 64 MiB)
+#[no_mangle]
+pub extern "C" fn mcp_set_max_response_bytes(max_bytes: u32) {
+    MAX_RESPONSE_BYTES.store(max_bytes, Ordering::Relaxed);
+}
+
+/// Replace invalid UTF-8 in server responses with U+FFFD instead of failing them, for servers sending latin-1 or other encodings
+/// enabled: 1 to decode leniently, 0 to reject invalid UTF-8 (default)
+#[no_mangle]
+pub extern "C" fn mcp_set_lossy_utf8(enabled: i32) {
+    LOSSY_UTF8.store(enabled != 0, Ordering::Relaxed);
+}
+
+/// Use OAuth2 access tokens for streamable HTTP connections made after this call
+/// token_url: Token endpoint of the authorization server, or NULL to stop using OAuth
+/// client_id: OAuth client id (required with token_url)
+/// client_secret: Optional client secret, can be NULL
+/// refresh_token: Optional refresh token, can be NULL to use the client credentials grant
+/// Returns NULL on success, error string on failure (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_set_oauth(
+    token_url: *const c_char,
+    client_id: *const c_char,
+    client_secret: *const c_char,
+    refresh_token: *const c_char,
+) -> *mut c_char {
+    ffi_guard(panic_error, || {
+        // Read an optional C string argument, rejecting invalid UTF-8
+        let optional = |value: *const c_char| -> Result<Option<String>, ()> {
+            if value.is_null() {
+                return Ok(None);
+            }
+            unsafe { CStr::from_ptr(value).to_str() }.map(|s| Some(s.to_string())).map_err(|_| ())
+        };
+
+        let (token_url, client_id, client_secret, refresh_token) = match (
+            optional(token_url),
+            optional(client_id),
+            optional(client_secret),
+            optional(refresh_token),
+        ) {
+            (Ok(a), Ok(b), Ok(c), Ok(d)) => (a, b, c, d),
+            _ => return to_c_string("Invalid OAuth arguments").into_raw(),
+        };
+
+        let config = match (token_url, client_id) {
+            (None, _) => None,
+            (Some(_), None) => return to_c_string("OAuth requires a client_id").into_raw(),
+            (Some(token_url), Some(client_id)) => Some(OAuthConfig {
+                token_url,
+                client_id,
+                client_secret,
+                refresh_token,
+            }),
+        };
+
+        *OAUTH_CONFIG.lock_or_recover() = config;
+        ptr::null_mut()
+    })
+}
+
+/// Free a string allocated by the MCP library
+#[no_mangle]
+pub extern "C" fn mcp_free_string(s: *mut c_char) {
+    ffi_guard(|_| (), || {
+        if s.is_null() {
+            return;
+        }
+        unsafe {
+            let _ = CString::from_raw(s);
+        }
+    })
+}
+
+/// Extract error message from JSON error response
+/// Returns the error message string if found, or NULL if no error
+#[no_mangle]
+pub extern "C" fn mcp_extract_error_message(json_str: *const c_char) -> *mut c_char {
+    ffi_guard(|_| ptr::null_mut(), || {
+        if json_str.is_null() {
+            return std::ptr::null_mut();
+        }
+
+        let json_string = unsafe {
+            match CStr::from_ptr(json_str).to_str() {
+                Ok(s) => s,
+                Err(_) => return std::ptr::null_mut(),
+            }
+        };
+
+        // Check if this is an error JSON
+        match serde_json::from_str::<serde_json::Value>(json_string) {
+            Ok(json) => {
+                if let Some(error) = error_message(&json) {
+                    // Found an error message, return it
+                    to_c_string(error).into_raw()
+                } else {
+                    // No error field found
+                    std::ptr::null_mut()
+                }
+            }
+            Err(_) => std::ptr::null_mut(),
+        }
+    })
+}
+
+/// Parse tools JSON and extract structured data for virtual table
+/// Returns number of tools found, or 0 on error
+#[no_mangle]
+pub extern "C" fn mcp_parse_tools_json(json_str: *const c_char) -> usize {
+    ffi_guard(|_| 0, || {
+        if json_str.is_null() {
+            return 0;
+        }
+
+        let json_string = unsafe {
+            match CStr::from_ptr(json_str).to_str() {
+                Ok(s) => s,
+                Err(_) => return 0,
+            }
+        };
+
+        // Parse JSON using serde_json
+        match serde_json::from_str::<serde_json::Value>(json_string) {
+            Ok(json) => {
+                if let Some(tools) = json.get("tools").and_then(|v| v.as_array()) {
+                    tools.len()
+                } else {
+                    0
+                }
+            }
+            Err(_) => 0,
+        }
+    })
+}
+
+/// Extract tool data by index for virtual table
+/// Returns allocated string that must be freed, or NULL if index out of bounds
+#[no_mangle]
+pub extern "C" fn mcp_get_tool_field(
+    json_str: *const c_char,
+    tool_index: usize,
+    field_name: *const c_char,
+) -> *mut c_char {
+    ffi_guard(|_| ptr::null_mut(), || {
+        if json_str.is_null() || field_name.is_null() {
+            return ptr::null_mut();
+        }
+
+        let json_string = unsafe {
+            match CStr::from_ptr(json_str).to_str() {
+                Ok(s) => s,
+                Err(_) => return ptr::null_mut(),
+            }
+        };
+
+        let field = unsafe {
+            match CStr::from_ptr(field_name).to_str() {
+                Ok(s) => s,
+                Err(_) => return ptr::null_mut(),
+            }
+        };
+
+        // Parse JSON using serde_json
+        if let Ok(json) = serde_json::from_str::<serde_json::Value>(json_string) {
+            // Check if this is a streaming result (single tool object) or batch result (tools array)
+            let tool = if let Some(tools) = json.get("tools").and_then(|v| v.as_array()) {
+                // Batch result: {"tools": [...]}, get tool by index
+                tools.get(tool_index)
+            } else if tool_index == 0 {
+                // Streaming result: single tool object, only valid for index 0
+                Some(&json)
+            } else {
+                None
+            };
+
+            if let Some(tool) = tool {
+                let value = match field {
+                    "name" => tool.get("name"),
+                    "title" => tool.get("title"),
+                    "description" => tool.get("description"),
+                    "inputSchema" => tool.get("inputSchema"),
+                    "outputSchema" => tool.get("outputSchema"),
+                    "annotations" => tool.get("annotations"),
+                    _ => None,
+                };
+
+                if let Some(v) = value {
+                    let result = if v.is_string() {
+                        v.as_str().unwrap_or("").to_string()
+                    } else {
+                        // For complex objects, serialize to JSON
+                        serde_json::to_string(v).unwrap_or_else(|_| "".to_string())
+                    };
+
+                    return to_c_string(result).into_raw();
+                }
+            }
+        }
+
+        // Return empty string if field not found
+        to_c_string("").into_raw()
+    })
+}
+
+/// Items of a list result, parsed once for a virtual table cursor to read field by field
+pub struct McpList {
+    items: Vec<serde_json::Value>,
+}
+
+/// Parse the array under list_key of a list result, such as "resources", for a list virtual table
+/// Returns a list to read with mcp_list_len and mcp_get_list_field and free with mcp_free_list,
+/// or NULL if the JSON is not valid or has no such array
+#[no_mangle]
+pub extern "C" fn mcp_parse_list_json(json_str: *const c_char, list_key: *const c_char) -> *mut McpList {
+    ffi_guard(|_| ptr::null_mut(), || {
+        if json_str.is_null() || list_key.is_null() {
+            return ptr::null_mut();
+        }
+
+        let (json_string, list_key) = unsafe {
+            match (CStr::from_ptr(json_str).to_str(), CStr::from_ptr(list_key).to_str()) {
+                (Ok(json_string), Ok(list_key)) => (json_string, list_key),
+                _ => return ptr::null_mut(),
+            }
+        };
+
+        match serde_json::from_str::<serde_json::Value>(json_string) {
+            Ok(mut json) => match json.get_mut(list_key).map(serde_json::Value::take) {
+                Some(serde_json::Value::Array(items)) => Box::into_raw(Box::new(McpList { items })),
+                _ => ptr::null_mut(),
+            },
+            Err(_) => ptr::null_mut(),
+        }
+    })
+}
+
+/// Number of items in a list returned by mcp_parse_list_json, 0 for NULL
+#[no_mangle]
+pub extern "C" fn mcp_list_len(list: *const McpList) -> usize {
+    ffi_guard(|_| 0, || unsafe { list.as_ref() }.map_or(0, |list| list.items.len()))
+}
+
+/// Extract a field of a list item by index for a list virtual table
+/// as_json: 1 to return every value as JSON text, 0 to return strings as they are and other values as JSON text
+/// Returns allocated string that must be freed, empty if the field is missing or null, or NULL if index out of bounds
+#[no_mangle]
+pub extern "C" fn mcp_get_list_field(list: *const McpList, index: usize, field_name: *const c_char, as_json: i32) -> *mut c_char {
+    ffi_guard(|_| ptr::null_mut(), || {
+        let Some(list) = (unsafe { list.as_ref() }) else {
+            return ptr::null_mut();
+        };
+        if field_name.is_null() {
+            return ptr::null_mut();
+        }
+        let Ok(field) = unsafe { CStr::from_ptr(field_name) }.to_str() else {
+            return ptr::null_mut();
+        };
+        let Some(item) = list.items.get(index) else {
+            return ptr::null_mut();
+        };
+
+        let result = match item.get(field) {
+            Some(serde_json::Value::String(s)) if as_json == 0 => s.clone(),
+            Some(serde_json::Value::Null) | None => String::new(),
+            Some(v) => v.to_string(),
+        };
+        to_c_string(result).into_raw()
+    })
+}
+
+/// Free a list returned by mcp_parse_list_json
+#[no_mangle]
+pub extern "C" fn mcp_free_list(list: *mut McpList) {
+    ffi_guard(|_| (), || {
+        if !list.is_null() {
+            drop(unsafe { Box::from_raw(list) });
+        }
+    })
+}
+
+/// Parse call tool result JSON and extract text content  
+/// Returns number of text results found, or 0 on error
+#[no_mangle]
+pub extern "C" fn mcp_parse_call_result_json(json_str: *const c_char) -> usize {
+    ffi_guard(|_| 0, || {
+        if json_str.is_null() {
+            return 0;
+        }
+
+        let json_string = unsafe {
+            match CStr::from_ptr(json_str).to_str() {
+                Ok(s) => s,
+                Err(_) => return 0,
+            }
+        };
+
+        // Parse JSON using serde_json
+        match serde_json::from_str::<serde_json::Value>(json_string) {
+            Ok(json) => {
+                // Try both direct content and nested result.content
+                let content_array = json.get("content").and_then(|v| v.as_array())
+                    .or_else(|| json.get("result").and_then(|r| r.get("content").and_then(|v| v.as_array())));
+                
+                if let Some(content) = content_array {
+                    content.len()
+                } else {
+                    0
+                }
+            }
+            Err(_) => 0,
+        }
+    })
+}
+
+/// Extract call result text by index for virtual table
+/// Returns allocated string that must be freed, or NULL if index out of bounds
+#[no_mangle]
+pub extern "C" fn mcp_get_call_result_text(
+    json_str: *const c_char,
+    content_index: usize,
+) -> *mut c_char {
+    ffi_guard(|_| ptr::null_mut(), || {
+        if json_str.is_null() {
+            return ptr::null_mut();
+        }
+
+        let json_string = unsafe {
+            match CStr::from_ptr(json_str).to_str() {
+                Ok(s) => s,
+                Err(_) => return ptr::null_mut(),
+            }
+        };
+
+        // Parse JSON using serde_json
+        if let Ok(json) = serde_json::from_str::<serde_json::Value>(json_string) {
+            // Try both direct content and nested result.content
+            let content_array = json.get("content").and_then(|v| v.as_array())
+                .or_else(|| json.get("result").and_then(|r| r.get("content").and_then(|v| v.as_array())));
+            
+            if let Some(content) = content_array {
+                if let Some(item) = content.get(content_index) {
+                    if let Some(text) = item.get("text").and_then(|v| v.as_str()) {
+                        return to_c_string(text).into_raw();
+                    }
+                }
+            }
+        }
+
+        // Return empty string if not found
+        to_c_string("").into_raw()
+    })
+}
+
+/// Extract one content item of a call tool result as a virtual table row, in the form of the streamed
+/// rows read with mcp_get_content_field, carrying the result's isError status
+/// Returns allocated string that must be freed, or NULL if index out of bounds
+#[no_mangle]
+pub extern "C" fn mcp_get_call_result_row(json_str: *const c_char, content_index: usize) -> *mut c_char {
+    ffi_guard(|_| ptr::null_mut(), || {
+        if json_str.is_null() {
+            return ptr::null_mut();
+        }
+
+        let Ok(json_string) = unsafe { CStr::from_ptr(json_str) }.to_str() else {
+            return ptr::null_mut();
+        };
+        let Ok(json) = serde_json::from_str::<serde_json::Value>(json_string) else {
+            return ptr::null_mut();
+        };
+
+        // Try both direct content and nested result.content
+        let result = json.get("result").filter(|r| r.get("content").is_some()).unwrap_or(&json);
+        let is_error = result.get("isError").and_then(|v| v.as_bool()).unwrap_or(false);
+        match result.get("content").and_then(|v| v.as_array()).and_then(|content| content.get(content_index)) {
+            Some(item) => to_c_string(content_row(item, is_error).to_string()).into_raw(),
+            None => ptr::null_mut(),
+        }
+    })
+}
+
+/// Extract a field from a streamed content row (see mcp_call_tool_init) for virtual table
+/// field_name: One of "type", "text", "data" or "is_error"
+/// Returns allocated string that must be freed, or NULL if the field is missing or null
+#[no_mangle]
+pub extern "C" fn mcp_get_content_field(json_str: *const c_char, field_name: *const c_char) -> *mut c_char {
+    ffi_guard(|_| ptr::null_mut(), || {
+        if json_str.is_null() || field_name.is_null() {
+            return ptr::null_mut();
+        }
+
+        let json_string = unsafe {
+            match CStr::from_ptr(json_str).to_str() {
+                Ok(s) => s,
+                Err(_) => return ptr::null_mut(),
+            }
+        };
+
+        let field = unsafe {
+            match CStr::from_ptr(field_name).to_str() {
+                Ok(s) => s,
+                Err(_) => return ptr::null_mut(),
+            }
+        };
+
+        // Parse JSON using serde_json
+        let value = match serde_json::from_str::<serde_json::Value>(json_string) {
+            Ok(json) => json.get(field).cloned(),
+            Err(_) => None,
+        };
+
+        let result = match value {
+            Some(serde_json::Value::Null) | None => return ptr::null_mut(),
+            Some(serde_json::Value::String(s)) => s,
+            // For complex objects and booleans, serialize to JSON
+            Some(v) => serde_json::to_string(&v).unwrap_or_default(),
+        };
+
+        to_c_string(result).into_raw()
+    })
+}
+
+type RunningClient = rmcp::service::RunningService<RoleClient, McpClientHandler>;
+
+/// Ping idle connections to find out early when the server or the network path to it has gone away
+/// interval_ms: Idle time before a ping is sent, also the time the server has to answer it, 0 to disable (default)
+/// A connection whose ping fails is marked dead, and re-established if mcp_set_auto_reconnect is enabled
+#[no_mangle]
+pub extern "C" fn mcp_set_keepalive(interval_ms: u32) {
+    keepalive_interval().send_replace(interval_ms);
+}
+
+/// HTTP client with the settings generation, connection overrides key and default headers it was built with
+type CachedHttpClient = (u32, String, Vec<(String, String)>, reqwest::Client);
+
+/// State of one connection, stored in the global connection map under its connection id
+pub struct McpClient {
+    connection_id: String,
+    runtime: Arc<tokio::runtime::Runtime>,
+    service: Arc<TokioMutex<Option<RunningClient>>>,
+    server_url: Mutex<Option<String>>,
+    connect_params: Mutex<Option<ConnectParams>>,
+    // Config the connection was opened with, as given to mcp_connect_json or built by mcp_connect
+    connect_config: Mutex<Option<ConnectConfig>>,
+    // HTTP client reused when reconnecting with the same headers and unchanged TLS and proxy settings
+    http_client: Mutex<Option<CachedHttpClient>>,
+    // Server's tools for mcp_list_tools_json_cached and argument and result validation, with the schema
+    // validators compiled from them, shared with the handler that clears it
+    tools_cache: ToolListCache,
+    // HTTP_SETTINGS_GENERATION when the connection was opened, to tell whether TLS or proxy settings changed since
+    settings_generation: u32,
+    // Resources subscribed to and server log level set on the connection, which mcp_reconnect restores
+    subscriptions: Mutex<BTreeSet<String>>,
+    server_log_level: Mutex<Option<LoggingLevel>>,
+    // Tasks spawned for the connection, aborted when it is closed or replaced since the runtime may outlive it
+    background_tasks: Mutex<Vec<tokio::task::AbortHandle>>,
+}
+
+impl Drop for McpClient {
+    fn drop(&mut self) {
+        for task in self.background_tasks.lock_or_recover().drain(..) {
+            task.abort();
+        }
+    }
+}
+
+// Times any connection's tool list may have changed, on tools/list_changed or a reconnect, so
+// SQL functions made for tools by mcp_register_tools know to register newly added tools
+static TOOL_LIST_CHANGES: AtomicU64 = AtomicU64::new(0);
+
+/// Count the times the tool list of any connection may have changed
+/// Returns a number that grows on every tools/list_changed notification and reconnect
+#[no_mangle]
+pub extern "C" fn mcp_tool_list_changes() -> u64 {
+    TOOL_LIST_CHANGES.load(Ordering::Relaxed)
+}
+
+// Tool list and the time it was fetched, loaded on first use and dropped on tools/list_changed
+type ToolListCache = Arc<Mutex<Option<(std::time::Instant, Arc<Vec<Tool>>, Arc<SchemaValidators>)>>>;
+
+// Schema validators of a cached tool list by tool name and whether they check the output schema,
+// None for tools they cannot be compiled for; dropped with the list, so a new list compiles them again
+type SchemaValidators = Mutex<HashMap<(String, bool), Option<Arc<jsonschema::Validator>>>>;
+
+/// Create a new MCP client handle, kept for API compatibility
+/// Connections live in the global connection map keyed by connection id, so the handle holds no
+/// state; it is a non-NULL placeholder that is never dereferenced
+#[no_mangle]
+pub extern "C" fn mcp_client_new() -> *mut McpClient {
+    ptr::NonNull::<McpClient>::dangling().as_ptr()
+}
+
+/// Free an MCP client handle returned by mcp_client_new (nothing to release)
+/// Use mcp_disconnect to close a connection
+#[no_mangle]
+pub extern "C" fn mcp_client_free(_client: *mut McpClient) {}
+
+/// Connect to an MCP server with optional custom headers
+/// server_url: URL of the MCP server (e.g., "http://localhost:8931/sse"), or a ws:// or wss:// URL to use the WebSocket transport
+/// headers_json: Optional JSON object of custom headers (e.g., '{"Authorization": "Bearer token", "X-MCP-Readonly": "true"}'),
+///               or an array of [name, value] pairs to send a header more than once, can be NULL
+/// legacy_sse: 1 to use SSE transport (legacy), 0 to use streamable HTTP transport (default)
+/// connection_id: Optional name for this connection, can be NULL to use the "default" connection
+/// force: 1 to reconnect even if the connection is already open with the same settings, 0 to keep it
+/// Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_connect(
+    _client_ptr: *mut McpClient,
+    server_url: *const c_char,
+    headers_json: *const c_char,
+    legacy_sse: i32,
+    connection_id: *const c_char,
+    force: i32,
+) -> *mut c_char {
+    ffi_guard(panic_error, || {
+        if server_url.is_null() {
+            let error = invalid_params_json("Invalid arguments");
+            return to_c_string(error).into_raw();
+        }
+
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let server_url_str = unsafe {
+            match CStr::from_ptr(server_url).to_str() {
+                Ok(s) => s.to_string(),
+                Err(_) => {
+                    let error = invalid_params_json("Invalid server URL");
+                    return to_c_string(error).into_raw();
+                }
+            }
+        };
+
+        // Parse optional headers_json (can be NULL, a JSON object or an array of [name, value] pairs)
+        let headers_map: Option<Vec<(String, String)>> = if headers_json.is_null() {
+            None
+        } else {
+            unsafe {
+                match CStr::from_ptr(headers_json).to_str() {
+                    Ok(json_str) => match parse_headers_json(json_str) {
+                        Ok(headers) => Some(headers),
+                        Err(error) => return to_c_string(error).into_raw(),
+                    },
+                    Err(_) => {
+                        let error = invalid_params_json("Invalid headers string");
+                        return to_c_string(error).into_raw();
+                    }
+                }
+            }
+        };
+
+        let config = ConnectConfig {
+            connection_id: Some(connection_id),
+            url: Some(server_url_str),
+            transport: (legacy_sse != 0).then_some(Transport::Sse),
+            headers: headers_map.map(|headers| serde_json::json!(headers)),
+            force: (force != 0).then_some(true),
+            ..Default::default()
+        };
+        connect_with_config(config)
+    })
+}
+
+/// Connect to an MCP server with every setting of the connection given in one JSON object
+/// config_json: JSON object such as '{"url": "http://localhost:8931/mcp", "headers": {"X-MCP-Readonly": "true"}, "timeout_ms": 5000}'
+///              with the fields url, transport, command, args, headers, connection_id, timeout_ms, tls, proxy and auth
+/// Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_connect_json(config_json: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error, || {
+        if config_json.is_null() {
+            return to_c_string("Invalid arguments").into_raw();
+        }
+
+        let config = match parse_connect_config(config_json) {
+            Ok(config) => config,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        connect_with_config(config)
+    })
+}
+
+/// Connect to several MCP servers at the same time, so their initialize handshakes overlap (returns raw JSON)
+/// configs_json: JSON array of configs with the fields mcp_connect_json accepts, each with its own connection_id
+/// Returns: JSON object mapping each connection id to {"connected": true} or {"connected": false, "error": "..."} (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_connect_many_json(configs_json: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        if configs_json.is_null() {
+            return to_c_string(invalid_params_json("Invalid arguments")).into_raw();
+        }
+
+        let configs = match unsafe { CStr::from_ptr(configs_json) }.to_str().ok().and_then(|s| serde_json::from_str::<Vec<serde_json::Value>>(s).ok()) {
+            Some(configs) => configs,
+            None => return to_c_string(invalid_params_json("Invalid configs JSON format. Expected a JSON array of connect configs")).into_raw(),
+        };
+
+        // Every config is checked before connecting, so a typo does not leave half of the set connected
+        let mut connections = Vec::with_capacity(configs.len());
+        for (index, config) in configs.into_iter().enumerate() {
+            let config = match serde_json::from_value::<ConnectConfig>(config) {
+                Ok(config) => config,
+                Err(e) => return to_c_string(invalid_params_json(&format!("Invalid connect config at index {}: {}", index, e))).into_raw(),
+            };
+            let connection_id = config.connection_id.clone().unwrap_or_else(|| DEFAULT_CONNECTION_ID.to_string());
+            if connections.iter().any(|(id, _)| *id == connection_id) {
+                let message = format!("Duplicate connection id '{}' at index {}", connection_id, index);
+                return to_c_string(invalid_params_json(&message)).into_raw();
+            }
+            connections.push((connection_id, config));
+        }
+
+        // Each connection blocks on its own runtime while it connects, so each connects on its own thread
+        let connects: Vec<_> = connections.into_iter().map(|(connection_id, config)| {
+            let connect = std::thread::spawn(move || {
+                let error = ffi_guard(panic_error, || connect_with_config(config));
+                (!error.is_null()).then(|| unsafe { CString::from_raw(error) }.to_string_lossy().into_owned())
+            });
+            (connection_id, connect)
+        }).collect();
+
+        let results: serde_json::Map<String, serde_json::Value> = connects.into_iter().map(|(connection_id, connect)| {
+            let result = match connect.join() {
+                Ok(None) => serde_json::json!({ "connected": true }),
+                Ok(Some(error)) => serde_json::json!({ "connected": false, "error": error }),
+                Err(_) => serde_json::json!({ "connected": false, "error": "Connect thread panicked" }),
+            };
+            (connection_id, result)
+        }).collect();
+
+        to_c_string(serde_json::Value::Object(results).to_string()).into_raw()
+    })
+}
+
+/// Check a connect config without connecting: parse it, load its certificate files and build its HTTP client
+/// config_json: JSON object with the fields mcp_connect_json accepts
+/// Returns: NULL if the config is valid, error string naming the problem otherwise (must be freed with mcp_free_string)
+/// Nothing is sent over the network; the host of a tls.server_name URL is not resolved either
+#[no_mangle]
+pub extern "C" fn mcp_validate_config_json(config_json: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error, || {
+        if config_json.is_null() {
+            return to_c_string("Invalid arguments").into_raw();
+        }
+
+        let config = match parse_connect_config(config_json) {
+            Ok(config) => config,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let result = config.connect_params(false).and_then(|params| match params {
+            ConnectParams::Http { headers, overrides, .. } => {
+                let header_map = default_header_map(headers.as_deref().unwrap_or_default())?;
+                http_client_builder(overrides.as_deref())
+                    .default_headers(header_map)
+                    .build()
+                    .map(|_| ())
+                    .map_err(|e| error_json(&format!("Failed to create HTTP client: {}", e)))
+            }
+            ConnectParams::Stdio { .. } | ConnectParams::WebSocket { .. } => Ok(()),
+        });
+
+        match result {
+            Ok(()) => ptr::null_mut(),
+            Err(error) => to_c_string(extract_error_message(&error)).into_raw(),
+        }
+    })
+}
+
+/// Parse the config JSON given to mcp_connect_json or mcp_validate_config_json
+/// Returns the error string to hand back if it is not valid UTF-8 or not a valid config
+fn parse_connect_config(config_json: *const c_char) -> Result<ConnectConfig, String> {
+    match unsafe { CStr::from_ptr(config_json) }.to_str() {
+        Ok(json_str) => serde_json::from_str::<ConnectConfig>(json_str).map_err(|e| format!("Invalid connect config: {}", e)),
+        Err(_) => Err("Invalid connect config string".to_string()),
+    }
+}
+
+/// Connect to a local MCP server by spawning it as a child process speaking JSON-RPC over stdio
+/// command: Program to execute (e.g., "npx")
+/// args_json: Optional JSON array of string arguments (e.g., '["@modelcontextprotocol/server-filesystem", "/data"]'), can be NULL
+/// connection_id: Optional name for this connection, can be NULL to use the "default" connection
+/// env_json: Optional JSON object of environment variables set for the process (e.g., '{"API_KEY": "..."}'), can be NULL
+/// cwd: Optional working directory of the process, can be NULL to use the current one
+/// inherit_env: 1 to start from this process's environment, 0 to pass only env_json
+/// Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_connect_stdio(
+    _client_ptr: *mut McpClient,
+    command: *const c_char,
+    args_json: *const c_char,
+    connection_id: *const c_char,
+    env_json: *const c_char,
+    cwd: *const c_char,
+    inherit_env: i32,
+) -> *mut c_char {
+    ffi_guard(panic_error, || {
+        if command.is_null() {
+            let error = invalid_params_json("Invalid arguments");
+            return to_c_string(error).into_raw();
+        }
+
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let command_str = unsafe {
+            match CStr::from_ptr(command).to_str() {
+                Ok(s) => s.to_string(),
+                Err(_) => {
+                    let error = invalid_params_json("Invalid command");
+                    return to_c_string(error).into_raw();
+                }
+            }
+        };
+
+        // Parse optional args_json (can be NULL or a JSON array of strings)
+        let args: Vec<String> = if args_json.is_null() {
+            Vec::new()
+        } else {
+            unsafe {
+                match CStr::from_ptr(args_json).to_str() {
+                    Ok(json_str) => match serde_json::from_str::<Vec<String>>(json_str) {
+                        Ok(args) => args,
+                        Err(_) => {
+                            let error = invalid_params_json("Invalid args JSON format. Expected: [\"arg1\", \"arg2\"]");
+                            return to_c_string(error).into_raw();
+                        }
+                    },
+                    Err(_) => {
+                        let error = invalid_params_json("Invalid args string");
+                        return to_c_string(error).into_raw();
+                    }
+                }
+            }
+        };
+
+        let env = if env_json.is_null() {
+            None
+        } else {
+            let env = unsafe { CStr::from_ptr(env_json) }.to_str().ok()
+                .and_then(|json_str| serde_json::from_str::<BTreeMap<String, String>>(json_str).ok());
+            match env {
+                Some(env) => Some(env),
+                None => {
+                    let error = invalid_params_json("Invalid env JSON format. Expected: {\"NAME\": \"value\"}");
+                    return to_c_string(error).into_raw();
+                }
+            }
+        };
+
+        let cwd = if cwd.is_null() {
+            None
+        } else {
+            match unsafe { CStr::from_ptr(cwd) }.to_str() {
+                Ok(cwd) => Some(cwd.to_string()),
+                Err(_) => {
+                    let error = invalid_params_json("Invalid cwd string");
+                    return to_c_string(error).into_raw();
+                }
+            }
+        };
+
+        let config = ConnectConfig {
+            connection_id: Some(connection_id),
+            transport: Some(Transport::Stdio),
+            command: Some(command_str),
+            args,
+            env,
+            cwd,
+            inherit_env: (inherit_env == 0).then_some(false),
+            ..Default::default()
+        };
+        connect_with_config(config)
+    })
+}
+
+/// Parse the headers argument of mcp_connect, keeping repeated headers in order
+/// Returns the error JSON string naming the header that is not a valid HTTP header
+fn parse_headers_json(json_str: &str) -> Result<Vec<(String, String)>, String> {
+    let json: serde_json::Value = serde_json::from_str(json_str).map_err(|_| {
+        invalid_params_json("Invalid headers JSON format. Expected: {\"Header-Name\": \"value\"} or [[\"Header-Name\", \"value\"]]")
+    })?;
+    parse_headers_value(json)
+}
+
+/// Parse headers given as a JSON object or an array of [name, value] pairs
+/// Returns the error JSON string naming the header that is not a valid HTTP header
+fn parse_headers_value(json: serde_json::Value) -> Result<Vec<(String, String)>, String> {
+    let headers = match json {
+        serde_json::Value::Object(map) => map
+            .into_iter()
+            .map(|(name, value)| match value {
+                serde_json::Value::String(value) => Ok((name, value)),
+                _ => Err(invalid_params_json(&format!("Invalid value for header '{}': expected a string", name))),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        serde_json::Value::Array(entries) => entries
+            .into_iter()
+            .enumerate()
+            .map(|(index, entry)| match serde_json::from_value::<(String, String)>(entry) {
+                Ok(header) => Ok(header),
+                Err(_) => Err(invalid_params_json(&format!(
+                    "Invalid header at index {}: expected [\"Header-Name\", \"value\"]",
+                    index
+                ))),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => return Err(invalid_params_json("Invalid headers JSON format. Expected: {\"Header-Name\": \"value\"} or [[\"Header-Name\", \"value\"]]")),
+    };
+
+    // Check every header now so the error names it, rather than failing later in the transport
+    for (name, value) in &headers {
+        if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err() {
+            return Err(invalid_params_json(&format!("Invalid header name '{}'", name)));
+        }
+        if reqwest::header::HeaderValue::from_str(value).is_err() {
+            return Err(invalid_params_json(&format!("Invalid value for header '{}'", name)));
+        }
+    }
+    Ok(headers)
+}
+
+/// Settings of one connection, given to mcp_connect_json or built from the arguments of mcp_connect
+/// Settings left out use the transport implied by the URL and the global TLS, proxy, OAuth and timeout settings
+/// Each connection keeps the config it was opened with, returned by mcp_connection_config_json
+#[derive(Clone, Default, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+struct ConnectConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connection_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transport: Option<Transport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    command: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    args: Vec<String>,
+    // Environment variables and working directory of a stdio server; false in inherit_env passes only env
+    #[serde(skip_serializing_if = "Option::is_none")]
+    env: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cwd: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inherit_env: Option<bool>,
+    // A JSON object or an array of [name, value] pairs, checked by parse_headers_value
+    #[serde(skip_serializing_if = "Option::is_none")]
+    headers: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeout_ms: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tls: Option<TlsSettings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxy: Option<String>,
+    // false stops asking HTTP servers for gzip, brotli or deflate compressed responses
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compression: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auth: Option<AuthSettings>,
+    // true reconnects even when the connection is already open with this config; never stored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    force: Option<bool>,
+}
+
+/// Transport named in a connect config, spelled as in the connection status
+#[derive(Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Transport {
+    StreamableHttp,
+    Sse,
+    Websocket,
+    Stdio,
+}
+
+/// TLS settings of a connect config, with the meaning of the mcp_set_tls arguments
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+struct TlsSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ca: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cert: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    #[serde(default)]
+    insecure: bool,
+    // Domain sent as SNI and checked against the certificate, while connecting to the address in the URL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    server_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sni: Option<bool>,
+}
+
+/// Credentials of a connect config: a static bearer token, or OAuth2 with the mcp_set_oauth settings
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum AuthSettings {
+    Bearer {
+        token: String,
+    },
+    Oauth {
+        token_url: String,
+        client_id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        client_secret: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        refresh_token: Option<String>,
+    },
+}
+
+/// Point a URL at a TLS server name while still connecting to the addresses of the URL's own host
+/// resolve_host: false to skip looking up the URL's host, returning no addresses
+/// Returns the URL with the server name as its host, and the addresses that name is resolved to
+fn pin_server_name(url: &str, server_name: &str, resolve_host: bool) -> Result<(String, Vec<std::net::SocketAddr>), String> {
+    use std::net::ToSocketAddrs;
+
+    let mut parsed = reqwest::Url::parse(url).map_err(|e| invalid_params_json(&format!("Invalid url '{}': {}", url, e)))?;
+    let (host, port) = match (parsed.host_str(), parsed.port_or_known_default()) {
+        (Some(host), Some(port)) => (host.trim_start_matches('[').trim_end_matches(']').to_string(), port),
+        _ => return Err(invalid_params_json("tls.server_name requires a url with a host")),
+    };
+    let addrs: Vec<std::net::SocketAddr> = if resolve_host {
+        (host.as_str(), port)
+            .to_socket_addrs()
+            .map_err(|e| error_json(&format!("Failed to resolve {}: {}", host, e)))?
+            .collect()
+    } else {
+        Vec::new()
+    };
+    parsed.set_host(Some(server_name))
+        .map_err(|_| invalid_params_json(&format!("Invalid tls.server_name '{}'", server_name)))?;
+    Ok((parsed.to_string(), addrs))
+}
+
+impl ConnectConfig {
+    /// Check the config and resolve it into the parameters the connection is opened with
+    /// resolve_hosts: false to leave out the DNS lookup tls.server_name needs, when only checking the config
+    /// Returns the error JSON string naming the setting that is missing, invalid or does not apply to the transport
+    fn connect_params(&self, resolve_hosts: bool) -> Result<ConnectParams, String> {
+        let transport = match (self.transport, &self.url, &self.command) {
+            (Some(transport), _, _) => transport,
+            (None, _, Some(_)) => Transport::Stdio,
+            (None, Some(url), None) if is_websocket_url(url) => Transport::Websocket,
+            (None, _, None) => Transport::StreamableHttp,
+        };
+
+        if transport == Transport::Stdio {
+            if self.url.is_some() || self.headers.is_some() || self.host.is_some() || self.tls.is_some() || self.proxy.is_some()
+                || self.compression.is_some() || self.auth.is_some()
+            {
+                return Err(invalid_params_json("url, headers, host, tls, proxy, compression and auth do not apply to the stdio transport"));
+            }
+            let command = self.command.clone()
+                .ok_or_else(|| invalid_params_json("The stdio transport requires a command"))?;
+            let env: Vec<(String, String)> = self.env.clone().unwrap_or_default().into_iter().collect();
+            if let Some((name, _)) = env.iter().find(|(name, _)| name.is_empty() || name.contains(['=', '\0'])) {
+                return Err(invalid_params_json(&format!("Invalid environment variable name '{}'", name)));
+            }
+            return Ok(ConnectParams::Stdio {
+                command,
+                args: self.args.clone(),
+                env,
+                cwd: self.cwd.clone(),
+                inherit_env: self.inherit_env.unwrap_or(true),
+            });
+        }
+
+        if self.command.is_some() || !self.args.is_empty() || self.env.is_some() || self.cwd.is_some() || self.inherit_env.is_some() {
+            return Err(invalid_params_json("command, args, env, cwd and inherit_env only apply to the stdio transport"));
+        }
+        let mut url = self.url.clone().ok_or_else(|| invalid_params_json("A url is required"))?;
+        match transport {
+            Transport::Websocket if !is_websocket_url(&url) => {
+                return Err(invalid_params_json("WebSocket transport requires a ws:// or wss:// URL"));
+            }
+            Transport::Sse if is_websocket_url(&url) => {
+                return Err(error_json("SSE transport cannot be used with a WebSocket URL"));
+            }
+            Transport::StreamableHttp if is_websocket_url(&url) => {
+                return Err(error_json("Streamable HTTP transport cannot be used with a WebSocket URL"));
+            }
+            _ => {}
+        }
+
+        let mut headers = self.headers.clone().map(parse_headers_value).transpose()?;
+        if let Some(AuthSettings::Bearer { token }) = &self.auth {
+            headers.get_or_insert_with(Vec::new).push(("Authorization".to_string(), format!("Bearer {}", token)));
+        }
+        if let Some(host) = &self.host {
+            if reqwest::header::HeaderValue::from_str(host).is_err() {
+                return Err(invalid_params_json(&format!("Invalid host '{}'", host)));
+            }
+            let headers = headers.get_or_insert_with(Vec::new);
+            headers.retain(|(name, _)| !name.eq_ignore_ascii_case("host"));
+            headers.push(("Host".to_string(), host.clone()));
+        }
+
+        if transport == Transport::Websocket {
+            if self.tls.is_some() || self.proxy.is_some() || self.compression.is_some() {
+                return Err(invalid_params_json("tls, proxy and compression do not apply to the websocket transport"));
+            }
+            if matches!(self.auth, Some(AuthSettings::Oauth { .. })) {
+                return Err(invalid_params_json("OAuth only applies to the streamable-http transport"));
+            }
+            return Ok(ConnectParams::WebSocket { url, headers });
+        }
+
+        let compression = self.compression.unwrap_or(true);
+        let overrides = if self.tls.is_some() || self.proxy.is_some() || !compression {
+            let tls = match &self.tls {
+                Some(tls) => Some(
+                    TlsConfig::load(tls.ca.clone(), tls.cert.clone(), tls.key.clone(), tls.insecure)
+                        .map_err(|e| invalid_params_json(&e))?,
+                ),
+                None => None,
+            };
+            let proxy = self.proxy.as_deref().map(parse_proxy).transpose().map_err(|e| invalid_params_json(&e))?;
+            let server_name = match self.tls.as_ref().and_then(|tls| tls.server_name.as_deref()) {
+                Some(server_name) => {
+                    let (server_url, addrs) = pin_server_name(&url, server_name, resolve_hosts)?;
+                    url = server_url;
+                    Some((server_name.to_string(), addrs))
+                }
+                None => None,
+            };
+            let sni = self.tls.as_ref().and_then(|tls| tls.sni).unwrap_or(true);
+            let key = serde_json::json!([
+                self.tls,
+                self.proxy,
+                server_name.as_ref().map(|(_, addrs)| format!("{:?}", addrs)),
+                compression,
+            ]).to_string();
+            Some(Arc::new(HttpOverrides { tls, proxy, server_name, sni, compression, key }))
+        } else {
+            None
+        };
+
+        // OAuth only applies to streamable HTTP; each connection keeps its own token
+        let legacy_sse = transport == Transport::Sse;
+        let oauth_config = match &self.auth {
+            Some(AuthSettings::Oauth { .. }) if legacy_sse => {
+                return Err(invalid_params_json("OAuth only applies to the streamable-http transport"));
+            }
+            Some(AuthSettings::Oauth { token_url, client_id, client_secret, refresh_token }) => Some(OAuthConfig {
+                token_url: token_url.clone(),
+                client_id: client_id.clone(),
+                client_secret: client_secret.clone(),
+                refresh_token: refresh_token.clone(),
+            }),
+            Some(AuthSettings::Bearer { .. }) => None,
+            None if legacy_sse => None,
+            None => OAUTH_CONFIG.lock_or_recover().clone(),
+        };
+        let oauth = match oauth_config {
+            Some(config) => Some(Arc::new(OAuthSession::new(config, overrides.as_deref())?)),
+            None => None,
+        };
+
+        Ok(ConnectParams::Http { url, headers, legacy_sse, oauth, overrides })
+    }
+
+    /// Copy of the config with credential headers, tokens, client secrets and proxy passwords replaced by "***"
+    /// The copy keeps its shape, so it still shows which settings were used
+    fn redacted(&self) -> ConnectConfig {
+        const REDACTED: &str = "***";
+        let is_secret_header = |name: &str| {
+            let name = name.to_ascii_lowercase();
+            matches!(name.as_str(), "authorization" | "proxy-authorization" | "cookie")
+                || ["token", "secret", "api-key", "apikey"].iter().any(|word| name.contains(word))
+        };
+
+        let mut config = self.clone();
+        match config.headers.as_mut() {
+            Some(serde_json::Value::Object(map)) => {
+                for (name, value) in map.iter_mut() {
+                    if is_secret_header(name) {
+                        *value = REDACTED.into();
+                    }
+                }
+            }
+            Some(serde_json::Value::Array(pairs)) => {
+                for pair in pairs.iter_mut() {
+                    if pair[0].as_str().is_some_and(is_secret_header) {
+                        pair[1] = REDACTED.into();
+                    }
+                }
+            }
+            _ => {}
+        }
+        match config.auth.as_mut() {
+            Some(AuthSettings::Bearer { token }) => *token = REDACTED.to_string(),
+            Some(AuthSettings::Oauth { client_secret, refresh_token, .. }) => {
+                for secret in [client_secret, refresh_token].into_iter().flatten() {
+                    *secret = REDACTED.to_string();
+                }
+            }
+            None => {}
+        }
+        // Environment variables are how stdio servers usually get their keys, so none is returned
+        for value in config.env.iter_mut().flat_map(|env| env.values_mut()) {
+            *value = REDACTED.to_string();
+        }
+        if let Some(proxy) = config.proxy.as_mut() {
+            if let Ok(mut url) = reqwest::Url::parse(proxy) {
+                if url.password().is_some() && url.set_password(Some(REDACTED)).is_ok() {
+                    *proxy = url.to_string();
+                }
+            }
+        }
+        config
+    }
+}
+
+/// Open the connection described by a config and store it under its connection id
+/// Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+/// A live connection already opened with the same config is kept, unless the config sets force
+fn connect_with_config(config: ConnectConfig) -> *mut c_char {
+    let connection_id = config.connection_id.clone().unwrap_or_else(|| DEFAULT_CONNECTION_ID.to_string());
+    let force = config.force.unwrap_or(false);
+    let config = ConnectConfig {
+        connection_id: Some(connection_id.clone()),
+        force: None,
+        ..config
+    };
+
+    let params = match config.connect_params(true) {
+        Ok(params) => params,
+        Err(error) => return to_c_string(extract_error_message(&error)).into_raw(),
+    };
+
+    // Reconnecting would drop the server session and its resource subscriptions for nothing
+    if !force && is_connected_with(&connection_id, &config) {
+        log_event(LOG_INFO, format_args!("[{}] already connected to {}, keeping the connection", connection_id, params.target()));
+        return ptr::null_mut();
+    }
+
+    let new_client = match new_connection_client(&connection_id) {
+        Ok(c) => c,
+        Err(error) => return to_c_string(error).into_raw(),
+    };
+
+    // Kept with the id it is stored under, so feeding it back to mcp_connect_json reopens the same connection
+    let timeout_ms = config.timeout_ms;
+    *new_client.connect_config.lock_or_recover() = Some(config);
+
+    connect_and_store(new_client, &connection_id, params, timeout_ms)
+}
+
+/// Whether a connection is open with the given config, its transport is alive, and the global
+/// TLS and proxy settings have not changed since it was opened
+fn is_connected_with(connection_id: &str, config: &ConnectConfig) -> bool {
+    let Some(client) = get_client(connection_id) else {
+        return false;
+    };
+    if client.settings_generation != HTTP_SETTINGS_GENERATION.load(Ordering::Relaxed) {
+        return false;
+    }
+    let same_config = client.connect_config.lock_or_recover().as_ref()
+        .is_some_and(|current| serde_json::to_value(current).ok() == serde_json::to_value(config).ok());
+    same_config && client.runtime.block_on(current_peer(&client)).is_some_and(|peer| !peer.is_transport_closed())
+}
+
+/// How a connection was opened, kept so the status can be reported and the connection re-established
+#[derive(Clone)]
+enum ConnectParams {
+    Http {
+        url: String,
+        headers: Option<Vec<(String, String)>>,
+        legacy_sse: bool,
+        oauth: Option<Arc<OAuthSession>>,
+        overrides: Option<Arc<HttpOverrides>>,
+    },
+    Stdio {
+        command: String,
+        args: Vec<String>,
+        env: Vec<(String, String)>,
+        cwd: Option<String>,
+        inherit_env: bool,
+    },
+    WebSocket {
+        url: String,
+        headers: Option<Vec<(String, String)>>,
+    },
+}
+
+/// Check whether a server URL uses the ws:// or wss:// scheme
+fn is_websocket_url(url: &str) -> bool {
+    let scheme = url.split_once("://").map(|(scheme, _)| scheme.to_ascii_lowercase());
+    matches!(scheme.as_deref(), Some("ws" | "wss"))
+}
+
+impl ConnectParams {
+    /// Transport name reported in the connection status
+    fn transport(&self) -> &'static str {
+        match self {
+            ConnectParams::Http { legacy_sse: true, .. } => "sse",
+            ConnectParams::Http { legacy_sse: false, .. } => "streamable-http",
+            ConnectParams::Stdio { .. } => "stdio",
+            ConnectParams::WebSocket { .. } => "websocket",
+        }
+    }
+
+    /// Server URL, or the command for stdio connections
+    fn target(&self) -> &str {
+        match self {
+            ConnectParams::Http { url, .. } | ConnectParams::WebSocket { url, .. } => url,
+            ConnectParams::Stdio { command, .. } => command,
+        }
+    }
+}
+
+/// Open the transport described by params and initialize an MCP service over it
+/// Returns the error JSON string if the transport cannot be opened or initialization fails
+async fn open_service(client: &McpClient, params: &ConnectParams) -> Result<RunningClient, String> {
+    match params {
+        ConnectParams::Http { url, headers, legacy_sse: true, overrides, .. } => {
+            // Get HTTP client with optional custom headers
+            let http_client = LimitedHttpClient {
+                http: http_client_for(client, headers.clone().unwrap_or_default(), overrides.as_deref())?,
+            };
+
+            // Build SSE transport with custom HTTP client
+            let sse_config = rmcp::transport::sse_client::SseClientConfig {
+                sse_endpoint: url.clone().into(),
+                ..Default::default()
+            };
+
+            let transport = SseClientTransport::start_with_client(http_client, sse_config)
+                .await
+                .map_err(|e| error_json(&format!("Failed to connect to MCP server: {}", e)))?;
+
+            // Create service from transport
+            client_handler(client)
+                .serve(transport)
+                .await
+                .map_err(|e| error_json(&format!("Failed to initialize service: {}", e)))
+        }
+        ConnectParams::Http { url, headers, legacy_sse: false, oauth, overrides } => {
+            // For Streamable HTTP, a bearer token has a dedicated field in the config, which adds the "Bearer " prefix
+            let bearer_token = |(name, value): &(String, String)| {
+                if !name.eq_ignore_ascii_case("authorization") {
+                    return None;
+                }
+                let (scheme, token) = value.split_once(' ')?;
+                scheme.eq_ignore_ascii_case("bearer").then(|| token.trim().to_string())
+            };
+            let auth_header_value = headers.iter().flatten().find_map(bearer_token);
+
+            // Build streamable HTTP transport config
+            let config = rmcp::transport::streamable_http_client::StreamableHttpClientTransportConfig {
+                uri: url.clone().into(),
+                auth_header: auth_header_value,
+                ..Default::default()
+            };
+
+            // Other custom headers, including other Authorization schemes, are sent by the HTTP client,
+            // which is reused across reconnects; with OAuth no static Authorization header is sent at all
+            let non_auth_headers = headers.iter()
+                .flatten()
+                .filter(|header| bearer_token(header).is_none())
+                .filter(|(name, _)| oauth.is_none() || !name.eq_ignore_ascii_case("authorization"))
+                .cloned()
+                .collect();
+            let http_client = LimitedHttpClient { http: http_client_for(client, non_auth_headers, overrides.as_deref())? };
+
+            // With OAuth the access token replaces any static Authorization header and is refreshed as needed
+            let service = if let Some(oauth) = oauth {
+                oauth
+                    .access_token()
+                    .await
+                    .map_err(|e| error_json(&format!("Failed to get OAuth access token: {}", e)))?;
+
+                let oauth_client = OAuthHttpClient { http: http_client, session: oauth.clone() };
+                let config = rmcp::transport::streamable_http_client::StreamableHttpClientTransportConfig {
+                    auth_header: None,
+                    ..config
+                };
+                client_handler(client).serve(StreamableHttpClientTransport::with_client(oauth_client, config)).await
+            } else {
+                client_handler(client).serve(StreamableHttpClientTransport::with_client(http_client, config)).await
+            };
+
+            service.map_err(|e| error_json(&format!("Failed to connect to MCP server: {}", e)))
+        }
+        ConnectParams::Stdio { command, args, env, cwd, inherit_env } => {
+            // The child is owned by the transport, so it lives as long as the service and is
+            // killed when the client is dropped (disconnect or reconnect)
+            let mut cmd = tokio::process::Command::new(command);
+            cmd.args(args).kill_on_drop(true);
+            if !inherit_env {
+                cmd.env_clear();
+            }
+            cmd.envs(env.iter().map(|(name, value)| (name, value)));
+            if let Some(cwd) = cwd {
+                cmd.current_dir(cwd);
+            }
+
+            // Lenient decoding reads the output through a decoder, so the child is owned by its stdout
+            if LOSSY_UTF8.load(Ordering::Relaxed) {
+                cmd.stdin(std::process::Stdio::piped()).stdout(std::process::Stdio::piped());
+                let mut child = cmd.spawn()
+                    .map_err(|e| error_json(&format!("Failed to spawn MCP server process: {}", e)))?;
+                let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+                    return Err(error_json("Failed to spawn MCP server process: stdio not piped"));
+                };
+                let stdout = LossyUtf8Stdout { stdout, _child: child, decoder: LossyUtf8Decoder::default(), decoded: Vec::new(), position: 0 };
+                return client_handler(client)
+                    .serve((stdout, stdin))
+                    .await
+                    .map_err(|e| error_json(&format!("Failed to connect to MCP server: {}", e)));
+            }
+
+            let transport = TokioChildProcess::new(cmd)
+                .map_err(|e| error_json(&format!("Failed to spawn MCP server process: {}", e)))?;
+
+            // Create service from transport
+            client_handler(client)
+                .serve(transport)
+                .await
+                .map_err(|e| error_json(&format!("Failed to connect to MCP server: {}", e)))
+        }
+        ConnectParams::WebSocket { url, headers } => {
+            let transport = websocket_transport(url, headers.as_ref()).await?;
+
+            client_handler(client)
+                .serve(transport)
+                .await
+                .map_err(|e| error_json(&format!("Failed to initialize service: {}", e)))
+        }
+    }
+}
+
+/// Open a WebSocket connection and adapt it to JSON-RPC messages, one message per text frame
+/// Returns the error JSON string if a header is invalid or the upgrade fails
+async fn websocket_transport(
+    url: &str,
+    headers: Option<&Vec<(String, String)>>,
+) -> Result<(impl futures::Sink<ClientJsonRpcMessage, Error = WsError> + Unpin, impl futures::Stream<Item = ServerJsonRpcMessage> + Unpin), String> {
+    use futures::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
+
+    let mut request = url
+        .into_client_request()
+        .map_err(|e| invalid_params_json(&format!("Invalid WebSocket URL: {}", e)))?;
+    if let Some(user_agent) = USER_AGENT.lock_or_recover().clone() {
+        request.headers_mut().insert(tokio_tungstenite::tungstenite::http::header::USER_AGENT, user_agent);
+    }
+    for (name, value) in &without_user_agent(headers.map(Vec::as_slice).unwrap_or_default()) {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| invalid_params_json(&format!("Invalid header name '{}'", name)))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|_| invalid_params_json(&format!("Invalid value for header '{}'", name)))?;
+        request.headers_mut().append(name, value);
+    }
+
+    // Requests are small frames that should go out at once rather than wait for more data
+    // A message over the response size limit fails the socket before it is read in full
+    let limit = max_response_bytes().map(|limit| limit as usize);
+    let config = tokio_tungstenite::tungstenite::protocol::WebSocketConfig::default()
+        .max_message_size(limit)
+        .max_frame_size(limit);
+    let (socket, _) = tokio_tungstenite::connect_async_with_config(request, Some(config), true)
+        .await
+        .map_err(|e| error_json(&format!("Failed to connect to MCP server: {}", e)))?;
+    let (sink, stream) = socket.split();
+
+    let sink = sink.with(|message: ClientJsonRpcMessage| {
+        futures::future::ready(
+            serde_json::to_string(&message)
+                .map(|text| WsMessage::Text(text.into()))
+                .map_err(|e| WsError::Io(std::io::Error::other(e))),
+        )
+    });
+
+    // The stream ends on the first read error or close frame, which closes the service
+    let stream = stream
+        .take_while(|message| futures::future::ready(matches!(message, Ok(message) if !message.is_close())))
+        .filter_map(|message| futures::future::ready(match message {
+            Ok(WsMessage::Text(text)) => serde_json::from_str(&text).ok(),
+            Ok(WsMessage::Binary(bytes)) => serde_json::from_slice(&lossy_utf8_body(bytes.into())).ok(),
+            _ => None,
+        }));
+
+    Ok((sink, stream))
+}
+
+// OAuth2 client settings set with mcp_set_oauth, picked up by each new streamable HTTP connection
+static OAUTH_CONFIG: Mutex<Option<OAuthConfig>> = Mutex::new(None);
+
+/// OAuth2 client settings for fetching access tokens
+#[derive(Clone)]
+struct OAuthConfig {
+    token_url: String,
+    client_id: String,
+    client_secret: Option<String>,
+    refresh_token: Option<String>,
+}
+
+/// Access token issued by the authorization server
+struct OAuthToken {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_at: Option<std::time::Instant>,
+}
+
+/// Access token cache of one connection, shared by every request it sends and kept across reconnects
+struct OAuthSession {
+    config: OAuthConfig,
+    // Plain client for the token endpoint so connection headers are never sent to it
+    http: reqwest::Client,
+    token: TokioMutex<Option<OAuthToken>>,
+}
+
+impl OAuthSession {
+    fn new(config: OAuthConfig, overrides: Option<&HttpOverrides>) -> Result<Self, String> {
+        let http = http_client_builder(overrides)
+            .build()
+            .map_err(|e| error_json_code(rmcp::model::ErrorCode::INTERNAL_ERROR.0, &format!("Failed to create HTTP client: {}", e)))?;
+        Ok(OAuthSession {
+            config,
+            http,
+            token: TokioMutex::new(None),
+        })
+    }
+
+    /// Get the cached access token, fetching a new one if there is none or it is about to expire
+    async fn access_token(&self) -> Result<String, String> {
+        let mut token = self.token.lock().await;
+        let valid = token.as_ref().filter(|t| {
+            t.expires_at.is_none_or(|at| at > std::time::Instant::now() + Duration::from_secs(30))
+        });
+        if let Some(t) = valid {
+            return Ok(t.access_token.clone());
+        }
+
+        let refresh_token = token.as_ref().and_then(|t| t.refresh_token.clone());
+        let fetched = self.fetch(refresh_token).await?;
+        let access_token = fetched.access_token.clone();
+        *token = Some(fetched);
+        Ok(access_token)
+    }
+
+    /// Replace an access token the server rejected, unless another request already replaced it
+    async fn refresh(&self, rejected: &str) -> Result<String, String> {
+        let mut token = self.token.lock().await;
+        if let Some(t) = token.as_ref().filter(|t| t.access_token != rejected) {
+            return Ok(t.access_token.clone());
+        }
+
+        let refresh_token = token.as_ref().and_then(|t| t.refresh_token.clone());
+        let fetched = self.fetch(refresh_token).await?;
+        let access_token = fetched.access_token.clone();
+        *token = Some(fetched);
+        Ok(access_token)
+    }
+
+    /// Request a token with the refresh token grant if a refresh token is known, client credentials otherwise
+    async fn fetch(&self, refresh_token: Option<String>) -> Result<OAuthToken, String> {
+        let refresh_token = refresh_token.or_else(|| self.config.refresh_token.clone());
+
+        let mut form = vec![("client_id", self.config.client_id.clone())];
+        if let Some(secret) = &self.config.client_secret {
+            form.push(("client_secret", secret.clone()));
+        }
+        match &refresh_token {
+            Some(refresh_token) => {
+                form.push(("grant_type", "refresh_token".to_string()));
+                form.push(("refresh_token", refresh_token.clone()));
+            }
+            None => form.push(("grant_type", "client_credentials".to_string())),
+        }
+
+        let response = self.http.post(&self.config.token_url).form(&form).send().await
+            .map_err(|e| format!("OAuth token request failed: {}", e))?;
+        let status = response.status();
+        let body: serde_json::Value = response.json().await
+            .map_err(|e| format!("Invalid OAuth token response: {}", e))?;
+        if !status.is_success() {
+            return Err(format!("OAuth token request failed with status {}: {}", status, body));
+        }
+
+        let access_token = body.get("access_token").and_then(|v| v.as_str())
+            .ok_or_else(|| "OAuth token response has no access_token".to_string())?;
+
+        Ok(OAuthToken {
+            access_token: access_token.to_string(),
+            // Servers may rotate the refresh token; keep using the previous one if they do not
+            refresh_token: body.get("refresh_token").and_then(|v| v.as_str()).map(String::from).or(refresh_token),
+            expires_at: body.get("expires_in").and_then(|v| v.as_u64())
+                .map(|secs| std::time::Instant::now() + Duration::from_secs(secs)),
+        })
+    }
+}
+
+/// Streamable HTTP client that sends the connection's OAuth access token and refreshes it once on 401
+#[derive(Clone)]
+struct OAuthHttpClient {
+    http: LimitedHttpClient,
+    session: Arc<OAuthSession>,
+}
+
+type HttpError = StreamableHttpError<reqwest::Error>;
+
+/// Report an OAuth failure through the transport error type
+fn oauth_error(message: String) -> HttpError {
+    StreamableHttpError::UnexpectedServerResponse(message.into())
+}
+
+/// Check whether the server rejected the access token
+fn is_unauthorized(error: &HttpError) -> bool {
+    match error {
+        StreamableHttpError::AuthRequired(_) => true,
+        StreamableHttpError::Client(e) => e.status() == Some(reqwest::StatusCode::UNAUTHORIZED),
+        _ => false,
+    }
+}
+
+impl StreamableHttpClient for OAuthHttpClient {
+    type Error = reqwest::Error;
+
+    async fn post_message(
+        &self,
+        uri: Arc<str>,
+        message: ClientJsonRpcMessage,
+        session_id: Option<Arc<str>>,
+        _auth_header: Option<String>,
+    ) -> Result<StreamableHttpPostResponse, HttpError> {
+        let token = self.session.access_token().await.map_err(oauth_error)?;
+        match StreamableHttpClient::post_message(&self.http, uri.clone(), message.clone(), session_id.clone(), Some(token.clone())).await {
+            Err(e) if is_unauthorized(&e) => {
+                let token = self.session.refresh(&token).await.map_err(oauth_error)?;
+                StreamableHttpClient::post_message(&self.http, uri, message, session_id, Some(token)).await
+            }
+            result => result,
+        }
+    }
+
+    async fn delete_session(
+        &self,
+        uri: Arc<str>,
+        session_id: Arc<str>,
+        _auth_header: Option<String>,
+    ) -> Result<(), HttpError> {
+        let token = self.session.access_token().await.map_err(oauth_error)?;
+        match StreamableHttpClient::delete_session(&self.http, uri.clone(), session_id.clone(), Some(token.clone())).await {
+            Err(e) if is_unauthorized(&e) => {
+                let token = self.session.refresh(&token).await.map_err(oauth_error)?;
+                StreamableHttpClient::delete_session(&self.http, uri, session_id, Some(token)).await
+            }
+            result => result,
+        }
+    }
+
+    async fn get_stream(
+        &self,
+        uri: Arc<str>,
+        session_id: Arc<str>,
+        last_event_id: Option<String>,
+        _auth_header: Option<String>,
+    ) -> Result<BoxStream<'static, Result<sse_stream::Sse, SseError>>, HttpError> {
+        let token = self.session.access_token().await.map_err(oauth_error)?;
+        match StreamableHttpClient::get_stream(&self.http, uri.clone(), session_id.clone(), last_event_id.clone(), Some(token.clone())).await {
+            Err(e) if is_unauthorized(&e) => {
+                let token = self.session.refresh(&token).await.map_err(oauth_error)?;
+                StreamableHttpClient::get_stream(&self.http, uri, session_id, last_event_id, Some(token)).await
+            }
+            result => result,
+        }
+    }
+}
+
+/// HTTP client of a connection that counts response bodies as they arrive and stops reading one
+/// that grows past mcp_set_max_response_bytes, instead of buffering it in full like reqwest::Client
+#[derive(Clone)]
+struct LimitedHttpClient {
+    http: reqwest::Client,
+}
+
+/// Error sending a request through LimitedHttpClient
+#[derive(Debug)]
+enum SendError {
+    Http(reqwest::Error),
+    TooManyRedirects(u32),
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::Http(e) => e.fmt(f),
+            SendError::TooManyRedirects(max) => write!(f, "Stopped after {} redirects. Raise the limit with mcp_set_redirect_policy()", max),
+        }
+    }
+}
+
+impl From<reqwest::Error> for SendError {
+    fn from(e: reqwest::Error) -> Self {
+        SendError::Http(e)
+    }
+}
+
+impl From<SendError> for HttpError {
+    fn from(e: SendError) -> Self {
+        match e {
+            SendError::Http(e) => e.into(),
+            e => StreamableHttpError::UnexpectedServerResponse(e.to_string().into()),
+        }
+    }
+}
+
+impl From<SendError> for SseTransportError<reqwest::Error> {
+    fn from(e: SendError) -> Self {
+        match e {
+            SendError::Http(e) => e.into(),
+            e => SseTransportError::Io(std::io::Error::other(e.to_string())),
+        }
+    }
+}
+
+impl LimitedHttpClient {
+    /// Send a request, following its redirects here when they keep credentials across hosts
+    /// reqwest drops the Authorization and Cookie headers on a redirect to another host and has no setting to keep them
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, SendError> {
+        use reqwest::StatusCode;
+
+        let max_redirects = MAX_REDIRECTS.load(Ordering::Relaxed);
+        if max_redirects == 0 || !PRESERVE_AUTH_CROSS_HOST.load(Ordering::Relaxed) {
+            return Ok(request.send().await?);
+        }
+
+        let mut request = request.build()?;
+        let mut redirects = 0;
+        loop {
+            // Request bodies are JSON bytes, which can be sent again
+            let next = request.try_clone();
+            let response = self.http.execute(request).await?;
+            let status = response.status();
+            let location = response.headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| response.url().join(location).ok())
+                .filter(|location| matches!(location.scheme(), "http" | "https"));
+            let redirected = matches!(
+                status,
+                StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND | StatusCode::SEE_OTHER
+                    | StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT
+            );
+            let (true, Some(location), Some(mut next)) = (redirected, location, next) else {
+                return Ok(response);
+            };
+            if redirects == max_redirects {
+                return Err(SendError::TooManyRedirects(max_redirects));
+            }
+
+            // Like reqwest, 301, 302 and 303 turn the request into a GET without a body
+            if matches!(status, StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND | StatusCode::SEE_OTHER)
+                && next.method() != reqwest::Method::HEAD
+            {
+                *next.method_mut() = reqwest::Method::GET;
+                *next.body_mut() = None;
+                next.headers_mut().remove(reqwest::header::CONTENT_TYPE);
+                next.headers_mut().remove(reqwest::header::CONTENT_LENGTH);
+            }
+            *next.url_mut() = location;
+            redirects += 1;
+            request = next;
+        }
+    }
+}
+
+/// Error reading a response body through LimitedHttpClient
+#[derive(Debug)]
+enum ResponseBodyError {
+    Http(reqwest::Error),
+    TooLarge(u64),
+}
+
+impl std::fmt::Display for ResponseBodyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResponseBodyError::Http(e) => e.fmt(f),
+            ResponseBodyError::TooLarge(limit) => response_too_large(*limit).message.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ResponseBodyError {}
+
+/// Check that a response is an event stream, returning its content type otherwise
+fn expect_event_stream(response: &reqwest::Response) -> Result<(), Option<String>> {
+    match response.headers().get(reqwest::header::CONTENT_TYPE) {
+        Some(ct) if ct.as_bytes().starts_with(EVENT_STREAM_MIME_TYPE.as_bytes()) => Ok(()),
+        Some(ct) => Err(Some(String::from_utf8_lossy(ct.as_bytes()).to_string())),
+        None => Err(None),
+    }
+}
+
+/// Parse the events of an event stream response, failing once one event grows past the size limit
+/// request_id: Request the stream answers, whose caller then gets a size error in place of the oversized response
+fn limited_event_stream(response: reqwest::Response, request_id: Option<RequestId>) -> BoxStream<'static, Result<sse_stream::Sse, SseError>> {
+    use futures::StreamExt;
+
+    let mut decoder = LOSSY_UTF8.load(Ordering::Relaxed).then(LossyUtf8Decoder::default);
+    let response = response.bytes_stream().map(move |chunk| chunk.map(|chunk| match &mut decoder {
+        Some(decoder) => decoder.decode(&chunk).into(),
+        None => chunk,
+    }));
+
+    let Some(limit) = max_response_bytes() else {
+        return sse_stream::SseStream::from_bytes_stream(response).boxed();
+    };
+
+    // Bytes of the event being received, reset at each blank line that ends an event
+    let mut event_bytes = 0u64;
+    let mut line_start = true;
+    let bytes = response.scan(false, move |failed, chunk| {
+        if *failed {
+            return futures::future::ready(None);
+        }
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => return futures::future::ready(Some(Err(ResponseBodyError::Http(e)))),
+        };
+        for &byte in chunk.iter() {
+            match byte {
+                b'\n' if line_start => event_bytes = 0,
+                b'\n' => line_start = true,
+                b'\r' => {}
+                _ => {
+                    line_start = false;
+                    event_bytes += 1;
+                    if event_bytes > limit {
+                        *failed = true;
+                        return futures::future::ready(Some(Err(ResponseBodyError::TooLarge(limit))));
+                    }
+                }
+            }
+        }
+        futures::future::ready(Some(Ok(chunk)))
+    });
+
+    sse_stream::SseStream::from_bytes_stream(bytes)
+        .map(move |event| match event {
+            Err(SseError::Body(e)) if matches!(e.downcast_ref(), Some(ResponseBodyError::TooLarge(_))) => match &request_id {
+                Some(id) => Ok(sse_stream::Sse {
+                    data: serde_json::to_string(&ServerJsonRpcMessage::error(response_too_large(limit), id.clone())).ok(),
+                    ..Default::default()
+                }),
+                None => Err(SseError::Body(e)),
+            },
+            event => event,
+        })
+        .boxed()
+}
+
+/// Read a JSON response, answering the request with a size error if the body is over the size limit
+async fn limited_json_response(mut response: reqwest::Response, request_id: Option<RequestId>) -> Result<ServerJsonRpcMessage, HttpError> {
+    let limit = max_response_bytes().unwrap_or(u64::MAX);
+    let too_large = || match &request_id {
+        Some(id) => Ok(ServerJsonRpcMessage::error(response_too_large(limit), id.clone())),
+        None => Err(StreamableHttpError::UnexpectedServerResponse(response_too_large(limit).message)),
+    };
+
+    if response.content_length().is_some_and(|length| length > limit) {
+        return too_large();
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if (body.len() + chunk.len()) as u64 > limit {
+            return too_large();
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(serde_json::from_slice(&lossy_utf8_body(body))?)
+}
+
+impl StreamableHttpClient for LimitedHttpClient {
+    type Error = reqwest::Error;
+
+    async fn post_message(
+        &self,
+        uri: Arc<str>,
+        message: ClientJsonRpcMessage,
+        session_id: Option<Arc<str>>,
+        auth_header: Option<String>,
+    ) -> Result<StreamableHttpPostResponse, HttpError> {
+        use reqwest::header::{ACCEPT, RETRY_AFTER, WWW_AUTHENTICATE};
+
+        let request_id = match &message {
+            ClientJsonRpcMessage::Request(request) => Some(request.id.clone()),
+            _ => None,
+        };
+        let mut request = self.http
+            .post(uri.as_ref())
+            .header(ACCEPT, [EVENT_STREAM_MIME_TYPE, JSON_MIME_TYPE].join(", "));
+        if let Some(token) = auth_header {
+            request = request.bearer_auth(token);
+        }
+        if let Some(session_id) = session_id {
+            request = request.header(HEADER_SESSION_ID, session_id.as_ref());
+        }
+        // Headers given for this request take the place of the connection's headers of the same name
+        if let ClientJsonRpcMessage::Request(json_rpc) = &message {
+            if let Some(RequestHeaders(headers)) = json_rpc.request.extensions().get::<RequestHeaders>() {
+                request = request.headers(headers.clone());
+            }
+        }
+        let response = self.send(request.json(&message)).await?;
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            if let Some(header) = response.headers().get(WWW_AUTHENTICATE) {
+                let header = header
+                    .to_str()
+                    .map_err(|_| StreamableHttpError::UnexpectedServerResponse("invalid www-authenticate header value".into()))?;
+                return Err(StreamableHttpError::AuthRequired(AuthRequiredError { www_authenticate_header: header.to_string() }));
+            }
+        }
+        // Answer a rate limited request with an error carrying the delay, so it can be retried or reported
+        if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            if let Some(id) = request_id {
+                let retry_after_ms = response.headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(parse_retry_after);
+                return Ok(StreamableHttpPostResponse::Json(ServerJsonRpcMessage::error(rate_limited(retry_after_ms), id), None));
+            }
+        }
+        let response = response.error_for_status()?;
+        if matches!(response.status(), reqwest::StatusCode::ACCEPTED | reqwest::StatusCode::NO_CONTENT) {
+            return Ok(StreamableHttpPostResponse::Accepted);
+        }
+
+        let session_id = response.headers()
+            .get(HEADER_SESSION_ID)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string());
+        match expect_event_stream(&response) {
+            Ok(()) => Ok(StreamableHttpPostResponse::Sse(limited_event_stream(response, request_id), session_id)),
+            Err(Some(ct)) if ct.starts_with(JSON_MIME_TYPE) => {
+                Ok(StreamableHttpPostResponse::Json(limited_json_response(response, request_id).await?, session_id))
+            }
+            Err(ct) => Err(StreamableHttpError::UnexpectedContentType(ct)),
+        }
+    }
+
+    async fn delete_session(
+        &self,
+        uri: Arc<str>,
+        session_id: Arc<str>,
+        auth_header: Option<String>,
+    ) -> Result<(), HttpError> {
+        self.http.delete_session(uri, session_id, auth_header).await
+    }
+
+    async fn get_stream(
+        &self,
+        uri: Arc<str>,
+        session_id: Arc<str>,
+        last_event_id: Option<String>,
+        auth_header: Option<String>,
+    ) -> Result<BoxStream<'static, Result<sse_stream::Sse, SseError>>, HttpError> {
+        let mut request = self.http
+            .get(uri.as_ref())
+            .header(reqwest::header::ACCEPT, EVENT_STREAM_MIME_TYPE)
+            .header(HEADER_SESSION_ID, session_id.as_ref());
+        if let Some(last_event_id) = last_event_id {
+            request = request.header(HEADER_LAST_EVENT_ID, last_event_id);
+        }
+        if let Some(token) = auth_header {
+            request = request.bearer_auth(token);
+        }
+        let response = self.send(request).await?;
+        if response.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED {
+            return Err(StreamableHttpError::ServerDoesNotSupportSse);
+        }
+        let response = response.error_for_status()?;
+        expect_event_stream(&response).map_err(StreamableHttpError::UnexpectedContentType)?;
+        Ok(limited_event_stream(response, None))
+    }
+}
+
+impl SseClient for LimitedHttpClient {
+    type Error = reqwest::Error;
+
+    async fn post_message(
+        &self,
+        uri: http::Uri,
+        message: ClientJsonRpcMessage,
+        auth_header: Option<String>,
+    ) -> Result<(), SseTransportError<reqwest::Error>> {
+        // Responses come on the event stream, the POST itself only returns a status
+        let mut request = self.http.post(uri.to_string()).json(&message);
+        if let Some(token) = auth_header {
+            request = request.bearer_auth(token);
+        }
+        self.send(request).await?.error_for_status()?;
+        Ok(())
+    }
+
+    async fn get_stream(
+        &self,
+        uri: http::Uri,
+        last_event_id: Option<String>,
+        auth_header: Option<String>,
+    ) -> Result<BoxStream<'static, Result<sse_stream::Sse, SseError>>, SseTransportError<reqwest::Error>> {
+        let mut request = self.http
+            .get(uri.to_string())
+            .header(reqwest::header::ACCEPT, EVENT_STREAM_MIME_TYPE);
+        if let Some(token) = auth_header {
+            request = request.bearer_auth(token);
+        }
+        if let Some(last_event_id) = last_event_id {
+            request = request.header(HEADER_LAST_EVENT_ID, last_event_id);
+        }
+        let response = self.send(request).await?.error_for_status()?;
+        expect_event_stream(&response).map_err(SseTransportError::UnexpectedContentType)?;
+        Ok(limited_event_stream(response, None))
+    }
+}
+
+/// Open the service for a client, logging the attempt and how it ended
+/// timeout_ms: Time allowed to open the transport and initialize, 0 to wait forever
+/// On timeout the half-open transport is dropped, which also kills a spawned stdio server
+async fn open_service_logged(client: &McpClient, params: &ConnectParams, timeout_ms: u32) -> Result<RunningClient, String> {
+    let max_attempts = INITIALIZE_MAX_ATTEMPTS.load(Ordering::Relaxed).max(1);
+    let mut backoff_ms = INITIALIZE_BACKOFF_MS.load(Ordering::Relaxed) as u64;
+    let mut attempt = 1;
+    loop {
+        log_event(LOG_INFO, format_args!(
+            "[{}] connecting to {} over {}",
+            client.connection_id, params.target(), params.transport()
+        ));
+        let started = std::time::Instant::now();
+        let result = match timeout_ms {
+            0 => open_service(client, params).await,
+            ms => match tokio::time::timeout(Duration::from_millis(ms as u64), open_service(client, params)).await {
+                Ok(result) => result,
+                // A server still busy booting may answer the next initialize, unlike one refusing the connection
+                Err(_) if attempt < max_attempts => {
+                    log_event(LOG_WARN, format_args!(
+                        "[{}] connect timed out after {}ms, retrying in {}ms (attempt {} of {})",
+                        client.connection_id, ms, backoff_ms, attempt + 1, max_attempts
+                    ));
+                    tokio::time::sleep(Duration::from_millis(backoff_ms)).await;
+                    backoff_ms = backoff_ms.saturating_mul(2);
+                    attempt += 1;
+                    continue;
+                }
+                Err(_) => Err(error_json_code(ERROR_TIMEOUT, &format!("Connect timed out after {} ms", ms))),
+            },
+        };
+        let elapsed_ms = started.elapsed().as_millis();
+        record_timing("initialize", elapsed_ms as u64);
+        match &result {
+            Ok(_) => log_event(LOG_INFO, format_args!("[{}] connected in {}ms", client.connection_id, elapsed_ms)),
+            Err(error) => log_event(LOG_ERROR, format_args!(
+                "[{}] connecting failed after {}ms: {}",
+                client.connection_id, elapsed_ms, extract_error_message(error)
+            )),
+        }
+        return result;
+    }
+}
+
+/// Re-establish a dropped connection with the parameters it was opened with, replacing its service
+/// Returns the peer of the new service, or the error JSON string if reconnecting failed
+async fn reconnect(client: &McpClient) -> Result<Peer<RoleClient>, String> {
+    let params = client.connect_params.lock_or_recover().clone();
+    let params = params.ok_or_else(|| error_json_code(ERROR_NOT_CONNECTED, "Not connected to server"))?;
+
+    // Without a connect timeout, the request that is being retried bounds the reconnect
+    let service = open_service_logged(client, &params, CONNECT_TIMEOUT_MS.load(Ordering::Relaxed)).await?;
+    let peer = service.peer().clone();
+    *client.service.lock().await = Some(service);
+    // The server may have restarted with different tools
+    *client.tools_cache.lock_or_recover() = None;
+    TOOL_LIST_CHANGES.fetch_add(1, Ordering::Relaxed);
+    RECONNECTS.fetch_add(1, Ordering::Relaxed);
+    Ok(peer)
+}
+
+/// Send keep-alive pings on a connection while it is idle, until the connection is closed or replaced
+/// Holds the service rather than the client, which aborts the task when it is dropped
+async fn keep_alive(connection_id: String, service: Arc<TokioMutex<Option<RunningClient>>>) {
+    let mut interval_ms = keepalive_interval().subscribe();
+    loop {
+        let interval = Duration::from_millis(*interval_ms.borrow_and_update() as u64);
+        if interval.is_zero() {
+            if interval_ms.changed().await.is_err() {
+                return;
+            }
+            continue;
+        }
+
+        let idle = connection_activity().lock_or_recover()
+            .get(&connection_id)
+            .map_or(interval, |last| last.elapsed());
+        let peer = service.lock().await.as_ref().map(|service| service.peer().clone());
+        let (Some(peer), true) = (peer, idle >= interval) else {
+            // Not idle yet, or dead and waiting to be reconnected
+            tokio::select! {
+                _ = tokio::time::sleep(interval.saturating_sub(idle).max(Duration::from_millis(1))) => {}
+                _ = interval_ms.changed() => {}
+            }
+            continue;
+        };
+
+        let request = ClientRequest::PingRequest(PingRequest::default());
+        let error = match tokio::time::timeout(interval, traced(&connection_id, "keep-alive PingRequest", peer.send_request(request))).await {
+            Ok(Ok(_)) => continue,
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => format!("no answer within {}ms", interval.as_millis()),
+        };
+
+        log_event(LOG_WARN, format_args!("[{}] keep-alive ping failed: {}, marking the connection dead", connection_id, error));
+        let dead = service.lock().await.take();
+        if let Some(dead) = dead {
+            let _ = tokio::time::timeout(Duration::from_secs(1), dead.cancel()).await;
+        }
+        if AUTO_RECONNECT_MAX_RETRIES.load(Ordering::Relaxed) > 0 {
+            reconnect_dead(connection_id.clone(), service.clone());
+        }
+    }
+}
+
+/// How often the transport watcher checks whether the server closed the connection
+const TRANSPORT_WATCH_INTERVAL_MS: u64 = 100;
+
+/// Drop a connection's service as soon as the server closes its transport, until the connection is closed or replaced
+/// Later requests then fail with a clean "not connected" error instead of a transport error
+/// Polls because waiting for the service to end needs to own it, and requests share it
+async fn watch_transport(connection_id: String, service: Arc<TokioMutex<Option<RunningClient>>>) {
+    loop {
+        tokio::time::sleep(Duration::from_millis(TRANSPORT_WATCH_INTERVAL_MS)).await;
+        // Only take the service if it is still the closed one, a reconnect may have replaced it
+        let dead = {
+            let mut guard = service.lock().await;
+            match guard.as_ref() {
+                Some(running) if running.peer().is_transport_closed() => guard.take(),
+                _ => None,
+            }
+        };
+        let Some(dead) = dead else {
+            continue;
+        };
+
+        log_event(LOG_WARN, format_args!("[{}] server closed the connection, marking it dead", connection_id));
+        let _ = tokio::time::timeout(Duration::from_secs(1), dead.cancel()).await;
+        if AUTO_RECONNECT_MAX_RETRIES.load(Ordering::Relaxed) > 0 {
+            reconnect_dead(connection_id.clone(), service.clone());
+        }
+    }
+}
+
+/// Re-establish a connection the keep-alive task or transport watcher found dead, with the auto-reconnect retries and backoff
+/// Runs on its own thread, which holds the client so that it is never dropped on the client's runtime
+fn reconnect_dead(connection_id: String, service: Arc<TokioMutex<Option<RunningClient>>>) {
+    std::thread::spawn(move || {
+        // The connection may have been closed or replaced since
+        let Some(client) = get_client(&connection_id).filter(|client| Arc::ptr_eq(&client.service, &service)) else {
+            return;
+        };
+        let max_retries = AUTO_RECONNECT_MAX_RETRIES.load(Ordering::Relaxed);
+        let base_delay_ms = AUTO_RECONNECT_BASE_DELAY_MS.load(Ordering::Relaxed) as u64;
+
+        client.runtime.block_on(async {
+            for attempt in 0..max_retries {
+                let delay_ms = base_delay_ms.saturating_mul(1 << attempt.min(16));
+                log_event(LOG_WARN, format_args!(
+                    "[{}] reconnecting in {}ms (attempt {}/{})",
+                    connection_id, delay_ms, attempt + 1, max_retries
+                ));
+                tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+                if reconnect(&client).await.is_ok() {
+                    return;
+                }
+            }
+        });
+    });
+}
+
+/// Get the peer of the client's current service without keeping the service locked
+/// Peers are cheap handles, so concurrent requests on one connection do not wait for each other
+async fn current_peer(client: &McpClient) -> Option<Peer<RoleClient>> {
+    Some(client.service.lock().await.as_ref()?.peer().clone())
+}
+
+/// Send a request through the client's current service, reconnecting and retrying on transport errors
+/// Only reconnects when auto-reconnect is enabled, and for a non-idempotent tool call only if retry_tool_calls
+/// was set; transient errors are retried on the same connection as set by mcp_set_retry, except non-idempotent calls
+/// Returns None if the client has no service
+async fn with_auto_reconnect<T, F, Fut>(client: &McpClient, non_idempotent: bool, request: F) -> Option<Result<T, rmcp::ServiceError>>
+where
+    F: Fn(Peer<RoleClient>) -> Fut,
+    Fut: std::future::Future<Output = Result<T, rmcp::ServiceError>>,
+{
+    let peer = current_peer(client).await?;
+    let mut result = with_retry(&client.connection_id, !non_idempotent, peer, &request).await;
+
+    let max_retries = if non_idempotent && !AUTO_RECONNECT_RETRY_TOOL_CALLS.load(Ordering::Relaxed) {
+        0
+    } else {
+        AUTO_RECONNECT_MAX_RETRIES.load(Ordering::Relaxed)
+    };
+    let base_delay_ms = AUTO_RECONNECT_BASE_DELAY_MS.load(Ordering::Relaxed) as u64;
+
+    for attempt in 0..max_retries {
+        if !matches!(result, Err(rmcp::ServiceError::TransportSend(_) | rmcp::ServiceError::TransportClosed)) {
+            break;
+        }
+
+        let delay_ms = base_delay_ms.saturating_mul(1 << attempt.min(16));
+        log_event(LOG_WARN, format_args!(
+            "[{}] transport error, reconnecting in {}ms (attempt {}/{})",
+            client.connection_id, delay_ms, attempt + 1, max_retries
+        ));
+        tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+
+        // If the server is still down, keep the last request error and try again after a longer delay
+        if let Ok(peer) = reconnect(client).await {
+            result = with_retry(&client.connection_id, !non_idempotent, peer, &request).await;
+        }
+    }
+
+    Some(result)
+}
+
+/// Send a request, retrying it on the same peer while it fails with a transient transport error
+/// Errors answered by the server, such as an unknown method or invalid params, are returned at once
+/// Rate limited requests were not run by the server, so they are retried even when retry is false, after the
+/// delay the server asked for; a delay longer than the request timeout fails at once with that delay instead
+async fn with_retry<T, F, Fut>(connection_id: &str, retry: bool, peer: Peer<RoleClient>, request: &F) -> Result<T, rmcp::ServiceError>
+where
+    F: Fn(Peer<RoleClient>) -> Fut,
+    Fut: std::future::Future<Output = Result<T, rmcp::ServiceError>>,
+{
+    let max_attempts = RETRY_MAX_ATTEMPTS.load(Ordering::Relaxed);
+    let backoff_ms = RETRY_BACKOFF_MS.load(Ordering::Relaxed) as u64;
+
+    let mut attempt = 1;
+    loop {
+        let result = request(peer.clone()).await;
+        let error = match &result {
+            Err(error) if attempt < max_attempts => error,
+            _ => return result,
+        };
+
+        let rate_limited = matches!(error, rmcp::ServiceError::McpError(e) if e.code.0 == ERROR_RATE_LIMITED);
+        if !(rate_limited || retry && is_transient_error(error)) {
+            return result;
+        }
+        let delay_ms = retry_after_ms(error).unwrap_or_else(|| backoff_ms.saturating_mul(1 << (attempt - 1).min(16)));
+        let timeout_ms = request_timeout_ms(connection_id) as u64;
+        if rate_limited && timeout_ms != 0 && delay_ms >= timeout_ms {
+            return result;
+        }
+
+        log_event(LOG_WARN, format_args!(
+            "[{}] {}, retrying in {}ms (attempt {}/{})",
+            connection_id,
+            if rate_limited { "rate limited" } else { "transient transport error" },
+            delay_ms, attempt + 1, max_attempts
+        ));
+        tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+        attempt += 1;
+    }
+}
+
+/// Check whether a request failed in a way that may succeed when sent again, such as a reset connection
+/// HTTP 4xx answers are not transient: the server received the request and rejected it
+fn is_transient_error(error: &rmcp::ServiceError) -> bool {
+    match error {
+        rmcp::ServiceError::Timeout { .. } => true,
+        rmcp::ServiceError::TransportSend(e) => match e.error.downcast_ref::<HttpError>() {
+            Some(StreamableHttpError::Client(e)) => !e.status().is_some_and(|status| status.is_client_error()),
+            Some(StreamableHttpError::AuthRequired(_)) => false,
+            _ => true,
+        },
+        _ => false,
+    }
+}
+
+/// Create a new McpClient for a connection, reusing the runtime and HTTP client of the one it replaces
+/// Pooled HTTP connections belong to the runtime that opened them, so both must survive a reconnect
+/// Returns the error JSON string if the runtime cannot be created
+fn new_connection_client(connection_id: &str) -> Result<McpClient, String> {
+    let previous = global_clients().lock_or_recover().get(connection_id).map(|client| {
+        (client.runtime.clone(), client.http_client.lock_or_recover().clone())
+    });
+
+    let (runtime, http_client) = match previous {
+        Some(state) => state,
+        None => match tokio::runtime::Runtime::new() {
+            Ok(runtime) => (Arc::new(runtime), None),
+            Err(e) => return Err(error_json_code(rmcp::model::ErrorCode::INTERNAL_ERROR.0, &format!("Failed to create runtime: {}", e))),
+        },
+    };
+
+    Ok(McpClient {
+        connection_id: connection_id.to_string(),
+        runtime,
+        service: Arc::new(TokioMutex::new(None)),
+        server_url: Mutex::new(None),
+        connect_params: Mutex::new(None),
+        connect_config: Mutex::new(None),
+        http_client: Mutex::new(http_client),
+        tools_cache: Arc::default(),
+        settings_generation: HTTP_SETTINGS_GENERATION.load(Ordering::Relaxed),
+        subscriptions: Mutex::new(BTreeSet::new()),
+        server_log_level: Mutex::new(None),
+        background_tasks: Mutex::new(Vec::new()),
+    })
+}
+
+// TLS settings set with mcp_set_tls, applied to every HTTP client built afterwards
+static TLS_CONFIG: Mutex<Option<TlsConfig>> = Mutex::new(None);
+
+// Bumped whenever HTTP client settings change so cached clients are rebuilt on the next connect
+static HTTP_SETTINGS_GENERATION: AtomicU32 = AtomicU32::new(0);
+
+/// Extra trust roots, client identity for mutual TLS, and whether certificate checks are skipped
+#[derive(Clone)]
+struct TlsConfig {
+    root_certs: Vec<reqwest::Certificate>,
+    identity: Option<reqwest::Identity>,
+    accept_invalid_certs: bool,
+}
+
+impl TlsConfig {
+    /// Load the CA bundle and client certificate/key from PEM files
+    fn load(
+        ca_pem_path: Option<String>,
+        client_cert_path: Option<String>,
+        client_key_path: Option<String>,
+        accept_invalid_certs: bool,
+    ) -> Result<Self, String> {
+        let read = |path: &str| std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e));
+
+        let root_certs = match ca_pem_path {
+            Some(path) => {
+                let certs = reqwest::Certificate::from_pem_bundle(&read(&path)?)
+                    .map_err(|e| format!("Invalid CA certificate in {}: {}", path, e))?;
+                if certs.is_empty() {
+                    return Err(format!("No certificates found in {}", path));
+                }
+                certs
+            }
+            None => Vec::new(),
+        };
+
+        let identity = match (client_cert_path, client_key_path) {
+            (None, None) => None,
+            (Some(cert_path), Some(key_path)) => {
+                let mut pem = read(&key_path)?;
+                pem.push(b'\n');
+                pem.extend(read(&cert_path)?);
+                Some(reqwest::Identity::from_pem(&pem)
+                    .map_err(|e| format!("Invalid client certificate or key: {}", e))?)
+            }
+            _ => return Err("Client certificate and key must be given together".to_string()),
+        };
+
+        Ok(TlsConfig {
+            root_certs,
+            identity,
+            accept_invalid_certs,
+        })
+    }
+}
+
+/// TLS and proxy settings from a connection's config, used by its HTTP clients instead of the global ones
+struct HttpOverrides {
+    tls: Option<TlsConfig>,
+    proxy: Option<reqwest::Proxy>,
+    // TLS server name the URL's host was replaced with, resolved to the addresses of the original host
+    server_name: Option<(String, Vec<std::net::SocketAddr>)>,
+    sni: bool,
+    compression: bool,
+    // The config values they were loaded from, compared to tell whether a cached HTTP client matches
+    key: String,
+}
+
+/// Start building an HTTP client with the connection's TLS and proxy settings, or the global ones it does not override
+fn http_client_builder(overrides: Option<&HttpOverrides>) -> reqwest::ClientBuilder {
+    let mut builder = reqwest::Client::builder();
+    if let Some(user_agent) = USER_AGENT.lock_or_recover().clone() {
+        builder = builder.user_agent(user_agent);
+    }
+    let proxy = match overrides.and_then(|o| o.proxy.clone()) {
+        Some(proxy) => Some(proxy),
+        None => PROXY.lock_or_recover().clone(),
+    };
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(proxy);
+    }
+    if let Some(local_address) = *LOCAL_ADDRESS.lock_or_recover() {
+        builder = builder.local_address(local_address);
+    }
+    let tls = match overrides.and_then(|o| o.tls.clone()) {
+        Some(tls) => Some(tls),
+        None => TLS_CONFIG.lock_or_recover().clone(),
+    };
+    if let Some(tls) = tls {
+        for cert in tls.root_certs {
+            builder = builder.add_root_certificate(cert);
+        }
+        if let Some(identity) = tls.identity {
+            builder = builder.identity(identity);
+        }
+        if tls.accept_invalid_certs {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+    }
+    if let Some(overrides) = overrides {
+        if let Some((server_name, addrs)) = &overrides.server_name {
+            builder = builder.resolve_to_addrs(server_name, addrs);
+        }
+        if !overrides.sni {
+            builder = builder.tls_sni(false);
+        }
+        if !overrides.compression {
+            builder = builder.no_gzip().no_brotli().no_deflate();
+        }
+    }
+    builder
+}
+
+/// Configure TLS for HTTP connections made after this call
+/// ca_pem_path: PEM file with extra CA certificates to trust, can be NULL
+/// client_cert_pem: PEM file with the client certificate for mutual TLS, can be NULL
+/// client_key_pem: PEM file with the client private key (required with client_cert_pem), can be NULL
+/// insecure_skip_verify: 1 to accept any server certificate (testing only), 0 to verify certificates
+/// Returns NULL on success, error string on failure (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_set_tls(
+    ca_pem_path: *const c_char,
+    client_cert_pem: *const c_char,
+    client_key_pem: *const c_char,
+    insecure_skip_verify: i32,
+) -> *mut c_char {
+    ffi_guard(panic_error, || {
+        // Read an optional C string argument, rejecting invalid UTF-8
+        let optional = |value: *const c_char| -> Result<Option<String>, ()> {
+            if value.is_null() {
+                return Ok(None);
+            }
+            unsafe { CStr::from_ptr(value).to_str() }.map(|s| Some(s.to_string())).map_err(|_| ())
+        };
+
+        let (ca_pem_path, client_cert_pem, client_key_pem) = match (
+            optional(ca_pem_path),
+            optional(client_cert_pem),
+            optional(client_key_pem),
+        ) {
+            (Ok(a), Ok(b), Ok(c)) => (a, b, c),
+            _ => return to_c_string("Invalid TLS arguments").into_raw(),
+        };
+
+        // Only the exact value 1 turns off verification, so a stray non-zero flag is an error instead
+        let accept_invalid_certs = match insecure_skip_verify {
+            0 => false,
+            1 => true,
+            _ => return to_c_string("insecure_skip_verify must be 0 or 1").into_raw(),
+        };
+
+        let config = if ca_pem_path.is_none() && client_cert_pem.is_none() && client_key_pem.is_none() && !accept_invalid_certs {
+            None
+        } else {
+            match TlsConfig::load(ca_pem_path, client_cert_pem, client_key_pem, accept_invalid_certs) {
+                Ok(config) => Some(config),
+                Err(error) => return to_c_string(error).into_raw(),
+            }
+        };
+
+        *TLS_CONFIG.lock_or_recover() = config;
+        HTTP_SETTINGS_GENERATION.fetch_add(1, Ordering::Relaxed);
+        ptr::null_mut()
+    })
+}
+
+// Proxy set with mcp_set_proxy that all HTTP traffic is routed through
+static PROXY: Mutex<Option<reqwest::Proxy>> = Mutex::new(None);
+
+/// Route HTTP connections made after this call through a proxy
+/// proxy_url: http://, https:// or socks5:// proxy URL with optional user:pass@ credentials, or NULL to connect directly
+/// Returns NULL on success, error string on failure (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_set_proxy(proxy_url: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error, || {
+        let proxy = if proxy_url.is_null() {
+            None
+        } else {
+            let proxy_url = unsafe { CStr::from_ptr(proxy_url) }.to_string_lossy();
+            match parse_proxy(&proxy_url) {
+                Ok(proxy) => Some(proxy),
+                Err(error) => return to_c_string(error).into_raw(),
+            }
+        };
+
+        *PROXY.lock_or_recover() = proxy;
+        HTTP_SETTINGS_GENERATION.fetch_add(1, Ordering::Relaxed);
+        ptr::null_mut()
+    })
+}
+
+/// Parse an http://, https:// or socks5:// proxy URL
+/// Returns the error message if the URL is invalid or uses another scheme
+fn parse_proxy(proxy_url: &str) -> Result<reqwest::Proxy, String> {
+    let url = reqwest::Url::parse(proxy_url).map_err(|e| format!("Invalid proxy URL: {}", e))?;
+    if !matches!(url.scheme(), "http" | "https" | "socks5" | "socks5h") {
+        return Err(format!("Unsupported proxy scheme '{}', expected http, https or socks5", url.scheme()));
+    }
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err("Invalid proxy URL: missing host".to_string());
+    }
+    reqwest::Proxy::all(url).map_err(|e| format!("Invalid proxy URL: {}", e))
+}
+
+// Local IP address set with mcp_set_local_address that outbound HTTP connections are bound to
+static LOCAL_ADDRESS: Mutex<Option<std::net::IpAddr>> = Mutex::new(None);
+
+/// Bind HTTP connections made after this call to a local IP address, so they leave through its interface
+/// ip: IPv4 or IPv6 address of a local interface, or NULL or an empty string to let the system choose
+/// Returns NULL on success, error string on failure (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_set_local_address(ip: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error, || {
+        let local_address = if ip.is_null() {
+            None
+        } else {
+            match unsafe { CStr::from_ptr(ip) }.to_str() {
+                Ok("") => None,
+                Ok(value) => match value.parse::<std::net::IpAddr>() {
+                    Ok(address) => Some(address),
+                    Err(_) => return to_c_string(format!("Invalid local address '{}': expected an IPv4 or IPv6 address", value)).into_raw(),
+                },
+                Err(_) => return to_c_string("Invalid local address: it must be valid UTF-8").into_raw(),
+            }
+        };
+
+        *LOCAL_ADDRESS.lock_or_recover() = local_address;
+        HTTP_SETTINGS_GENERATION.fetch_add(1, Ordering::Relaxed);
+        ptr::null_mut()
+    })
+}
+
+// User-Agent set with mcp_set_user_agent, sent instead of any User-Agent in connection or request headers
+static USER_AGENT: Mutex<Option<reqwest::header::HeaderValue>> = Mutex::new(None);
+
+/// Set the User-Agent sent on HTTP, SSE and WebSocket connections made after this call
+/// user_agent: User-Agent value, or NULL or an empty string to send none unless the headers give one
+/// Returns NULL on success, error string on failure (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_set_user_agent(user_agent: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error, || {
+        let user_agent = if user_agent.is_null() {
+            None
+        } else {
+            match unsafe { CStr::from_ptr(user_agent) }.to_str() {
+                Ok("") => None,
+                Ok(value) => match reqwest::header::HeaderValue::from_str(value) {
+                    Ok(value) => Some(value),
+                    Err(_) => return to_c_string("Invalid User-Agent: it must not contain control characters").into_raw(),
+                },
+                Err(_) => return to_c_string("Invalid User-Agent: it must be valid UTF-8").into_raw(),
+            }
+        };
+
+        *USER_AGENT.lock_or_recover() = user_agent;
+        HTTP_SETTINGS_GENERATION.fetch_add(1, Ordering::Relaxed);
+        ptr::null_mut()
+    })
+}
+
+/// Drop any User-Agent from connection or request headers when one was set with mcp_set_user_agent, which wins
+fn without_user_agent(headers: &[(String, String)]) -> Vec<(String, String)> {
+    if USER_AGENT.lock_or_recover().is_none() {
+        return headers.to_vec();
+    }
+    let (dropped, kept): (Vec<_>, Vec<_>) = headers.iter().cloned().partition(|(name, _)| name.eq_ignore_ascii_case("user-agent"));
+    if !dropped.is_empty() {
+        log_event(LOG_WARN, "User-Agent header ignored, the one set with mcp_set_user_agent() is sent instead");
+    }
+    kept
+}
+
+// Redirects an HTTP request follows before failing, set with mcp_set_redirect_policy
+static MAX_REDIRECTS: AtomicU32 = AtomicU32::new(DEFAULT_MAX_REDIRECTS);
+const DEFAULT_MAX_REDIRECTS: u32 = 10;
+// Whether redirects to another host keep the Authorization and Cookie headers, which reqwest drops
+static PRESERVE_AUTH_CROSS_HOST: AtomicBool = AtomicBool::new(false);
+
+/// Set how HTTP connections made after this call follow redirects
+/// max_redirects: Redirects followed per request before it fails, 0 to return redirects without following them
+/// preserve_auth_cross_host: 1 to keep sending the Authorization and Cookie headers when a redirect leads to another host, 0 to drop them (default)
+#[no_mangle]
+pub extern "C" fn mcp_set_redirect_policy(max_redirects: u32, preserve_auth_cross_host: i32) {
+    MAX_REDIRECTS.store(max_redirects, Ordering::Relaxed);
+    PRESERVE_AUTH_CROSS_HOST.store(preserve_auth_cross_host != 0, Ordering::Relaxed);
+    HTTP_SETTINGS_GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Redirect policy of the HTTP clients built for connections
+/// Redirects that keep credentials across hosts are followed by LimitedHttpClient instead, so reqwest follows none
+fn redirect_policy() -> reqwest::redirect::Policy {
+    match MAX_REDIRECTS.load(Ordering::Relaxed) {
+        0 => reqwest::redirect::Policy::none(),
+        _ if PRESERVE_AUTH_CROSS_HOST.load(Ordering::Relaxed) => reqwest::redirect::Policy::none(),
+        max_redirects => reqwest::redirect::Policy::limited(max_redirects as usize),
+    }
+}
+
+/// Get an HTTP client sending the given default headers, reusing the cached one if its headers match
+/// Clones share one connection pool; a different header set gets its own client so headers never leak
+fn http_client_for(
+    client: &McpClient,
+    headers: Vec<(String, String)>,
+    overrides: Option<&HttpOverrides>,
+) -> Result<reqwest::Client, String> {
+    let generation = HTTP_SETTINGS_GENERATION.load(Ordering::Relaxed);
+    let overrides_key = overrides.map(|o| o.key.clone()).unwrap_or_default();
+    let mut cached = client.http_client.lock_or_recover();
+    if let Some((cached_generation, cached_overrides, cached_headers, http_client)) = cached.as_ref() {
+        if *cached_generation == generation && *cached_overrides == overrides_key && *cached_headers == headers {
+            return Ok(http_client.clone());
+        }
+    }
+
+    let header_map = default_header_map(&without_user_agent(&headers))?;
+    let http_client = http_client_builder(overrides)
+        .default_headers(header_map)
+        .redirect(redirect_policy())
+        .build()
+        .map_err(|e| error_json_code(rmcp::model::ErrorCode::INTERNAL_ERROR.0, &format!("Failed to create HTTP client: {}", e)))?;
+
+    *cached = Some((generation, overrides_key, headers, http_client.clone()));
+    Ok(http_client)
+}
+
+/// Build the default header map of an HTTP client from a connection's headers
+/// Default headers hold one value per name, so repeated headers are combined into one list value,
+/// which HTTP treats as the same header (cookies are joined with "; " as a Cookie header expects)
+fn default_header_map(headers: &[(String, String)]) -> Result<reqwest::header::HeaderMap, String> {
+    use reqwest::header::{HeaderMap, HeaderValue, HeaderName};
+
+    let mut combined: Vec<(HeaderName, String)> = Vec::new();
+    for (key, value) in headers {
+        let header_name = HeaderName::from_bytes(key.as_bytes())
+            .map_err(|_| invalid_params_json(&format!("Invalid header format: {}: {}", key, value)))?;
+        match combined.iter_mut().find(|(name, _)| *name == header_name) {
+            Some((name, combined_value)) => {
+                combined_value.push_str(if *name == reqwest::header::COOKIE { "; " } else { ", " });
+                combined_value.push_str(value);
+            }
+            None => combined.push((header_name, value.clone())),
+        }
+    }
+
+    let mut header_map = HeaderMap::new();
+    for (header_name, value) in combined {
+        let header_value = HeaderValue::from_str(&value)
+            .map_err(|_| invalid_params_json(&format!("Invalid header format: {}: {}", header_name, value)))?;
+        header_map.insert(header_name, header_value);
+    }
+    Ok(header_map)
+}
+
+// Client name and version set with mcp_set_client_info, None uses the built-in default
+static CLIENT_NAME: Mutex<Option<String>> = Mutex::new(None);
+static CLIENT_VERSION: Mutex<Option<String>> = Mutex::new(None);
+
+/// Set the client name and version sent to servers on connections made after this call
+/// name: Client name, or NULL for the default "sqlite-mcp"
+/// version: Client version, or NULL for the extension version
+/// Returns NULL on success, error string on failure (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_set_client_info(name: *const c_char, version: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error, || {
+        // Read an optional C string argument, rejecting invalid UTF-8 and empty values
+        let optional = |value: *const c_char| -> Result<Option<String>, ()> {
+            if value.is_null() {
+                return Ok(None);
+            }
+            match unsafe { CStr::from_ptr(value).to_str() } {
+                Ok(s) if !s.trim().is_empty() => Ok(Some(s.to_string())),
+                _ => Err(()),
+            }
+        };
+
+        let (name, version) = match (optional(name), optional(version)) {
+            (Ok(name), Ok(version)) => (name, version),
+            (Err(_), _) => return to_c_string("Client name must be a non-empty string").into_raw(),
+            (_, Err(_)) => return to_c_string("Client version must be a non-empty string").into_raw(),
+        };
+
+        *CLIENT_NAME.lock_or_recover() = name;
+        *CLIENT_VERSION.lock_or_recover() = version;
+        ptr::null_mut()
+    })
+}
+
+// Protocol version requested during initialization, set with mcp_set_protocol_version
+static PROTOCOL_VERSION: Mutex<Option<ProtocolVersion>> = Mutex::new(None);
+
+// Experimental capabilities declared during initialization, set with mcp_set_experimental_capabilities
+static EXPERIMENTAL_CAPABILITIES: Mutex<Option<rmcp::model::ExperimentalCapabilities>> = Mutex::new(None);
+
+/// Set the MCP protocol version requested from servers on connections made after this call
+/// version: Protocol revision such as "2024-11-05", or NULL for the latest supported version
+/// Returns NULL on success, error string on failure (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_set_protocol_version(version: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error, || {
+        let version = if version.is_null() {
+            None
+        } else {
+            let supported = [
+                ProtocolVersion::V_2024_11_05,
+                ProtocolVersion::V_2025_03_26,
+                ProtocolVersion::V_2025_06_18,
+            ];
+            let requested = unsafe { CStr::from_ptr(version) }.to_string_lossy();
+            match supported.into_iter().find(|v| v.to_string() == requested) {
+                Some(v) => Some(v),
+                None => {
+                    return to_c_string(format!(
+                        "Unsupported protocol version '{}', expected 2024-11-05, 2025-03-26 or 2025-06-18",
+                        requested
+                    ))
+                    .into_raw()
+                }
+            }
+        };
+
+        *PROTOCOL_VERSION.lock_or_recover() = version;
+        ptr::null_mut()
+    })
+}
+
+/// Declare experimental capabilities to servers on connections made after this call
+/// capabilities_json: JSON object mapping each capability name to its settings object, or NULL to declare none
+/// Returns NULL on success, error string on failure (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_set_experimental_capabilities(capabilities_json: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error, || {
+        let capabilities = if capabilities_json.is_null() {
+            None
+        } else {
+            let capabilities_json = match unsafe { CStr::from_ptr(capabilities_json).to_str() } {
+                Ok(s) => s,
+                Err(_) => return to_c_string("Invalid experimental capabilities JSON").into_raw(),
+            };
+            let capabilities = match serde_json::from_str::<serde_json::Value>(capabilities_json) {
+                Ok(serde_json::Value::Object(capabilities)) => capabilities,
+                Ok(_) => return to_c_string("Experimental capabilities must be a JSON object").into_raw(),
+                Err(e) => return to_c_string(format!("Invalid JSON: {}", e)).into_raw(),
+            };
+            let mut experimental = rmcp::model::ExperimentalCapabilities::new();
+            for (name, settings) in capabilities {
+                match settings {
+                    serde_json::Value::Object(settings) => experimental.insert(name, settings),
+                    _ => return to_c_string(format!("Experimental capability '{}' must be a JSON object", name)).into_raw(),
+                };
+            }
+            (!experimental.is_empty()).then_some(experimental)
+        };
+
+        *EXPERIMENTAL_CAPABILITIES.lock_or_recover() = capabilities;
+        ptr::null_mut()
+    })
+}
+
+/// Client info sent to the server during initialization
+fn client_info() -> ClientInfo {
+    // Only offer sampling when the host can answer it
+    let sampling = SAMPLING_HANDLER.lock_or_recover().is_some().then(serde_json::Map::new);
+
+    ClientInfo {
+        protocol_version: PROTOCOL_VERSION.lock_or_recover().clone().unwrap_or_default(),
+        capabilities: ClientCapabilities {
+            experimental: EXPERIMENTAL_CAPABILITIES.lock_or_recover().clone(),
+            roots: Some(RootsCapabilities {
+                list_changed: Some(true),
+            }),
+            sampling,
+            ..Default::default()
+        },
+        client_info: Implementation {
+            name: CLIENT_NAME.lock_or_recover().clone().unwrap_or_else(|| "sqlite-mcp".to_string()),
+            title: None,
+            version: CLIENT_VERSION.lock_or_recover().clone().unwrap_or_else(|| env!("CARGO_PKG_VERSION").to_string()),
+            website_url: None,
+            icons: None,
+        },
+    }
+}
+
+/// Handler for messages the server sends to the client on its own
+/// Progress, log message and resource update notifications are queued for mcp_poll_notifications_json
+struct McpClientHandler {
+    info: ClientInfo,
+    connection_id: String,
+    tools_cache: ToolListCache,
+}
+
+impl ClientHandler for McpClientHandler {
+    async fn create_message(
+        &self,
+        params: CreateMessageRequestParam,
+        _context: RequestContext<RoleClient>,
+    ) -> Result<CreateMessageResult, McpError> {
+        let handler = match *SAMPLING_HANDLER.lock_or_recover() {
+            Some(handler) => handler,
+            None => return Err(McpError::method_not_found::<CreateMessageRequestMethod>()),
+        };
+
+        let request_json = serde_json::to_string(&params)
+            .map_err(|e| McpError::internal_error(format!("Serialization failed: {}", e), None))?;
+
+        // The callback may block for as long as the model takes, so keep it off the runtime threads
+        let response_json = tokio::task::spawn_blocking(move || handler.call(&request_json))
+            .await
+            .map_err(|e| McpError::internal_error(format!("Sampling callback failed: {}", e), None))?
+            .map_err(|e| McpError::internal_error(e, None))?;
+
+        let response: serde_json::Value = serde_json::from_str(&response_json)
+            .map_err(|e| McpError::internal_error(format!("Invalid sampling response JSON: {}", e), None))?;
+        if let Some(error) = error_message(&response) {
+            return Err(McpError::internal_error(error.to_string(), None));
+        }
+
+        serde_json::from_value(response)
+            .map_err(|e| McpError::internal_error(format!("Invalid sampling response: {}", e), None))
+    }
+
+    async fn list_roots(&self, _context: RequestContext<RoleClient>) -> Result<ListRootsResult, McpError> {
+        Ok(ListRootsResult {
+            roots: ROOTS.lock_or_recover().clone(),
+        })
+    }
+
+    async fn on_progress(&self, params: ProgressNotificationParam, _context: NotificationContext<RoleClient>) {
+        // Progress of a streamed tool call also goes to its stream, dropped if the stream's buffer is full
+        let key = (self.connection_id.clone(), params.progress_token.clone());
+        if let Some(stream) = tool_stream_progress().lock_or_recover().get(&key).cloned() {
+            stream.try_push(serde_json::json!({
+                "type": "progress",
+                "progress": params.progress,
+                "total": params.total,
+                "message": params.message,
+            }));
+        }
+
+        queue_notification(serde_json::json!({
+            "connection_id": self.connection_id,
+            "method": "notifications/progress",
+            "progress_token": params.progress_token,
+            "progress": params.progress,
+            "total": params.total,
+            "message": params.message,
+        }));
+    }
+
+    async fn on_tool_list_changed(&self, _context: NotificationContext<RoleClient>) {
+        *self.tools_cache.lock_or_recover() = None;
+        TOOL_LIST_CHANGES.fetch_add(1, Ordering::Relaxed);
+        queue_notification(serde_json::json!({
+            "connection_id": self.connection_id,
+            "method": "notifications/tools/list_changed",
+        }));
+    }
+
+    async fn on_resource_list_changed(&self, _context: NotificationContext<RoleClient>) {
+        queue_notification(serde_json::json!({
+            "connection_id": self.connection_id,
+            "method": "notifications/resources/list_changed",
+        }));
+    }
+
+    async fn on_prompt_list_changed(&self, _context: NotificationContext<RoleClient>) {
+        queue_notification(serde_json::json!({
+            "connection_id": self.connection_id,
+            "method": "notifications/prompts/list_changed",
+        }));
+    }
+
+    async fn on_logging_message(&self, params: LoggingMessageNotificationParam, _context: NotificationContext<RoleClient>) {
+        queue_notification(serde_json::json!({
+            "connection_id": self.connection_id,
+            "method": "notifications/message",
+            "level": params.level,
+            "logger": params.logger,
+            "data": params.data,
+        }));
+    }
+
+    async fn on_resource_updated(&self, params: ResourceUpdatedNotificationParam, _context: NotificationContext<RoleClient>) {
+        queue_notification(serde_json::json!({
+            "connection_id": self.connection_id,
+            "method": "notifications/resources/updated",
+            "uri": params.uri,
+        }));
+    }
+
+    fn get_info(&self) -> ClientInfo {
+        self.info.clone()
+    }
+}
+
+/// Callback the host registers to answer sampling/createMessage requests from servers
+/// Receives the request params JSON and returns a CreateMessageResult JSON, or {"error": "..."} (or an error object) to refuse
+pub type McpSamplingCallback = extern "C" fn(context: *mut c_void, request_json: *const c_char) -> *mut c_char;
+
+/// Callback that frees a response returned by a McpSamplingCallback
+pub type McpFreeCallback = extern "C" fn(response: *mut c_char);
+
+/// Sampling callback registered with mcp_set_sampling_callback
+#[derive(Clone, Copy)]
+struct SamplingHandler {
+    callback: McpSamplingCallback,
+    free_response: Option<McpFreeCallback>,
+    context: *mut c_void,
+}
+
+// The host promises the callback and its context can be used from any thread while registered
+unsafe impl Send for SamplingHandler {}
+
+impl SamplingHandler {
+    /// Run the callback and copy its response
+    fn call(&self, request_json: &str) -> Result<String, String> {
+        let request = to_c_string(request_json);
+        let response = (self.callback)(self.context, request.as_ptr());
+        if response.is_null() {
+            return Err("Sampling callback returned no response".to_string());
+        }
+
+        let response_json = unsafe { CStr::from_ptr(response) }.to_string_lossy().into_owned();
+        if let Some(free_response) = self.free_response {
+            free_response(response);
+        }
+        Ok(response_json)
+    }
+}
+
+static SAMPLING_HANDLER: Mutex<Option<SamplingHandler>> = Mutex::new(None);
+
+/// Register the callback that answers sampling requests, advertised to servers connected afterwards
+/// callback: Function receiving the request JSON and returning the response JSON, or NULL to stop handling sampling
+/// free_response: Optional function called to free each response once it has been copied, can be NULL
+/// context: Pointer passed back to the callback unchanged, can be NULL
+#[no_mangle]
+pub extern "C" fn mcp_set_sampling_callback(
+    callback: Option<McpSamplingCallback>,
+    free_response: Option<McpFreeCallback>,
+    context: *mut c_void,
+) {
+    ffi_guard(|_| (), || {
+        *SAMPLING_HANDLER.lock_or_recover() = callback.map(|callback| SamplingHandler {
+            callback,
+            free_response,
+            context,
+        });
+    })
+}
+
+// Roots set with mcp_set_roots_json, listed to servers that ask for roots/list
+static ROOTS: Mutex<Vec<Root>> = Mutex::new(Vec::new());
+
+/// Parse a roots list given either as URI strings or as {"uri": ..., "name": ...} objects
+fn parse_roots(roots_json: &str) -> Result<Vec<Root>, String> {
+    let value: serde_json::Value = serde_json::from_str(roots_json)
+        .map_err(|e| format!("Invalid roots JSON: {}", e))?;
+    let items = value.as_array().ok_or("Roots must be a JSON array")?;
+
+    items.iter().map(|item| match item {
+        serde_json::Value::String(uri) => Ok(Root { uri: uri.clone(), name: None }),
+        serde_json::Value::Object(_) => serde_json::from_value(item.clone())
+            .map_err(|e| format!("Invalid root: {}", e)),
+        _ => Err("Each root must be a URI string or an object with a uri".to_string()),
+    }).collect()
+}
+
+/// Set the roots (allowed directories) offered to servers, notifying connected servers of the change
+/// roots_json: JSON array of root URIs or {"uri": ..., "name": ...} objects, or NULL to clear the roots
+/// Returns NULL on success, error string on failure (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_set_roots_json(roots_json: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error, || {
+        let roots = if roots_json.is_null() {
+            Vec::new()
+        } else {
+            let roots_json = match unsafe { CStr::from_ptr(roots_json).to_str() } {
+                Ok(s) => s,
+                Err(_) => return to_c_string("Invalid roots JSON").into_raw(),
+            };
+            match parse_roots(roots_json) {
+                Ok(roots) => roots,
+                Err(error) => return to_c_string(error).into_raw(),
+            }
+        };
+
+        {
+            let mut current = ROOTS.lock_or_recover();
+            if *current == roots {
+                return ptr::null_mut();
+            }
+            *current = roots;
+        }
+
+        // Best effort: a server that misses the notification still gets the new roots on its next roots/list
+        let clients: Vec<Arc<McpClient>> = global_clients().lock_or_recover().values().cloned().collect();
+        for client in clients {
+            let _ = client.runtime.block_on(with_request_timeout(&client.connection_id, async {
+                if let Some(peer) = current_peer(&client).await {
+                    let _ = peer.notify_roots_list_changed().await;
+                }
+            }));
+        }
+        ptr::null_mut()
+    })
+}
+
+/// Create the handler for a new service of the given client
+fn client_handler(client: &McpClient) -> McpClientHandler {
+    McpClientHandler {
+        info: client_info(),
+        connection_id: client.connection_id.clone(),
+        tools_cache: client.tools_cache.clone(),
+    }
+}
+
+// Notifications received from servers and not yet polled, oldest first
+static NOTIFICATIONS: Mutex<VecDeque<QueuedNotification>> = Mutex::new(VecDeque::new());
+
+// Sequence number of the last queued notification, never reused so consumers can resume after one
+static NOTIFICATION_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// A queued notification with its sequence number and when it arrived, as listed by mcp_events
+struct QueuedNotification {
+    seq: u64,
+    received_ms: u64,
+    notification: serde_json::Value,
+}
+
+// Maximum number of queued notifications; the oldest are dropped first so a chatty server cannot exhaust memory
+static NOTIFICATION_QUEUE_SIZE: AtomicU32 = AtomicU32::new(1000);
+
+/// Add a notification to the queue, dropping the oldest one if it is full, and pass it to the notification callback
+fn queue_notification(notification: serde_json::Value) {
+    dispatch_notification(&notification);
+    let size = NOTIFICATION_QUEUE_SIZE.load(Ordering::Relaxed) as usize;
+    if size == 0 {
+        return;
+    }
+    let received_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_millis() as u64);
+    let dropped = {
+        let mut queue = NOTIFICATIONS.lock_or_recover();
+        let dropped = queue.len() >= size;
+        if dropped {
+            queue.pop_front();
+        }
+        // Numbered under the lock, so sequence numbers follow queue order
+        let seq = NOTIFICATION_SEQ.fetch_add(1, Ordering::Relaxed) + 1;
+        queue.push_back(QueuedNotification { seq, received_ms, notification });
+        dropped
+    };
+    if dropped {
+        log_event(LOG_WARN, format_args!("notification queue full, dropped the oldest of {} notifications", size));
+    }
+}
+
+/// Callback the host registers to receive each notification as it arrives, as the JSON mcp_poll_notifications_json returns
+pub type McpNotificationCallback = extern "C" fn(context: *mut c_void, notification_json: *const c_char);
+
+/// Notification callback registered with mcp_set_notification_callback
+#[derive(Clone, Copy)]
+struct NotificationHandler {
+    callback: McpNotificationCallback,
+    context: *mut c_void,
+}
+
+// The host promises the callback and its context can be used from any thread while registered
+unsafe impl Send for NotificationHandler {}
+
+static NOTIFICATION_HANDLER: Mutex<Option<NotificationHandler>> = Mutex::new(None);
+
+// Held while the callback runs, so replacing it waits until a call in progress has returned
+static NOTIFICATION_CALLING: Mutex<()> = Mutex::new(());
+
+// Notifications waiting for the callback; when full, new ones are dropped rather than stall the connection
+static NOTIFICATION_DISPATCH: OnceLock<std::sync::mpsc::SyncSender<String>> = OnceLock::new();
+const NOTIFICATION_DISPATCH_SIZE: usize = 1000;
+
+thread_local! {
+    // Set on the dispatch thread, where waiting for the callback to return would wait for itself
+    static IN_NOTIFICATION_CALLBACK: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Hand a notification to the dispatch thread, which runs the callback so a slow one never holds up a connection
+fn dispatch_notification(notification: &serde_json::Value) {
+    if NOTIFICATION_HANDLER.lock_or_recover().is_none() {
+        return;
+    }
+    let sender = NOTIFICATION_DISPATCH.get_or_init(|| {
+        let (sender, receiver) = std::sync::mpsc::sync_channel::<String>(NOTIFICATION_DISPATCH_SIZE);
+        std::thread::spawn(move || {
+            IN_NOTIFICATION_CALLBACK.with(|flag| flag.set(true));
+            for notification in receiver {
+                let _calling = NOTIFICATION_CALLING.lock_or_recover();
+                let handler = *NOTIFICATION_HANDLER.lock_or_recover();
+                if let Some(handler) = handler {
+                    let notification = to_c_string(notification);
+                    (handler.callback)(handler.context, notification.as_ptr());
+                }
+            }
+        });
+        sender
+    });
+    if sender.try_send(notification.to_string()).is_err() {
+        log_event(LOG_WARN, format_args!(
+            "notification callback is {} notifications behind, dropped a notification",
+            NOTIFICATION_DISPATCH_SIZE
+        ));
+    }
+}
+
+/// Register the callback receiving each notification from every connection as it arrives
+/// Notifications are still queued for mcp_poll_notifications_json unless the queue size is 0
+/// callback: Function receiving the context and the notification JSON, or NULL to stop
+/// context: Pointer passed back to the callback unchanged, can be NULL
+/// Once this returns, the previous callback is no longer running, unless this was called from that callback
+#[no_mangle]
+pub extern "C" fn mcp_set_notification_callback(callback: Option<McpNotificationCallback>, context: *mut c_void) {
+    ffi_guard(|_| (), || {
+        *NOTIFICATION_HANDLER.lock_or_recover() = callback.map(|callback| NotificationHandler { callback, context });
+        if !IN_NOTIFICATION_CALLBACK.with(|flag| flag.get()) {
+            drop(NOTIFICATION_CALLING.lock_or_recover());
+        }
+    })
+}
+
+/// Set how many notifications are queued until polled, dropping the oldest ones over the new size
+/// size: Maximum number of queued notifications, 1000 by default, 0 to stop queuing
+#[no_mangle]
+pub extern "C" fn mcp_set_notification_queue_size(size: u32) {
+    ffi_guard(|_| (), || {
+        NOTIFICATION_QUEUE_SIZE.store(size, Ordering::Relaxed);
+        let mut queue = NOTIFICATIONS.lock_or_recover();
+        let excess = queue.len().saturating_sub(size as usize);
+        queue.drain(..excess);
+    })
+}
+
+/// Drain the notifications received from all connections since the last poll (returns raw JSON)
+/// Returns: JSON array of notifications, oldest first (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_poll_notifications_json() -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let notifications: Vec<serde_json::Value> = NOTIFICATIONS.lock_or_recover().drain(..).map(|queued| queued.notification).collect();
+        to_c_string(serde_json::Value::Array(notifications).to_string()).into_raw()
+    })
+}
+
+/// Get the queued log messages servers sent, for the mcp_logs virtual table (returns raw JSON)
+/// clear: 1 to remove the returned log messages from the queue, 0 to leave them; other notifications are always kept
+/// Returns: JSON {"logs": [...]} with connection_id, level, logger and data of each message, oldest first (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_server_logs_json(clear: i32) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let is_log = |queued: &QueuedNotification| queued.notification["method"] == "notifications/message";
+        let mut queue = NOTIFICATIONS.lock_or_recover();
+        let logs: Vec<serde_json::Value> = queue.iter().filter(|n| is_log(n)).map(|n| n.notification.clone()).collect();
+        if clear != 0 {
+            queue.retain(|n| !is_log(n));
+        }
+        drop(queue);
+        to_c_string(serde_json::json!({ "logs": logs }).to_string()).into_raw()
+    })
+}
+
+/// Get the queued notifications of every kind, for the mcp_events virtual table (returns raw JSON)
+/// since: Sequence number of the last event already seen, 0 to list every queued event; events are not removed
+/// Returns: JSON {"events": [...]} with seq, kind, ts, connection_id and payload of each event, oldest first (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_events_json(since: u64) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let events: Vec<serde_json::Value> = NOTIFICATIONS.lock_or_recover()
+            .iter()
+            .filter(|queued| queued.seq > since)
+            .map(|queued| {
+                let method = queued.notification["method"].as_str().unwrap_or_default();
+                serde_json::json!({
+                    "seq": queued.seq,
+                    "kind": method.strip_prefix("notifications/").unwrap_or(method),
+                    "ts": queued.received_ms,
+                    "connection_id": queued.notification["connection_id"],
+                    "payload": queued.notification,
+                })
+            })
+            .collect();
+        to_c_string(serde_json::json!({ "events": events }).to_string()).into_raw()
+    })
+}
+
+/// Report request counters and queue depths across all connections, cheap enough to poll often
+/// Returns: JSON {"requests_total", "requests_in_flight", "last_latency_ms", "reconnects", "connections", "queued_notifications"} (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_metrics_json() -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let result = serde_json::json!({
+            "requests_total": REQUESTS_TOTAL.load(Ordering::Relaxed),
+            "requests_in_flight": REQUESTS_IN_FLIGHT.load(Ordering::Relaxed),
+            "last_latency_ms": LAST_LATENCY_MS.load(Ordering::Relaxed),
+            "reconnects": RECONNECTS.load(Ordering::Relaxed),
+            "connections": global_clients().lock_or_recover().len(),
+            "queued_notifications": NOTIFICATIONS.lock_or_recover().len(),
+        });
+        to_c_string(result.to_string()).into_raw()
+    })
+}
+
+/// Report request latencies per MCP method and per tool, across all connections
+/// Returns: JSON {"methods": {method: timing}, "tools": {name: timing}} with timing
+/// {"count", "min_ms", "max_ms", "avg_ms", "p95_ms"} (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_timing_json() -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let timings = TIMINGS.get_or_init(Mutex::default).lock_or_recover();
+        let to_json = |map: &std::collections::BTreeMap<String, MethodTiming>| -> serde_json::Map<String, serde_json::Value> {
+            map.iter().map(|(name, timing)| (name.clone(), timing.to_json())).collect()
+        };
+        let result = serde_json::json!({ "methods": to_json(&timings.methods), "tools": to_json(&timings.tools) });
+        to_c_string(result.to_string()).into_raw()
+    })
+}
+
+/// List the requests waiting for a connect in progress or held by mcp_pause, and those waiting for the server's answer, oldest first
+/// Returns: JSON {"requests": [{"connection", "kind", "state", "age_ms"}]}, state being "queued" or "in_flight"
+/// (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_queue_json() -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let requests: Vec<serde_json::Value> = tracked_requests().lock_or_recover().values().map(|request| serde_json::json!({
+            "connection": request.connection_id,
+            "kind": request.kind,
+            "state": if request.queued { "queued" } else { "in_flight" },
+            "age_ms": request.started.elapsed().as_millis() as u64,
+        })).collect();
+        to_c_string(serde_json::json!({ "requests": requests }).to_string()).into_raw()
+    })
+}
+
+/// Cancel the requests waiting for a connect in progress or held by mcp_pause, which fail with a cancelled error without being sent
+/// Requests already sent are left running, mcp_cancel stops those
+/// Returns: The number of requests cancelled
+#[no_mangle]
+pub extern "C" fn mcp_clear_queue() -> usize {
+    ffi_guard(|_| 0, || {
+        let (pending, finished) = pending_connects();
+        let _pending = pending.lock_or_recover();
+        let cleared = tracked_requests().lock_or_recover().values().filter(|request| request.queued).count();
+        QUEUE_CLEARS.fetch_add(1, Ordering::Relaxed);
+        finished.notify_all();
+        cleared
+    })
+}
+
+/// Pause a connection: tool calls, list and read requests are held, or fail, until mcp_resume
+/// The transport, subscriptions, keep-alive pings and requests already sent are left running
+/// queue: 1 to hold requests until resumed, up to the request timeout, 0 to fail them with code -32007
+/// connection_id: Connection to pause, can be NULL for the "default" connection
+/// Returns: NULL on success, error message if the connection does not exist (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_pause(queue: i32, connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error, || {
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(extract_error_message(&error)).into_raw(),
+        };
+        if get_client(&connection_id).is_none() {
+            return to_c_string(extract_error_message(&not_connected_error(&connection_id))).into_raw();
+        }
+
+        {
+            let (pending, finished) = pending_connects();
+            let _pending = pending.lock_or_recover();
+            PAUSED_CONNECTIONS.lock_or_recover().insert(connection_id.clone(), queue != 0);
+            // Requests already held fail at once when the pause switches to failing them
+            finished.notify_all();
+        }
+        log_event(LOG_INFO, format_args!("[{}] paused", connection_id));
+        ptr::null_mut()
+    })
+}
+
+/// Resume a connection paused with mcp_pause, sending the requests held while it was paused
+/// connection_id: Connection to resume, can be NULL for the "default" connection
+/// Returns: The number of held requests released, 0 if the connection was not paused
+#[no_mangle]
+pub extern "C" fn mcp_resume(connection_id: *const c_char) -> usize {
+    ffi_guard(|_| 0, || {
+        let Ok(connection_id) = resolve_connection_id(connection_id) else {
+            return 0;
+        };
+        resume_connection(&connection_id)
+    })
+}
+
+/// Drop a connection's paused state and wake the requests held by it
+/// Returns the number of requests that were held
+fn resume_connection(connection_id: &str) -> usize {
+    let held = {
+        let (pending, finished) = pending_connects();
+        let _pending = pending.lock_or_recover();
+        if PAUSED_CONNECTIONS.lock_or_recover().remove(connection_id).is_none() {
+            return 0;
+        }
+        finished.notify_all();
+        tracked_requests().lock_or_recover().values()
+            .filter(|request| request.queued && request.connection_id == connection_id)
+            .count()
+    };
+    log_event(LOG_INFO, format_args!("[{}] resumed, releasing {} held requests", connection_id, held));
+    held
+}
+
+/// Build the connection status JSON from the server info of a running service
+fn connected_status(service: &RunningClient, transport: &str) -> String {
+    let info = service.peer_info();
+    let (server_name, server_version) = if let Some(info) = info {
+        (
+            serde_json::to_string(&info.server_info.name).unwrap_or_else(|_| "\"unknown\"".to_string()),
+            serde_json::to_string(&info.server_info.version).unwrap_or_else(|_| "\"0.0.0\"".to_string())
+        )
+    } else {
+        ("\"unknown\"".to_string(), "\"0.0.0\"".to_string())
+    };
+
+    format!(
+        r#"{{"status": "connected", "server": {}, "version": {}, "transport": "{}"}}"#,
+        server_name, server_version, transport
+    )
+}
+
+/// Timeout for connecting: the connect timeout if one is set, the connection's or the global request timeout otherwise
+fn connect_timeout_ms(timeout_ms: Option<u32>) -> u32 {
+    match CONNECT_TIMEOUT_MS.load(Ordering::Relaxed) {
+        0 => timeout_ms.unwrap_or_else(|| REQUEST_TIMEOUT_MS.load(Ordering::Relaxed)),
+        ms => ms,
+    }
+}
+
+/// Open the service for a new client and store it under its connection id if that succeeds
+/// timeout_ms: Request timeout of the connection, or None to use the global one
+/// Connecting is bounded by the connect timeout if one is set, by the request timeout otherwise
+/// Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+fn connect_and_store(new_client: McpClient, connection_id: &str, params: ConnectParams, timeout_ms: Option<u32>) -> *mut c_char {
+    let _pending = PendingConnect::new(connection_id);
+    let connect_timeout_ms = connect_timeout_ms(timeout_ms);
+    let (result, maybe_service) = new_client.runtime.block_on(run_cancellable(connection_id, async {
+        match open_service_logged(&new_client, &params, connect_timeout_ms).await {
+            Ok(service) => (connected_status(&service, params.transport()), Some(service)),
+            Err(error) => (error, None),
+        }
+    })).unwrap_or_else(|error| (error, None));
+
+    // Store service and connection parameters if connection succeeded
+    if let Some(service) = maybe_service {
+        new_client.runtime.block_on(async {
+            *new_client.service.lock().await = Some(service);
+        });
+        connection_activity().lock_or_recover().insert(connection_id.to_string(), std::time::Instant::now());
+        let keep_alive = new_client.runtime.spawn(keep_alive(connection_id.to_string(), new_client.service.clone()));
+        let watch_transport = new_client.runtime.spawn(watch_transport(connection_id.to_string(), new_client.service.clone()));
+        new_client.background_tasks.lock_or_recover().extend([keep_alive.abort_handle(), watch_transport.abort_handle()]);
+        *new_client.server_url.lock_or_recover() = Some(params.target().to_string());
+        *new_client.connect_params.lock_or_recover() = Some(params);
+
+        // Store the client globally, replacing any previous connection with the same id
+        let runtime = new_client.runtime.clone();
+        let replaced = global_clients().lock_or_recover().insert(connection_id.to_string(), Arc::new(new_client));
+        // The runtime is shared with the new connection, so the replaced session would otherwise stay open
+        if let Some(replaced) = replaced {
+            runtime.block_on(async {
+                let stale = replaced.service.lock().await.take();
+                if let Some(stale) = stale {
+                    let _ = tokio::time::timeout(Duration::from_secs(1), stale.cancel()).await;
+                }
+            });
+        }
+        match timeout_ms {
+            Some(ms) => connection_timeouts().lock_or_recover().insert(connection_id.to_string(), ms),
+            None => connection_timeouts().lock_or_recover().remove(connection_id),
+        };
+    }
+
+    // Parse the JSON response using serde_json to check status
+    match serde_json::from_str::<serde_json::Value>(&result) {
+        Ok(json) => {
+            if let Some(status) = json.get("status").and_then(|v| v.as_str()) {
+                if status == "connected" {
+                    // Return NULL on successful connection
+                    ptr::null_mut()
+                } else {
+                    // Return error string (extracted from JSON)
+                    let error_msg = extract_error_message(&result);
+                    to_c_string(error_msg).into_raw()
+                }
+            } else {
+                // No status field found, extract error message
+                let error_msg = extract_error_message(&result);
+                to_c_string(error_msg).into_raw()
+            }
+        }
+        Err(_) => {
+            // Invalid JSON, return as error string
+            let error_msg = extract_error_message(&result);
+            to_c_string(error_msg).into_raw()
+        }
+    }
+}
+
+/// Close a connection and open it again with the config it was opened with, for a fresh transport after
+/// a network change, then subscribe again to its resources and set the server log level set on it
+/// connection_id: Connection to reconnect, can be NULL for the "default" connection
+/// Returns: JSON {"reconnected": true, "subscriptions": [...], "log_level": ..., "failed": [...]} listing what was
+/// restored and what could not be, or {"error": ...} if there is no connection or it cannot be opened (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_reconnect(connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let Some(client) = get_client(&connection_id) else {
+            return to_c_string(not_connected_error(&connection_id)).into_raw();
+        };
+        let Some(config) = client.connect_config.lock_or_recover().clone() else {
+            return to_c_string(not_connected_error(&connection_id)).into_raw();
+        };
+        let subscriptions = client.subscriptions.lock_or_recover().clone();
+        let log_level = *client.server_log_level.lock_or_recover();
+        // Connecting resets the timeout to the config's, losing one set since with mcp_set_connection_timeout_ms
+        let timeout_ms = connection_timeouts().lock_or_recover().get(&connection_id).copied();
+        drop(client);
+
+        let error = connect_with_config(ConnectConfig { force: Some(true), ..config });
+        if !error.is_null() {
+            let message = unsafe { CString::from_raw(error) }.to_string_lossy().into_owned();
+            return to_c_string(error_json(&message)).into_raw();
+        }
+        match timeout_ms {
+            Some(ms) => connection_timeouts().lock_or_recover().insert(connection_id.clone(), ms),
+            None => connection_timeouts().lock_or_recover().remove(&connection_id),
+        };
+
+        let Some(client) = get_client(&connection_id) else {
+            return to_c_string(not_connected_error(&connection_id)).into_raw();
+        };
+        let result = client.runtime.block_on(run_request(&connection_id, async {
+            let Some(peer) = current_peer(&client).await else {
+                return error_json_code(ERROR_NOT_CONNECTED, "Not connected to server");
+            };
+
+            // The new session starts with no subscriptions, so only the ones restored are kept
+            let mut failed = Vec::new();
+            for uri in subscriptions {
+                let request = format!("SubscribeRequest '{}'", uri);
+                match traced(&connection_id, &request, peer.subscribe(rmcp::model::SubscribeRequestParam { uri: uri.clone() })).await {
+                    Ok(()) => {
+                        client.subscriptions.lock_or_recover().insert(uri);
+                    }
+                    Err(e) => {
+                        log_event(LOG_WARN, format_args!("[{}] subscription to '{}' not restored: {}", connection_id, uri, e));
+                        failed.push(serde_json::json!({ "uri": uri, "error": service_error_value("Failed to subscribe", &e) }));
+                    }
+                }
+            }
+            if let Some(level) = log_level {
+                match traced(&connection_id, "SetLevelRequest", peer.set_level(SetLevelRequestParam { level })).await {
+                    Ok(()) => *client.server_log_level.lock_or_recover() = Some(level),
+                    Err(e) => {
+                        log_event(LOG_WARN, format_args!("[{}] server log level not restored: {}", connection_id, e));
+                        failed.push(serde_json::json!({ "log_level": level, "error": service_error_value("Failed to set log level", &e) }));
+                    }
+                }
+            }
+
+            serde_json::json!({
+                "reconnected": true,
+                "subscriptions": *client.subscriptions.lock_or_recover(),
+                "log_level": *client.server_log_level.lock_or_recover(),
+                "timeout_ms": timeout_ms,
+                "failed": failed,
+            })
+            .to_string()
+        })).unwrap_or_else(|error| error);
+
+        to_c_string(result).into_raw()
+    })
+}
+
+/// Get the config a connection was opened with, in the form mcp_connect_json accepts
+/// redact_secrets: 1 to replace credential headers, tokens, client secrets and proxy passwords with "***", 0 to return them
+/// connection_id: Connection whose config to return, can be NULL for the "default" connection
+/// Returns: JSON string with the config, or {"error": ...} if not connected (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_connection_config_json(redact_secrets: i32, connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let config = match get_client(&connection_id) {
+            Some(client) => client.connect_config.lock_or_recover().clone(),
+            None => None,
+        };
+        let Some(config) = config else {
+            return to_c_string(not_connected_error(&connection_id)).into_raw();
+        };
+
+        let config = if redact_secrets != 0 { config.redacted() } else { config };
+        match serde_json::to_string(&config) {
+            Ok(json) => to_c_string(json).into_raw(),
+            Err(e) => to_c_string(error_json_code(rmcp::model::ErrorCode::INTERNAL_ERROR.0, &format!("Serialization failed: {}", e))).into_raw(),
+        }
+    })
+}
+
+/// Disconnect from an MCP server and drop its client
+/// connection_id: Connection to close, can be NULL to close the "default" connection
+/// Returns NULL on success, error string on failure (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_disconnect(connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error, || {
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(extract_error_message(&error)).into_raw(),
+        };
+
+        // Drop the client outside the lock so its runtime shutdown does not block other connections
+        let (removed, remaining) = {
+            let mut clients = global_clients().lock_or_recover();
+            let removed = clients.remove(&connection_id);
+            (removed, clients.len())
+        };
+        drop(removed);
+        connection_timeouts().lock_or_recover().remove(&connection_id);
+        connection_activity().lock_or_recover().remove(&connection_id);
+        close_tool_streams(Some(&connection_id));
+        // Requests held by a pause wake up and fail as not connected
+        resume_connection(&connection_id);
+
+        // Other connections may still own active streams
+        if remaining > 0 {
+            return ptr::null_mut();
+        }
+
+        // Also clear any active stream channels
+        STREAM_CHANNELS.blocking_lock().clear();
+    
+        // Reset stream counter
+        *STREAM_COUNTER.lock_or_recover() = 0;
+    
+        ptr::null_mut()
+    })
+}
+
+/// Close every connection and stop the runtimes they run on, waiting for their worker threads to exit
+/// Running requests are cancelled; settings are kept, and later calls start again from a clean state
+#[no_mangle]
+pub extern "C" fn mcp_shutdown() {
+    ffi_guard(|_| (), || {
+        let clients: Vec<(String, Arc<McpClient>)> = global_clients().lock_or_recover().drain().collect();
+        for (connection_id, _) in &clients {
+            cancel_signal(connection_id).notify_waiters();
+        }
+        connection_timeouts().lock_or_recover().clear();
+        connection_activity().lock_or_recover().clear();
+        for (connection_id, _) in &clients {
+            resume_connection(connection_id);
+        }
+        log_event(LOG_INFO, format_args!("shutting down {} connections", clients.len()));
+
+        for (_, client) in clients {
+            client.runtime.block_on(async {
+                if let Some(service) = client.service.lock().await.take() {
+                    let _ = tokio::time::timeout(Duration::from_secs(1), service.cancel()).await;
+                }
+            });
+
+            // A request still returning on another thread keeps its client, whose runtime is dropped after it
+            let runtime = Arc::try_unwrap(client).ok().map(|client| client.runtime.clone());
+            if let Some(runtime) = runtime.and_then(|runtime| Arc::try_unwrap(runtime).ok()) {
+                runtime.shutdown_timeout(Duration::from_secs(1));
+            }
+        }
+
+        STREAM_CHANNELS.blocking_lock().clear();
+        *STREAM_COUNTER.lock_or_recover() = 0;
+        close_tool_streams(None);
+    })
+}
+
+/// Check that an MCP server is still responding by sending it a ping request
+/// connection_id: Connection to ping, can be NULL to ping the "default" connection
+/// Returns NULL on success, JSON error string on failure (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_ping(connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let client = match get_client(&connection_id) {
+            Some(c) => c,
+            None => return to_c_string(not_connected_error(&connection_id)).into_raw(),
+        };
+
+        let result = client.runtime.block_on(run_request(&connection_id, async {
+            let peer = match current_peer(&client).await {
+                Some(peer) => peer,
+                None => return Some(error_json_code(ERROR_NOT_CONNECTED, "Not connected to server")),
+            };
+
+            let request = ClientRequest::PingRequest(PingRequest::default());
+            match traced(&connection_id, "PingRequest", peer.send_request(request)).await {
+                Ok(ServerResult::EmptyResult(_)) => None,
+                Ok(_) => Some(error_json("Ping failed: unexpected response from server")),
+                Err(e) => Some(service_error_json("Ping failed", &e)),
+            }
+        })).unwrap_or_else(Some);
+
+        match result {
+            Some(error) => to_c_string(error).into_raw(),
+            None => ptr::null_mut(),
+        }
+    })
+}
+
+/// Measure the round-trip time to an MCP server by sending it ping requests one after another (returns raw JSON)
+/// count: Number of pings to send
+/// connection_id: Connection to measure, can be NULL to measure the "default" connection
+/// Returns: JSON {"count", "samples_ms", "min_ms", "max_ms", "avg_ms", "p50_ms", "p95_ms"}, or the error of the first failed ping (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_echo_latency_json(count: u32, connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let client = match get_client(&connection_id) {
+            Some(c) => c,
+            None => return to_c_string(not_connected_error(&connection_id)).into_raw(),
+        };
+
+        let result = client.runtime.block_on(run_cancellable(&connection_id, async {
+            let peer = match current_peer(&client).await {
+                Some(peer) => peer,
+                None => return error_json_code(ERROR_NOT_CONNECTED, "Not connected to server"),
+            };
+
+            // Pings are sent one at a time so each sample is a full round trip on an otherwise idle connection
+            let mut samples = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                let started = std::time::Instant::now();
+                let request = ClientRequest::PingRequest(PingRequest::default());
+                match with_request_timeout(&connection_id, traced(&connection_id, "PingRequest", peer.send_request(request))).await {
+                    Ok(Ok(ServerResult::EmptyResult(_))) => samples.push(started.elapsed().as_micros() as f64 / 1000.0),
+                    Ok(Ok(_)) => return error_json("Ping failed: unexpected response from server"),
+                    Ok(Err(e)) => return service_error_json("Ping failed", &e),
+                    Err(error) => return error,
+                }
+            }
+
+            let mut sorted = samples.clone();
+            sorted.sort_by(f64::total_cmp);
+            let percentile = |p: usize| sorted.get((sorted.len() * p).div_ceil(100).saturating_sub(1)).copied().unwrap_or(0.0);
+            serde_json::json!({
+                "count": samples.len(),
+                "samples_ms": samples,
+                "min_ms": sorted.first().copied().unwrap_or(0.0),
+                "max_ms": sorted.last().copied().unwrap_or(0.0),
+                "avg_ms": sorted.iter().sum::<f64>() / sorted.len().max(1) as f64,
+                "p50_ms": percentile(50),
+                "p95_ms": percentile(95),
+            }).to_string()
+        })).unwrap_or_else(|error| error);
+
+        to_c_string(result).into_raw()
+    })
+}
+
+/// Report the status of a connection (returns raw JSON)
+/// connection_id: Connection to inspect, can be NULL to inspect the "default" connection
+/// Returns: JSON string with the connection status (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_status_json(_client_ptr: *mut McpClient, connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let client = match get_client(&connection_id) {
+            Some(c) => c,
+            None => return to_c_string(serde_json::json!({ "connected": false }).to_string()).into_raw(),
+        };
+
+        let url = client.server_url.lock_or_recover().clone();
+        let transport = client.connect_params.lock_or_recover().as_ref().map(ConnectParams::transport);
+
+        let result = client.runtime.block_on(async {
+            let peer = current_peer(&client).await;
+            match peer.as_ref().and_then(|peer| peer.peer_info()) {
+                Some(info) => serde_json::json!({
+                    "connected": true,
+                    "connection_id": connection_id,
+                    "url": url,
+                    "transport": transport,
+                    "server_name": info.server_info.name,
+                    "server_version": info.server_info.version,
+                    "protocol_version": info.protocol_version,
+                }),
+                None => serde_json::json!({ "connected": false }),
+            }
+        });
+
+        to_c_string(result.to_string()).into_raw()
+    })
+}
+
+/// Get the transport a connection uses: "streamable-http", "sse", "websocket" or "stdio"
+/// connection_id: Connection to inspect, can be NULL to inspect the "default" connection
+/// Returns: Transport name, or NULL if not connected (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_transport(connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(|_| ptr::null_mut(), || {
+        let Some(client) = resolve_connection_id(connection_id).ok().and_then(|id| get_client(&id)) else {
+            return ptr::null_mut();
+        };
+        let transport = client.connect_params.lock_or_recover().as_ref().map(ConnectParams::transport);
+        // Connections whose service was dropped, such as dead keep-alive connections, are not connected
+        match transport {
+            Some(transport) if client.runtime.block_on(current_peer(&client)).is_some() => to_c_string(transport).into_raw(),
+            _ => ptr::null_mut(),
+        }
+    })
+}
+
+/// Get what the server reported about itself when the connection was initialized (returns raw JSON)
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: JSON string with name, title, version, instructions, protocol_version and capabilities (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_server_info_json(connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let client = match get_client(&connection_id) {
+            Some(c) => c,
+            None => return to_c_string(not_connected_error(&connection_id)).into_raw(),
+        };
+
+        let result = client.runtime.block_on(async {
+            let peer = current_peer(&client).await;
+            match peer.as_ref().and_then(|peer| peer.peer_info()) {
+                Some(info) => serde_json::json!({
+                    "name": info.server_info.name,
+                    "title": info.server_info.title,
+                    "version": info.server_info.version,
+                    "instructions": info.instructions,
+                    "protocol_version": info.protocol_version,
+                    "capabilities": info.capabilities,
+                }).to_string(),
+                None => error_json_code(ERROR_NOT_CONNECTED, "Not connected to server"),
+            }
+        });
+
+        to_c_string(result).into_raw()
+    })
+}
+
+/// Get the InitializeResult the server answered the initialize request with, as sent on the wire (returns raw JSON)
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: JSON string with protocolVersion, capabilities, serverInfo and instructions (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_initialize_result_json(connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let client = match get_client(&connection_id) {
+            Some(c) => c,
+            None => return to_c_string(not_connected_error(&connection_id)).into_raw(),
+        };
+
+        let result = client.runtime.block_on(async {
+            let peer = current_peer(&client).await;
+            match peer.as_ref().and_then(|peer| peer.peer_info()) {
+                Some(info) => serde_json::to_string(info).unwrap_or_else(|e| {
+                    error_json_code(rmcp::model::ErrorCode::INTERNAL_ERROR.0, &format!("Serialization failed: {}", e))
+                }),
+                None => error_json_code(ERROR_NOT_CONNECTED, "Not connected to server"),
+            }
+        });
+
+        to_c_string(result).into_raw()
+    })
+}
+
+/// Report what this build supports and how it is configured (returns raw JSON)
+/// connection_id: Connection whose negotiated server capabilities are included, can be NULL for the "default" connection
+/// Returns: JSON string with transports, sampling, roots, timeout_ms and server_capabilities (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_capabilities_json(connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        // Capabilities the server advertised when the connection was initialized, null when not connected
+        let server_capabilities = get_client(&connection_id).and_then(|client| {
+            client.runtime.block_on(async {
+                let peer = current_peer(&client).await?;
+                let info = peer.peer_info()?;
+                serde_json::to_value(&info.capabilities).ok()
+            })
+        });
+
+        let result = serde_json::json!({
+            "transports": ["streamable-http", "sse", "stdio", "websocket"],
+            "sampling": SAMPLING_HANDLER.lock_or_recover().is_some(),
+            "roots": !ROOTS.lock_or_recover().is_empty(),
+            "timeout_ms": request_timeout_ms(&connection_id),
+            "connect_timeout_ms": CONNECT_TIMEOUT_MS.load(Ordering::Relaxed),
+            "keepalive_ms": *keepalive_interval().borrow(),
+            "max_response_bytes": MAX_RESPONSE_BYTES.load(Ordering::Relaxed),
+            "server_capabilities": server_capabilities,
+        });
+
+        to_c_string(result.to_string()).into_raw()
+    })
+}
+
+/// Ask an MCP server to send log messages of the given level and above
+/// Log messages are queued as notifications/message for mcp_poll_notifications_json
+/// level: One of debug, info, notice, warning, error, critical, alert, emergency
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns NULL on success, JSON error string on failure (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_set_server_log_level(level: *const c_char, connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        if level.is_null() {
+            return to_c_string(invalid_params_json("Invalid arguments")).into_raw();
+        }
+
+        let level_str = match unsafe { CStr::from_ptr(level) }.to_str() {
+            Ok(s) => s,
+            Err(_) => return to_c_string(invalid_params_json("Invalid log level")).into_raw(),
+        };
+        let level: LoggingLevel = match serde_json::from_value(serde_json::Value::String(level_str.to_string())) {
+            Ok(level) => level,
+            Err(_) => {
+                let error = format!(
+                    "Invalid log level '{}'. Expected one of: debug, info, notice, warning, error, critical, alert, emergency",
+                    level_str
+                );
+                return to_c_string(invalid_params_json(&error)).into_raw();
+            }
+        };
+
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let client = match get_client(&connection_id) {
+            Some(c) => c,
+            None => return to_c_string(not_connected_error(&connection_id)).into_raw(),
+        };
+
+        let result = client.runtime.block_on(run_request(&connection_id, async {
+            let peer = match current_peer(&client).await {
+                Some(peer) => peer,
+                None => return Some(error_json_code(ERROR_NOT_CONNECTED, "Not connected to server")),
+            };
+
+            if !supports_logging(&peer) {
+                return Some(error_json_code(ERROR_UNSUPPORTED, "Server does not support logging"));
+            }
+
+            match traced(&connection_id, "SetLevelRequest", peer.set_level(SetLevelRequestParam { level })).await {
+                Ok(()) => {
+                    *client.server_log_level.lock_or_recover() = Some(level);
+                    None
+                }
+                Err(e) => Some(service_error_json("Failed to set log level", &e)),
+            }
+        })).unwrap_or_else(Some);
+
+        match result {
+            Some(error) => to_c_string(error).into_raw(),
+            None => ptr::null_mut(),
+        }
+    })
+}
+
+/// List tools available on the connected MCP server (returns raw JSON)
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: JSON string with tools list (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_list_tools_json(_client_ptr: *mut McpClient, connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        // Get global client
+        let client = match get_unpaused_client(&connection_id, "ListToolsRequest") {
+            Ok(c) => c,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let result = client.runtime.block_on(run_request(&connection_id, async {
+            let connection_id = &connection_id;
+            let response = with_auto_reconnect(&client, false, |peer| async move {
+                traced(connection_id, "ListToolsRequest", list_all_tools(&peer)).await
+            }).await;
+
+            match response {
+                None => error_json_code(ERROR_NOT_CONNECTED, "Not connected to server"),
+                Some(Ok(tools)) => tools_list_json(&tools),
+                Some(Err(e)) => service_error_json("Failed to list tools", &e),
+            }
+        })).unwrap_or_else(|error| error);
+
+        to_c_string(result).into_raw()
+    })
+}
+
+/// List tools like mcp_list_tools_json, serving the connection's cached list when there is one
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: JSON string with tools list (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_list_tools_json_cached(connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || list_tools_cached(connection_id, false, tools_list_json))
+}
+
+/// List only the names of the connection's tools, from the cached list when there is one
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: JSON array of tool names in server order (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_list_tool_names_json(connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || list_tools_cached(connection_id, false, tool_names_json))
+}
+
+/// Drop the connection's cached tool list and fetch it again, for servers that change their tools
+/// without sending tools/list_changed
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: JSON string with the new tools list, as returned by mcp_list_tools_json (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_refresh_tools(connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || list_tools_cached(connection_id, true, tools_list_json))
+}
+
+/// Shared implementation of mcp_list_tools_json_cached, mcp_list_tool_names_json and mcp_refresh_tools
+/// refresh: Whether to drop the cached list first, as tools/list_changed does
+/// format: Turns the tool list into the returned JSON
+fn list_tools_cached(connection_id: *const c_char, refresh: bool, format: fn(&[Tool]) -> String) -> *mut c_char {
+    let connection_id = match resolve_connection_id(connection_id) {
+        Ok(id) => id,
+        Err(error) => return to_c_string(error).into_raw(),
+    };
+
+    let client = match get_unpaused_client(&connection_id, "ListToolsRequest") {
+        Ok(c) => c,
+        Err(error) => return to_c_string(error).into_raw(),
+    };
+
+    if refresh {
+        *client.tools_cache.lock_or_recover() = None;
+        TOOL_LIST_CHANGES.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let result = client.runtime.block_on(run_request(&connection_id, async {
+        let response = with_auto_reconnect(&client, false, |peer| {
+            let cache = &client.tools_cache;
+            async move { cached_tools(cache, &peer).await }
+        }).await;
+
+        match response {
+            None => error_json_code(ERROR_NOT_CONNECTED, "Not connected to server"),
+            Some(Ok(tools)) => format(&tools),
+            Some(Err(e)) => service_error_json("Failed to list tools", &e),
+        }
+    })).unwrap_or_else(|error| error);
+
+    to_c_string(result).into_raw()
+}
+
+/// Get the input schema, and output schema if it has one, of one tool (returns raw JSON)
+/// tool_name: Name of the tool
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: JSON {"inputSchema": ..., "outputSchema": ...} from the cached tool list, or an error naming a missing tool (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_tool_schema_json(tool_name: *const c_char, connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let tool_name = match (!tool_name.is_null()).then(|| unsafe { CStr::from_ptr(tool_name) }.to_str()) {
+            Some(Ok(name)) => name.to_string(),
+            _ => return to_c_string(invalid_params_json("Invalid tool name")).into_raw(),
+        };
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let client = match get_client(&connection_id) {
+            Some(c) => c,
+            None => return to_c_string(not_connected_error(&connection_id)).into_raw(),
+        };
+
+        let result = client.runtime.block_on(run_request(&connection_id, async {
+            let response = with_auto_reconnect(&client, false, |peer| {
+                let cache = &client.tools_cache;
+                let tool_name = &tool_name;
+                async move {
+                    let tool = cached_tools(cache, &peer).await?.iter().find(|tool| tool.name == *tool_name).cloned();
+                    if tool.is_some() {
+                        return Ok(tool);
+                    }
+                    // The tool may have been added since the list was cached, by a server that does not send list_changed
+                    *cache.lock_or_recover() = None;
+                    Ok(cached_tools(cache, &peer).await?.iter().find(|tool| tool.name == *tool_name).cloned())
+                }
+            }).await;
+
+            match response {
+                None => error_json_code(ERROR_NOT_CONNECTED, "Not connected to server"),
+                Some(Ok(Some(tool))) => {
+                    let mut schema = serde_json::json!({ "inputSchema": tool.input_schema });
+                    if let Some(output_schema) = tool.output_schema {
+                        schema["outputSchema"] = serde_json::Value::Object((*output_schema).clone());
+                    }
+                    schema.to_string()
+                }
+                Some(Ok(None)) => invalid_params_json(&format!("Tool '{}' not found", tool_name)),
+                Some(Err(e)) => service_error_json("Failed to list tools", &e),
+            }
+        })).unwrap_or_else(|error| error);
+
+        to_c_string(result).into_raw()
+    })
+}
+
+/// Call a tool on the connected MCP server (returns raw JSON)
+/// tool_name: Name of the tool to call
+/// arguments_json: JSON string with tool arguments
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: JSON string with tool result (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_call_tool_json(
+    _client_ptr: *mut McpClient,
+    tool_name: *const c_char,
+    arguments_json: *const c_char,
+    connection_id: *const c_char,
+) -> *mut c_char {
+    call_tool_json(tool_name, arguments_json, connection_id, false, None)
+}
+
+/// Call a tool that is safe to run more than once, so that it is retried like other requests
+/// Takes the same arguments and returns the same JSON as mcp_call_tool_json
+#[no_mangle]
+pub extern "C" fn mcp_call_tool_idempotent_json(
+    tool_name: *const c_char,
+    arguments_json: *const c_char,
+    connection_id: *const c_char,
+) -> *mut c_char {
+    call_tool_json(tool_name, arguments_json, connection_id, true, None)
+}
+
+/// Call a tool like mcp_call_tool_json, sending extra HTTP headers with this call only
+/// headers_json: JSON object of headers, or array of [name, value] pairs to repeat one, as for mcp_connect; can be NULL
+/// Only streamable HTTP sends per-request headers; SSE, WebSocket and stdio connections ignore them
+/// Returns: JSON string with tool result (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_call_tool_with_headers_json(
+    tool_name: *const c_char,
+    arguments_json: *const c_char,
+    headers_json: *const c_char,
+    connection_id: *const c_char,
+) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        if headers_json.is_null() {
+            return call_tool_json(tool_name, arguments_json, connection_id, false, None);
+        }
+        let headers = match unsafe { CStr::from_ptr(headers_json) }.to_str() {
+            Ok(json) => parse_headers_json(json).and_then(|headers| RequestHeaders::new(&headers)),
+            Err(_) => Err(invalid_params_json("Invalid headers JSON")),
+        };
+        match headers {
+            Ok(headers) => call_tool_json(tool_name, arguments_json, connection_id, false, Some(headers)),
+            Err(error) => to_c_string(error).into_raw(),
+        }
+    })
+}
+
+/// Call a tool like mcp_call_tool_json with binary data base64 encoded into one of its arguments
+/// arguments_json: JSON object of the other arguments, can be NULL for none
+/// blob_param_name: Argument the encoded data is set to, replacing any value arguments_json gives it
+/// blob_data, blob_len: Bytes to encode, blob_data can be NULL when blob_len is 0
+/// encoding: "standard" (default when NULL), "url_safe" or "url_safe_no_pad"
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: JSON string with tool result (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_call_tool_with_blob(
+    tool_name: *const c_char,
+    arguments_json: *const c_char,
+    blob_param_name: *const c_char,
+    blob_data: *const u8,
+    blob_len: usize,
+    encoding: *const c_char,
+    connection_id: *const c_char,
+) -> *mut c_char {
+    use base64::Engine;
+
+    ffi_guard(panic_error_json, || {
+        let read = |value: *const c_char| (!value.is_null()).then(|| unsafe { CStr::from_ptr(value) }.to_str());
+        let blob_param_name = match read(blob_param_name) {
+            Some(Ok(name)) if !name.is_empty() => name,
+            _ => return to_c_string(invalid_params_json("Invalid blob parameter name")).into_raw(),
+        };
+        let engine = match read(encoding) {
+            None | Some(Ok("standard")) => base64::engine::general_purpose::STANDARD,
+            Some(Ok("url_safe")) => base64::engine::general_purpose::URL_SAFE,
+            Some(Ok("url_safe_no_pad")) => base64::engine::general_purpose::URL_SAFE_NO_PAD,
+            Some(encoding) => {
+                let message = format!("Unknown blob encoding '{}', expected standard, url_safe or url_safe_no_pad", encoding.unwrap_or_default());
+                return to_c_string(invalid_params_json(&message)).into_raw();
+            }
+        };
+        let mut arguments = match read(arguments_json) {
+            None => serde_json::Map::new(),
+            Some(Ok(json)) => match serde_json::from_str::<serde_json::Value>(json) {
+                Ok(serde_json::Value::Object(arguments)) => arguments,
+                Ok(_) => return to_c_string(invalid_params_json("Tool arguments must be a JSON object")).into_raw(),
+                Err(e) => return to_c_string(invalid_params_json(&format!("Invalid JSON: {}", e))).into_raw(),
+            },
+            Some(Err(_)) => return to_c_string(invalid_params_json("Invalid arguments JSON")).into_raw(),
+        };
+
+        let blob = if blob_data.is_null() || blob_len == 0 {
+            &[][..]
+        } else {
+            unsafe { std::slice::from_raw_parts(blob_data, blob_len) }
+        };
+        arguments.insert(blob_param_name.to_string(), serde_json::Value::String(engine.encode(blob)));
+
+        let arguments_json = to_c_string(serde_json::Value::Object(arguments).to_string());
+        call_tool_json(tool_name, arguments_json.as_ptr(), connection_id, false, None)
+    })
+}
+
+/// Shared implementation of mcp_call_tool_json, mcp_call_tool_idempotent_json, mcp_call_tool_with_headers_json
+/// and mcp_call_tool_with_blob
+/// idempotent: Whether the call may be retried after a transport error without duplicating side effects
+/// headers: HTTP headers sent with this call only, or None
+fn call_tool_json(
+    tool_name: *const c_char,
+    arguments_json: *const c_char,
+    connection_id: *const c_char,
+    idempotent: bool,
+    headers: Option<RequestHeaders>,
+) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        if tool_name.is_null() || arguments_json.is_null() {
+            let error = invalid_params_json("Invalid arguments");
+            return to_c_string(error).into_raw();
+        }
+
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let tool_name_str = unsafe {
+            match CStr::from_ptr(tool_name).to_str() {
+                Ok(s) => s.to_string(),
+                Err(_) => {
+                    let error = invalid_params_json("Invalid tool name");
+                    return to_c_string(error).into_raw();
+                }
+            }
+        };
+
+        let arguments_str = unsafe {
+            match CStr::from_ptr(arguments_json).to_str() {
+                Ok(s) => s,
+                Err(_) => {
+                    let error = invalid_params_json("Invalid arguments JSON");
+                    return to_c_string(error).into_raw();
+                }
+            }
+        };
+
+        let arguments: serde_json::Value = match serde_json::from_str(arguments_str) {
+            Ok(v) => with_tool_defaults(&tool_name_str, v),
+            Err(e) => {
+                let error = invalid_params_json(&format!("Invalid JSON: {}", e));
+                return to_c_string(error).into_raw();
+            }
+        };
+
+        if let Some(error) = tool_filter_error(&tool_name_str) {
+            return to_c_string(error.to_string()).into_raw();
+        }
+
+        // Get global client
+        let request = format!("CallToolRequest '{}'", tool_name_str);
+        let client = match get_client_or_wait(&connection_id, &request) {
+            Ok(c) => c,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let result = client.runtime.block_on(run_request(&connection_id, async {
+            if let Some(peer) = current_peer(&client).await {
+                if let Some(error) = validate_tool_arguments(&client.tools_cache, &peer, &tool_name_str, arguments.as_object()).await {
+                    return error.to_string();
+                }
+            }
+
+            let call_param = rmcp::model::CallToolRequestParam {
+                name: std::borrow::Cow::Owned(tool_name_str),
+                arguments: arguments.as_object().cloned(),
+            };
+
+            let (connection_id, request, headers) = (&connection_id, &request, &headers);
+            let (response, request_id) = with_sent_request_id(with_auto_reconnect(&client, !idempotent, |peer| {
+                let call_param = call_param.clone();
+                async move { traced(connection_id, request, call_tool_with_progress(&peer, call_param, None, headers.clone())).await }
+            })).await;
+
+            let mut json = match response {
+                None => return error_json_code(ERROR_NOT_CONNECTED, "Not connected to server"),
+                Some(Ok(mut result)) => match serde_json::to_value(&result) {
+                    Ok(mut value) => {
+                        let peer = current_peer(&client).await;
+                        if let Some(peer) = &peer {
+                            if resolve_resource_links(connection_id, peer, &mut result).await {
+                                value = serde_json::to_value(&result).unwrap_or(value);
+                            }
+                        }
+                        let mut json = serde_json::json!({ "result": value });
+                        if let Some(peer) = peer {
+                            insert_validation_error(&mut json, validate_tool_result(&client.tools_cache, &peer, &call_param.name, &result).await);
+                        }
+                        json
+                    }
+                    Err(e) => return error_json_code(rmcp::model::ErrorCode::INTERNAL_ERROR.0, &format!("Serialization failed: {}", e)),
+                },
+                Some(Err(e)) => serde_json::json!({ "error": service_error_value("Tool call failed", &e) }),
+            };
+            insert_request_id(&mut json, request_id);
+            json.to_string()
+        })).unwrap_or_else(|error| error);
+
+        to_c_string(result).into_raw()
+    })
+}
+
+/// Replace the resource_link items of a tool result with the contents read from their URIs,
+/// when mcp_set_resolve_resources is enabled
+/// Contents are embedded resources, which are never resolved again; a link that cannot be read
+/// or would pass MAX_RESOLVED_RESOURCES or MAX_RESOLVED_RESOURCE_BYTES is kept as it is
+/// Returns whether any link was replaced
+async fn resolve_resource_links(connection_id: &str, peer: &Peer<RoleClient>, result: &mut CallToolResult) -> bool {
+    use rmcp::model::ResourceContents;
+
+    if !RESOLVE_RESOURCES.load(Ordering::Relaxed) || !result.content.iter().any(|item| item.as_resource_link().is_some()) {
+        return false;
+    }
+
+    let (mut resolved, mut bytes) = (0, 0);
+    let mut content = Vec::with_capacity(result.content.len());
+    for item in std::mem::take(&mut result.content) {
+        let Some(uri) = item.as_resource_link().map(|link| link.uri.clone()) else {
+            content.push(item);
+            continue;
+        };
+        if resolved == MAX_RESOLVED_RESOURCES {
+            log_event(LOG_WARN, format_args!("Resource link '{}' not resolved, a result resolves at most {} links", uri, MAX_RESOLVED_RESOURCES));
+            content.push(item);
+            continue;
+        }
+
+        let request = format!("ReadResourceRequest '{}'", uri);
+        let read_param = rmcp::model::ReadResourceRequestParam { uri: uri.clone() };
+        let contents = match traced(connection_id, &request, peer.read_resource(read_param)).await {
+            Ok(read) => read.contents,
+            Err(e) => {
+                log_event(LOG_WARN, format_args!("Resource link '{}' not resolved: {}", uri, e));
+                content.push(item);
+                continue;
+            }
+        };
+        let size: usize = contents
+            .iter()
+            .map(|contents| match contents {
+                ResourceContents::TextResourceContents { text, .. } => text.len(),
+                ResourceContents::BlobResourceContents { blob, .. } => blob.len(),
+            })
+            .sum();
+        if bytes + size > MAX_RESOLVED_RESOURCE_BYTES {
+            log_event(LOG_WARN, format_args!("Resource link '{}' not resolved, its {} bytes would pass the {} bytes a result resolves", uri, size, MAX_RESOLVED_RESOURCE_BYTES));
+            content.push(item);
+            continue;
+        }
+
+        resolved += 1;
+        bytes += size;
+        content.extend(contents.into_iter().map(rmcp::model::Content::resource));
+    }
+    result.content = content;
+    resolved > 0
+}
+
+/// Call several tools on the connected MCP server at the same time (returns raw JSON)
+/// calls_json: JSON array of calls, e.g. '[{"name": "search", "arguments": {"q": "rome"}}]'
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: JSON array with one {"result": ...} or {"error": ...} per call, in input order (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_call_tools_batch_json(calls_json: *const c_char, connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        if calls_json.is_null() {
+            return to_c_string(invalid_params_json("Invalid arguments")).into_raw();
+        }
+
+        let calls = match unsafe { CStr::from_ptr(calls_json) }.to_str() {
+            Ok(s) => match serde_json::from_str::<serde_json::Value>(s) {
+                Ok(serde_json::Value::Array(calls)) => calls,
+                Ok(_) => {
+                    let error = invalid_params_json("Invalid calls JSON format. Expected: [{\"name\": \"tool\", \"arguments\": {}}]");
+                    return to_c_string(error).into_raw();
+                }
+                Err(e) => return to_c_string(invalid_params_json(&format!("Invalid JSON: {}", e))).into_raw(),
+            },
+            Err(_) => return to_c_string(invalid_params_json("Invalid calls JSON")).into_raw(),
+        };
+
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let client = match get_client_or_wait(&connection_id, "batch of CallToolRequest") {
+            Ok(c) => c,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        // Each call gets its own timeout so one slow or failing call only fails its own entry
+        let result = client.runtime.block_on(run_cancellable(&connection_id, async {
+            // List the tools once up front rather than from every call when validating arguments
+            if VALIDATE_ARGS.load(Ordering::Relaxed) {
+                if let Some(peer) = current_peer(&client).await {
+                    let _ = cached_tools(&client.tools_cache, &peer).await;
+                }
+            }
+
+            let calls = calls.iter().enumerate().map(|(index, call)| {
+                let (client, connection_id) = (&client, &connection_id);
+                async move {
+                    let call_param = match parse_batch_call(call) {
+                        Ok(param) => param,
+                        Err(message) => {
+                            let message = format!("Invalid call at index {}: {}", index, message);
+                            return serde_json::json!({ "error": { "code": rmcp::model::ErrorCode::INVALID_PARAMS.0, "message": message } });
+                        }
+                    };
+
+                    call_tool_entry(client, connection_id, call_param).await
+                }
+            });
+            serde_json::Value::Array(futures::future::join_all(calls).await).to_string()
+        })).unwrap_or_else(|error| error);
+
+        to_c_string(result).into_raw()
+    })
+}
+
+/// Call every tool whose name matches a pattern on the connected MCP server at the same time (returns raw JSON)
+/// pattern: Tool name pattern in which * stands for any run of characters, e.g. "report_*"
+/// args_json: JSON object of arguments passed to every matching tool, can be NULL for no arguments
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: JSON object mapping each matching tool name to its {"result": ...} or {"error": ...}, empty if no tool matches (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_call_tools_matching_json(pattern: *const c_char, args_json: *const c_char, connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        if pattern.is_null() {
+            return to_c_string(invalid_params_json("Invalid arguments")).into_raw();
+        }
+        let pattern = match unsafe { CStr::from_ptr(pattern) }.to_str() {
+            Ok(s) => s.to_string(),
+            Err(_) => return to_c_string(invalid_params_json("Invalid pattern")).into_raw(),
+        };
+
+        let arguments = if args_json.is_null() {
+            serde_json::Value::Null
+        } else {
+            match unsafe { CStr::from_ptr(args_json) }.to_str().ok().and_then(|s| serde_json::from_str::<serde_json::Value>(s).ok()) {
+                Some(arguments @ serde_json::Value::Object(_)) => arguments,
+                _ => return to_c_string(invalid_params_json("Invalid arguments JSON format. Expected a JSON object")).into_raw(),
+            }
+        };
+
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let client = match get_client_or_wait(&connection_id, "batch of CallToolRequest") {
+            Ok(c) => c,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let result = client.runtime.block_on(run_cancellable(&connection_id, async {
+            let tools = with_auto_reconnect(&client, false, |peer| {
+                let cache = &client.tools_cache;
+                async move { cached_tools(cache, &peer).await }
+            }).await;
+            let tools = match tools {
+                None => return error_json_code(ERROR_NOT_CONNECTED, "Not connected to server"),
+                Some(Ok(tools)) => tools,
+                Some(Err(e)) => return service_error_json("Failed to list tools", &e),
+            };
+
+            let names: Vec<String> = tools.iter()
+                .map(|tool| tool.name.to_string())
+                .filter(|name| wildcard_match(&pattern, name))
+                .collect();
+            let calls = names.iter().map(|name| {
+                let call_param = CallToolRequestParam {
+                    name: std::borrow::Cow::Owned(name.clone()),
+                    arguments: match with_tool_defaults(name, arguments.clone()) {
+                        serde_json::Value::Object(arguments) => Some(arguments),
+                        _ => None,
+                    },
+                };
+                call_tool_entry(&client, &connection_id, call_param)
+            });
+            let entries = futures::future::join_all(calls).await;
+            let results: serde_json::Map<String, serde_json::Value> = names.into_iter().zip(entries).collect();
+            serde_json::Value::Object(results).to_string()
+        })).unwrap_or_else(|error| error);
+
+        to_c_string(result).into_raw()
+    })
+}
+
+/// Call one tool of a batch, through the tool filter, argument validation, timeout and auto-reconnect
+/// Returns the {"result": ...} or {"error": ...} entry for the call
+async fn call_tool_entry(client: &McpClient, connection_id: &str, call_param: CallToolRequestParam) -> serde_json::Value {
+    if let Some(error) = tool_filter_error(&call_param.name) {
+        return error;
+    }
+
+    if let Some(peer) = current_peer(client).await {
+        if let Some(error) = validate_tool_arguments(&client.tools_cache, &peer, &call_param.name, call_param.arguments.as_ref()).await {
+            return error;
+        }
+    }
+
+    let request = format!("CallToolRequest '{}'", call_param.name);
+    let (response, request_id) = with_sent_request_id(with_request_timeout(connection_id, with_auto_reconnect(client, true, |peer| {
+        let (call_param, request) = (call_param.clone(), &request);
+        async move { traced(connection_id, request, call_tool_cancellable(&peer, call_param)).await }
+    }))).await;
+
+    let mut json = match response {
+        Err(error) => serde_json::from_str(&error).unwrap_or_else(|_| serde_json::json!({ "error": error })),
+        Ok(None) => serde_json::json!({ "error": { "code": ERROR_NOT_CONNECTED, "message": "Not connected to server" } }),
+        Ok(Some(Ok(result))) => {
+            let mut json = serde_json::json!({ "result": result });
+            if let Some(peer) = current_peer(client).await {
+                insert_validation_error(&mut json, validate_tool_result(&client.tools_cache, &peer, &call_param.name, &result).await);
+            }
+            json
+        }
+        Ok(Some(Err(e))) => serde_json::json!({ "error": service_error_value("Tool call failed", &e) }),
+    };
+    insert_request_id(&mut json, request_id);
+    json
+}
+
+/// Read one entry of a batch of tool calls
+/// Returns the call parameters, or a message describing why the entry is invalid
+fn parse_batch_call(call: &serde_json::Value) -> Result<CallToolRequestParam, String> {
+    let name = match call.get("name").and_then(|name| name.as_str()) {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => return Err("expected a non-empty \"name\" string".to_string()),
+    };
+
+    let arguments = match call.get("arguments") {
+        None | Some(serde_json::Value::Null) => serde_json::Value::Null,
+        Some(arguments @ serde_json::Value::Object(_)) => arguments.clone(),
+        Some(_) => return Err("\"arguments\" must be a JSON object".to_string()),
+    };
+    let arguments = match with_tool_defaults(&name, arguments) {
+        serde_json::Value::Object(arguments) => Some(arguments),
+        _ => None,
+    };
+
+    Ok(CallToolRequestParam {
+        name: std::borrow::Cow::Owned(name),
+        arguments,
+    })
+}
+
+/// List resources available on the connected MCP server (returns raw JSON)
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: JSON string with resources list (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_list_resources_json(_client_ptr: *mut McpClient, connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        // Get global client
+        let client = match get_unpaused_client(&connection_id, "ListResourcesRequest") {
+            Ok(c) => c,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let result = client.runtime.block_on(run_request(&connection_id, async {
+            let peer = match current_peer(&client).await {
+                Some(peer) => peer,
+                None => {
+                    return error_json_code(ERROR_NOT_CONNECTED, "Not connected to server");
+                }
+            };
+
+            if !supports_resources(&peer) {
+                return error_json_code(ERROR_UNSUPPORTED, "Server does not support resources");
+            }
+
+            match traced(&connection_id, "ListResourcesRequest", list_all_pages("resources", |cursor| async {
+                peer.list_resources(cursor).await.map(|result| (result.resources, result.next_cursor))
+            })).await {
+                Ok(resources) => {
+                    match serde_json::to_string(&serde_json::json!({
+                        "resources": resources
+                    })) {
+                        Ok(json) => json,
+                        Err(e) => error_json_code(rmcp::model::ErrorCode::INTERNAL_ERROR.0, &format!("Serialization failed: {}", e)),
+                    }
+                }
+                Err(e) => service_error_json("Failed to list resources", &e),
+            }
+        })).unwrap_or_else(|error| error);
+
+        to_c_string(result).into_raw()
+    })
+}
+
+/// List resource templates available on the connected MCP server (returns raw JSON)
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: JSON string with {"resourceTemplates": [...]} (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_list_resource_templates_json(connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let client = match get_unpaused_client(&connection_id, "ListResourceTemplatesRequest") {
+            Ok(c) => c,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let result = client.runtime.block_on(run_request(&connection_id, async {
+            let peer = match current_peer(&client).await {
+                Some(peer) => peer,
+                None => return error_json_code(ERROR_NOT_CONNECTED, "Not connected to server"),
+            };
+
+            if !supports_resources(&peer) {
+                return error_json_code(ERROR_UNSUPPORTED, "Server does not support resources");
+            }
+
+            match traced(&connection_id, "ListResourceTemplatesRequest", list_all_pages("resource templates", |cursor| async {
+                peer.list_resource_templates(cursor).await.map(|result| (result.resource_templates, result.next_cursor))
+            })).await {
+                Ok(templates) => match serde_json::to_string(&serde_json::json!({ "resourceTemplates": templates })) {
+                    Ok(json) => json,
+                    Err(e) => error_json_code(rmcp::model::ErrorCode::INTERNAL_ERROR.0, &format!("Serialization failed: {}", e)),
+                },
+                Err(e) => service_error_json("Failed to list resource templates", &e),
+            }
+        })).unwrap_or_else(|error| error);
+
+        to_c_string(result).into_raw()
+    })
+}
+
+/// Expand an RFC 6570 URI template, such as "file:///{path}" or "http://x/search{?q,lang}", with JSON variables
+/// Strings, numbers and booleans expand as values, arrays as lists and objects as key/value pairs
+/// Returns the expanded URI, or the error message naming a missing variable or the malformed part of the template
+fn expand_uri_template(template: &str, vars: &serde_json::Map<String, serde_json::Value>) -> Result<String, String> {
+    fn encode(value: &str, allow_reserved: bool, out: &mut String) {
+        let bytes = value.as_bytes();
+        for (i, &b) in bytes.iter().enumerate() {
+            let keep = b.is_ascii_alphanumeric()
+                || b"-._~".contains(&b)
+                || (allow_reserved && b":/?#[]@!$&'()*+,;=".contains(&b));
+            // With reserved expansion, percent-encoded triplets already in the value pass through
+            let triplet = allow_reserved && b == b'%'
+                && bytes.get(i + 1).is_some_and(u8::is_ascii_hexdigit)
+                && bytes.get(i + 2).is_some_and(u8::is_ascii_hexdigit);
+            if keep || triplet {
+                out.push(b as char);
+            } else {
+                out.push_str(&format!("%{:02X}", b));
+            }
+        }
+    }
+
+    fn scalar(value: &serde_json::Value) -> Option<String> {
+        match value {
+            serde_json::Value::String(s) => Some(s.clone()),
+            serde_json::Value::Number(n) => Some(n.to_string()),
+            serde_json::Value::Bool(b) => Some(b.to_string()),
+            _ => None,
+        }
+    }
+
+    let invalid = |reason: &str| format!("Invalid resource template '{}': {}", template, reason);
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or_else(|| invalid("unclosed '{'"))? + start;
+        let mut expression = &rest[start + 1..end];
+        rest = &rest[end + 1..];
+
+        let operator = expression.chars().next().filter(|c| "+#./;?&".contains(*c));
+        if operator.is_some() {
+            expression = &expression[1..];
+        }
+
+        // first, separator, named, value when empty, allow reserved
+        let (first, sep, named, if_empty, allow_reserved) = match operator {
+            Some('+') => ("", ",", false, "", true),
+            Some('#') => ("#", ",", false, "", true),
+            Some('.') => (".", ".", false, "", false),
+            Some('/') => ("/", "/", false, "", false),
+            Some(';') => (";", ";", true, "", false),
+            Some('?') => ("?", "&", true, "=", false),
+            Some('&') => ("&", "&", true, "=", false),
+            _ => ("", ",", false, "", false),
+        };
+
+        let mut parts = Vec::new();
+        for spec in expression.split(',') {
+            let (name, explode, prefix) = if let Some(name) = spec.strip_suffix('*') {
+                (name, true, None)
+            } else if let Some((name, length)) = spec.split_once(':') {
+                match length.parse::<usize>() {
+                    Ok(length) if length > 0 && length < 10000 => (name, false, Some(length)),
+                    _ => return Err(invalid(&format!("bad prefix length in '{}'", spec))),
+                }
+            } else {
+                (spec, false, None)
+            };
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '%') {
+                return Err(invalid(&format!("bad variable name '{}'", name)));
+            }
+
+            let value = match vars.get(name) {
+                Some(serde_json::Value::Null) | None => {
+                    return Err(format!("Missing variable '{}' for resource template '{}'", name, template));
+                }
+                Some(value) => value,
+            };
+
+            let mut part = String::new();
+            if let Some(value) = scalar(value) {
+                let value: String = match prefix {
+                    Some(length) => value.chars().take(length).collect(),
+                    None => value,
+                };
+                if named {
+                    part.push_str(name);
+                    if value.is_empty() {
+                        part.push_str(if_empty);
+                        parts.push(part);
+                        continue;
+                    }
+                    part.push('=');
+                }
+                encode(&value, allow_reserved, &mut part);
+                parts.push(part);
+                continue;
+            }
+
+            if prefix.is_some() {
+                return Err(invalid(&format!("prefix '{}' only applies to string values", spec)));
+            }
+            // Lists and objects of key/value pairs; empty ones are left out, as RFC 6570 does
+            let items: Vec<(Option<&str>, String)> = match value {
+                serde_json::Value::Array(values) => values.iter().map(|v| scalar(v).map(|v| (None, v))).collect::<Option<_>>(),
+                serde_json::Value::Object(map) => map.iter().map(|(k, v)| scalar(v).map(|v| (Some(k.as_str()), v))).collect::<Option<_>>(),
+                _ => None,
+            }
+            .ok_or_else(|| format!("Variable '{}' for resource template '{}' must hold strings, numbers or booleans", name, template))?;
+            if items.is_empty() {
+                continue;
+            }
+
+            if explode {
+                let mut exploded = Vec::new();
+                for (key, value) in items {
+                    let mut item = String::new();
+                    match key {
+                        Some(key) => {
+                            encode(key, allow_reserved, &mut item);
+                            item.push('=');
+                        }
+                        None if named => {
+                            item.push_str(name);
+                            if value.is_empty() {
+                                item.push_str(if_empty);
+                                exploded.push(item);
+                                continue;
+                            }
+                            item.push('=');
+                        }
+                        None => {}
+                    }
+                    encode(&value, allow_reserved, &mut item);
+                    exploded.push(item);
+                }
+                part.push_str(&exploded.join(sep));
+            } else {
+                if named {
+                    part.push_str(name);
+                    part.push('=');
+                }
+                let mut joined = Vec::new();
+                for (key, value) in items {
+                    let mut item = String::new();
+                    if let Some(key) = key {
+                        encode(key, allow_reserved, &mut item);
+                        item.push(',');
+                    }
+                    encode(&value, allow_reserved, &mut item);
+                    joined.push(item);
+                }
+                part.push_str(&joined.join(","));
+            }
+            parts.push(part);
+        }
 
-            let transport = match SseClientTransport::start_with_client(http_client, sse_config).await {
-                Ok(t) => t,
-                Err(e) => {
-                    let error = format!(r#"{{"error": "Failed to connect to MCP server: {}"}}"#, e);
-                    return (error, None);
+        if !parts.is_empty() {
+            out.push_str(first);
+            out.push_str(&parts.join(sep));
+        }
+    }
+    if rest.contains('}') {
+        return Err(invalid("unmatched '}'"));
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Expand a resource template with variables and read the resulting resource (returns raw JSON)
+/// template_uri: RFC 6570 URI template, e.g. one listed by mcp_list_resource_templates_json
+/// vars_json: JSON object with a value for every variable of the template
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: JSON string with the resource contents, as returned by mcp_read_resource_json (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_read_resource_template(
+    template_uri: *const c_char,
+    vars_json: *const c_char,
+    connection_id: *const c_char,
+) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        if template_uri.is_null() {
+            return to_c_string(invalid_params_json("Invalid arguments")).into_raw();
+        }
+        let template = match unsafe { CStr::from_ptr(template_uri) }.to_str() {
+            Ok(template) => template,
+            Err(_) => return to_c_string(invalid_params_json("Invalid resource template")).into_raw(),
+        };
+
+        let vars = if vars_json.is_null() {
+            serde_json::Map::new()
+        } else {
+            let parsed = unsafe { CStr::from_ptr(vars_json) }
+                .to_str()
+                .ok()
+                .and_then(|json| serde_json::from_str::<serde_json::Value>(json).ok());
+            match parsed {
+                Some(serde_json::Value::Object(vars)) => vars,
+                _ => return to_c_string(invalid_params_json("Template variables must be a JSON object")).into_raw(),
+            }
+        };
+
+        let result = expand_uri_template(template, &vars)
+            .map_err(|message| invalid_params_json(&message))
+            .and_then(|uri| read_resource_uri(uri, connection_id))
+            .and_then(|result| {
+                serde_json::to_string(&result)
+                    .map_err(|e| error_json_code(rmcp::model::ErrorCode::INTERNAL_ERROR.0, &format!("Serialization failed: {}", e)))
+            });
+
+        to_c_string(result.unwrap_or_else(|error| error)).into_raw()
+    })
+}
+
+/// Read a resource, for mcp_read_resource_json and mcp_read_resource_content
+/// Returns the resource contents, or the error JSON string if the arguments are invalid or the read failed
+fn read_resource(uri: *const c_char, connection_id: *const c_char) -> Result<rmcp::model::ReadResourceResult, String> {
+    if uri.is_null() {
+        return Err(invalid_params_json("Invalid arguments"));
+    }
+
+    let uri_str = unsafe { CStr::from_ptr(uri) }
+        .to_str()
+        .map_err(|_| invalid_params_json("Invalid resource URI"))?
+        .to_string();
+
+    read_resource_uri(uri_str, connection_id)
+}
+
+/// Read the resource at a URI, for read_resource and mcp_read_resource_template
+fn read_resource_uri(uri_str: String, connection_id: *const c_char) -> Result<rmcp::model::ReadResourceResult, String> {
+    let connection_id = resolve_connection_id(connection_id)?;
+
+    let request = format!("ReadResourceRequest '{}'", uri_str);
+    let client = get_unpaused_client(&connection_id, &request)?;
+
+    client.runtime.block_on(run_request(&connection_id, async {
+        let peer = current_peer(&client)
+            .await
+            .ok_or_else(|| error_json_code(ERROR_NOT_CONNECTED, "Not connected to server"))?;
+
+        if !supports_resources(&peer) {
+            return Err(error_json_code(ERROR_UNSUPPORTED, "Server does not support resources"));
+        }
+
+        let read_param = rmcp::model::ReadResourceRequestParam { uri: uri_str };
+
+        traced(&connection_id, &request, peer.read_resource(read_param))
+            .await
+            .map_err(|e| service_error_json("Failed to read resource", &e))
+    }))?
+}
+
+/// Read a resource from the connected MCP server (returns raw JSON)
+/// uri: URI of the resource to read
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: JSON string with the resource contents (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_read_resource_json(
+    _client_ptr: *mut McpClient,
+    uri: *const c_char,
+    connection_id: *const c_char,
+) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let result = read_resource(uri, connection_id).and_then(|result| {
+            serde_json::to_string(&result)
+                .map_err(|e| error_json_code(rmcp::model::ErrorCode::INTERNAL_ERROR.0, &format!("Serialization failed: {}", e)))
+        });
+
+        to_c_string(result.unwrap_or_else(|error| error)).into_raw()
+    })
+}
+
+// Most reads of a batch sent to a server at once, so a large batch does not flood it
+const BATCH_READ_CONCURRENCY: usize = 8;
+
+/// Read several resources from the connected MCP server at the same time (returns raw JSON)
+/// uris_json: JSON array of resource URIs, e.g. '["file:///a.txt", "file:///b.txt"]'
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: JSON array with one {"result": ...} or {"error": ...} per URI, in input order (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_read_resources_batch_json(uris_json: *const c_char, connection_id: *const c_char) -> *mut c_char {
+    use futures::StreamExt;
+
+    ffi_guard(panic_error_json, || {
+        if uris_json.is_null() {
+            return to_c_string(invalid_params_json("Invalid arguments")).into_raw();
+        }
+
+        let uris = match unsafe { CStr::from_ptr(uris_json) }.to_str() {
+            Ok(s) => match serde_json::from_str::<serde_json::Value>(s) {
+                Ok(serde_json::Value::Array(uris)) => uris,
+                Ok(_) => {
+                    let error = invalid_params_json("Invalid URIs JSON format. Expected: [\"file:///a.txt\", ...]");
+                    return to_c_string(error).into_raw();
                 }
-            };
+                Err(e) => return to_c_string(invalid_params_json(&format!("Invalid JSON: {}", e))).into_raw(),
+            },
+            Err(_) => return to_c_string(invalid_params_json("Invalid URIs JSON")).into_raw(),
+        };
 
-            // Create client info
-            let client_info = ClientInfo {
-                protocol_version: Default::default(),
-                capabilities: ClientCapabilities::default(),
-                client_info: Implementation {
-                    name: "sqlite-mcp".to_string(),
-                    title: None,
-                    version: env!("CARGO_PKG_VERSION").to_string(),
-                    website_url: None,
-                    icons: None,
-                },
-            };
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
 
-            // Create service from transport
-            let service = match client_info.serve(transport).await {
-                Ok(s) => s,
-                Err(e) => {
-                    let error = format!(r#"{{"error": "Failed to initialize service: {}"}}"#, e);
-                    return (error, None);
+        let client = match get_client_or_wait(&connection_id, "batch of ReadResourceRequest") {
+            Ok(c) => c,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        // Each read gets its own timeout so one slow or failing read only fails its own entry
+        let result = client.runtime.block_on(run_cancellable(&connection_id, async {
+            match current_peer(&client).await {
+                None => return error_json_code(ERROR_NOT_CONNECTED, "Not connected to server"),
+                Some(peer) if !supports_resources(&peer) => {
+                    return error_json_code(ERROR_UNSUPPORTED, "Server does not support resources");
                 }
-            };
+                Some(_) => {}
+            }
 
-            // Get server info
-            let info = service.peer_info();
-            let (server_name, server_version) = if let Some(info) = info {
-                (
-                    serde_json::to_string(&info.server_info.name).unwrap_or_else(|_| "\"unknown\"".to_string()),
-                    serde_json::to_string(&info.server_info.version).unwrap_or_else(|_| "\"0.0.0\"".to_string())
-                )
-            } else {
-                ("\"unknown\"".to_string(), "\"0.0.0\"".to_string())
-            };
+            let reads = uris.iter().enumerate().map(|(index, uri)| {
+                let (client, connection_id) = (&client, &connection_id);
+                async move {
+                    let uri = match uri.as_str() {
+                        Some(uri) if !uri.is_empty() => uri.to_string(),
+                        _ => {
+                            let message = format!("Invalid URI at index {}: expected a non-empty string", index);
+                            return serde_json::json!({ "error": { "code": rmcp::model::ErrorCode::INVALID_PARAMS.0, "message": message } });
+                        }
+                    };
 
-            let result_msg = format!(
-                r#"{{"status": "connected", "server": {}, "version": {}, "transport": "sse"}}"#,
-                server_name, server_version
-            );
+                    let request = format!("ReadResourceRequest '{}'", uri);
+                    let response = with_request_timeout(connection_id, with_auto_reconnect(client, false, |peer| {
+                        let (read_param, request) = (rmcp::model::ReadResourceRequestParam { uri: uri.clone() }, &request);
+                        async move { traced(connection_id, request, peer.read_resource(read_param)).await }
+                    })).await;
 
-            (result_msg, Some((service, server_url_str)))
-        })
-    } else {
-        // Use streamable HTTP transport (default) with optional custom headers
-        new_client.runtime.block_on(async {
-            // For Streamable HTTP, we need to extract the Authorization header specifically
-            // since it has a dedicated field, and we'll use a custom HTTP client for other headers
-            let auth_header_value = headers_map.as_ref().and_then(|m| m.get("Authorization")).map(|s| s.clone());
+                    match response {
+                        Err(error) => serde_json::from_str(&error).unwrap_or_else(|_| serde_json::json!({ "error": error })),
+                        Ok(None) => serde_json::json!({ "error": { "code": ERROR_NOT_CONNECTED, "message": "Not connected to server" } }),
+                        Ok(Some(Ok(result))) => serde_json::json!({ "result": result }),
+                        Ok(Some(Err(e))) => serde_json::json!({ "error": service_error_value("Failed to read resource", &e) }),
+                    }
+                }
+            });
+            let results: Vec<serde_json::Value> = futures::stream::iter(reads).buffered(BATCH_READ_CONCURRENCY).collect().await;
+            serde_json::Value::Array(results).to_string()
+        })).unwrap_or_else(|error| error);
 
-            // Build streamable HTTP transport config
-            let config = rmcp::transport::streamable_http_client::StreamableHttpClientTransportConfig {
-                uri: server_url_str.clone().into(),
-                auth_header: auth_header_value.map(|s| s.into()),
-                ..Default::default()
-            };
+        to_c_string(result).into_raw()
+    })
+}
+
+/// Read the first text or the first binary content of a resource, for SQL functions returning it as TEXT or BLOB
+/// uri: URI of the resource to read
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// binary: 1 to take the first blob content, decoded from base64, 0 to take the first text content,
+/// -1 to take the first content of either kind
+/// out_data, out_len: Receive the content bytes, to be freed with mcp_free_bytes
+/// Returns: NULL on success, JSON error string if the read failed or no content matches (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_read_resource_content(
+    uri: *const c_char,
+    connection_id: *const c_char,
+    binary: i32,
+    out_data: *mut *mut u8,
+    out_len: *mut usize,
+) -> *mut c_char {
+    use base64::Engine;
+    use rmcp::model::ResourceContents;
 
-            // If there are other custom headers besides Authorization, create a custom HTTP client
-            let transport = if let Some(ref headers_map) = headers_map {
-                let non_auth_headers: std::collections::HashMap<String, String> = headers_map.iter()
-                    .filter(|(k, _)| k.as_str() != "Authorization")
-                    .map(|(k, v)| (k.clone(), v.clone()))
+    ffi_guard(panic_error_json, || {
+        if out_data.is_null() || out_len.is_null() {
+            return to_c_string(invalid_params_json("Invalid arguments")).into_raw();
+        }
+
+        let content = read_resource(uri, connection_id).and_then(|result| {
+            let uri = unsafe { CStr::from_ptr(uri) }.to_string_lossy();
+            let mime_types = || {
+                let found: Vec<&str> = result.contents.iter()
+                    .map(|content| match content {
+                        ResourceContents::TextResourceContents { mime_type, .. } => mime_type.as_deref().unwrap_or("text"),
+                        ResourceContents::BlobResourceContents { mime_type, .. } => mime_type.as_deref().unwrap_or("blob"),
+                    })
                     .collect();
+                if found.is_empty() { "none".to_string() } else { found.join(", ") }
+            };
 
-                if !non_auth_headers.is_empty() {
-                    // Create HTTP client with custom headers
-                    use reqwest::header::{HeaderMap, HeaderValue, HeaderName};
-                    let mut headers = HeaderMap::new();
+            let content = result.contents.iter().find_map(|content| match content {
+                ResourceContents::TextResourceContents { text, .. } if binary <= 0 => Some(Ok(text.as_bytes().to_vec())),
+                ResourceContents::BlobResourceContents { blob, .. } if binary != 0 => {
+                    Some(base64::engine::general_purpose::STANDARD.decode(blob).map_err(|e| {
+                        error_json_code(rmcp::model::ErrorCode::INTERNAL_ERROR.0, &format!("Invalid base64 in resource '{}': {}", uri, e))
+                    }))
+                }
+                _ => None,
+            });
+            content.unwrap_or_else(|| Err(error_json_code(
+                rmcp::model::ErrorCode::INVALID_PARAMS.0,
+                &format!("Resource '{}' has no {}content (contents: {})", uri, match binary {
+                    0 => "text ",
+                    1 => "binary ",
+                    _ => "",
+                }, mime_types()),
+            )))
+        });
 
-                    for (key, value) in non_auth_headers {
-                        match (HeaderName::from_bytes(key.as_bytes()), HeaderValue::from_str(&value)) {
-                            (Ok(header_name), Ok(header_value)) => {
-                                headers.insert(header_name, header_value);
-                            }
-                            _ => {
-                                let error = format!(r#"{{"error": "Invalid header format: {}: {}"}}"#, key, value);
-                                return (error, None);
-                            }
-                        }
+        match content {
+            Ok(bytes) => {
+                let bytes = bytes.into_boxed_slice();
+                unsafe {
+                    *out_len = bytes.len();
+                    *out_data = Box::into_raw(bytes) as *mut u8;
+                }
+                ptr::null_mut()
+            }
+            Err(error) => to_c_string(error).into_raw(),
+        }
+    })
+}
+
+/// Free bytes returned by mcp_read_resource_content
+#[no_mangle]
+pub extern "C" fn mcp_free_bytes(data: *mut u8, len: usize) {
+    ffi_guard(|_| (), || {
+        if data.is_null() {
+            return;
+        }
+        unsafe {
+            let _ = Box::from_raw(ptr::slice_from_raw_parts_mut(data, len));
+        }
+    })
+}
+
+/// Subscribe to updates of a resource on the connected MCP server
+/// Updates are queued as notifications/resources/updated for mcp_poll_notifications_json
+/// uri: URI of the resource to watch
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns NULL on success, JSON error string on failure (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_subscribe_resource(uri: *const c_char, connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || resource_subscription(uri, connection_id, true))
+}
+
+/// Stop receiving updates of a resource subscribed with mcp_subscribe_resource
+/// uri: URI of the resource to stop watching
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns NULL on success, JSON error string on failure (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_unsubscribe_resource(uri: *const c_char, connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || resource_subscription(uri, connection_id, false))
+}
+
+/// Send a resources/subscribe or resources/unsubscribe request for mcp_subscribe_resource and mcp_unsubscribe_resource
+fn resource_subscription(uri: *const c_char, connection_id: *const c_char, subscribe: bool) -> *mut c_char {
+    if uri.is_null() {
+        return to_c_string(invalid_params_json("Invalid arguments")).into_raw();
+    }
+
+    let uri = match unsafe { CStr::from_ptr(uri) }.to_str() {
+        Ok(s) => s.to_string(),
+        Err(_) => return to_c_string(invalid_params_json("Invalid resource URI")).into_raw(),
+    };
+
+    let connection_id = match resolve_connection_id(connection_id) {
+        Ok(id) => id,
+        Err(error) => return to_c_string(error).into_raw(),
+    };
+
+    let client = match get_client(&connection_id) {
+        Some(c) => c,
+        None => return to_c_string(not_connected_error(&connection_id)).into_raw(),
+    };
+
+    let result = client.runtime.block_on(run_request(&connection_id, async {
+        let peer = match current_peer(&client).await {
+            Some(peer) => peer,
+            None => return Some(error_json_code(ERROR_NOT_CONNECTED, "Not connected to server")),
+        };
+
+        if !supports_resource_subscriptions(&peer) {
+            return Some(error_json_code(ERROR_UNSUPPORTED, "Server does not support resource subscriptions"));
+        }
+
+        let response = if subscribe {
+            let request = format!("SubscribeRequest '{}'", uri);
+            traced(&connection_id, &request, peer.subscribe(rmcp::model::SubscribeRequestParam { uri: uri.clone() })).await
+        } else {
+            let request = format!("UnsubscribeRequest '{}'", uri);
+            traced(&connection_id, &request, peer.unsubscribe(rmcp::model::UnsubscribeRequestParam { uri: uri.clone() })).await
+        };
+
+        match response {
+            Ok(()) => {
+                let mut subscriptions = client.subscriptions.lock_or_recover();
+                if subscribe {
+                    subscriptions.insert(uri);
+                } else {
+                    subscriptions.remove(&uri);
+                }
+                None
+            }
+            Err(e) if subscribe => Some(service_error_json("Failed to subscribe", &e)),
+            Err(e) => Some(service_error_json("Failed to unsubscribe", &e)),
+        }
+    })).unwrap_or_else(Some);
+
+    match result {
+        Some(error) => to_c_string(error).into_raw(),
+        None => ptr::null_mut(),
+    }
+}
+
+/// List prompts available on the connected MCP server (returns raw JSON)
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: JSON string with prompts list (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_list_prompts_json(_client_ptr: *mut McpClient, connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        // Get global client
+        let client = match get_unpaused_client(&connection_id, "ListPromptsRequest") {
+            Ok(c) => c,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let result = client.runtime.block_on(run_request(&connection_id, async {
+            let peer = match current_peer(&client).await {
+                Some(peer) => peer,
+                None => {
+                    return error_json_code(ERROR_NOT_CONNECTED, "Not connected to server");
+                }
+            };
+
+            if !supports_prompts(&peer) {
+                return error_json_code(ERROR_UNSUPPORTED, "Server does not support prompts");
+            }
+
+            match traced(&connection_id, "ListPromptsRequest", list_all_pages("prompts", |cursor| async {
+                peer.list_prompts(cursor).await.map(|result| (result.prompts, result.next_cursor))
+            })).await {
+                Ok(prompts) => {
+                    match serde_json::to_string(&serde_json::json!({
+                        "prompts": prompts
+                    })) {
+                        Ok(json) => json,
+                        Err(e) => error_json_code(rmcp::model::ErrorCode::INTERNAL_ERROR.0, &format!("Serialization failed: {}", e)),
                     }
+                }
+                Err(e) => service_error_json("Failed to list prompts", &e),
+            }
+        })).unwrap_or_else(|error| error);
+
+        to_c_string(result).into_raw()
+    })
+}
+
+/// Get a prompt from the connected MCP server rendered with the given arguments (returns raw JSON)
+/// name: Name of the prompt
+/// arguments_json: Optional JSON object with prompt arguments (e.g., '{"language": "rust"}'), can be NULL
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: JSON string with the prompt description and messages (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_get_prompt_json(
+    _client_ptr: *mut McpClient,
+    name: *const c_char,
+    arguments_json: *const c_char,
+    connection_id: *const c_char,
+) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        if name.is_null() {
+            let error = invalid_params_json("Invalid arguments");
+            return to_c_string(error).into_raw();
+        }
+
+        let name_str = unsafe {
+            match CStr::from_ptr(name).to_str() {
+                Ok(s) => s.to_string(),
+                Err(_) => {
+                    let error = invalid_params_json("Invalid prompt name");
+                    return to_c_string(error).into_raw();
+                }
+            }
+        };
 
-                    let http_client = match reqwest::Client::builder()
-                        .default_headers(headers)
-                        .build() {
-                        Ok(c) => c,
+        // Parse optional arguments_json (can be NULL or a JSON object)
+        let arguments = if arguments_json.is_null() {
+            None
+        } else {
+            unsafe {
+                match CStr::from_ptr(arguments_json).to_str() {
+                    Ok(json_str) => match serde_json::from_str::<serde_json::Value>(json_str) {
+                        Ok(serde_json::Value::Object(map)) => Some(map),
+                        Ok(_) => {
+                            let error = invalid_params_json("Invalid arguments JSON format. Expected: {\"name\": \"value\"}");
+                            return to_c_string(error).into_raw();
+                        }
                         Err(e) => {
-                            let error = format!(r#"{{"error": "Failed to create HTTP client: {}"}}"#, e);
-                            return (error, None);
+                            let error = invalid_params_json(&format!("Invalid JSON: {}", e));
+                            return to_c_string(error).into_raw();
                         }
-                    };
+                    },
+                    Err(_) => {
+                        let error = invalid_params_json("Invalid arguments JSON");
+                        return to_c_string(error).into_raw();
+                    }
+                }
+            }
+        };
 
-                    StreamableHttpClientTransport::with_client(http_client, config)
-                } else {
-                    StreamableHttpClientTransport::from_config(config)
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        // Get global client
+        let client = match get_client(&connection_id) {
+            Some(c) => c,
+            None => {
+                let error = not_connected_error(&connection_id);
+                return to_c_string(error).into_raw();
+            }
+        };
+
+        let result = client.runtime.block_on(run_request(&connection_id, async {
+            let peer = match current_peer(&client).await {
+                Some(peer) => peer,
+                None => {
+                    return error_json_code(ERROR_NOT_CONNECTED, "Not connected to server");
                 }
-            } else {
-                // No custom headers
-                StreamableHttpClientTransport::from_config(config)
             };
 
-            // Create client info
-            let client_info = ClientInfo {
-                protocol_version: Default::default(),
-                capabilities: ClientCapabilities::default(),
-                client_info: Implementation {
-                    name: "sqlite-mcp".to_string(),
-                    title: None,
-                    version: env!("CARGO_PKG_VERSION").to_string(),
-                    website_url: None,
-                    icons: None,
-                },
-            };
+            if !supports_prompts(&peer) {
+                return error_json_code(ERROR_UNSUPPORTED, "Server does not support prompts");
+            }
+
+            let request = format!("GetPromptRequest '{}'", name_str);
+            let prompt_param = rmcp::model::GetPromptRequestParam {
+                name: name_str,
+                arguments,
+            };
+
+            match traced(&connection_id, &request, peer.get_prompt(prompt_param)).await {
+                Ok(result) => {
+                    match serde_json::to_string(&result) {
+                        Ok(json) => json,
+                        Err(e) => error_json_code(rmcp::model::ErrorCode::INTERNAL_ERROR.0, &format!("Serialization failed: {}", e)),
+                    }
+                }
+                // Pass the server's message through unchanged so rejected argument names are visible
+                Err(e) => service_error_json("Failed to get prompt", &e),
+            }
+        })).unwrap_or_else(|error| error);
+
+        to_c_string(result).into_raw()
+    })
+}
+
+/// Send a JSON-RPC request for a method that has no dedicated function (returns raw JSON)
+/// method: JSON-RPC method name, e.g. "resources/templates/list"
+/// params_json: JSON object with the request params, can be NULL to send none
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: JSON string with {"result": ...} or {"error": {"code", "message", "data"}} as answered by the server (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_raw_request_json(method: *const c_char, params_json: *const c_char, connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let method = match (!method.is_null()).then(|| unsafe { CStr::from_ptr(method) }.to_str()) {
+            Some(Ok(method)) => method.to_string(),
+            _ => return to_c_string(invalid_params_json("Invalid arguments")).into_raw(),
+        };
+
+        let params = if params_json.is_null() {
+            None
+        } else {
+            match unsafe { CStr::from_ptr(params_json) }.to_str().ok().and_then(|s| serde_json::from_str::<serde_json::Value>(s).ok()) {
+                Some(params @ serde_json::Value::Object(_)) => Some(params),
+                _ => return to_c_string(invalid_params_json("Invalid params JSON format. Expected a JSON object")).into_raw(),
+            }
+        };
+
+        // The session is initialized once when connecting; initializing it again would desync the peer
+        if method == "initialize" {
+            return to_c_string(invalid_params_json("The initialize request is sent by mcp_connect")).into_raw();
+        }
+
+        // Tool calls sent as raw requests go through the tool filter like every other tool call
+        if method == "tools/call" {
+            let name = params.as_ref().and_then(|params| params.get("name")).and_then(|name| name.as_str()).unwrap_or_default();
+            if let Some(error) = tool_filter_error(name) {
+                return to_c_string(error.to_string()).into_raw();
+            }
+        }
+
+        // The SDK only sends requests it has a model for, so the method must be one the MCP schema
+        // of this build knows about; params are passed through and checked when they are parsed
+        let mut message = serde_json::json!({ "method": method });
+        if let Some(params) = params {
+            message["params"] = params;
+        }
+        let request: ClientRequest = match serde_json::from_value(message) {
+            Ok(request) => request,
+            Err(_) => {
+                let error = invalid_params_json(&format!("Method '{}' is not supported by the MCP SDK in this build, or its params are invalid", method));
+                return to_c_string(error).into_raw();
+            }
+        };
+
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let kind = format!("request '{}'", method);
+        let client = match get_unpaused_client(&connection_id, &kind) {
+            Ok(c) => c,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let result = client.runtime.block_on(run_request(&connection_id, async {
+            let peer = match current_peer(&client).await {
+                Some(peer) => peer,
+                None => return error_json_code(ERROR_NOT_CONNECTED, "Not connected to server"),
+            };
+
+            match traced(&connection_id, &kind, peer.send_request(request)).await {
+                Ok(result) => serde_json::json!({ "result": result }).to_string(),
+                Err(rmcp::ServiceError::McpError(e)) => serde_json::json!({ "error": e }).to_string(),
+                Err(e) => service_error_json("Request failed", &e),
+            }
+        })).unwrap_or_else(|error| error);
+
+        to_c_string(result).into_raw()
+    })
+}
+
+/// Ask the connected MCP server to complete a prompt or resource template argument (returns raw JSON)
+/// ref_json: Reference, e.g. '{"type": "ref/prompt", "name": "review"}' or '{"type": "ref/resource", "uri": "file:///{path}"}'
+/// argument_json: Argument being completed and its partial value, e.g. '{"name": "language", "value": "py"}'
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: JSON string with values, total, hasMore and a supported flag (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_complete_json(ref_json: *const c_char, argument_json: *const c_char, connection_id: *const c_char) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        if ref_json.is_null() || argument_json.is_null() {
+            return to_c_string(invalid_params_json("Invalid arguments")).into_raw();
+        }
+
+        let reference: Reference = match unsafe { CStr::from_ptr(ref_json) }.to_str().ok().and_then(|s| serde_json::from_str(s).ok()) {
+            Some(reference) => reference,
+            None => {
+                let error = invalid_params_json("Invalid reference JSON format. Expected: {\"type\": \"ref/prompt\", \"name\": \"...\"} or {\"type\": \"ref/resource\", \"uri\": \"...\"}");
+                return to_c_string(error).into_raw();
+            }
+        };
+
+        let argument: ArgumentInfo = match unsafe { CStr::from_ptr(argument_json) }.to_str().ok().and_then(|s| serde_json::from_str(s).ok()) {
+            Some(argument) => argument,
+            None => {
+                let error = invalid_params_json("Invalid argument JSON format. Expected: {\"name\": \"...\", \"value\": \"...\"}");
+                return to_c_string(error).into_raw();
+            }
+        };
+
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(error) => return to_c_string(error).into_raw(),
+        };
+
+        let client = match get_client(&connection_id) {
+            Some(c) => c,
+            None => return to_c_string(not_connected_error(&connection_id)).into_raw(),
+        };
+
+        // Many servers do not implement completion, so that is reported as an empty, unsupported result
+        let unsupported = serde_json::json!({ "values": [], "supported": false }).to_string();
+
+        let result = client.runtime.block_on(run_request(&connection_id, async {
+            let peer = match current_peer(&client).await {
+                Some(peer) => peer,
+                None => return error_json_code(ERROR_NOT_CONNECTED, "Not connected to server"),
+            };
+
+            if !supports_completions(&peer) {
+                return unsupported;
+            }
+
+            let request = match &reference {
+                Reference::Prompt(prompt) => format!("CompleteRequest '{}'", prompt.name),
+                Reference::Resource(resource) => format!("CompleteRequest '{}'", resource.uri),
+            };
+            let complete_param = CompleteRequestParam {
+                r#ref: reference,
+                argument,
+                context: None,
+            };
+
+            match traced(&connection_id, &request, peer.complete(complete_param)).await {
+                Ok(result) => {
+                    let mut completion = serde_json::to_value(&result.completion).unwrap_or_default();
+                    if let Some(completion) = completion.as_object_mut() {
+                        completion.insert("supported".to_string(), serde_json::Value::Bool(true));
+                    }
+                    completion.to_string()
+                }
+                Err(rmcp::ServiceError::McpError(e)) if e.code == rmcp::model::ErrorCode::METHOD_NOT_FOUND => unsupported,
+                Err(e) => service_error_json("Failed to complete argument", &e),
+            }
+        })).unwrap_or_else(|error| error);
+
+        to_c_string(result).into_raw()
+    })
+}
+
+/// Check whether the connected server advertised the tools capability during initialization
+fn supports_tools(peer: &Peer<RoleClient>) -> bool {
+    peer
+        .peer_info()
+        .map(|info| info.capabilities.tools.is_some())
+        .unwrap_or(false)
+}
+
+/// Error for a tool request to a server without the tools capability, answered without sending it
+fn tools_unsupported() -> rmcp::ServiceError {
+    rmcp::ServiceError::McpError(McpError::new(rmcp::model::ErrorCode(ERROR_UNSUPPORTED), "Server does not support tools", None))
+}
+
+/// Check whether the connected server advertised the completions capability during initialization
+fn supports_completions(peer: &Peer<RoleClient>) -> bool {
+    peer
+        .peer_info()
+        .map(|info| info.capabilities.completions.is_some())
+        .unwrap_or(false)
+}
+
+/// Check whether the connected server advertised the prompts capability during initialization
+fn supports_prompts(peer: &Peer<RoleClient>) -> bool {
+    peer
+        .peer_info()
+        .map(|info| info.capabilities.prompts.is_some())
+        .unwrap_or(false)
+}
+
+/// Check whether the connected server advertised the resources capability during initialization
+fn supports_resources(peer: &Peer<RoleClient>) -> bool {
+    peer
+        .peer_info()
+        .map(|info| info.capabilities.resources.is_some())
+        .unwrap_or(false)
+}
+
+/// Check whether the connected server advertised the logging capability during initialization
+fn supports_logging(peer: &Peer<RoleClient>) -> bool {
+    peer
+        .peer_info()
+        .map(|info| info.capabilities.logging.is_some())
+        .unwrap_or(false)
+}
+
+/// Check whether the connected server advertised resources.subscribe during initialization
+fn supports_resource_subscriptions(peer: &Peer<RoleClient>) -> bool {
+    peer
+        .peer_info()
+        .and_then(|info| info.capabilities.resources.as_ref())
+        .and_then(|resources| resources.subscribe)
+        .unwrap_or(false)
+}
+
+// Most bytes of chunks a tool stream buffers; a call producing more waits until mcp_poll_stream_json takes some
+const TOOL_STREAM_BUFFER_BYTES: usize = 4 * 1024 * 1024;
 
-            // Create service from transport
-            let service = match client_info.serve(transport).await {
-                Ok(s) => s,
-                Err(e) => {
-                    let error = format!(r#"{{"error": "Failed to connect to MCP server: {}"}}"#, e);
-                    return (error, None);
-                }
-            };
+// Tool streams started with mcp_call_tool_stream, kept until their done chunk is polled or they are closed
+static TOOL_STREAMS: OnceLock<Mutex<HashMap<u64, Arc<ToolStream>>>> = OnceLock::new();
+static TOOL_STREAM_COUNTER: AtomicU64 = AtomicU64::new(0);
 
-            // Get server info
-            let info = service.peer_info();
-            let (server_name, server_version) = if let Some(info) = info {
-                (
-                    serde_json::to_string(&info.server_info.name).unwrap_or_else(|_| "\"unknown\"".to_string()),
-                    serde_json::to_string(&info.server_info.version).unwrap_or_else(|_| "\"0.0.0\"".to_string())
-                )
-            } else {
-                ("\"unknown\"".to_string(), "\"0.0.0\"".to_string())
-            };
+// Streams of the tool calls in flight, keyed by connection id and the progress token of the call
+type ToolStreamProgressMap = HashMap<(String, ProgressToken), Arc<ToolStream>>;
+static TOOL_STREAM_PROGRESS: OnceLock<Mutex<ToolStreamProgressMap>> = OnceLock::new();
 
-            let result_msg = format!(
-                r#"{{"status": "connected", "server": {}, "version": {}, "transport": "streamable-http"}}"#,
-                server_name, server_version
-            );
+/// Get the tool stream map, creating it on first use
+fn tool_streams() -> &'static Mutex<HashMap<u64, Arc<ToolStream>>> {
+    TOOL_STREAMS.get_or_init(|| Mutex::new(HashMap::new()))
+}
 
-            (result_msg, Some((service, server_url_str)))
-        })
-    };
+/// Get the map routing progress notifications to tool streams, creating it on first use
+fn tool_stream_progress() -> &'static Mutex<ToolStreamProgressMap> {
+    TOOL_STREAM_PROGRESS.get_or_init(|| Mutex::new(HashMap::new()))
+}
 
-    // Store service and URL if connection succeeded
-    if let Some((service, url)) = maybe_service {
-        new_client.runtime.block_on(async {
-            *new_client.service.lock().await = Some(service);
-        });
-        *new_client.server_url.lock().unwrap() = Some(url);
+/// Chunks of one streamed tool call waiting to be polled, and the task running the call
+/// The semaphore holds the free buffer space in bytes, so the call stops adding content while the buffer is full
+struct ToolStream {
+    connection_id: String,
+    chunks: Mutex<VecDeque<(serde_json::Value, usize)>>,
+    arrived: std::sync::Condvar,
+    space: tokio::sync::Semaphore,
+    task: Mutex<Option<tokio::task::AbortHandle>>,
+}
 
-        // Store the client globally
-        let global_client = GLOBAL_CLIENT.get_or_init(|| Mutex::new(None));
-        *global_client.lock().unwrap() = Some(new_client);
+impl ToolStream {
+    fn new(connection_id: &str) -> Self {
+        ToolStream {
+            connection_id: connection_id.to_string(),
+            chunks: Mutex::new(VecDeque::new()),
+            arrived: std::sync::Condvar::new(),
+            space: tokio::sync::Semaphore::new(TOOL_STREAM_BUFFER_BYTES),
+            task: Mutex::new(None),
+        }
     }
 
-    // Parse the JSON response using serde_json to check status
-    match serde_json::from_str::<serde_json::Value>(&result) {
-        Ok(json) => {
-            if let Some(status) = json.get("status").and_then(|v| v.as_str()) {
-                if status == "connected" {
-                    // Return NULL on successful connection
-                    ptr::null_mut()
-                } else {
-                    // Return error string (extracted from JSON)
-                    let error_msg = extract_error_message(&result);
-                    match CString::new(error_msg) {
-                        Ok(c_str) => c_str.into_raw(),
-                        Err(_) => ptr::null_mut(),
-                    }
-                }
-            } else {
-                // No status field found, extract error message
-                let error_msg = extract_error_message(&result);
-                match CString::new(error_msg) {
-                    Ok(c_str) => c_str.into_raw(),
-                    Err(_) => ptr::null_mut(),
-                }
-            }
+    /// Stop the call adding chunks to the stream, if it is still running
+    fn stop(&self) {
+        if let Some(task) = self.task.lock_or_recover().take() {
+            task.abort();
         }
-        Err(_) => {
-            // Invalid JSON, return as error string
-            let error_msg = extract_error_message(&result);
-            match CString::new(error_msg) {
-                Ok(c_str) => c_str.into_raw(),
-                Err(_) => ptr::null_mut(),
-            }
+    }
+
+    /// Buffer space a chunk takes, capped so a chunk larger than the buffer is let through once the buffer is empty
+    fn chunk_size(chunk: &serde_json::Value) -> usize {
+        chunk.to_string().len().clamp(1, TOOL_STREAM_BUFFER_BYTES)
+    }
+
+    /// Add a chunk, waiting until the buffer has room for it
+    async fn push(&self, chunk: serde_json::Value) {
+        let size = Self::chunk_size(&chunk);
+        if let Ok(permit) = self.space.acquire_many(size as u32).await {
+            permit.forget();
         }
+        self.enqueue(chunk, size);
     }
-}
 
-/// Disconnect from MCP server and reset global client state
-/// Returns NULL on success
-#[no_mangle]
-pub extern "C" fn mcp_disconnect() -> *mut c_char {
-    let global_client = GLOBAL_CLIENT.get_or_init(|| Mutex::new(None));
-    *global_client.lock().unwrap() = None;
-    
-    // Also clear any active stream channels
-    {
-        let runtime = tokio::runtime::Runtime::new().unwrap();
-        runtime.block_on(async {
-            let mut channels = STREAM_CHANNELS.lock().await;
-            channels.clear();
-        });
+    /// Add a chunk if the buffer has room for it, dropping it otherwise
+    fn try_push(&self, chunk: serde_json::Value) {
+        let size = Self::chunk_size(&chunk);
+        if let Ok(permit) = self.space.try_acquire_many(size as u32) {
+            permit.forget();
+            self.enqueue(chunk, size);
+        }
     }
-    
-    // Reset stream counter
-    *STREAM_COUNTER.lock().unwrap() = 0;
-    
-    ptr::null_mut()
-}
 
-/// List tools available on the connected MCP server (returns raw JSON)
-/// Returns: JSON string with tools list (must be freed with mcp_free_string)
-#[no_mangle]
-pub extern "C" fn mcp_list_tools_json(_client_ptr: *mut McpClient) -> *mut c_char {
-    // Get global client
-    let global_client_guard = GLOBAL_CLIENT.get()
-        .and_then(|c| Some(c.lock().unwrap()));
-    let client = match global_client_guard.as_ref().and_then(|g| g.as_ref()) {
-        Some(c) => c,
-        None => {
-            let error = r#"{"error": "Not connected. Call mcp_connect() first"}"#;
-            return CString::new(error).unwrap_or_default().into_raw();
+    /// End the stream with an error chunk, if any, and the done chunk, without waiting for buffer space
+    fn finish(&self, error: Option<serde_json::Value>, is_error: bool) {
+        if let Some(error) = error {
+            self.enqueue(serde_json::json!({ "type": "error", "error": error }), 0);
         }
-    };
+        self.enqueue(serde_json::json!({ "type": "done", "is_error": is_error }), 0);
+    }
 
-    let result = client.runtime.block_on(async {
-        let service_guard = client.service.lock().await;
-        let service = match service_guard.as_ref() {
-            Some(s) => s,
-            None => {
-                return r#"{"error": "Not connected to server"}"#.to_string();
-            }
-        };
+    fn enqueue(&self, chunk: serde_json::Value, size: usize) {
+        self.chunks.lock_or_recover().push_back((chunk, size));
+        self.arrived.notify_all();
+    }
 
-        match service.list_tools(Default::default()).await {
-            Ok(tools_response) => {
-                let tools_json: Vec<serde_json::Value> = tools_response
-                    .tools
-                    .iter()
-                    .map(|tool| {
-                        serde_json::json!({
-                            "name": tool.name,
-                            "description": tool.description,
-                            "inputSchema": tool.input_schema
-                        })
-                    })
-                    .collect();
+    /// Take every buffered chunk, waiting up to wait_ms for one to arrive if there are none
+    fn drain(&self, wait_ms: u32) -> Vec<serde_json::Value> {
+        let chunks = self.chunks.lock_or_recover();
+        let (mut chunks, _) = self.arrived
+            .wait_timeout_while(chunks, Duration::from_millis(wait_ms as u64), |chunks| chunks.is_empty())
+            .unwrap_or_else(|e| e.into_inner());
+        let drained: Vec<(serde_json::Value, usize)> = chunks.drain(..).collect();
+        drop(chunks);
 
-                match serde_json::to_string(&serde_json::json!({
-                    "tools": tools_json
-                })) {
-                    Ok(json) => json,
-                    Err(e) => format!(r#"{{"error": "Serialization failed: {}"}}"#, e),
-                }
-            }
-            Err(e) => format!(r#"{{"error": "Failed to list tools: {}"}}"#, e),
+        self.space.add_permits(drained.iter().map(|(_, size)| size).sum());
+        drained.into_iter().map(|(chunk, _)| chunk).collect()
+    }
+}
+
+/// Remove the tool streams of a connection, or every stream if connection_id is None, stopping their calls
+/// Returns: The number of streams removed
+fn close_tool_streams(connection_id: Option<&str>) -> usize {
+    let mut closed = Vec::new();
+    tool_streams().lock_or_recover().retain(|_, stream| {
+        let keep = connection_id.is_some_and(|id| stream.connection_id != id);
+        if !keep {
+            closed.push(stream.clone());
         }
+        keep
     });
+    for stream in &closed {
+        stream.stop();
+    }
+    closed.len()
+}
+
+/// Progress routing of one tool call to its stream, removed when the call completes or is dropped
+struct ToolStreamProgress {
+    key: (String, ProgressToken),
+}
 
-    match CString::new(result) {
-        Ok(c_str) => c_str.into_raw(),
-        Err(_) => ptr::null_mut(),
+impl ToolStreamProgress {
+    fn register(key: (String, ProgressToken), stream: Arc<ToolStream>) -> Self {
+        tool_stream_progress().lock_or_recover().insert(key.clone(), stream);
+        ToolStreamProgress { key }
     }
 }
 
-/// Call a tool on the connected MCP server (returns raw JSON)
-/// tool_name: Name of the tool to call
-/// arguments_json: JSON string with tool arguments
-/// Returns: JSON string with tool result (must be freed with mcp_free_string)
-#[no_mangle]
-pub extern "C" fn mcp_call_tool_json(
-    _client_ptr: *mut McpClient,
-    tool_name: *const c_char,
-    arguments_json: *const c_char,
-) -> *mut c_char {
-    if tool_name.is_null() || arguments_json.is_null() {
-        let error = r#"{"error": "Invalid arguments"}"#;
-        return CString::new(error).unwrap_or_default().into_raw();
+impl Drop for ToolStreamProgress {
+    fn drop(&mut self) {
+        tool_stream_progress().lock_or_recover().remove(&self.key);
     }
+}
 
-    let tool_name_str = unsafe {
-        match CStr::from_ptr(tool_name).to_str() {
-            Ok(s) => s.to_string(),
-            Err(_) => {
-                let error = r#"{"error": "Invalid tool name"}"#;
-                return CString::new(error).unwrap_or_default().into_raw();
-            }
-        }
-    };
+/// Ends a tool stream whose call is dropped before completing, which happens when its connection closes
+struct UnfinishedToolStream(Option<Arc<ToolStream>>);
 
-    let arguments_str = unsafe {
-        match CStr::from_ptr(arguments_json).to_str() {
-            Ok(s) => s,
-            Err(_) => {
-                let error = r#"{"error": "Invalid arguments JSON"}"#;
-                return CString::new(error).unwrap_or_default().into_raw();
-            }
+impl Drop for UnfinishedToolStream {
+    fn drop(&mut self) {
+        if let Some(stream) = self.0.take() {
+            let error = serde_json::json!({ "code": ERROR_TRANSPORT, "message": "Connection closed before the tool call completed" });
+            stream.finish(Some(error), true);
         }
-    };
+    }
+}
 
-    let arguments: serde_json::Value = match serde_json::from_str(arguments_str) {
-        Ok(v) => v,
-        Err(e) => {
-            let error = format!(r#"{{"error": "Invalid JSON: {}"}}"#, e);
-            return CString::new(error).unwrap_or_default().into_raw();
-        }
+/// Run a streamed tool call, adding its content chunks to the stream as the buffer has room and then the done chunk
+async fn run_tool_stream(
+    connection_id: String,
+    service: Arc<TokioMutex<Option<RunningClient>>>,
+    tools_cache: ToolListCache,
+    stream: Arc<ToolStream>,
+    call_param: CallToolRequestParam,
+) {
+    let mut unfinished = UnfinishedToolStream(Some(stream.clone()));
+    // Error JSON strings carry the error object the error chunk holds
+    let error_object = |error: String| {
+        serde_json::from_str::<serde_json::Value>(&error)
+            .ok()
+            .and_then(|json| json.get("error").cloned())
+            .unwrap_or_else(|| serde_json::json!({ "code": ERROR_TRANSPORT, "message": error }))
     };
 
-    // Get global client
-    let global_client_guard = GLOBAL_CLIENT.get()
-        .and_then(|c| Some(c.lock().unwrap()));
-    let client = match global_client_guard.as_ref().and_then(|g| g.as_ref()) {
-        Some(c) => c,
-        None => {
-            let error = r#"{"error": "Not connected. Call mcp_connect() first"}"#;
-            return CString::new(error).unwrap_or_default().into_raw();
-        }
+    // Held while the connection is paused; the wait blocks the thread, so it runs on a blocking one
+    let request = format!("CallToolRequest '{}'", call_param.name);
+    let held = {
+        let (connection_id, request) = (connection_id.clone(), request.clone());
+        tokio::task::spawn_blocking(move || wait_while_paused(&connection_id, &request)).await
     };
+    let paused = held.unwrap_or_else(|_| Err(error_json_code(ERROR_CANCELLED, "cancelled"))).err();
 
-    let result = client.runtime.block_on(async {
-        let service_guard = client.service.lock().await;
-        let service = match service_guard.as_ref() {
-            Some(s) => s,
+    let peer = service.lock().await.as_ref().map(|service| service.peer().clone());
+    let (error, is_error) = match (paused, peer) {
+        (Some(error), _) => (Some(error_object(error)), true),
+        (None, None) => (Some(error_object(error_json_code(ERROR_NOT_CONNECTED, "Not connected to server"))), true),
+        (None, Some(peer)) => match validate_tool_arguments(&tools_cache, &peer, &call_param.name, call_param.arguments.as_ref()).await {
+            Some(error) => (error.get("error").cloned(), true),
             None => {
-                return r#"{"error": "Not connected to server"}"#.to_string();
+                let call = call_tool_with_progress(&peer, call_param, Some((&connection_id, &stream)), None);
+                match run_request(&connection_id, traced(&connection_id, &request, call)).await {
+                    Ok(Ok(result)) => {
+                        let result_json = serde_json::to_value(&result).unwrap_or_default();
+                        for item in result_json.get("content").and_then(|v| v.as_array()).into_iter().flatten() {
+                            stream.push(serde_json::json!({ "type": "content", "content": item })).await;
+                        }
+                        (None, result.is_error.unwrap_or(false))
+                    }
+                    Ok(Err(e)) => (Some(service_error_value("Tool call failed", &e)), true),
+                    Err(error) => (Some(error_object(error)), true),
+                }
             }
+        },
+    };
+
+    unfinished.0 = None;
+    stream.finish(error, is_error);
+}
+
+/// Start a tool call whose progress and content are read in chunks with mcp_poll_stream_json
+/// tool_name: Name of the tool to call
+/// arguments_json: JSON object with the tool arguments
+/// connection_id: Connection to use, can be NULL to use the "default" connection
+/// Returns: Stream id, or 0 if the tool name or arguments are not valid; other errors are reported as stream chunks
+#[no_mangle]
+pub extern "C" fn mcp_call_tool_stream(tool_name: *const c_char, arguments_json: *const c_char, connection_id: *const c_char) -> u64 {
+    ffi_guard(|_| 0, || {
+        if tool_name.is_null() || arguments_json.is_null() {
+            return 0;
+        }
+        let (tool_name, arguments) = match unsafe { (CStr::from_ptr(tool_name).to_str(), CStr::from_ptr(arguments_json).to_str()) } {
+            (Ok(name), Ok(arguments)) => match serde_json::from_str::<serde_json::Value>(arguments) {
+                Ok(arguments @ serde_json::Value::Object(_)) => match with_tool_defaults(name, arguments) {
+                    serde_json::Value::Object(arguments) => (name.to_string(), arguments),
+                    _ => return 0,
+                },
+                _ => return 0,
+            },
+            _ => return 0,
         };
+        let connection_id = match resolve_connection_id(connection_id) {
+            Ok(id) => id,
+            Err(_) => return 0,
+        };
+
+        let stream = Arc::new(ToolStream::new(&connection_id));
+        let stream_id = TOOL_STREAM_COUNTER.fetch_add(1, Ordering::Relaxed) + 1;
+        tool_streams().lock_or_recover().insert(stream_id, stream.clone());
+
+        let not_started = tool_filter_error(&tool_name)
+            .or_else(|| get_client(&connection_id).is_none().then(|| serde_json::from_str(&not_connected_error(&connection_id)).unwrap_or_default()));
+        if let Some(error) = not_started {
+            stream.finish(error.get("error").cloned(), true);
+            return stream_id;
+        }
+
+        // The task holds the service and cache rather than the client, so closing the connection drops it
+        if let Some(client) = get_client(&connection_id) {
+            let call_param = CallToolRequestParam {
+                name: std::borrow::Cow::Owned(tool_name),
+                arguments: Some(arguments),
+            };
+            let task = run_tool_stream(connection_id, client.service.clone(), client.tools_cache.clone(), stream.clone(), call_param);
+            *stream.task.lock_or_recover() = Some(client.runtime.spawn(task).abort_handle());
+        }
+        stream_id
+    })
+}
 
-        let call_param = rmcp::model::CallToolRequestParam {
-            name: std::borrow::Cow::Owned(tool_name_str),
-            arguments: arguments.as_object().cloned(),
+/// Take the chunks a streamed tool call produced since the last poll (returns raw JSON)
+/// stream_id: Id returned by mcp_call_tool_stream
+/// wait_ms: Milliseconds to wait for a chunk when none is buffered, 0 to return at once
+/// Returns: JSON {"chunks": [...], "done": bool}; after done the stream is closed (must be freed with mcp_free_string)
+#[no_mangle]
+pub extern "C" fn mcp_poll_stream_json(stream_id: u64, wait_ms: u32) -> *mut c_char {
+    ffi_guard(panic_error_json, || {
+        let stream = match tool_streams().lock_or_recover().get(&stream_id).cloned() {
+            Some(stream) => stream,
+            None => return to_c_string(invalid_params_json(&format!("Unknown stream id {}", stream_id))).into_raw(),
         };
 
-        match service.call_tool(call_param).await {
-            Ok(result) => {
-                match serde_json::to_string(&serde_json::json!({
-                    "result": result
-                })) {
-                    Ok(json) => json,
-                    Err(e) => format!(r#"{{"error": "Serialization failed: {}"}}"#, e),
-                }
-            }
-            Err(e) => format!(r#"{{"error": "Tool call failed: {}"}}"#, e),
+        let chunks = stream.drain(wait_ms);
+        let done = chunks.iter().any(|chunk| chunk["type"] == "done");
+        if done {
+            tool_streams().lock_or_recover().remove(&stream_id);
         }
-    });
+        to_c_string(serde_json::json!({ "chunks": chunks, "done": done }).to_string()).into_raw()
+    })
+}
 
-    match CString::new(result) {
-        Ok(c_str) => c_str.into_raw(),
-        Err(_) => ptr::null_mut(),
-    }
+/// Close a tool stream before its done chunk is polled, stopping the call if it is still running
+/// stream_id: Id returned by mcp_call_tool_stream
+/// Returns: 1 if the stream was closed, 0 if there is no stream with that id
+#[no_mangle]
+pub extern "C" fn mcp_close_stream(stream_id: u64) -> i32 {
+    ffi_guard(|_| 0, || {
+        let Some(stream) = tool_streams().lock_or_recover().remove(&stream_id) else {
+            return 0;
+        };
+        stream.stop();
+        1
+    })
 }
 
 // Streaming API
 use std::sync::Arc;
-use std::collections::HashMap;
 use tokio::sync::Mutex as TokioMutex;
 
 lazy_static::lazy_static! {
@@ -781,13 +7190,13 @@ lazy_static::lazy_static! {
 // FFI-compatible StreamResult struct (must match C definition)
 #[repr(C)]
 pub struct StreamResult {
-    pub result_type: i32,  // 0=tool, 1=text, 2=error, 3=done
+    pub result_type: i32,  // 0=tool, 1=content, 2=error, 3=done
     pub data: *mut c_char,
 }
 
 // Stream type constants (must match C)
 const STREAM_TYPE_TOOL: i32 = 0;
-const STREAM_TYPE_TEXT: i32 = 1;
+const STREAM_TYPE_CONTENT: i32 = 1;
 const STREAM_TYPE_ERROR: i32 = 2;
 const STREAM_TYPE_DONE: i32 = 3;
 
@@ -795,7 +7204,7 @@ const STREAM_TYPE_DONE: i32 = 3;
 #[derive(Debug, Clone)]
 enum StreamChunk {
     Tool(serde_json::Value),
-    Text(String),
+    Content(serde_json::Value),
     Error(String),
     Done,
 }
@@ -804,204 +7213,224 @@ enum StreamChunk {
 /// Returns a stream ID that can be used to fetch results
 #[no_mangle]
 pub extern "C" fn mcp_list_tools_init() -> usize {
-    // Get next stream ID
-    let stream_id = {
-        let mut counter = STREAM_COUNTER.lock().unwrap();
-        *counter += 1;
-        *counter
-    };
+    ffi_guard(|_| 0, || {
+        // Get next stream ID
+        let stream_id = {
+            let mut counter = STREAM_COUNTER.lock_or_recover();
+            *counter += 1;
+            *counter
+        };
+
+        // Create unbounded channel for streaming
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
 
-    // Create unbounded channel for streaming
-    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
-
-    // Get the global client
-    let client_mutex = GLOBAL_CLIENT.get_or_init(|| Mutex::new(None));
-
-    // Spawn the async task
-    {
-        let client_opt = client_mutex.lock().unwrap();
-        if let Some(client) = client_opt.as_ref() {
-            // Clone the Arc to share the service across async boundaries
-            let service_arc = client.service.clone();
-
-            // Use the client's runtime to spawn the task
-            client.runtime.spawn(async move {
-                let service_guard = service_arc.lock().await;
-                if let Some(service) = service_guard.as_ref() {
-                    match service.list_tools(None).await {
-                        Ok(response) => {
-                            // Send each tool as a separate chunk
-                            for tool in response.tools {
-                                if let Ok(tool_json) = serde_json::to_value(&tool) {
-                                    let _ = tx.send(StreamChunk::Tool(tool_json));
+        // Streams run on the default connection
+        // Spawn the async task
+        {
+            if let Err(error) = wait_while_paused(DEFAULT_CONNECTION_ID, "ListToolsRequest") {
+                let _ = tx.send(StreamChunk::Error(error));
+                let _ = tx.send(StreamChunk::Done);
+            } else if let Some(client) = get_client(DEFAULT_CONNECTION_ID) {
+                // Clone the Arc to share the service across async boundaries
+                let service_arc = client.service.clone();
+
+                // Use the client's runtime to spawn the task
+                client.runtime.spawn(async move {
+                    let peer = service_arc.lock().await.as_ref().map(|service| service.peer().clone());
+                    if let Some(peer) = peer {
+                        match run_request(DEFAULT_CONNECTION_ID, traced(DEFAULT_CONNECTION_ID, "ListToolsRequest", list_all_tools(&peer))).await {
+                            Ok(Ok(tools)) => {
+                                // Send each tool as a separate chunk
+                                for tool in tools {
+                                    if let Ok(tool_json) = serde_json::to_value(&tool) {
+                                        let _ = tx.send(StreamChunk::Tool(tool_json));
+                                    }
                                 }
+                                let _ = tx.send(StreamChunk::Done);
+                            }
+                            Ok(Err(e)) => {
+                                let _ = tx.send(StreamChunk::Error(format!("Failed to list tools: {}", e)));
+                                let _ = tx.send(StreamChunk::Done);
+                            }
+                            Err(error) => {
+                                let _ = tx.send(StreamChunk::Error(error));
+                                let _ = tx.send(StreamChunk::Done);
                             }
-                            let _ = tx.send(StreamChunk::Done);
-                        }
-                        Err(e) => {
-                            let _ = tx.send(StreamChunk::Error(format!("Failed to list tools: {}", e)));
-                            let _ = tx.send(StreamChunk::Done);
                         }
+                    } else {
+                        // No service connected
+                        let _ = tx.send(StreamChunk::Error("Not connected. Call mcp_connect() first".to_string()));
+                        let _ = tx.send(StreamChunk::Done);
                     }
-                } else {
-                    // No service connected
-                    let _ = tx.send(StreamChunk::Error("Not connected. Call mcp_connect() first".to_string()));
-                    let _ = tx.send(StreamChunk::Done);
-                }
-            });
-        } else {
-            // No client initialized
-            let _ = tx.send(StreamChunk::Error("Client not initialized".to_string()));
-            let _ = tx.send(StreamChunk::Done);
+                });
+            } else {
+                // No client initialized
+                let _ = tx.send(StreamChunk::Error("Client not initialized".to_string()));
+                let _ = tx.send(StreamChunk::Done);
+            }
         }
-    } // Release the lock here
 
-    // Store the receiver in global storage (now safe to acquire lock again)
-    let client_opt = client_mutex.lock().unwrap();
-    if let Some(client) = client_opt.as_ref() {
-        client.runtime.block_on(async {
-            let mut channels = STREAM_CHANNELS.lock().await;
-            channels.insert(stream_id, rx);
-        });
-    }
+        // Store the receiver in global storage
+        if let Some(client) = get_client(DEFAULT_CONNECTION_ID) {
+            client.runtime.block_on(async {
+                let mut channels = STREAM_CHANNELS.lock().await;
+                channels.insert(stream_id, rx);
+            });
+        }
 
-    stream_id
+        stream_id
+    })
 }
 
 /// Initialize a stream for calling a tool and retrieving results
 /// Returns a stream ID that can be used with mcp_stream_next/wait/cleanup
 #[no_mangle]
 pub extern "C" fn mcp_call_tool_init(tool_name: *const c_char, arguments: *const c_char) -> usize {
-    let tool_name_str = unsafe {
-        if tool_name.is_null() {
-            return 0;
-        }
-        match CStr::from_ptr(tool_name).to_str() {
-            Ok(s) => s.to_string(),
-            Err(_) => return 0,
-        }
-    };
+    ffi_guard(|_| 0, || {
+        let tool_name_str = unsafe {
+            if tool_name.is_null() {
+                return 0;
+            }
+            match CStr::from_ptr(tool_name).to_str() {
+                Ok(s) => s.to_string(),
+                Err(_) => return 0,
+            }
+        };
 
-    let arguments_str = unsafe {
-        if arguments.is_null() {
-            return 0;
-        }
-        match CStr::from_ptr(arguments).to_str() {
-            Ok(s) => s.to_string(),
-            Err(_) => return 0,
-        }
-    };
+        let arguments_str = unsafe {
+            if arguments.is_null() {
+                return 0;
+            }
+            match CStr::from_ptr(arguments).to_str() {
+                Ok(s) => s.to_string(),
+                Err(_) => return 0,
+            }
+        };
 
-    // Generate unique stream ID
-    let stream_id = {
-        let mut counter = STREAM_COUNTER.lock().unwrap();
-        *counter += 1;
-        *counter
-    };
+        // Generate unique stream ID
+        let stream_id = {
+            let mut counter = STREAM_COUNTER.lock_or_recover();
+            *counter += 1;
+            *counter
+        };
 
-    // Create unbounded channel for streaming
-    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
-
-    // Get the global client
-    let client_mutex = GLOBAL_CLIENT.get_or_init(|| Mutex::new(None));
-
-    // Spawn the async task
-    {
-        let client_opt = client_mutex.lock().unwrap();
-        if let Some(client) = client_opt.as_ref() {
-            let service_arc = client.service.clone();
-
-            // Use the client's runtime to spawn the task
-            client.runtime.spawn(async move {
-                let service_guard = service_arc.lock().await;
-                if let Some(service) = service_guard.as_ref() {
-                    // Parse arguments
-                    let arguments_json: serde_json::Value = match serde_json::from_str(&arguments_str) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            let _ = tx.send(StreamChunk::Error(format!("Invalid JSON arguments: {}", e)));
+        // Create unbounded channel for streaming
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+
+        // Streams run on the default connection
+        // Spawn the async task
+        {
+            let request = format!("CallToolRequest '{}'", tool_name_str);
+            if let Err(error) = wait_while_paused(DEFAULT_CONNECTION_ID, &request) {
+                let _ = tx.send(StreamChunk::Error(error));
+                let _ = tx.send(StreamChunk::Done);
+            } else if let Some(client) = get_client(DEFAULT_CONNECTION_ID) {
+                let service_arc = client.service.clone();
+                let tools_cache = client.tools_cache.clone();
+
+                // Use the client's runtime to spawn the task
+                client.runtime.spawn(async move {
+                    let peer = service_arc.lock().await.as_ref().map(|service| service.peer().clone());
+                    if let Some(peer) = peer {
+                        // Parse arguments
+                        let arguments_json: serde_json::Value = match serde_json::from_str(&arguments_str) {
+                            Ok(v) => with_tool_defaults(&tool_name_str, v),
+                            Err(e) => {
+                                let _ = tx.send(StreamChunk::Error(format!("Invalid JSON arguments: {}", e)));
+                                let _ = tx.send(StreamChunk::Done);
+                                return;
+                            }
+                        };
+
+                        if let Some(error) = tool_filter_error(&tool_name_str) {
+                            let message = error_message(&error).unwrap_or_default().to_string();
+                            let _ = tx.send(StreamChunk::Error(message));
                             let _ = tx.send(StreamChunk::Done);
                             return;
                         }
-                    };
 
-                    // Create the call tool parameter
-                    let call_param = rmcp::model::CallToolRequestParam {
-                        name: std::borrow::Cow::Owned(tool_name_str),
-                        arguments: arguments_json.as_object().cloned(),
-                    };
+                        if let Some(error) = validate_tool_arguments(&tools_cache, &peer, &tool_name_str, arguments_json.as_object()).await {
+                            let message = error_message(&error).unwrap_or_default().to_string();
+                            let _ = tx.send(StreamChunk::Error(message));
+                            let _ = tx.send(StreamChunk::Done);
+                            return;
+                        }
+
+                        // Create the call tool parameter
+                        let call_param = rmcp::model::CallToolRequestParam {
+                            name: std::borrow::Cow::Owned(tool_name_str),
+                            arguments: arguments_json.as_object().cloned(),
+                        };
 
-                    // Call the tool
-                    match service.call_tool(call_param).await {
-                        Ok(result) => {
-                            // Serialize the result to JSON and extract text content
-                            if let Ok(result_json) = serde_json::to_value(&result) {
-                                if let Some(content_array) = result_json.get("content").and_then(|v| v.as_array()) {
-                                    for item in content_array {
-                                        if let Some(item_type) = item.get("type").and_then(|v| v.as_str()) {
-                                            if item_type == "text" {
-                                                if let Some(text) = item.get("text").and_then(|v| v.as_str()) {
-                                                    let _ = tx.send(StreamChunk::Text(text.to_string()));
-                                                }
-                                            }
+                        // Call the tool
+                        match run_request(DEFAULT_CONNECTION_ID, traced(DEFAULT_CONNECTION_ID, &request, call_tool_cancellable(&peer, call_param))).await {
+                            Ok(Ok(result)) => {
+                                // Serialize the result to JSON and send each content item as a row
+                                let is_error = result.is_error.unwrap_or(false);
+                                if let Ok(result_json) = serde_json::to_value(&result) {
+                                    if let Some(content_array) = result_json.get("content").and_then(|v| v.as_array()) {
+                                        for item in content_array {
+                                            let _ = tx.send(StreamChunk::Content(content_row(item, is_error)));
                                         }
                                     }
                                 }
+                                let _ = tx.send(StreamChunk::Done);
+                            }
+                            Ok(Err(e)) => {
+                                let _ = tx.send(StreamChunk::Error(format!("Failed to call tool: {}", e)));
+                                let _ = tx.send(StreamChunk::Done);
+                            }
+                            Err(error) => {
+                                let _ = tx.send(StreamChunk::Error(error));
+                                let _ = tx.send(StreamChunk::Done);
                             }
-                            let _ = tx.send(StreamChunk::Done);
-                        }
-                        Err(e) => {
-                            let _ = tx.send(StreamChunk::Error(format!("Failed to call tool: {}", e)));
-                            let _ = tx.send(StreamChunk::Done);
                         }
+                    } else {
+                        let _ = tx.send(StreamChunk::Error("Not connected. Call mcp_connect() first".to_string()));
+                        let _ = tx.send(StreamChunk::Done);
                     }
-                } else {
-                    let _ = tx.send(StreamChunk::Error("Not connected. Call mcp_connect() first".to_string()));
-                    let _ = tx.send(StreamChunk::Done);
-                }
-            });
-        } else {
-            let _ = tx.send(StreamChunk::Error("Client not initialized".to_string()));
-            let _ = tx.send(StreamChunk::Done);
+                });
+            } else {
+                let _ = tx.send(StreamChunk::Error("Client not initialized".to_string()));
+                let _ = tx.send(StreamChunk::Done);
+            }
         }
-    }
 
-    // Store the receiver
-    let client_opt = client_mutex.lock().unwrap();
-    if let Some(client) = client_opt.as_ref() {
-        client.runtime.block_on(async {
-            let mut channels = STREAM_CHANNELS.lock().await;
-            channels.insert(stream_id, rx);
-        });
-    }
+        // Store the receiver
+        if let Some(client) = get_client(DEFAULT_CONNECTION_ID) {
+            client.runtime.block_on(async {
+                let mut channels = STREAM_CHANNELS.lock().await;
+                channels.insert(stream_id, rx);
+            });
+        }
 
-    stream_id
+        stream_id
+    })
 }
 
 /// Try to get the next chunk from a stream (non-blocking)
 /// Returns NULL if no data is available
 #[no_mangle]
 pub extern "C" fn mcp_stream_next(stream_id: usize) -> *mut StreamResult {
-    let client_mutex = GLOBAL_CLIENT.get_or_init(|| Mutex::new(None));
-    let client_opt = client_mutex.lock().unwrap();
-
-    if let Some(client) = client_opt.as_ref() {
-        client.runtime.block_on(async {
-            let mut channels = STREAM_CHANNELS.lock().await;
-            if let Some(rx) = channels.get_mut(&stream_id) {
-                match rx.try_recv() {
-                    Ok(chunk) => {
-                        Box::into_raw(Box::new(chunk_to_stream_result(chunk)))
+    ffi_guard(|_| ptr::null_mut(), || {
+        if let Some(client) = get_client(DEFAULT_CONNECTION_ID) {
+            client.runtime.block_on(async {
+                let mut channels = STREAM_CHANNELS.lock().await;
+                if let Some(rx) = channels.get_mut(&stream_id) {
+                    match rx.try_recv() {
+                        Ok(chunk) => {
+                            Box::into_raw(Box::new(chunk_to_stream_result(chunk)))
+                        }
+                        Err(_) => ptr::null_mut(),
                     }
-                    Err(_) => ptr::null_mut(),
+                } else {
+                    ptr::null_mut()
                 }
-            } else {
-                ptr::null_mut()
-            }
-        })
-    } else {
-        ptr::null_mut()
-    }
+            })
+        } else {
+            ptr::null_mut()
+        }
+    })
 }
 
 /// Wait for the next chunk from a stream (blocking with timeout)
@@ -1009,55 +7438,55 @@ pub extern "C" fn mcp_stream_next(stream_id: usize) -> *mut StreamResult {
 /// Returns NULL if timeout occurs or stream is closed
 #[no_mangle]
 pub extern "C" fn mcp_stream_wait(stream_id: usize, timeout_ms: u64) -> *mut StreamResult {
-    let client_mutex = GLOBAL_CLIENT.get_or_init(|| Mutex::new(None));
-    let client_opt = client_mutex.lock().unwrap();
-
-    if let Some(client) = client_opt.as_ref() {
-        client.runtime.block_on(async {
-            let mut channels = STREAM_CHANNELS.lock().await;
-            if let Some(rx) = channels.get_mut(&stream_id) {
-                let timeout = tokio::time::Duration::from_millis(timeout_ms);
-                match tokio::time::timeout(timeout, rx.recv()).await {
-                    Ok(Some(chunk)) => {
-                        Box::into_raw(Box::new(chunk_to_stream_result(chunk)))
+    ffi_guard(|_| ptr::null_mut(), || {
+        if let Some(client) = get_client(DEFAULT_CONNECTION_ID) {
+            client.runtime.block_on(async {
+                let mut channels = STREAM_CHANNELS.lock().await;
+                if let Some(rx) = channels.get_mut(&stream_id) {
+                    let timeout = tokio::time::Duration::from_millis(timeout_ms);
+                    match tokio::time::timeout(timeout, rx.recv()).await {
+                        Ok(Some(chunk)) => {
+                            Box::into_raw(Box::new(chunk_to_stream_result(chunk)))
+                        }
+                        _ => ptr::null_mut(),
                     }
-                    _ => ptr::null_mut(),
+                } else {
+                    ptr::null_mut()
                 }
-            } else {
-                ptr::null_mut()
-            }
-        })
-    } else {
-        ptr::null_mut()
-    }
+            })
+        } else {
+            ptr::null_mut()
+        }
+    })
 }
 
 /// Clean up a stream and free its resources
 #[no_mangle]
 pub extern "C" fn mcp_stream_cleanup(stream_id: usize) {
-    let client_mutex = GLOBAL_CLIENT.get_or_init(|| Mutex::new(None));
-    let client_opt = client_mutex.lock().unwrap();
-
-    if let Some(client) = client_opt.as_ref() {
-        client.runtime.block_on(async {
-            let mut channels = STREAM_CHANNELS.lock().await;
-            channels.remove(&stream_id);
-        });
-    }
+    ffi_guard(|_| (), || {
+        if let Some(client) = get_client(DEFAULT_CONNECTION_ID) {
+            client.runtime.block_on(async {
+                let mut channels = STREAM_CHANNELS.lock().await;
+                channels.remove(&stream_id);
+            });
+        }
+    })
 }
 
 /// Free a StreamResult returned by mcp_stream_next or mcp_stream_wait
 #[no_mangle]
 pub extern "C" fn mcp_stream_free_result(result: *mut StreamResult) {
-    if result.is_null() {
-        return;
-    }
-    unsafe {
-        let result = Box::from_raw(result);
-        if !result.data.is_null() {
-            let _ = CString::from_raw(result.data);
+    ffi_guard(|_| (), || {
+        if result.is_null() {
+            return;
         }
-    }
+        unsafe {
+            let result = Box::from_raw(result);
+            if !result.data.is_null() {
+                let _ = CString::from_raw(result.data);
+            }
+        }
+    })
 }
 
 // Helper function to convert StreamChunk to StreamResult
@@ -1065,21 +7494,22 @@ fn chunk_to_stream_result(chunk: StreamChunk) -> StreamResult {
     match chunk {
         StreamChunk::Tool(tool_json) => {
             let json_str = serde_json::to_string(&tool_json).unwrap_or_else(|_| "{}".to_string());
-            let c_str = CString::new(json_str).unwrap_or_else(|_| CString::new("{}").unwrap());
+            let c_str = to_c_string(json_str);
             StreamResult {
                 result_type: STREAM_TYPE_TOOL,
                 data: c_str.into_raw(),
             }
         }
-        StreamChunk::Text(text) => {
-            let c_str = CString::new(text).unwrap_or_else(|_| CString::new("").unwrap());
+        StreamChunk::Content(row) => {
+            let json_str = serde_json::to_string(&row).unwrap_or_else(|_| "{}".to_string());
+            let c_str = to_c_string(json_str);
             StreamResult {
-                result_type: STREAM_TYPE_TEXT,
+                result_type: STREAM_TYPE_CONTENT,
                 data: c_str.into_raw(),
             }
         }
         StreamChunk::Error(error) => {
-            let c_str = CString::new(error).unwrap_or_else(|_| CString::new("Unknown error").unwrap());
+            let c_str = to_c_string(error);
             StreamResult {
                 result_type: STREAM_TYPE_ERROR,
                 data: c_str.into_raw(),
@@ -1094,3 +7524,20 @@ fn chunk_to_stream_result(chunk: StreamChunk) -> StreamResult {
     }
 }
 
+// Helper function to flatten a CallToolResult content item into a virtual table row
+// Text content maps to "text", any other content (image, audio, resource) to "data" as JSON
+fn content_row(item: &serde_json::Value, is_error: bool) -> serde_json::Value {
+    let item_type = item.get("type").and_then(|v| v.as_str()).unwrap_or("");
+    let (text, data) = if item_type == "text" {
+        (item.get("text").cloned().unwrap_or(serde_json::Value::Null), serde_json::Value::Null)
+    } else {
+        (serde_json::Value::Null, item.clone())
+    };
+
+    serde_json::json!({
+        "type": item_type,
+        "text": text,
+        "data": data,
+        "is_error": is_error
+    })
+}
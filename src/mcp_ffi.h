@@ -13,8 +13,9 @@ extern "C" {
 #endif
 
 #include <stdint.h>
+#include <stddef.h>
 
-/* Opaque handle for MCP client */
+/* Opaque MCP client handle, kept for compatibility: connections are identified by connection_id */
 typedef struct McpClient McpClient;
 
 /**
@@ -23,37 +24,672 @@ typedef struct McpClient McpClient;
  */
 int32_t mcp_init(void);
 
+/**
+ * Set the timeout applied to every MCP request, and to connecting unless mcp_set_connect_timeout_ms set one
+ * timeout_ms: Milliseconds to wait before giving up, 0 to wait forever (default)
+ */
+void mcp_set_timeout_ms(uint32_t timeout_ms);
+
+/**
+ * Set the request timeout of one connection, used instead of the global one from mcp_set_timeout_ms
+ * timeout_ms: Milliseconds to wait before giving up, 0 to wait forever, or -1 to use the global timeout again
+ * connection_id: Connection to set the timeout of, can be NULL for the "default" connection
+ * Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+ */
+char* mcp_set_connection_timeout_ms(int64_t timeout_ms, const char* connection_id);
+
+/**
+ * Set the timeout for opening a transport and completing the initialize handshake, including reconnects
+ * timeout_ms: Milliseconds to wait before giving up, 0 to use the request timeout (default)
+ */
+void mcp_set_connect_timeout_ms(uint32_t timeout_ms);
+
+/**
+ * Enable re-establishing dropped connections, retrying the failed request with exponential backoff
+ * max_retries: Reconnect attempts per request, 0 to disable (default)
+ * base_delay_ms: Delay before the first attempt, doubled on each following attempt
+ * retry_tool_calls: 1 to also retry tool calls, which may run a non-idempotent tool twice, 0 to only retry read-only requests
+ */
+void mcp_set_auto_reconnect(uint32_t max_retries, uint32_t base_delay_ms, int32_t retry_tool_calls);
+
+/**
+ * Retry requests that fail with a transient transport error, such as a reset connection, with exponential backoff
+ * max_attempts: Attempts per request including the first, 0 or 1 to disable (default)
+ * backoff_ms: Delay before the second attempt, doubled on each following attempt
+ * Tool calls are only retried when made with mcp_call_tool_idempotent_json
+ */
+void mcp_set_retry(uint32_t max_attempts, uint32_t backoff_ms);
+
+/**
+ * Retry connecting when the connect timeout expires before the initialize handshake completes, with exponential backoff
+ * max_attempts: Attempts per connect including the first, 0 or 1 to disable (default)
+ * backoff_ms: Delay before the second attempt, doubled on each following attempt
+ * Connects failing for other reasons, such as a refused connection, are not retried
+ */
+void mcp_set_initialize_retry(uint32_t max_attempts, uint32_t backoff_ms);
+
+/**
+ * Ping idle connections to find out early when the server or the network path to it has gone away
+ * interval_ms: Idle time before a ping is sent, also the time the server has to answer it, 0 to disable (default)
+ * A connection whose ping fails is marked dead, and re-established if mcp_set_auto_reconnect is enabled
+ */
+void mcp_set_keepalive(uint32_t interval_ms);
+
+/**
+ * Check tool arguments against the tool's input schema before calling it, failing invalid calls locally
+ * enabled: 1 to validate, 0 to send arguments unchecked (default)
+ */
+void mcp_set_validate_args(int32_t enabled);
+
+/**
+ * Check the structured content of tool results against the tool's output schema, flagging mismatches
+ * enabled: 1 to validate, 0 to return results unchecked (default)
+ * A mismatching result is still returned, with a "validation_error" next to it
+ */
+void mcp_set_validate_results(int32_t enabled);
+
+/**
+ * Read the resources that tool results link to and return their contents in place of the links
+ * enabled: 1 to resolve resource_link items into embedded resources, 0 to return the links as they are (default)
+ */
+void mcp_set_resolve_resources(int32_t enabled);
+
+/**
+ * Indent the JSON returned by SQL functions, to make it readable in the SQLite shell
+ * enabled: 1 to indent, 0 to return compact JSON (default)
+ */
+void mcp_set_pretty(int32_t enabled);
+
+/**
+ * Indent a JSON result of a SQL function when mcp_set_pretty is enabled
+ * json: Result text of the function
+ * Returns: The indented JSON, or NULL if pretty output is off or the text is not a JSON object or array
+ * (must be freed with mcp_free_string)
+ */
+char* mcp_pretty_json(const char* json);
+
+/**
+ * Include the JSON-RPC id a tool call was sent with in its result, as {"id": ..., "result": ...}
+ * enabled: 1 to include the id, 0 to leave it out (default)
+ */
+void mcp_set_include_request_id(int32_t enabled);
+
+/**
+ * Let tool calls wait for a connect in progress on their connection instead of failing as not connected
+ * enabled: 1 to wait up to the connect timeout, 0 to fail at once (default)
+ */
+void mcp_set_lazy(int32_t enabled);
+
+/**
+ * Set how long mcp_list_tools_json_cached serves a cached tool list before fetching it again
+ * ttl_ms: Lifetime in milliseconds, 0 to keep the list until the server reports that it changed (default)
+ */
+void mcp_set_tools_cache_ttl_ms(uint32_t ttl_ms);
+
+/**
+ * Set how many pages a list request fetches before failing, guarding against servers that never stop paginating
+ * max_pages: Page limit, 0 to follow nextCursor without a limit (default 100)
+ */
+void mcp_set_max_list_pages(uint32_t max_pages);
+
+/**
+ * Set the largest response accepted from a server, guarding the host against runaway tool results
+ * max_bytes: Limit in bytes, 0 for no limit (default 64 MiB)
+ */
+void mcp_set_max_response_bytes(uint32_t max_bytes);
+
+/**
+ * Replace invalid UTF-8 in server responses with U+FFFD instead of failing them, for servers sending latin-1 or other encodings
+ * enabled: 1 to decode leniently, 0 to reject invalid UTF-8 (default)
+ */
+void mcp_set_lossy_utf8(int32_t enabled);
+
+/**
+ * Use OAuth2 access tokens for streamable HTTP connections made after this call
+ * token_url: Token endpoint of the authorization server, or NULL to stop using OAuth
+ * client_id: OAuth client id (required with token_url)
+ * client_secret: Optional client secret, can be NULL
+ * refresh_token: Optional refresh token, can be NULL to use the client credentials grant
+ * Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+ */
+char* mcp_set_oauth(const char* token_url, const char* client_id, const char* client_secret, const char* refresh_token);
+
+/**
+ * Configure TLS for HTTP connections made after this call
+ * ca_pem_path: PEM file with extra CA certificates to trust, can be NULL
+ * client_cert_pem: PEM file with the client certificate for mutual TLS, can be NULL
+ * client_key_pem: PEM file with the client private key (required with client_cert_pem), can be NULL
+ * insecure_skip_verify: 1 to accept any server certificate (testing only), 0 to verify certificates
+ * Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+ */
+char* mcp_set_tls(const char* ca_pem_path, const char* client_cert_pem, const char* client_key_pem, int32_t insecure_skip_verify);
+
+/**
+ * Route HTTP connections made after this call through a proxy
+ * proxy_url: http://, https:// or socks5:// proxy URL with optional user:pass@ credentials, or NULL to connect directly
+ * Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+ */
+char* mcp_set_proxy(const char* proxy_url);
+
+/**
+ * Set the User-Agent sent on HTTP, SSE and WebSocket connections made after this call
+ * user_agent: User-Agent value, or NULL or an empty string to send none unless the headers give one
+ * Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+ */
+char* mcp_set_user_agent(const char* user_agent);
+
+/**
+ * Bind HTTP connections made after this call to a local IP address, so they leave through its interface
+ * ip: IPv4 or IPv6 address of a local interface, or NULL or an empty string to let the system choose
+ * Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+ */
+char* mcp_set_local_address(const char* ip);
+
+/**
+ * Set how HTTP connections made after this call follow redirects
+ * max_redirects: Redirects followed per request before it fails, 0 to return redirects without following them
+ * preserve_auth_cross_host: 1 to keep sending the Authorization and Cookie headers when a redirect leads to another host, 0 to drop them (default)
+ */
+void mcp_set_redirect_policy(uint32_t max_redirects, int32_t preserve_auth_cross_host);
+
+/**
+ * Set the client name and version sent to servers on connections made after this call
+ * name: Client name, or NULL for the default "sqlite-mcp"
+ * version: Client version, or NULL for the extension version
+ * Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+ */
+char* mcp_set_client_info(const char* name, const char* version);
+
+/**
+ * Set the MCP protocol version requested from servers on connections made after this call
+ * version: Protocol revision such as "2024-11-05", or NULL for the latest supported version
+ * Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+ */
+char* mcp_set_protocol_version(const char* version);
+
+/**
+ * Declare experimental capabilities to servers on connections made after this call
+ * capabilities_json: JSON object mapping each capability name to its settings object, or NULL to declare none
+ * Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+ */
+char* mcp_set_experimental_capabilities(const char* capabilities_json);
+
+/**
+ * Cancel the requests currently running on a connection; call it from another thread than the one waiting
+ * The waiting calls return {"error":"cancelled"} and servers are notified of cancelled tool calls
+ * connection_id: Connection whose requests to cancel, can be NULL for the "default" connection
+ */
+void mcp_cancel(const char* connection_id);
+
+/**
+ * Set the roots (allowed directories) offered to servers; connected servers are notified of the change
+ * roots_json: JSON array of root URIs or {"uri": ..., "name": ...} objects, or NULL to clear the roots
+ * Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+ */
+char* mcp_set_roots_json(const char* roots_json);
+
+/**
+ * Allow or deny tool calls by name on every connection, whatever tools the servers advertise
+ * filter_json: JSON object with optional "allow" and "deny" arrays of tool names, where * matches any run of characters
+ *              (e.g., "{\"allow\": [\"fs_*\"], \"deny\": [\"fs_delete\"]}"), or NULL to allow every tool
+ * Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+ */
+char* mcp_set_tool_filter(const char* filter_json);
+
+/**
+ * Set the default arguments of a tool, merged under the arguments of every call to it on any connection
+ * Nested objects are merged key by key; the caller's values win over the defaults
+ * tool_name: Name of the tool
+ * defaults_json: JSON object of default arguments, or NULL to clear the tool's defaults
+ * Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+ */
+char* mcp_set_tool_defaults(const char* tool_name, const char* defaults_json);
+
 /**
  * Free a string allocated by the MCP library
  */
 void mcp_free_string(char* s);
 
 /**
- * Create a new MCP client
- * Returns NULL on error
+ * Create a new MCP client handle
+ * Deprecated: the handle holds no state; pass NULL as the client argument instead
  */
 McpClient* mcp_client_new(void);
 
 /**
- * Free an MCP client
+ * Free an MCP client handle (does nothing; use mcp_disconnect to close a connection)
  */
 void mcp_client_free(McpClient* client);
 
 /**
  * Connect to an MCP server with optional custom headers
- * client: MCP client pointer (can be NULL to use global client)
- * server_url: URL of the MCP server (e.g., "http://localhost:8931/mcp")
+ * client: Unused, pass NULL (connections are looked up by connection_id)
+ * server_url: URL of the MCP server (e.g., "http://localhost:8931/mcp"), or a ws:// or wss:// URL to use the WebSocket transport
  * headers_json: Optional JSON string with custom headers (e.g., "{\"Authorization\": \"Bearer token\", \"X-MCP-Readonly\": \"true\"}"), can be NULL
  * legacy_sse: 1 to use SSE transport (legacy), 0 to use streamable HTTP transport (default)
- * Returns: JSON string with status (must be freed with mcp_free_string)
+ * connection_id: Optional name for this connection, can be NULL to use the "default" connection
+ * force: 1 to reconnect even if the connection is already open with the same settings, 0 to keep it
+ * Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+ */
+char* mcp_connect(McpClient* client, const char* server_url, const char* headers_json, int32_t legacy_sse, const char* connection_id, int32_t force);
+
+/**
+ * Connect to a local MCP server over stdio by spawning it as a child process
+ * client: Unused, pass NULL (connections are looked up by connection_id)
+ * command: Program to execute (e.g., "npx")
+ * args_json: Optional JSON array of string arguments (e.g., "[\"@modelcontextprotocol/server-filesystem\", \"/data\"]"), can be NULL
+ * connection_id: Optional name for this connection, can be NULL to use the "default" connection
+ * env_json: Optional JSON object of environment variables set for the process (e.g., "{\"API_KEY\": \"...\"}"), can be NULL
+ * cwd: Optional working directory of the process, can be NULL to use the current one
+ * inherit_env: 1 to start from this process's environment, 0 to pass only env_json
+ * Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+ */
+char* mcp_connect_stdio(McpClient* client, const char* command, const char* args_json, const char* connection_id,
+                        const char* env_json, const char* cwd, int32_t inherit_env);
+
+/**
+ * Connect to an MCP server with every setting of the connection given in one JSON object
+ * config_json: JSON object with "url" or "command" and optional "transport", "args", "headers", "connection_id",
+ *              "timeout_ms", "tls", "proxy" and "auth" (e.g., "{\"url\": \"http://localhost:8931/mcp\", \"timeout_ms\": 5000}")
+ * Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+ */
+char* mcp_connect_json(const char* config_json);
+
+/**
+ * Connect to several MCP servers at the same time, so their initialize handshakes overlap
+ * configs_json: JSON array of configs with the fields mcp_connect_json accepts, each with its own "connection_id"
+ * Returns: JSON object mapping each connection id to {"connected": true} or {"connected": false, "error": "..."} (must be freed with mcp_free_string)
+ */
+char* mcp_connect_many_json(const char* configs_json);
+
+/**
+ * Check a connect config without connecting: parse it, load its certificate files and build its HTTP client
+ * config_json: JSON object with the fields mcp_connect_json accepts
+ * Returns: NULL if the config is valid, error string naming the problem otherwise (must be freed with mcp_free_string)
+ */
+char* mcp_validate_config_json(const char* config_json);
+
+/**
+ * Get the config a connection was opened with, in the form mcp_connect_json accepts
+ * redact_secrets: 1 to replace credential headers, tokens, client secrets and proxy passwords with "***", 0 to return them
+ * connection_id: Connection whose config to return, can be NULL for the "default" connection
+ * Returns: JSON string with the config, or {"error": ...} if not connected (must be freed with mcp_free_string)
+ */
+char* mcp_connection_config_json(int32_t redact_secrets, const char* connection_id);
+
+/**
+ * Close a connection and open it again with the config it was opened with, for a fresh transport after
+ * a network change, then subscribe again to its resources and set the server log level set on it
+ * connection_id: Connection to reconnect, can be NULL for the "default" connection
+ * Returns: JSON {"reconnected": true, "subscriptions": [...], "log_level": ..., "failed": [...]} listing what was
+ * restored and what could not be, or {"error": ...} if there is no connection or it cannot be opened (must be freed with mcp_free_string)
+ */
+char* mcp_reconnect(const char* connection_id);
+
+/**
+ * Callback receiving log events
+ * level: 0 = error, 1 = warning, 2 = info, 3 = debug
+ * message: Event text, prefixed with the connection id in brackets when it belongs to a connection
+ */
+typedef void (*McpLogCallback)(int32_t level, const char* message);
+
+/**
+ * Register the callback receiving connect attempts, requests and their timings, timeouts and dropped notifications.
+ * It may run on a background thread, is not called while the library holds a lock, and should return quickly.
+ * callback: Log callback, or NULL to stop logging
+ */
+void mcp_set_log_callback(McpLogCallback callback);
+
+/**
+ * Callback answering sampling/createMessage requests from servers
+ * context: Pointer given to mcp_set_sampling_callback
+ * request_json: JSON of the CreateMessageRequest params
+ * Returns: CreateMessageResult JSON, {"error": "..."} to refuse the request, or NULL on failure
+ */
+typedef char* (*McpSamplingCallback)(void* context, const char* request_json);
+
+/**
+ * Callback freeing a response returned by a McpSamplingCallback
+ */
+typedef void (*McpFreeCallback)(char* response);
+
+/**
+ * Register the callback that answers sampling requests; the sampling capability is advertised
+ * to servers connected afterwards. The callback runs on a background thread.
+ * callback: Sampling callback, or NULL to stop handling sampling (servers get "method not found")
+ * free_response: Optional function called to free each response once copied, can be NULL
+ * context: Pointer passed back to the callback unchanged, can be NULL
+ */
+void mcp_set_sampling_callback(McpSamplingCallback callback, McpFreeCallback free_response, void* context);
+
+/**
+ * Callback receiving each notification from a server as it arrives
+ * context: Pointer given to mcp_set_notification_callback
+ * notification_json: JSON object of the notification, as returned by mcp_poll_notifications_json (only valid during the call)
+ */
+typedef void (*McpNotificationCallback)(void* context, const char* notification_json);
+
+/**
+ * Register the callback receiving progress, log, resource update and list changed notifications.
+ * It runs on a dedicated thread, one notification at a time, with no library lock held.
+ * Notifications are still queued for mcp_poll_notifications_json unless the queue size is 0.
+ * callback: Notification callback, or NULL to stop
+ * context: Pointer passed back to the callback unchanged, can be NULL
+ */
+void mcp_set_notification_callback(McpNotificationCallback callback, void* context);
+
+/**
+ * Disconnect from an MCP server and drop its client
+ * connection_id: Connection to close, can be NULL to close the "default" connection
+ * Returns: NULL on success, error string on failure (must be freed with mcp_free_string)
+ */
+char* mcp_disconnect(const char* connection_id);
+
+/**
+ * Close every connection and stop the runtimes they run on, waiting for their worker threads to exit
+ * Running requests are cancelled; settings are kept, and later calls start again from a clean state
+ */
+void mcp_shutdown(void);
+
+/**
+ * Check that an MCP server is still responding by sending it a ping request
+ * connection_id: Connection to ping, can be NULL to ping the "default" connection
+ * Returns: NULL on success, JSON error string on failure (must be freed with mcp_free_string)
+ */
+char* mcp_ping(const char* connection_id);
+
+/**
+ * Measure the round-trip time to an MCP server by sending it ping requests one after another
+ * count: Number of pings to send
+ * connection_id: Connection to measure, can be NULL to measure the "default" connection
+ * Returns: JSON {"count", "samples_ms", "min_ms", "max_ms", "avg_ms", "p50_ms", "p95_ms"}, or the error of the first failed ping (must be freed with mcp_free_string)
+ */
+char* mcp_echo_latency_json(uint32_t count, const char* connection_id);
+
+/**
+ * Ask an MCP server to send log messages of the given level and above
+ * Log messages are queued as notifications/message for mcp_poll_notifications_json
+ * level: One of debug, info, notice, warning, error, critical, alert, emergency
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * Returns: NULL on success, JSON error string on failure (must be freed with mcp_free_string)
+ */
+char* mcp_set_server_log_level(const char* level, const char* connection_id);
+
+/**
+ * Set how many notifications are queued until polled; the oldest are dropped first
+ * size: Maximum number of queued notifications, 0 to stop queuing (default 1000)
+ */
+void mcp_set_notification_queue_size(uint32_t size);
+
+/**
+ * Get the queued log messages servers sent, leaving other notifications in the queue
+ * clear: 1 to remove the returned log messages from the queue, 0 to leave them
+ * Returns: JSON {"logs": [{"connection_id", "level", "logger", "data"}, ...]}, oldest first (must be freed with mcp_free_string)
+ */
+char* mcp_server_logs_json(int32_t clear);
+
+/**
+ * Get the queued notifications of every kind with their sequence numbers, leaving them in the queue
+ * since: Sequence number of the last event already seen, 0 to list every queued event
+ * Returns: JSON {"events": [{"seq", "kind", "ts", "connection_id", "payload"}, ...]}, oldest first (must be freed with mcp_free_string)
+ */
+char* mcp_events_json(uint64_t since);
+
+/**
+ * List tools like mcp_list_tools_json, serving the connection's cached list when there is one
+ * The cache is filled on first use and dropped on tools/list_changed, on reconnect and after the TTL
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * Returns: JSON string with tools list (must be freed with mcp_free_string)
+ */
+char* mcp_list_tools_json_cached(const char* connection_id);
+
+/**
+ * List only the names of the connection's tools, from the cached list when there is one
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * Returns: JSON array of tool names in server order (must be freed with mcp_free_string)
+ */
+char* mcp_list_tool_names_json(const char* connection_id);
+
+/**
+ * Drop the connection's cached tool list and fetch it again, for servers that change their tools
+ * without sending tools/list_changed
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * Returns: JSON string with the new tools list, as returned by mcp_list_tools_json (must be freed with mcp_free_string)
+ */
+char* mcp_refresh_tools(const char* connection_id);
+
+/**
+ * Get the input schema, and output schema if it has one, of one tool from the cached tool list
+ * tool_name: Name of the tool
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * Returns: JSON {"inputSchema": ..., "outputSchema": ...}, or an error naming a missing tool (must be freed with mcp_free_string)
+ */
+char* mcp_tool_schema_json(const char* tool_name, const char* connection_id);
+
+/**
+ * Count the times the tool list of any connection may have changed
+ * Returns: A number that grows on every tools/list_changed notification and reconnect
+ */
+uint64_t mcp_tool_list_changes(void);
+
+/**
+ * Call a tool that is safe to run more than once, so that it is retried like other requests
+ * tool_name: Name of the tool to call
+ * arguments_json: JSON string with tool arguments
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * Returns: JSON string with tool result, as returned by mcp_call_tool_json (must be freed with mcp_free_string)
+ */
+char* mcp_call_tool_idempotent_json(const char* tool_name, const char* arguments_json, const char* connection_id);
+
+/**
+ * Call a tool like mcp_call_tool_json, sending extra HTTP headers with this call only
+ * tool_name: Name of the tool to call
+ * arguments_json: JSON string with tool arguments
+ * headers_json: JSON object of headers, or array of [name, value] pairs, as for mcp_connect; can be NULL
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * Only streamable HTTP sends per-request headers; SSE, WebSocket and stdio connections ignore them
+ * Returns: JSON string with tool result, as returned by mcp_call_tool_json (must be freed with mcp_free_string)
+ */
+char* mcp_call_tool_with_headers_json(const char* tool_name, const char* arguments_json, const char* headers_json, const char* connection_id);
+
+/**
+ * Call a tool like mcp_call_tool_json with binary data base64 encoded into one of its arguments
+ * tool_name: Name of the tool to call
+ * arguments_json: JSON object of the other arguments, can be NULL for none
+ * blob_param_name: Argument the encoded data is set to, replacing any value arguments_json gives it
+ * blob_data, blob_len: Bytes to encode, blob_data can be NULL when blob_len is 0
+ * encoding: "standard" (default when NULL), "url_safe" or "url_safe_no_pad"
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * Returns: JSON string with tool result, as returned by mcp_call_tool_json (must be freed with mcp_free_string)
+ */
+char* mcp_call_tool_with_blob(const char* tool_name, const char* arguments_json, const char* blob_param_name,
+                              const uint8_t* blob_data, size_t blob_len, const char* encoding, const char* connection_id);
+
+/**
+ * Call several tools on the connected MCP server at the same time
+ * calls_json: JSON array of calls, e.g. [{"name": "search", "arguments": {"q": "rome"}}]
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * Returns: JSON array with one {"result": ...} or {"error": ...} per call, in input order (must be freed with mcp_free_string)
+ */
+char* mcp_call_tools_batch_json(const char* calls_json, const char* connection_id);
+
+/**
+ * Call every tool whose name matches a pattern on the connected MCP server at the same time
+ * pattern: Tool name pattern in which * stands for any run of characters, e.g. "report_*"
+ * args_json: JSON object of arguments passed to every matching tool, can be NULL for no arguments
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * Returns: JSON object mapping each matching tool name to its {"result": ...} or {"error": ...}, empty if no tool matches (must be freed with mcp_free_string)
+ */
+char* mcp_call_tools_matching_json(const char* pattern, const char* args_json, const char* connection_id);
+
+/**
+ * Read several resources from the connected MCP server at the same time, at most 8 at once
+ * uris_json: JSON array of resource URIs, e.g. ["file:///a.txt", "file:///b.txt"]
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * Returns: JSON array with one {"result": ...} or {"error": ...} per URI, in input order (must be freed with mcp_free_string)
+ */
+char* mcp_read_resources_batch_json(const char* uris_json, const char* connection_id);
+
+/**
+ * Start a tool call whose progress and content are read in chunks with mcp_poll_stream_json
+ * tool_name: Name of the tool to call
+ * arguments_json: JSON object with tool arguments
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * Returns: Stream id, or 0 if the tool name or arguments are not valid; other errors arrive as stream chunks
+ */
+uint64_t mcp_call_tool_stream(const char* tool_name, const char* arguments_json, const char* connection_id);
+
+/**
+ * Take the chunks a streamed tool call produced since the last poll
+ * stream_id: Id returned by mcp_call_tool_stream
+ * wait_ms: Milliseconds to wait for a chunk when none is buffered, 0 to return at once
+ * Returns: JSON {"chunks": [...], "done": bool}; after done the stream id is no longer valid (must be freed with mcp_free_string)
+ */
+char* mcp_poll_stream_json(uint64_t stream_id, uint32_t wait_ms);
+
+/**
+ * Close a tool stream before its done chunk is polled, stopping the call if it is still running
+ * stream_id: Id returned by mcp_call_tool_stream
+ * Returns: 1 if the stream was closed, 0 if there is no stream with that id
+ */
+int mcp_close_stream(uint64_t stream_id);
+
+/**
+ * Subscribe to updates of a resource; updates are queued for mcp_poll_notifications_json
+ * uri: URI of the resource to watch
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * Returns: NULL on success, JSON error string on failure (must be freed with mcp_free_string)
+ */
+char* mcp_subscribe_resource(const char* uri, const char* connection_id);
+
+/**
+ * Stop receiving updates of a resource subscribed with mcp_subscribe_resource
+ * uri: URI of the resource to stop watching
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * Returns: NULL on success, JSON error string on failure (must be freed with mcp_free_string)
+ */
+char* mcp_unsubscribe_resource(const char* uri, const char* connection_id);
+
+/**
+ * List the resource templates of the server, following pagination
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * Returns: JSON string with {"resourceTemplates": [...]} (must be freed with mcp_free_string)
+ */
+char* mcp_list_resource_templates_json(const char* connection_id);
+
+/**
+ * Expand an RFC 6570 resource template with variables and read the resulting resource
+ * template_uri: URI template, e.g. "file:///{path}"
+ * vars_json: JSON object with a value for every variable, can be NULL for templates without variables
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * Returns: JSON string with the resource contents, or an error naming a missing variable (must be freed with mcp_free_string)
+ */
+char* mcp_read_resource_template(const char* template_uri, const char* vars_json, const char* connection_id);
+
+/**
+ * Read the first text or binary content of a resource
+ * uri: URI of the resource to read
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * binary: 1 for the first blob content, decoded from base64, 0 for the first text content,
+ *         -1 for the first content of either kind
+ * out_data, out_len: Receive the content bytes (must be freed with mcp_free_bytes)
+ * Returns: NULL on success, JSON error string on failure or when no content matches (must be freed with mcp_free_string)
+ */
+char* mcp_read_resource_content(const char* uri, const char* connection_id, int32_t binary, uint8_t** out_data, size_t* out_len);
+
+/**
+ * Free bytes returned by mcp_read_resource_content
+ */
+void mcp_free_bytes(uint8_t* data, size_t len);
+
+/**
+ * Ask the server to complete a prompt or resource template argument
+ * ref_json: Reference, e.g. {"type": "ref/prompt", "name": "review"} or {"type": "ref/resource", "uri": "file:///{path}"}
+ * argument_json: Argument being completed and its partial value, e.g. {"name": "language", "value": "py"}
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * Returns: JSON string with values, total, hasMore and a supported flag (must be freed with mcp_free_string)
+ */
+char* mcp_complete_json(const char* ref_json, const char* argument_json, const char* connection_id);
+
+/**
+ * Send a JSON-RPC request for a method that has no dedicated function, returning the server's answer unvalidated
+ * method: JSON-RPC method name, e.g. "resources/templates/list"
+ * params_json: JSON object with the request params, can be NULL to send none
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * Returns: JSON string with {"result": ...} or {"error": {"code", "message", "data"}} (must be freed with mcp_free_string)
+ */
+char* mcp_raw_request_json(const char* method, const char* params_json, const char* connection_id);
+
+/**
+ * Get the transport a connection uses: "streamable-http", "sse", "websocket" or "stdio"
+ * connection_id: Connection to inspect, can be NULL to inspect the "default" connection
+ * Returns: Transport name, or NULL if not connected (must be freed with mcp_free_string)
+ */
+char* mcp_transport(const char* connection_id);
+
+/**
+ * Get what the server reported about itself when the connection was initialized
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * Returns: JSON string with name, title, version, instructions, protocol_version and capabilities (must be freed with mcp_free_string)
+ */
+char* mcp_server_info_json(const char* connection_id);
+
+/**
+ * Get the InitializeResult the server answered the initialize request with, as sent on the wire
+ * connection_id: Connection to use, can be NULL to use the "default" connection
+ * Returns: JSON string with protocolVersion, capabilities, serverInfo and instructions (must be freed with mcp_free_string)
+ */
+char* mcp_initialize_result_json(const char* connection_id);
+
+/**
+ * Report what this build supports and how it is configured
+ * connection_id: Connection whose negotiated server capabilities are included, can be NULL for the "default" connection
+ * Returns: JSON string with transports, sampling, roots, timeout_ms and server_capabilities (must be freed with mcp_free_string)
+ */
+char* mcp_capabilities_json(const char* connection_id);
+
+/**
+ * Report request counters and queue depths across all connections, cheap enough to poll often
+ * Returns: JSON {"requests_total", "requests_in_flight", "last_latency_ms", "reconnects", "connections", "queued_notifications"} (must be freed with mcp_free_string)
+ */
+char* mcp_metrics_json(void);
+
+/**
+ * Report request latencies per MCP method and per tool, across all connections
+ * Returns: JSON {"methods": {method: timing}, "tools": {name: timing}} with timing
+ * {"count", "min_ms", "max_ms", "avg_ms", "p95_ms"} (must be freed with mcp_free_string)
+ */
+char* mcp_timing_json(void);
+
+/**
+ * List the requests waiting for a connect in progress or held by mcp_pause, and those waiting for the server's answer, oldest first
+ * Returns: JSON {"requests": [{"connection", "kind", "state", "age_ms"}]}, state being "queued" or "in_flight"
+ * (must be freed with mcp_free_string)
+ */
+char* mcp_queue_json(void);
+
+/**
+ * Cancel the requests waiting for a connect in progress or held by mcp_pause, which fail with a cancelled error without being sent
+ * Requests already sent are left running, mcp_cancel stops those
+ * Returns: The number of requests cancelled
+ */
+size_t mcp_clear_queue(void);
+
+/**
+ * Pause a connection: tool calls, list and read requests are held, or fail, until mcp_resume
+ * The transport, subscriptions, keep-alive pings and requests already sent are left running
+ * queue: 1 to hold requests until resumed, up to the request timeout, 0 to fail them with code -32007
+ * connection_id: Connection to pause, can be NULL for the "default" connection
+ * Returns: NULL on success, error message if the connection does not exist (must be freed with mcp_free_string)
  */
-char* mcp_connect(McpClient* client, const char* server_url, const char* headers_json, int32_t legacy_sse);
+char* mcp_pause(int32_t queue, const char* connection_id);
 
 /**
- * Disconnect from MCP server and reset global client state
- * Returns: NULL on success
+ * Resume a connection paused with mcp_pause, sending the requests held while it was paused
+ * connection_id: Connection to resume, can be NULL for the "default" connection
+ * Returns: The number of held requests released, 0 if the connection was not paused
  */
-char* mcp_disconnect(void);
+size_t mcp_resume(const char* connection_id);
 
 #ifdef __cplusplus
 }
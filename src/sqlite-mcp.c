@@ -21,6 +21,7 @@
 #include <string.h>
 #include <stdlib.h>
 #include <stdio.h>
+#include <limits.h>
 
 // Ensure SQLITE_INDEX_CONSTRAINT_FUNCTION is defined (SQLite 3.9.0+)
 #ifndef SQLITE_INDEX_CONSTRAINT_FUNCTION
@@ -29,8 +30,14 @@
 
 SQLITE_EXTENSION_INIT1
 
-/* Helper function to set result as text */
+/* Helper function to set result as text, indenting JSON when mcp_set_pretty is enabled */
 static void mcp_result_text(sqlite3_context *context, const char *text, int len) {
+  char *pretty = len < 0 ? mcp_pretty_json(text) : NULL;
+  if (pretty) {
+    sqlite3_result_text(context, pretty, -1, SQLITE_TRANSIENT);
+    mcp_free_string(pretty);
+    return;
+  }
   sqlite3_result_text(context, text, len, SQLITE_TRANSIENT);
 }
 
@@ -43,18 +50,49 @@ static void mcp_version_func(
 }
 
 /*
-** SQL function: mcp_connect(server_url, [headers_json], [legacy_sse])
+** Helper: read the optional connection id argument at position idx
+** Returns NULL (the "default" connection) if the argument is missing or NULL
+*/
+static const char *mcp_connection_id_arg(int argc, sqlite3_value **argv, int idx) {
+  if (argc > idx && sqlite3_value_type(argv[idx]) != SQLITE_NULL) {
+    return (const char*)sqlite3_value_text(argv[idx]);
+  }
+  return NULL;
+}
+
+/*
+** Helper: set the result of a connect function
+** NULL result means success: returns the connection id if one was given, NULL otherwise
+*/
+static void mcp_connect_result(sqlite3_context *context, char *result, const char *connection_id) {
+  if (!result) {
+    if (connection_id) {
+      sqlite3_result_text(context, connection_id, -1, SQLITE_TRANSIENT);
+    } else {
+      sqlite3_result_null(context);
+    }
+    return;
+  }
+
+  // Non-NULL result is an error message
+  sqlite3_result_text(context, result, -1, SQLITE_TRANSIENT);
+  mcp_free_string(result);
+}
+
+/*
+** SQL function: mcp_connect(server_url, [headers_json], [legacy_sse], [connection_id], [force])
 ** Connects to an MCP server with optional custom headers
+** A connection that is already open with the same settings is kept unless force is 1
 **
-** Returns NULL on successful connection, error string on failure
+** Returns NULL (or the connection id if given) on successful connection, error string on failure
 */
 static void mcp_connect_func(
   sqlite3_context *context,
   int argc,
   sqlite3_value **argv
 ){
-  if (argc < 1 || argc > 3) {
-    sqlite3_result_error(context, "mcp_connect requires 1-3 arguments: (server_url, [headers_json], [legacy_sse])", -1);
+  if (argc < 1 || argc > 5) {
+    sqlite3_result_error(context, "mcp_connect requires 1-5 arguments: (server_url, [headers_json], [legacy_sse], [connection_id], [force])", -1);
     return;
   }
 
@@ -74,641 +112,1692 @@ static void mcp_connect_func(
     legacy_sse = sqlite3_value_int(argv[2]);
   }
 
-  char *result = mcp_connect(NULL, server_url, headers_json, legacy_sse);
+  const char *connection_id = mcp_connection_id_arg(argc, argv, 3);
 
-  if (!result) {
-    // NULL result means success
-    sqlite3_result_null(context);
-    return;
+  int force = 0;
+  if (argc >= 5 && sqlite3_value_type(argv[4]) != SQLITE_NULL) {
+    if (sqlite3_value_type(argv[4]) != SQLITE_INTEGER || (sqlite3_value_int64(argv[4]) != 0 && sqlite3_value_int64(argv[4]) != 1)) {
+      sqlite3_result_error(context, "mcp_connect requires force to be 0 or 1", -1);
+      return;
+    }
+    force = sqlite3_value_int(argv[4]);
   }
 
-  // Non-NULL result is an error message
-  sqlite3_result_text(context, result, -1, SQLITE_TRANSIENT);
-  mcp_free_string(result);
+  char *result = mcp_connect(NULL, server_url, headers_json, legacy_sse, connection_id, force);
+  mcp_connect_result(context, result, connection_id);
 }
 
-static void mcp_disconnect_func(
+/*
+** SQL function: mcp_connect_stdio(command, [args_json], [connection_id], [env_json], [cwd], [inherit_env])
+** Spawns a local MCP server process and connects to it over stdio
+** env_json sets environment variables of the process, on top of this process's environment
+** unless inherit_env is 0
+**
+** Returns NULL (or the connection id if given) on successful connection, error string on failure
+*/
+static void mcp_connect_stdio_func(
   sqlite3_context *context,
   int argc,
   sqlite3_value **argv
 ){
-  if (argc != 0) {
-    sqlite3_result_error(context, "mcp_disconnect takes no arguments", -1);
+  if (argc < 1 || argc > 6) {
+    sqlite3_result_error(context, "mcp_connect_stdio requires 1-6 arguments: (command, [args_json], [connection_id], [env_json], [cwd], [inherit_env])", -1);
     return;
   }
 
-  char *result = mcp_disconnect();
-  
-  // Should always return NULL (success)
-  if (!result) {
-    sqlite3_result_null(context);
-  } else {
-    sqlite3_result_text(context, result, -1, SQLITE_TRANSIENT);
-    mcp_free_string(result);
+  const char *command = (const char*)sqlite3_value_text(argv[0]);
+  if (!command) {
+    sqlite3_result_error(context, "mcp_connect_stdio requires a command", -1);
+    return;
   }
-}
-
-/*
-** STREAMING Virtual Table for mcp_list_tools
-** Returns parsed tool information as rows using streaming API
-*/
 
-// Include Rust FFI streaming types
-typedef struct StreamResult {
-  int result_type;
-  char *data;
-} StreamResult;
+  const char *args_json = NULL;
+  if (argc >= 2 && sqlite3_value_type(argv[1]) != SQLITE_NULL) {
+    args_json = (const char*)sqlite3_value_text(argv[1]);
+  }
 
-// Stream type constants (must match Rust)
-#define STREAM_TYPE_TOOL  0
-#define STREAM_TYPE_TEXT  1
-#define STREAM_TYPE_ERROR 2
-#define STREAM_TYPE_DONE  3
+  const char *connection_id = mcp_connection_id_arg(argc, argv, 2);
 
-// Rust FFI streaming functions
-extern size_t mcp_list_tools_init(void);
-extern size_t mcp_call_tool_init(const char* tool_name, const char* arguments);
-extern StreamResult* mcp_stream_next(size_t stream_id);
-extern StreamResult* mcp_stream_wait(size_t stream_id, uint64_t timeout_ms);
-extern void mcp_stream_cleanup(size_t stream_id);
-extern void mcp_stream_free_result(StreamResult* result);
+  const char *env_json = NULL;
+  if (argc >= 4 && sqlite3_value_type(argv[3]) != SQLITE_NULL) {
+    env_json = (const char*)sqlite3_value_text(argv[3]);
+  }
 
-// Rust FFI JSON functions
-extern char* mcp_list_tools_json(void*);
-extern char* mcp_call_tool_json(void*, const char*, const char*);
-extern void mcp_free_string(char*);
-extern char* mcp_extract_error_message(const char*);
+  const char *cwd = NULL;
+  if (argc >= 5 && sqlite3_value_type(argv[4]) != SQLITE_NULL) {
+    cwd = (const char*)sqlite3_value_text(argv[4]);
+  }
 
-// JSON parsing functions (using serde_json in Rust)
-extern size_t mcp_parse_tools_json(const char* json_str);
-extern char* mcp_get_tool_field(const char* json_str, size_t tool_index, const char* field_name);
-extern size_t mcp_parse_call_result_json(const char* json_str);
-extern char* mcp_get_call_result_text(const char* json_str, size_t content_index);
+  int inherit_env = 1;
+  if (argc >= 6 && sqlite3_value_type(argv[5]) != SQLITE_NULL) {
+    if (sqlite3_value_type(argv[5]) != SQLITE_INTEGER || (sqlite3_value_int64(argv[5]) != 0 && sqlite3_value_int64(argv[5]) != 1)) {
+      sqlite3_result_error(context, "mcp_connect_stdio requires inherit_env to be 0 or 1", -1);
+      return;
+    }
+    inherit_env = sqlite3_value_int(argv[5]);
+  }
 
-typedef struct mcp_stream_vtab {
-  sqlite3_vtab base;
-} mcp_stream_vtab;
+  char *result = mcp_connect_stdio(NULL, command, args_json, connection_id, env_json, cwd, inherit_env);
+  mcp_connect_result(context, result, connection_id);
+}
 
-typedef struct mcp_stream_cursor {
-  sqlite3_vtab_cursor base;
-  size_t stream_id;           // Rust stream ID
-  char *current_data;         // Current tool JSON data
-  int eof;                    // End of stream flag
-  sqlite_int64 rowid;
-} mcp_stream_cursor;
+/*
+** SQL function: mcp_connect_json(config_json)
+** Connects with every setting of the connection given in one JSON object
+**
+** Returns NULL on successful connection, error string on failure
+*/
+static void mcp_connect_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  const char *config_json = (const char*)sqlite3_value_text(argv[0]);
+  if (!config_json) {
+    sqlite3_result_error(context, "mcp_connect_json requires a JSON config object", -1);
+    return;
+  }
 
-// Forward declarations
-static int mcp_stream_next_impl(sqlite3_vtab_cursor *cur);
-static int mcp_stream_vtab_next(sqlite3_vtab_cursor *cur);
+  char *result = mcp_connect_json(config_json);
+  mcp_connect_result(context, result, NULL);
+}
 
-static int mcp_stream_connect(
-  sqlite3 *db,
-  void *pAux,
-  int argc, const char *const*argv,
-  sqlite3_vtab **ppVtab,
-  char **pzErr
+/*
+** SQL function: mcp_connect_many_json(configs_json)
+** Connects to several servers at the same time, each config as accepted by mcp_connect_json
+**
+** Returns a JSON object mapping each connection id to whether it connected and its error
+*/
+static void mcp_connect_many_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
 ){
-  mcp_stream_vtab *pNew = sqlite3_malloc(sizeof(*pNew));
-  if (pNew==0) return SQLITE_NOMEM;
-  memset(pNew, 0, sizeof(*pNew));
-  *ppVtab = (sqlite3_vtab*)pNew;
-
-  int rc = sqlite3_declare_vtab(db,
-    "CREATE TABLE x(name TEXT, title TEXT, description TEXT, "
-    "inputSchema TEXT, outputSchema TEXT, annotations TEXT)");
+  const char *configs_json = (const char*)sqlite3_value_text(argv[0]);
+  if (!configs_json) {
+    sqlite3_result_error(context, "mcp_connect_many_json requires a JSON array of config objects", -1);
+    return;
+  }
 
-  return rc;
+  char *result = mcp_connect_many_json(configs_json);
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to connect", -1);
+  }
 }
 
-static int mcp_stream_disconnect(sqlite3_vtab *pVtab){
-  sqlite3_free(pVtab);
-  return SQLITE_OK;
-}
+/*
+** SQL function: mcp_validate_config_json(config_json)
+** Checks a config for mcp_connect_json without connecting
+**
+** Returns NULL if the config is valid, error string naming the problem otherwise
+*/
+static void mcp_validate_config_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  const char *config_json = (const char*)sqlite3_value_text(argv[0]);
+  if (!config_json) {
+    sqlite3_result_error(context, "mcp_validate_config_json requires a JSON config object", -1);
+    return;
+  }
 
-static int mcp_stream_open(sqlite3_vtab *pVtab, sqlite3_vtab_cursor **ppCursor){
-  mcp_stream_cursor *pCur = sqlite3_malloc(sizeof(*pCur));
-  if (pCur==0) return SQLITE_NOMEM;
-  memset(pCur, 0, sizeof(*pCur));
-  *ppCursor = (sqlite3_vtab_cursor*)pCur;
-  return SQLITE_OK;
+  char *result = mcp_validate_config_json(config_json);
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_null(context);
+  }
 }
 
-static int mcp_stream_close(sqlite3_vtab_cursor *cur){
-  mcp_stream_cursor *pCur = (mcp_stream_cursor*)cur;
-
-  // Cleanup stream resources
-  if (pCur->stream_id > 0) {
-    mcp_stream_cleanup(pCur->stream_id);
+/*
+** SQL function: mcp_connection_config_json([redact_secrets], [connection_id])
+** Returns the config a connection was opened with, which mcp_connect_json accepts back
+** Secrets are redacted unless redact_secrets is 0
+*/
+static void mcp_connection_config_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc > 2) {
+    sqlite3_result_error(context, "mcp_connection_config_json takes at most 2 arguments: ([redact_secrets], [connection_id])", -1);
+    return;
   }
 
-  if (pCur->current_data) {
-    sqlite3_free(pCur->current_data);
+  int redact_secrets = 1;
+  if (argc >= 1 && sqlite3_value_type(argv[0]) != SQLITE_NULL) {
+    if (sqlite3_value_type(argv[0]) != SQLITE_INTEGER || (sqlite3_value_int64(argv[0]) != 0 && sqlite3_value_int64(argv[0]) != 1)) {
+      sqlite3_result_error(context, "mcp_connection_config_json requires redact_secrets to be 0 or 1", -1);
+      return;
+    }
+    redact_secrets = sqlite3_value_int(argv[0]);
   }
 
-  sqlite3_free(pCur);
-  return SQLITE_OK;
+  char *result = mcp_connection_config_json(redact_secrets, mcp_connection_id_arg(argc, argv, 1));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to get connection config", -1);
+  }
 }
 
-static int mcp_stream_filter(
-  sqlite3_vtab_cursor *pVtabCursor,
-  int idxNum, const char *idxStr,
-  int argc, sqlite3_value **argv
+/*
+** SQL function: mcp_reconnect([connection_id])
+** Closes a connection and opens it again with its config, then restores its resource subscriptions
+** and server log level
+*/
+static void mcp_reconnect_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
 ){
-  mcp_stream_cursor *pCur = (mcp_stream_cursor*)pVtabCursor;
+  if (argc > 1) {
+    sqlite3_result_error(context, "mcp_reconnect takes at most 1 argument: ([connection_id])", -1);
+    return;
+  }
 
-  D("mcp_stream_filter: Starting mcp_list_tools streaming");
+  char *result = mcp_reconnect(mcp_connection_id_arg(argc, argv, 0));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to reconnect", -1);
+  }
+}
 
-  // Initialize streaming
-  pCur->stream_id = mcp_list_tools_init();
-  DF("mcp_stream_filter: stream_id=%zu", pCur->stream_id);
+/*
+** SQL function: mcp_set_timeout_ms(timeout_ms, [connection_id])
+** With a connection id, sets that connection's timeout instead; NULL makes it use the global one again
+** Sets the timeout applied to every MCP request, 0 to wait forever
+*/
+static void mcp_set_timeout_ms_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 1 || argc > 2) {
+    sqlite3_result_error(context, "mcp_set_timeout_ms requires 1-2 arguments: (timeout_ms, [connection_id])", -1);
+    return;
+  }
 
-  if (pCur->stream_id == 0) {
-    D("mcp_stream_filter: Failed to initialize stream (stream_id=0)");
-    pCur->eof = 1;
-    return SQLITE_ERROR;
+  sqlite3_int64 timeout_ms = sqlite3_value_int64(argv[0]);
+  int clear = argc == 2 && sqlite3_value_type(argv[0]) == SQLITE_NULL;
+  if (!clear && (sqlite3_value_type(argv[0]) != SQLITE_INTEGER || timeout_ms < 0 || timeout_ms > UINT32_MAX)) {
+    sqlite3_result_error(context, "mcp_set_timeout_ms requires a non-negative integer number of milliseconds", -1);
+    return;
   }
 
-  pCur->rowid = 0;
-  pCur->eof = 0;
+  if (argc == 1) {
+    mcp_set_timeout_ms((uint32_t)timeout_ms);
+    sqlite3_result_null(context);
+    return;
+  }
 
-  D("mcp_stream_filter: Getting first result");
-  // Get first result
-  return mcp_stream_next_impl(pVtabCursor);
+  char *result = mcp_set_connection_timeout_ms(clear ? -1 : timeout_ms, mcp_connection_id_arg(argc, argv, 1));
+  if (result) {
+    sqlite3_result_error(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_null(context);
+  }
 }
 
-static int mcp_stream_next_impl(sqlite3_vtab_cursor *cur) {
-  mcp_stream_cursor *pCur = (mcp_stream_cursor*)cur;
-
-  DF("mcp_stream_next_impl: rowid=%lld, stream_id=%zu", pCur->rowid, pCur->stream_id);
-
-  // Free previous data
-  if (pCur->current_data) {
-    sqlite3_free(pCur->current_data);
-    pCur->current_data = NULL;
+/*
+** SQL function: mcp_set_connect_timeout_ms(timeout_ms)
+** Sets the timeout for connecting and the initialize handshake, 0 to use the request timeout
+*/
+static void mcp_set_connect_timeout_ms_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  sqlite3_int64 timeout_ms = sqlite3_value_int64(argv[0]);
+  if (sqlite3_value_type(argv[0]) != SQLITE_INTEGER || timeout_ms < 0 || timeout_ms > UINT32_MAX) {
+    sqlite3_result_error(context, "mcp_set_connect_timeout_ms requires a non-negative integer number of milliseconds", -1);
+    return;
   }
 
-  // Poll for next chunk (with 100ms wait if needed)
-  D("mcp_stream_next_impl: Waiting for next stream result...");
-  StreamResult *result = mcp_stream_wait(pCur->stream_id, 100);
+  mcp_set_connect_timeout_ms((uint32_t)timeout_ms);
+  sqlite3_result_null(context);
+}
 
-  if (!result) {
-    D("mcp_stream_next_impl: No result received (NULL)");
-    pCur->eof = 1;
-    return SQLITE_OK;
+/*
+** SQL function: mcp_set_tools_cache_ttl_ms(ttl_ms)
+** Sets how long mcp_list_tools_json_cached serves a cached tool list, 0 until the tools change
+*/
+static void mcp_set_tools_cache_ttl_ms_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  sqlite3_int64 ttl_ms = sqlite3_value_int64(argv[0]);
+  if (sqlite3_value_type(argv[0]) != SQLITE_INTEGER || ttl_ms < 0 || ttl_ms > UINT32_MAX) {
+    sqlite3_result_error(context, "mcp_set_tools_cache_ttl_ms requires a non-negative integer number of milliseconds", -1);
+    return;
   }
 
-  DF("mcp_stream_next_impl: Received result_type=%d", result->result_type);
+  mcp_set_tools_cache_ttl_ms((uint32_t)ttl_ms);
+  sqlite3_result_null(context);
+}
 
-  switch (result->result_type) {
-    case STREAM_TYPE_TOOL:
-      // Store tool JSON data
-      if (result->data) {
-        size_t data_len = strlen(result->data);
-        pCur->current_data = sqlite3_mprintf("%s", result->data);
-        DF("mcp_stream_next_impl: STREAM_TYPE_TOOL received (%zu bytes)", data_len);
-        if (data_len < 200) {
-          DF("  Data: %s", result->data);
-        } else {
-          DF("  Data (first 200 chars): %.200s...", result->data);
-        }
-      }
-      pCur->rowid++;
-      break;
-
-    case STREAM_TYPE_ERROR:
-      // Stream error - stop iteration and set error message
-      DF("mcp_stream_next_impl: STREAM_TYPE_ERROR - %s", result->data ? result->data : "unknown error");
-      if (result->data) {
-        char *error_msg = mcp_extract_error_message(result->data);
-        if (error_msg) {
-          ((mcp_stream_vtab*)pCur->base.pVtab)->base.zErrMsg = sqlite3_mprintf("%s", error_msg);
-          mcp_free_string(error_msg);
-        } else {
-          // If not JSON error format, use the data directly
-          ((mcp_stream_vtab*)pCur->base.pVtab)->base.zErrMsg = sqlite3_mprintf("%s", result->data);
-        }
-      }
-      pCur->eof = 1;
-      mcp_stream_free_result(result);
-      return SQLITE_ERROR;
-
-    case STREAM_TYPE_DONE:
-      // Stream complete
-      D("mcp_stream_next_impl: STREAM_TYPE_DONE - stream complete");
-      pCur->eof = 1;
-      break;
-
-    default:
-      DF("mcp_stream_next_impl: Unknown result_type=%d", result->result_type);
-      pCur->eof = 1;
-      break;
+/*
+** SQL function: mcp_set_max_list_pages(max_pages)
+** Sets how many pages a list request follows before failing, 0 for no limit
+*/
+static void mcp_set_max_list_pages_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  sqlite3_int64 max_pages = sqlite3_value_int64(argv[0]);
+  if (sqlite3_value_type(argv[0]) != SQLITE_INTEGER || max_pages < 0 || max_pages > UINT32_MAX) {
+    sqlite3_result_error(context, "mcp_set_max_list_pages requires a non-negative integer number of pages", -1);
+    return;
   }
 
-  mcp_stream_free_result(result);
-  return SQLITE_OK;
+  mcp_set_max_list_pages((uint32_t)max_pages);
+  sqlite3_result_null(context);
 }
 
-static int mcp_stream_vtab_next(sqlite3_vtab_cursor *cur){
-  return mcp_stream_next_impl(cur);
-}
+/*
+** SQL function: mcp_set_max_response_bytes(max_bytes)
+** Sets the largest response accepted from a server, 0 for no limit
+*/
+static void mcp_set_max_response_bytes_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  sqlite3_int64 max_bytes = sqlite3_value_int64(argv[0]);
+  if (sqlite3_value_type(argv[0]) != SQLITE_INTEGER || max_bytes < 0 || max_bytes > UINT32_MAX) {
+    sqlite3_result_error(context, "mcp_set_max_response_bytes requires a non-negative integer number of bytes", -1);
+    return;
+  }
 
-static int mcp_stream_eof(sqlite3_vtab_cursor *cur){
-  mcp_stream_cursor *pCur = (mcp_stream_cursor*)cur;
-  return pCur->eof;
+  mcp_set_max_response_bytes((uint32_t)max_bytes);
+  sqlite3_result_null(context);
 }
 
-static int mcp_stream_column(sqlite3_vtab_cursor *cur, sqlite3_context *context, int iCol){
-  mcp_stream_cursor *pCur = (mcp_stream_cursor*)cur;
-
-  DF("mcp_stream_column: iCol=%d, rowid=%lld", iCol, pCur->rowid);
-
-  if (!pCur->current_data) {
-    D("mcp_stream_column: current_data is NULL");
-    sqlite3_result_null(context);
-    return SQLITE_OK;
+/*
+** SQL function: mcp_set_lossy_utf8(enabled)
+** When enabled is 1, invalid UTF-8 in server responses is replaced with U+FFFD instead of failing them
+*/
+static void mcp_set_lossy_utf8_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  sqlite3_int64 value = sqlite3_value_int64(argv[0]);
+  if (sqlite3_value_type(argv[0]) != SQLITE_INTEGER || (value != 0 && value != 1)) {
+    sqlite3_result_error(context, "enabled must be 0 or 1", -1);
+    return;
   }
 
-  // Parse JSON using Rust serde_json functions
-  const char *json = pCur->current_data;
+  mcp_set_lossy_utf8((int)value);
+  sqlite3_result_null(context);
+}
 
-  const char *field_name = NULL;
-  switch (iCol) {
-    case 0: field_name = "name"; break;
-    case 1: field_name = "title"; break;
-    case 2: field_name = "description"; break;
-    case 3: field_name = "inputSchema"; break;
-    case 4: field_name = "outputSchema"; break;
-    case 5: field_name = "annotations"; break;
-    default: sqlite3_result_null(context); return SQLITE_OK;
+/*
+** SQL function: mcp_set_notification_queue_size(size)
+** Sets how many notifications are kept until polled, 0 to stop queuing them
+*/
+static void mcp_set_notification_queue_size_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  sqlite3_int64 size = sqlite3_value_int64(argv[0]);
+  if (sqlite3_value_type(argv[0]) != SQLITE_INTEGER || size < 0 || size > UINT32_MAX) {
+    sqlite3_result_error(context, "mcp_set_notification_queue_size requires a non-negative integer number of notifications", -1);
+    return;
   }
 
-  DF("mcp_stream_column: Extracting field '%s'", field_name);
+  mcp_set_notification_queue_size((uint32_t)size);
+  sqlite3_result_null(context);
+}
 
-  // Parse JSON in the Rust layer
-  char *field_value = mcp_get_tool_field(json, 0, field_name); // index 0 for single tool
-  if (field_value && strlen(field_value) > 0) {
-    DF("mcp_stream_column: Field '%s' = '%s'", field_name, field_value);
-    sqlite3_result_text(context, field_value, -1, SQLITE_TRANSIENT);
-    mcp_free_string(field_value);
-  } else {
-    DF("mcp_stream_column: Field '%s' is NULL or empty", field_name);
-    sqlite3_result_null(context);
+/*
+** SQL function: mcp_set_auto_reconnect(max_retries, base_delay_ms, [retry_tool_calls])
+** Re-establishes dropped connections and retries the failed request with exponential backoff
+** Tool calls are only retried when retry_tool_calls is 1, since a tool may not be safe to run twice
+*/
+static void mcp_set_auto_reconnect_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 2 || argc > 3) {
+    sqlite3_result_error(context, "mcp_set_auto_reconnect requires 2-3 arguments: (max_retries, base_delay_ms, [retry_tool_calls])", -1);
+    return;
   }
 
-  return SQLITE_OK;
-}
+  for (int i = 0; i < 2; i++) {
+    sqlite3_int64 value = sqlite3_value_int64(argv[i]);
+    if (sqlite3_value_type(argv[i]) != SQLITE_INTEGER || value < 0 || value > UINT32_MAX) {
+      sqlite3_result_error(context, "mcp_set_auto_reconnect requires non-negative integer max_retries and base_delay_ms", -1);
+      return;
+    }
+  }
 
-static int mcp_stream_rowid(sqlite3_vtab_cursor *cur, sqlite_int64 *pRowid){
-  mcp_stream_cursor *pCur = (mcp_stream_cursor*)cur;
-  *pRowid = pCur->rowid;
-  return SQLITE_OK;
-}
+  int retry_tool_calls = (argc > 2) ? sqlite3_value_int(argv[2]) : 0;
 
-static int mcp_stream_best_index(sqlite3_vtab *tab, sqlite3_index_info *pIdxInfo){
-  pIdxInfo->estimatedCost = (double)10;
-  return SQLITE_OK;
+  mcp_set_auto_reconnect((uint32_t)sqlite3_value_int64(argv[0]), (uint32_t)sqlite3_value_int64(argv[1]), retry_tool_calls);
+  sqlite3_result_null(context);
 }
 
-static sqlite3_module mcp_stream_module = {
-  0,                          /* iVersion */
-  0,                          /* xCreate */
-  mcp_stream_connect,         /* xConnect */
-  mcp_stream_best_index,      /* xBestIndex */
-  mcp_stream_disconnect,      /* xDisconnect */
-  0,                          /* xDestroy */
-  mcp_stream_open,            /* xOpen - open a cursor */
-  mcp_stream_close,           /* xClose - close a cursor */
-  mcp_stream_filter,          /* xFilter - configure scan constraints */
-  mcp_stream_vtab_next,       /* xNext - advance a cursor */
-  mcp_stream_eof,             /* xEof - check for end of scan */
-  mcp_stream_column,          /* xColumn - read data */
-  mcp_stream_rowid,           /* xRowid - read data */
-  0,                          /* xUpdate */
-  0,                          /* xBegin */
-  0,                          /* xSync */
-  0,                          /* xCommit */
-  0,                          /* xRollback */
-  0,                          /* xFindMethod */
-  0,                          /* xRename */
-  0,                          /* xSavepoint */
-  0,                          /* xRelease */
-  0,                          /* xRollbackTo */
-  0,                          /* xShadowName */
-  0,                          /* xIntegrity */
-};
-
 /*
-** Virtual table for mcp_call_tool (streaming)
-** Returns streamed text chunks from tool calls
+** SQL function: mcp_set_redirect_policy(max_redirects, preserve_auth_cross_host)
+** Sets how many redirects HTTP requests follow, and whether redirects to another host keep the Authorization header
 */
-typedef struct mcp_call_tool_stream_vtab {
-  sqlite3_vtab base;
-} mcp_call_tool_stream_vtab;
-
-typedef struct mcp_call_tool_stream_cursor {
-  sqlite3_vtab_cursor base;
-  size_t stream_id;
-  char *current_text;
-  int eof;
-  sqlite_int64 rowid;
-} mcp_call_tool_stream_cursor;
-
-static int mcp_call_tool_stream_connect(
-  sqlite3 *db,
-  void *pAux,
-  int argc, const char *const*argv,
-  sqlite3_vtab **ppVtab,
-  char **pzErr
+static void mcp_set_redirect_policy_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
 ){
-  mcp_call_tool_stream_vtab *pNew = sqlite3_malloc(sizeof(*pNew));
-  if (pNew==0) return SQLITE_NOMEM;
-  memset(pNew, 0, sizeof(*pNew));
-
-  DF("mcp_call_tool_stream_connect: argc=%d", argc);
-  for (int i = 0; i < argc; i++) {
-    DF("  argv[%d]='%s'", i, argv[i] ? argv[i] : "NULL");
+  if (argc != 2) {
+    sqlite3_result_error(context, "mcp_set_redirect_policy requires 2 arguments: (max_redirects, preserve_auth_cross_host)", -1);
+    return;
   }
 
-  *ppVtab = (sqlite3_vtab*)pNew;
+  sqlite3_int64 max_redirects = sqlite3_value_int64(argv[0]);
+  if (sqlite3_value_type(argv[0]) != SQLITE_INTEGER || max_redirects < 0 || max_redirects > UINT32_MAX) {
+    sqlite3_result_error(context, "mcp_set_redirect_policy requires a non-negative integer max_redirects", -1);
+    return;
+  }
+  sqlite3_int64 preserve = sqlite3_value_int64(argv[1]);
+  if (sqlite3_value_type(argv[1]) != SQLITE_INTEGER || (preserve != 0 && preserve != 1)) {
+    sqlite3_result_error(context, "preserve_auth_cross_host must be 0 or 1", -1);
+    return;
+  }
 
-  int rc = sqlite3_declare_vtab(db, "CREATE TABLE x(text TEXT, tool_name HIDDEN, arguments HIDDEN)");
-  return rc;
+  mcp_set_redirect_policy((uint32_t)max_redirects, (int)preserve);
+  sqlite3_result_null(context);
 }
 
-static int mcp_call_tool_stream_disconnect(sqlite3_vtab *pVtab){
-  mcp_call_tool_stream_vtab *p = (mcp_call_tool_stream_vtab*)pVtab;
-  sqlite3_free(p);
-  return SQLITE_OK;
-}
+/*
+** SQL function: mcp_set_retry(max_attempts, backoff_ms)
+** Retries requests that fail with a transient transport error, 0 or 1 attempts to disable
+*/
+static void mcp_set_retry_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc != 2) {
+    sqlite3_result_error(context, "mcp_set_retry requires 2 arguments: (max_attempts, backoff_ms)", -1);
+    return;
+  }
 
-static int mcp_call_tool_stream_open(sqlite3_vtab *pVtab, sqlite3_vtab_cursor **ppCursor){
-  mcp_call_tool_stream_cursor *pCur = sqlite3_malloc(sizeof(*pCur));
-  if (pCur==0) return SQLITE_NOMEM;
-  memset(pCur, 0, sizeof(*pCur));
-  *ppCursor = (sqlite3_vtab_cursor*)pCur;
-  return SQLITE_OK;
-}
+  for (int i = 0; i < 2; i++) {
+    sqlite3_int64 value = sqlite3_value_int64(argv[i]);
+    if (sqlite3_value_type(argv[i]) != SQLITE_INTEGER || value < 0 || value > UINT32_MAX) {
+      sqlite3_result_error(context, "mcp_set_retry requires non-negative integer max_attempts and backoff_ms", -1);
+      return;
+    }
+  }
 
-static int mcp_call_tool_stream_close(sqlite3_vtab_cursor *cur){
-  mcp_call_tool_stream_cursor *pCur = (mcp_call_tool_stream_cursor*)cur;
+  mcp_set_retry((uint32_t)sqlite3_value_int64(argv[0]), (uint32_t)sqlite3_value_int64(argv[1]));
+  sqlite3_result_null(context);
+}
 
-  if (pCur->stream_id > 0) {
-    mcp_stream_cleanup(pCur->stream_id);
+/*
+** SQL function: mcp_set_initialize_retry(max_attempts, backoff_ms)
+** Retries connects that time out before the initialize handshake completes, 0 or 1 attempts to disable
+*/
+static void mcp_set_initialize_retry_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc != 2) {
+    sqlite3_result_error(context, "mcp_set_initialize_retry requires 2 arguments: (max_attempts, backoff_ms)", -1);
+    return;
   }
 
-  if (pCur->current_text) {
-    sqlite3_free(pCur->current_text);
+  for (int i = 0; i < 2; i++) {
+    sqlite3_int64 value = sqlite3_value_int64(argv[i]);
+    if (sqlite3_value_type(argv[i]) != SQLITE_INTEGER || value < 0 || value > UINT32_MAX) {
+      sqlite3_result_error(context, "mcp_set_initialize_retry requires non-negative integer max_attempts and backoff_ms", -1);
+      return;
+    }
   }
 
-  sqlite3_free(pCur);
-  return SQLITE_OK;
+  mcp_set_initialize_retry((uint32_t)sqlite3_value_int64(argv[0]), (uint32_t)sqlite3_value_int64(argv[1]));
+  sqlite3_result_null(context);
 }
 
-static int mcp_call_tool_stream_filter(
-  sqlite3_vtab_cursor *pVtabCursor,
-  int idxNum, const char *idxStr,
-  int argc, sqlite3_value **argv
+/*
+** SQL function: mcp_set_keepalive(interval_ms)
+** Sets how long a connection may stay idle before a keep-alive ping is sent, 0 to disable
+*/
+static void mcp_set_keepalive_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
 ){
-  mcp_call_tool_stream_cursor *pCur = (mcp_call_tool_stream_cursor*)pVtabCursor;
-
-  D("mcp_call_tool_stream_filter: called");
-  DF("  argc=%d, idxNum=%d", argc, idxNum);
-  for (int i = 0; i < argc; i++) {
-    DF("    argv[%d] = '%s'", i, sqlite3_value_text(argv[i]) ? (const char*)sqlite3_value_text(argv[i]) : "NULL");
+  sqlite3_int64 interval_ms = sqlite3_value_int64(argv[0]);
+  if (sqlite3_value_type(argv[0]) != SQLITE_INTEGER || interval_ms < 0 || interval_ms > UINT32_MAX) {
+    sqlite3_result_error(context, "mcp_set_keepalive requires a non-negative integer number of milliseconds", -1);
+    return;
   }
 
-  if (idxNum == 0) {
-    D("mcp_call_tool_stream_filter: Invalid query plan (idxNum=0)");
-    pCur->eof = 1;
-    return SQLITE_ERROR;
-  }
+  mcp_set_keepalive((uint32_t)interval_ms);
+  sqlite3_result_null(context);
+}
 
-  const char *tool_name = NULL;
-  const char *arguments = NULL;
-
-  // Function arguments come through the constraint system
-  if (argc >= 2) {
-    tool_name = (const char*)sqlite3_value_text(argv[0]);
-    arguments = (const char*)sqlite3_value_text(argv[1]);
-  } else {
-    D("mcp_call_tool_stream_filter: Not enough arguments");
-    pCur->eof = 1;
-    return SQLITE_ERROR;
+/*
+** SQL function: mcp_set_validate_args(enabled)
+** When enabled is 1, tool arguments are checked against the tool's input schema before calls are sent
+*/
+static void mcp_set_validate_args_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc != 1) {
+    sqlite3_result_error(context, "mcp_set_validate_args requires 1 argument: (enabled)", -1);
+    return;
   }
 
-  if (!tool_name || !arguments) {
-    D("mcp_call_tool_stream_filter: tool_name or arguments is NULL");
-    pCur->eof = 1;
-    return SQLITE_ERROR;
+  sqlite3_int64 value = sqlite3_value_int64(argv[0]);
+  if (sqlite3_value_type(argv[0]) != SQLITE_INTEGER || (value != 0 && value != 1)) {
+    sqlite3_result_error(context, "enabled must be 0 or 1", -1);
+    return;
   }
 
-  DF("mcp_call_tool_stream_filter: tool_name='%s', arguments='%s'", tool_name, arguments);
+  mcp_set_validate_args((int)value);
+  sqlite3_result_null(context);
+}
 
-  // Initialize streaming
-  pCur->stream_id = mcp_call_tool_init(tool_name, arguments);
-  DF("mcp_call_tool_stream_filter: stream_id=%d", (int)pCur->stream_id);
-  if (pCur->stream_id == 0) {
-    D("mcp_call_tool_stream_filter: stream_id is 0, failed");
-    pCur->eof = 1;
-    return SQLITE_ERROR;
+/*
+** SQL function: mcp_set_pretty(enabled)
+** When enabled is 1, functions returning JSON indent it for reading
+*/
+static void mcp_set_pretty_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc != 1) {
+    sqlite3_result_error(context, "mcp_set_pretty requires 1 argument: (enabled)", -1);
+    return;
   }
 
-  pCur->rowid = 0;
-  pCur->eof = 0;
-
-  // Get first result - use longer timeout for tool execution
-  StreamResult *result = mcp_stream_wait(pCur->stream_id, 5000); // 5 seconds timeout
-  if (!result) {
-    D("mcp_call_tool_stream_filter: No initial result");
-    pCur->eof = 1;
-    return SQLITE_OK;
+  sqlite3_int64 value = sqlite3_value_int64(argv[0]);
+  if (sqlite3_value_type(argv[0]) != SQLITE_INTEGER || (value != 0 && value != 1)) {
+    sqlite3_result_error(context, "enabled must be 0 or 1", -1);
+    return;
   }
 
-  if (result->result_type == STREAM_TYPE_DONE) {
-    D("mcp_call_tool_stream_filter: Stream immediately done");
-    pCur->eof = 1;
-    mcp_stream_free_result(result);
-    return SQLITE_OK;
+  mcp_set_pretty((int)value);
+  sqlite3_result_null(context);
+}
+
+/*
+** SQL function: mcp_set_validate_results(enabled)
+** When enabled is 1, the structured content of tool results is checked against the tool's output schema
+*/
+static void mcp_set_validate_results_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc != 1) {
+    sqlite3_result_error(context, "mcp_set_validate_results requires 1 argument: (enabled)", -1);
+    return;
   }
 
-  if (result->result_type == STREAM_TYPE_TEXT && result->data) {
-    pCur->current_text = sqlite3_mprintf("%s", result->data);
-    pCur->rowid++;
-    DF("mcp_call_tool_stream_filter: Got first text chunk (%d bytes)", (int)strlen(result->data));
+  sqlite3_int64 value = sqlite3_value_int64(argv[0]);
+  if (sqlite3_value_type(argv[0]) != SQLITE_INTEGER || (value != 0 && value != 1)) {
+    sqlite3_result_error(context, "enabled must be 0 or 1", -1);
+    return;
   }
 
-  mcp_stream_free_result(result);
-  return SQLITE_OK;
+  mcp_set_validate_results((int)value);
+  sqlite3_result_null(context);
 }
 
-static int mcp_call_tool_stream_next(sqlite3_vtab_cursor *cur){
-  mcp_call_tool_stream_cursor *pCur = (mcp_call_tool_stream_cursor*)cur;
-
-  if (pCur->current_text) {
-    sqlite3_free(pCur->current_text);
-    pCur->current_text = NULL;
+/*
+** SQL function: mcp_set_resolve_resources(enabled)
+** When enabled is 1, tool results return the contents of the resources they link to in place of the links
+*/
+static void mcp_set_resolve_resources_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc != 1) {
+    sqlite3_result_error(context, "mcp_set_resolve_resources requires 1 argument: (enabled)", -1);
+    return;
   }
 
-  StreamResult *result = mcp_stream_wait(pCur->stream_id, 1000); // 1 second timeout for subsequent results
-  if (!result) {
-    pCur->eof = 1;
-    return SQLITE_OK;
+  sqlite3_int64 value = sqlite3_value_int64(argv[0]);
+  if (sqlite3_value_type(argv[0]) != SQLITE_INTEGER || (value != 0 && value != 1)) {
+    sqlite3_result_error(context, "enabled must be 0 or 1", -1);
+    return;
   }
 
-  if (result->result_type == STREAM_TYPE_ERROR) {
-    // Stream error - stop iteration and set error message
-    DF("mcp_call_tool_stream_next: STREAM_TYPE_ERROR - %s", result->data ? result->data : "unknown error");
-    if (result->data) {
-      char *error_msg = mcp_extract_error_message(result->data);
-      if (error_msg) {
-        ((mcp_call_tool_stream_vtab*)pCur->base.pVtab)->base.zErrMsg = sqlite3_mprintf("%s", error_msg);
-        mcp_free_string(error_msg);
-      } else {
-        // If not JSON error format, use the data directly
-        ((mcp_call_tool_stream_vtab*)pCur->base.pVtab)->base.zErrMsg = sqlite3_mprintf("%s", result->data);
-      }
-    }
-    pCur->eof = 1;
-    mcp_stream_free_result(result);
-    return SQLITE_ERROR;
-  }
+  mcp_set_resolve_resources((int)value);
+  sqlite3_result_null(context);
+}
 
-  if (result->result_type == STREAM_TYPE_DONE) {
-    pCur->eof = 1;
-    mcp_stream_free_result(result);
-    return SQLITE_OK;
+/*
+** SQL function: mcp_set_include_request_id(enabled)
+** When enabled is 1, tool call results include the JSON-RPC id they were sent with
+*/
+static void mcp_set_include_request_id_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc != 1) {
+    sqlite3_result_error(context, "mcp_set_include_request_id requires 1 argument: (enabled)", -1);
+    return;
   }
 
-  if (result->result_type == STREAM_TYPE_TEXT && result->data) {
-    pCur->current_text = sqlite3_mprintf("%s", result->data);
-    pCur->rowid++;
+  sqlite3_int64 value = sqlite3_value_int64(argv[0]);
+  if (sqlite3_value_type(argv[0]) != SQLITE_INTEGER || (value != 0 && value != 1)) {
+    sqlite3_result_error(context, "enabled must be 0 or 1", -1);
+    return;
   }
 
-  mcp_stream_free_result(result);
-  return SQLITE_OK;
+  mcp_set_include_request_id((int)value);
+  sqlite3_result_null(context);
 }
 
-static int mcp_call_tool_stream_eof(sqlite3_vtab_cursor *cur){
-  mcp_call_tool_stream_cursor *pCur = (mcp_call_tool_stream_cursor*)cur;
-  return pCur->eof;
+/*
+** SQL function: mcp_set_lazy(enabled)
+** When enabled is 1, tool calls wait for a connect in progress on their connection to finish
+*/
+static void mcp_set_lazy_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc != 1) {
+    sqlite3_result_error(context, "mcp_set_lazy requires 1 argument: (enabled)", -1);
+    return;
+  }
+
+  sqlite3_int64 value = sqlite3_value_int64(argv[0]);
+  if (sqlite3_value_type(argv[0]) != SQLITE_INTEGER || (value != 0 && value != 1)) {
+    sqlite3_result_error(context, "enabled must be 0 or 1", -1);
+    return;
+  }
+
+  mcp_set_lazy((int)value);
+  sqlite3_result_null(context);
 }
 
-static int mcp_call_tool_stream_column(
-  sqlite3_vtab_cursor *cur,
+/*
+** SQL function: mcp_set_oauth(token_url, [client_id], [client_secret], [refresh_token])
+** Uses OAuth2 access tokens for streamable HTTP connections made afterwards
+** Pass NULL as token_url to stop using OAuth
+*/
+static void mcp_set_oauth_func(
   sqlite3_context *context,
-  int iCol
+  int argc,
+  sqlite3_value **argv
 ){
-  mcp_call_tool_stream_cursor *pCur = (mcp_call_tool_stream_cursor*)cur;
+  if (argc < 1 || argc > 4) {
+    sqlite3_result_error(context, "mcp_set_oauth requires 1-4 arguments: (token_url, [client_id], [client_secret], [refresh_token])", -1);
+    return;
+  }
 
-  if (iCol == 0 && pCur->current_text) {
-    sqlite3_result_text(context, pCur->current_text, -1, SQLITE_TRANSIENT);
+  const char *token_url = (const char*)sqlite3_value_text(argv[0]);
+  const char *client_id = (argc > 1) ? (const char*)sqlite3_value_text(argv[1]) : NULL;
+  const char *client_secret = (argc > 2) ? (const char*)sqlite3_value_text(argv[2]) : NULL;
+  const char *refresh_token = (argc > 3) ? (const char*)sqlite3_value_text(argv[3]) : NULL;
+
+  char *result = mcp_set_oauth(token_url, client_id, client_secret, refresh_token);
+  if (result) {
+    sqlite3_result_error(context, result, -1);
+    mcp_free_string(result);
   } else {
     sqlite3_result_null(context);
   }
-
-  return SQLITE_OK;
 }
 
-static int mcp_call_tool_stream_rowid(sqlite3_vtab_cursor *cur, sqlite_int64 *pRowid){
-  mcp_call_tool_stream_cursor *pCur = (mcp_call_tool_stream_cursor*)cur;
-  *pRowid = pCur->rowid;
-  return SQLITE_OK;
-}
+/*
+** SQL function: mcp_set_tls(ca_pem_path, [client_cert_pem], [client_key_pem], [insecure_skip_verify])
+** Trusts extra CA certificates and sets a client certificate for HTTP connections made afterwards
+** insecure_skip_verify must be the integer 1 to disable certificate checks
+*/
+static void mcp_set_tls_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 1 || argc > 4) {
+    sqlite3_result_error(context, "mcp_set_tls requires 1-4 arguments: (ca_pem_path, [client_cert_pem], [client_key_pem], [insecure_skip_verify])", -1);
+    return;
+  }
 
-static int mcp_call_tool_stream_best_index(sqlite3_vtab *tab, sqlite3_index_info *pIdxInfo){
-  D("mcp_call_tool_stream_best_index: called");
-  DF("  nConstraint=%d", pIdxInfo->nConstraint);
-  
-  int has_tool_name = 0, has_arguments = 0;
-  int has_function_constraint = 0;
-  
-  for (int i = 0; i < pIdxInfo->nConstraint; i++) {
-    DF("    constraint[%d]: iColumn=%d, op=%d, usable=%d", 
-       i, pIdxInfo->aConstraint[i].iColumn, 
-       pIdxInfo->aConstraint[i].op, 
-       pIdxInfo->aConstraint[i].usable);
-    
-    if (!pIdxInfo->aConstraint[i].usable) continue;
-    
-    // Check for function-style constraints (arguments passed directly to table function)
-    if (pIdxInfo->aConstraint[i].op == SQLITE_INDEX_CONSTRAINT_FUNCTION) {
-      has_function_constraint = 1;
-      pIdxInfo->aConstraintUsage[i].argvIndex = 1; // Use first position for function args
-      pIdxInfo->aConstraintUsage[i].omit = 1;
-      continue;
-    }
-    
-    // Check for WHERE clause constraints on hidden columns
-    if (pIdxInfo->aConstraint[i].op == SQLITE_INDEX_CONSTRAINT_EQ) {
-      if (pIdxInfo->aConstraint[i].iColumn == 1) { // tool_name HIDDEN column
-        has_tool_name = 1;
-        pIdxInfo->aConstraintUsage[i].argvIndex = has_function_constraint ? 3 : 1;
-        pIdxInfo->aConstraintUsage[i].omit = 1;
-      } else if (pIdxInfo->aConstraint[i].iColumn == 2) { // arguments HIDDEN column  
-        has_arguments = 1;
-        pIdxInfo->aConstraintUsage[i].argvIndex = has_function_constraint ? 4 : 2;
-        pIdxInfo->aConstraintUsage[i].omit = 1;
-      }
+  const char *ca_pem_path = (const char*)sqlite3_value_text(argv[0]);
+  const char *client_cert_pem = (argc > 1) ? (const char*)sqlite3_value_text(argv[1]) : NULL;
+  const char *client_key_pem = (argc > 2) ? (const char*)sqlite3_value_text(argv[2]) : NULL;
+
+  int insecure_skip_verify = 0;
+  if (argc > 3 && sqlite3_value_type(argv[3]) != SQLITE_NULL) {
+    sqlite3_int64 value = sqlite3_value_int64(argv[3]);
+    if (sqlite3_value_type(argv[3]) != SQLITE_INTEGER || (value != 0 && value != 1)) {
+      sqlite3_result_error(context, "insecure_skip_verify must be 0 or 1", -1);
+      return;
     }
+    insecure_skip_verify = (int)value;
   }
-  
-  if (has_function_constraint || (has_tool_name && has_arguments)) {
-    D("mcp_call_tool_stream_best_index: Found function constraint or both tool_name and arguments constraints");
-    pIdxInfo->estimatedCost = 100.0;
-    pIdxInfo->idxNum = 1; // Mark as valid query plan
+
+  char *result = mcp_set_tls(ca_pem_path, client_cert_pem, client_key_pem, insecure_skip_verify);
+  if (result) {
+    sqlite3_result_error(context, result, -1);
+    mcp_free_string(result);
   } else {
-    D("mcp_call_tool_stream_best_index: Missing required constraints");
-    pIdxInfo->estimatedCost = 1000000.0; // High cost for unsupported query
-    pIdxInfo->idxNum = 0;
+    sqlite3_result_null(context);
   }
-  
-  return SQLITE_OK;
 }
 
-static sqlite3_module mcp_call_tool_stream_module = {
-  0,                                   /* iVersion */
-  0,                                   /* xCreate */
-  mcp_call_tool_stream_connect,       /* xConnect */
-  mcp_call_tool_stream_best_index,    /* xBestIndex */
-  mcp_call_tool_stream_disconnect,    /* xDisconnect */
-  0,                                   /* xDestroy */
-  mcp_call_tool_stream_open,          /* xOpen */
-  mcp_call_tool_stream_close,         /* xClose */
-  mcp_call_tool_stream_filter,        /* xFilter */
-  mcp_call_tool_stream_next,          /* xNext */
-  mcp_call_tool_stream_eof,           /* xEof */
-  mcp_call_tool_stream_column,        /* xColumn */
-  mcp_call_tool_stream_rowid,         /* xRowid */
-  0,                                   /* xUpdate */
-  0,                                   /* xBegin */
-  0,                                   /* xSync */
-  0,                                   /* xCommit */
-  0,                                   /* xRollback */
-  0,                                   /* xFindMethod */
-  0,                                   /* xRename */
-  0,                                   /* xSavepoint */
-  0,                                   /* xRelease */
-  0,                                   /* xRollbackTo */
-  0,                                   /* xShadowName */
-  0,                                   /* xIntegrity */
-};
-
 /*
-** Virtual table for mcp_list_tools_respond (cached)
-** Returns parsed tool information as rows from cached temp table
+** SQL function: mcp_set_proxy(proxy_url)
+** Routes HTTP connections made afterwards through an http://, https:// or socks5:// proxy
+** Pass NULL to connect directly
 */
-typedef struct mcp_tools_vtab {
-  sqlite3_vtab base;
-  sqlite3 *db;
-  char *temp_table_name;  // Name of temporary table with cached results
-  int table_created;       // Flag to track if temp table was created
-} mcp_tools_vtab;
+static void mcp_set_proxy_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  const char *proxy_url = (const char*)sqlite3_value_text(argv[0]);
 
-typedef struct mcp_tools_cursor {
-  sqlite3_vtab_cursor base;
-  sqlite3_stmt *stmt;
-  int eof;
-} mcp_tools_cursor;
+  char *result = mcp_set_proxy(proxy_url);
+  if (result) {
+    sqlite3_result_error(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_null(context);
+  }
+}
+
+/*
+** SQL function: mcp_set_user_agent(user_agent)
+** Sets the User-Agent sent by connections made afterwards, replacing one given in their headers
+** Pass NULL to restore the default
+*/
+static void mcp_set_user_agent_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  const char *user_agent = (const char*)sqlite3_value_text(argv[0]);
+
+  char *result = mcp_set_user_agent(user_agent);
+  if (result) {
+    sqlite3_result_error(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_null(context);
+  }
+}
+
+/*
+** SQL function: mcp_set_local_address(ip)
+** Binds HTTP connections made afterwards to a local IPv4 or IPv6 address
+** Pass NULL to let the system choose
+*/
+static void mcp_set_local_address_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  const char *ip = (const char*)sqlite3_value_text(argv[0]);
+
+  char *result = mcp_set_local_address(ip);
+  if (result) {
+    sqlite3_result_error(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_null(context);
+  }
+}
+
+/*
+** SQL function: mcp_set_client_info(name, [version])
+** Sets the client name and version sent to servers on the next connect
+** Pass NULL to restore the default
+*/
+static void mcp_set_client_info_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 1 || argc > 2) {
+    sqlite3_result_error(context, "mcp_set_client_info requires 1-2 arguments: (name, [version])", -1);
+    return;
+  }
+
+  const char *name = (const char*)sqlite3_value_text(argv[0]);
+  const char *version = (argc > 1) ? (const char*)sqlite3_value_text(argv[1]) : NULL;
+
+  char *result = mcp_set_client_info(name, version);
+  if (result) {
+    sqlite3_result_error(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_null(context);
+  }
+}
+
+/*
+** SQL function: mcp_set_protocol_version(version)
+** Pins the MCP protocol version requested on the next connect, e.g. '2024-11-05'
+** Pass NULL to use the latest supported version
+*/
+static void mcp_set_protocol_version_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  const char *version = (const char*)sqlite3_value_text(argv[0]);
+
+  char *result = mcp_set_protocol_version(version);
+  if (result) {
+    sqlite3_result_error(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_null(context);
+  }
+}
+
+/*
+** SQL function: mcp_set_experimental_capabilities(capabilities_json)
+** Declares experimental capabilities on the next connect, as a JSON object of settings objects
+** Pass NULL to declare none
+*/
+static void mcp_set_experimental_capabilities_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  const char *capabilities_json = (const char*)sqlite3_value_text(argv[0]);
+
+  char *result = mcp_set_experimental_capabilities(capabilities_json);
+  if (result) {
+    sqlite3_result_error(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_null(context);
+  }
+}
+
+/*
+** SQL function: mcp_set_roots_json(roots_json)
+** Sets the roots (allowed directories) offered to servers as a JSON array of URIs
+** Pass NULL to clear the roots
+*/
+static void mcp_set_roots_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  const char *roots_json = (const char*)sqlite3_value_text(argv[0]);
+
+  char *result = mcp_set_roots_json(roots_json);
+  if (result) {
+    sqlite3_result_error(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_null(context);
+  }
+}
+
+/*
+** SQL function: mcp_set_tool_filter(filter_json)
+** Sets the allow and deny lists of tool names checked before every tool call
+** Pass NULL to allow every tool again
+*/
+static void mcp_set_tool_filter_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  const char *filter_json = (const char*)sqlite3_value_text(argv[0]);
+
+  char *result = mcp_set_tool_filter(filter_json);
+  if (result) {
+    sqlite3_result_error(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_null(context);
+  }
+}
+
+/*
+** SQL function: mcp_set_tool_defaults(tool_name, defaults_json)
+** Sets default arguments merged under the arguments of every call to the tool
+** Pass NULL as defaults_json to clear them
+*/
+static void mcp_set_tool_defaults_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  const char *tool_name = (const char*)sqlite3_value_text(argv[0]);
+  const char *defaults_json = (const char*)sqlite3_value_text(argv[1]);
+
+  char *result = mcp_set_tool_defaults(tool_name, defaults_json);
+  if (result) {
+    sqlite3_result_error(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_null(context);
+  }
+}
+
+/*
+** SQL function: mcp_cancel([connection_id])
+** Cancels the requests running on the given connection, or the default connection if none is given
+** Must be called from another database connection or thread than the one waiting on the request
+*/
+static void mcp_cancel_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc > 1) {
+    sqlite3_result_error(context, "mcp_cancel takes at most 1 argument: ([connection_id])", -1);
+    return;
+  }
+
+  mcp_cancel(mcp_connection_id_arg(argc, argv, 0));
+  sqlite3_result_null(context);
+}
+
+/*
+** SQL function: mcp_disconnect([connection_id])
+** Closes the given connection, or the default connection if none is given
+*/
+static void mcp_disconnect_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc > 1) {
+    sqlite3_result_error(context, "mcp_disconnect takes at most 1 argument: ([connection_id])", -1);
+    return;
+  }
+
+  char *result = mcp_disconnect(mcp_connection_id_arg(argc, argv, 0));
+  
+  // Should always return NULL (success)
+  if (!result) {
+    sqlite3_result_null(context);
+  } else {
+    sqlite3_result_text(context, result, -1, SQLITE_TRANSIENT);
+    mcp_free_string(result);
+  }
+}
+
+/*
+** SQL function: mcp_shutdown()
+** Closes every connection and stops their runtimes; later calls start again from a clean state
+*/
+static void mcp_shutdown_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  mcp_shutdown();
+  sqlite3_result_null(context);
+}
+
+/*
+** SQL function: mcp_ping([connection_id])
+** Returns NULL if the server answers a ping, or a JSON error otherwise
+*/
+static void mcp_ping_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc > 1) {
+    sqlite3_result_error(context, "mcp_ping takes at most 1 argument: ([connection_id])", -1);
+    return;
+  }
+
+  char *result = mcp_ping(mcp_connection_id_arg(argc, argv, 0));
+
+  if (!result) {
+    sqlite3_result_null(context);
+  } else {
+    sqlite3_result_text(context, result, -1, SQLITE_TRANSIENT);
+    mcp_free_string(result);
+  }
+}
+
+/*
+** SQL function: mcp_echo_latency_json([count], [connection_id])
+** Sends count pings (default 5) one after another and returns their round-trip times as JSON
+*/
+static void mcp_echo_latency_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc > 2) {
+    sqlite3_result_error(context, "mcp_echo_latency_json takes at most 2 arguments: ([count], [connection_id])", -1);
+    return;
+  }
+
+  sqlite3_int64 count = 5;
+  if (argc > 0 && sqlite3_value_type(argv[0]) != SQLITE_NULL) {
+    count = sqlite3_value_int64(argv[0]);
+    if (sqlite3_value_type(argv[0]) != SQLITE_INTEGER || count < 1 || count > 1000) {
+      sqlite3_result_error(context, "mcp_echo_latency_json requires a count between 1 and 1000", -1);
+      return;
+    }
+  }
+
+  char *result = mcp_echo_latency_json((uint32_t)count, mcp_connection_id_arg(argc, argv, 1));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to measure latency", -1);
+  }
+}
+
+/*
+** SQL function: mcp_set_server_log_level(level, [connection_id])
+** Returns NULL once the server accepted the level, or a JSON error otherwise
+*/
+static void mcp_set_server_log_level_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 1 || argc > 2) {
+    sqlite3_result_error(context, "mcp_set_server_log_level requires 1-2 arguments: (level, [connection_id])", -1);
+    return;
+  }
+
+  const char *level = (const char*)sqlite3_value_text(argv[0]);
+  if (!level) {
+    sqlite3_result_error(context, "mcp_set_server_log_level requires a level", -1);
+    return;
+  }
+
+  char *result = mcp_set_server_log_level(level, mcp_connection_id_arg(argc, argv, 1));
+
+  if (!result) {
+    sqlite3_result_null(context);
+  } else {
+    sqlite3_result_text(context, result, -1, SQLITE_TRANSIENT);
+    mcp_free_string(result);
+  }
+}
+
+/*
+** STREAMING Virtual Table for mcp_list_tools
+** Returns parsed tool information as rows using streaming API
+*/
+
+// Include Rust FFI streaming types
+typedef struct StreamResult {
+  int result_type;
+  char *data;
+} StreamResult;
+
+// Stream type constants (must match Rust)
+#define STREAM_TYPE_TOOL    0
+#define STREAM_TYPE_CONTENT 1
+#define STREAM_TYPE_ERROR   2
+#define STREAM_TYPE_DONE    3
+
+// Rust FFI streaming functions
+extern size_t mcp_list_tools_init(void);
+extern size_t mcp_call_tool_init(const char* tool_name, const char* arguments);
+extern StreamResult* mcp_stream_next(size_t stream_id);
+extern StreamResult* mcp_stream_wait(size_t stream_id, uint64_t timeout_ms);
+extern void mcp_stream_cleanup(size_t stream_id);
+extern void mcp_stream_free_result(StreamResult* result);
+
+// Rust FFI JSON functions
+extern char* mcp_list_tools_json(void*, const char*);
+extern char* mcp_call_tool_json(void*, const char*, const char*, const char*);
+extern char* mcp_list_resources_json(void*, const char*);
+extern char* mcp_read_resource_json(void*, const char*, const char*);
+extern char* mcp_read_resource_content(const char*, const char*, int32_t, uint8_t**, size_t*);
+extern void mcp_free_bytes(uint8_t*, size_t);
+extern char* mcp_list_prompts_json(void*, const char*);
+extern char* mcp_get_prompt_json(void*, const char*, const char*, const char*);
+extern char* mcp_status_json(void*, const char*);
+extern char* mcp_poll_notifications_json(void);
+extern char* mcp_server_logs_json(int32_t clear);
+extern char* mcp_events_json(uint64_t since);
+extern void mcp_free_string(char*);
+extern char* mcp_extract_error_message(const char*);
+
+// JSON parsing functions (using serde_json in Rust)
+extern size_t mcp_parse_tools_json(const char* json_str);
+extern char* mcp_get_tool_field(const char* json_str, size_t tool_index, const char* field_name);
+typedef struct McpList McpList;
+extern McpList* mcp_parse_list_json(const char* json_str, const char* list_key);
+extern size_t mcp_list_len(const McpList* list);
+extern char* mcp_get_list_field(const McpList* list, size_t index, const char* field_name, int32_t as_json);
+extern void mcp_free_list(McpList* list);
+extern size_t mcp_parse_call_result_json(const char* json_str);
+extern char* mcp_get_call_result_text(const char* json_str, size_t content_index);
+extern char* mcp_get_call_result_row(const char* json_str, size_t content_index);
+extern char* mcp_get_content_field(const char* json_str, const char* field_name);
+
+typedef struct mcp_stream_vtab {
+  sqlite3_vtab base;
+} mcp_stream_vtab;
+
+typedef struct mcp_stream_cursor {
+  sqlite3_vtab_cursor base;
+  size_t stream_id;           // Rust stream ID
+  char *current_data;         // Current tool JSON data
+  int eof;                    // End of stream flag
+  sqlite_int64 rowid;
+} mcp_stream_cursor;
+
+// Forward declarations
+static int mcp_stream_next_impl(sqlite3_vtab_cursor *cur);
+static int mcp_stream_vtab_next(sqlite3_vtab_cursor *cur);
+
+static int mcp_stream_connect(
+  sqlite3 *db,
+  void *pAux,
+  int argc, const char *const*argv,
+  sqlite3_vtab **ppVtab,
+  char **pzErr
+){
+  mcp_stream_vtab *pNew = sqlite3_malloc(sizeof(*pNew));
+  if (pNew==0) return SQLITE_NOMEM;
+  memset(pNew, 0, sizeof(*pNew));
+  *ppVtab = (sqlite3_vtab*)pNew;
+
+  int rc = sqlite3_declare_vtab(db,
+    "CREATE TABLE x(name TEXT, title TEXT, description TEXT, "
+    "inputSchema TEXT, outputSchema TEXT, annotations TEXT)");
+
+  return rc;
+}
+
+static int mcp_stream_disconnect(sqlite3_vtab *pVtab){
+  sqlite3_free(pVtab);
+  return SQLITE_OK;
+}
+
+static int mcp_stream_open(sqlite3_vtab *pVtab, sqlite3_vtab_cursor **ppCursor){
+  mcp_stream_cursor *pCur = sqlite3_malloc(sizeof(*pCur));
+  if (pCur==0) return SQLITE_NOMEM;
+  memset(pCur, 0, sizeof(*pCur));
+  *ppCursor = (sqlite3_vtab_cursor*)pCur;
+  return SQLITE_OK;
+}
+
+static int mcp_stream_close(sqlite3_vtab_cursor *cur){
+  mcp_stream_cursor *pCur = (mcp_stream_cursor*)cur;
+
+  // Cleanup stream resources
+  if (pCur->stream_id > 0) {
+    mcp_stream_cleanup(pCur->stream_id);
+  }
+
+  if (pCur->current_data) {
+    sqlite3_free(pCur->current_data);
+  }
+
+  sqlite3_free(pCur);
+  return SQLITE_OK;
+}
+
+static int mcp_stream_filter(
+  sqlite3_vtab_cursor *pVtabCursor,
+  int idxNum, const char *idxStr,
+  int argc, sqlite3_value **argv
+){
+  mcp_stream_cursor *pCur = (mcp_stream_cursor*)pVtabCursor;
+
+  D("mcp_stream_filter: Starting mcp_list_tools streaming");
+
+  // Initialize streaming
+  pCur->stream_id = mcp_list_tools_init();
+  DF("mcp_stream_filter: stream_id=%zu", pCur->stream_id);
+
+  if (pCur->stream_id == 0) {
+    D("mcp_stream_filter: Failed to initialize stream (stream_id=0)");
+    pCur->eof = 1;
+    return SQLITE_ERROR;
+  }
+
+  pCur->rowid = 0;
+  pCur->eof = 0;
+
+  D("mcp_stream_filter: Getting first result");
+  // Get first result
+  return mcp_stream_next_impl(pVtabCursor);
+}
+
+static int mcp_stream_next_impl(sqlite3_vtab_cursor *cur) {
+  mcp_stream_cursor *pCur = (mcp_stream_cursor*)cur;
+
+  DF("mcp_stream_next_impl: rowid=%lld, stream_id=%zu", pCur->rowid, pCur->stream_id);
+
+  // Free previous data
+  if (pCur->current_data) {
+    sqlite3_free(pCur->current_data);
+    pCur->current_data = NULL;
+  }
+
+  // Poll for next chunk (with 100ms wait if needed)
+  D("mcp_stream_next_impl: Waiting for next stream result...");
+  StreamResult *result = mcp_stream_wait(pCur->stream_id, 100);
+
+  if (!result) {
+    D("mcp_stream_next_impl: No result received (NULL)");
+    pCur->eof = 1;
+    return SQLITE_OK;
+  }
+
+  DF("mcp_stream_next_impl: Received result_type=%d", result->result_type);
+
+  switch (result->result_type) {
+    case STREAM_TYPE_TOOL:
+      // Store tool JSON data
+      if (result->data) {
+        size_t data_len = strlen(result->data);
+        pCur->current_data = sqlite3_mprintf("%s", result->data);
+        DF("mcp_stream_next_impl: STREAM_TYPE_TOOL received (%zu bytes)", data_len);
+        if (data_len < 200) {
+          DF("  Data: %s", result->data);
+        } else {
+          DF("  Data (first 200 chars): %.200s...", result->data);
+        }
+      }
+      pCur->rowid++;
+      break;
+
+    case STREAM_TYPE_ERROR:
+      // Stream error - stop iteration and set error message
+      DF("mcp_stream_next_impl: STREAM_TYPE_ERROR - %s", result->data ? result->data : "unknown error");
+      if (result->data) {
+        char *error_msg = mcp_extract_error_message(result->data);
+        if (error_msg) {
+          ((mcp_stream_vtab*)pCur->base.pVtab)->base.zErrMsg = sqlite3_mprintf("%s", error_msg);
+          mcp_free_string(error_msg);
+        } else {
+          // If not JSON error format, use the data directly
+          ((mcp_stream_vtab*)pCur->base.pVtab)->base.zErrMsg = sqlite3_mprintf("%s", result->data);
+        }
+      }
+      pCur->eof = 1;
+      mcp_stream_free_result(result);
+      return SQLITE_ERROR;
+
+    case STREAM_TYPE_DONE:
+      // Stream complete
+      D("mcp_stream_next_impl: STREAM_TYPE_DONE - stream complete");
+      pCur->eof = 1;
+      break;
+
+    default:
+      DF("mcp_stream_next_impl: Unknown result_type=%d", result->result_type);
+      pCur->eof = 1;
+      break;
+  }
+
+  mcp_stream_free_result(result);
+  return SQLITE_OK;
+}
+
+static int mcp_stream_vtab_next(sqlite3_vtab_cursor *cur){
+  return mcp_stream_next_impl(cur);
+}
+
+static int mcp_stream_eof(sqlite3_vtab_cursor *cur){
+  mcp_stream_cursor *pCur = (mcp_stream_cursor*)cur;
+  return pCur->eof;
+}
+
+static int mcp_stream_column(sqlite3_vtab_cursor *cur, sqlite3_context *context, int iCol){
+  mcp_stream_cursor *pCur = (mcp_stream_cursor*)cur;
+
+  DF("mcp_stream_column: iCol=%d, rowid=%lld", iCol, pCur->rowid);
+
+  if (!pCur->current_data) {
+    D("mcp_stream_column: current_data is NULL");
+    sqlite3_result_null(context);
+    return SQLITE_OK;
+  }
+
+  // Parse JSON using Rust serde_json functions
+  const char *json = pCur->current_data;
+
+  const char *field_name = NULL;
+  switch (iCol) {
+    case 0: field_name = "name"; break;
+    case 1: field_name = "title"; break;
+    case 2: field_name = "description"; break;
+    case 3: field_name = "inputSchema"; break;
+    case 4: field_name = "outputSchema"; break;
+    case 5: field_name = "annotations"; break;
+    default: sqlite3_result_null(context); return SQLITE_OK;
+  }
+
+  DF("mcp_stream_column: Extracting field '%s'", field_name);
+
+  // Parse JSON in the Rust layer
+  char *field_value = mcp_get_tool_field(json, 0, field_name); // index 0 for single tool
+  if (field_value && strlen(field_value) > 0) {
+    DF("mcp_stream_column: Field '%s' = '%s'", field_name, field_value);
+    sqlite3_result_text(context, field_value, -1, SQLITE_TRANSIENT);
+    mcp_free_string(field_value);
+  } else {
+    DF("mcp_stream_column: Field '%s' is NULL or empty", field_name);
+    sqlite3_result_null(context);
+  }
+
+  return SQLITE_OK;
+}
+
+static int mcp_stream_rowid(sqlite3_vtab_cursor *cur, sqlite_int64 *pRowid){
+  mcp_stream_cursor *pCur = (mcp_stream_cursor*)cur;
+  *pRowid = pCur->rowid;
+  return SQLITE_OK;
+}
+
+static int mcp_stream_best_index(sqlite3_vtab *tab, sqlite3_index_info *pIdxInfo){
+  pIdxInfo->estimatedCost = (double)10;
+  return SQLITE_OK;
+}
+
+static sqlite3_module mcp_stream_module = {
+  0,                          /* iVersion */
+  0,                          /* xCreate */
+  mcp_stream_connect,         /* xConnect */
+  mcp_stream_best_index,      /* xBestIndex */
+  mcp_stream_disconnect,      /* xDisconnect */
+  0,                          /* xDestroy */
+  mcp_stream_open,            /* xOpen - open a cursor */
+  mcp_stream_close,           /* xClose - close a cursor */
+  mcp_stream_filter,          /* xFilter - configure scan constraints */
+  mcp_stream_vtab_next,       /* xNext - advance a cursor */
+  mcp_stream_eof,             /* xEof - check for end of scan */
+  mcp_stream_column,          /* xColumn - read data */
+  mcp_stream_rowid,           /* xRowid - read data */
+  0,                          /* xUpdate */
+  0,                          /* xBegin */
+  0,                          /* xSync */
+  0,                          /* xCommit */
+  0,                          /* xRollback */
+  0,                          /* xFindMethod */
+  0,                          /* xRename */
+  0,                          /* xSavepoint */
+  0,                          /* xRelease */
+  0,                          /* xRollbackTo */
+  0,                          /* xShadowName */
+  0,                          /* xIntegrity */
+};
+
+/*
+** Virtual table for mcp_call_tool (streaming)
+** Returns streamed content items from tool calls, one row per item
+*/
+typedef struct mcp_call_tool_stream_vtab {
+  sqlite3_vtab base;
+} mcp_call_tool_stream_vtab;
+
+typedef struct mcp_call_tool_stream_cursor {
+  sqlite3_vtab_cursor base;
+  size_t stream_id;
+  char *current_content;      // Current content row JSON data
+  int eof;
+  sqlite_int64 rowid;
+} mcp_call_tool_stream_cursor;
+
+static int mcp_call_tool_stream_connect(
+  sqlite3 *db,
+  void *pAux,
+  int argc, const char *const*argv,
+  sqlite3_vtab **ppVtab,
+  char **pzErr
+){
+  mcp_call_tool_stream_vtab *pNew = sqlite3_malloc(sizeof(*pNew));
+  if (pNew==0) return SQLITE_NOMEM;
+  memset(pNew, 0, sizeof(*pNew));
+
+  DF("mcp_call_tool_stream_connect: argc=%d", argc);
+  for (int i = 0; i < argc; i++) {
+    DF("  argv[%d]='%s'", i, argv[i] ? argv[i] : "NULL");
+  }
+
+  *ppVtab = (sqlite3_vtab*)pNew;
+
+  int rc = sqlite3_declare_vtab(db,
+    "CREATE TABLE x(type TEXT, text TEXT, data TEXT, is_error INTEGER, "
+    "tool_name HIDDEN, arguments HIDDEN)");
+  return rc;
+}
+
+static int mcp_call_tool_stream_disconnect(sqlite3_vtab *pVtab){
+  mcp_call_tool_stream_vtab *p = (mcp_call_tool_stream_vtab*)pVtab;
+  sqlite3_free(p);
+  return SQLITE_OK;
+}
+
+static int mcp_call_tool_stream_open(sqlite3_vtab *pVtab, sqlite3_vtab_cursor **ppCursor){
+  mcp_call_tool_stream_cursor *pCur = sqlite3_malloc(sizeof(*pCur));
+  if (pCur==0) return SQLITE_NOMEM;
+  memset(pCur, 0, sizeof(*pCur));
+  *ppCursor = (sqlite3_vtab_cursor*)pCur;
+  return SQLITE_OK;
+}
+
+static int mcp_call_tool_stream_close(sqlite3_vtab_cursor *cur){
+  mcp_call_tool_stream_cursor *pCur = (mcp_call_tool_stream_cursor*)cur;
+
+  if (pCur->stream_id > 0) {
+    mcp_stream_cleanup(pCur->stream_id);
+  }
+
+  if (pCur->current_content) {
+    sqlite3_free(pCur->current_content);
+  }
+
+  sqlite3_free(pCur);
+  return SQLITE_OK;
+}
+
+static int mcp_call_tool_stream_filter(
+  sqlite3_vtab_cursor *pVtabCursor,
+  int idxNum, const char *idxStr,
+  int argc, sqlite3_value **argv
+){
+  mcp_call_tool_stream_cursor *pCur = (mcp_call_tool_stream_cursor*)pVtabCursor;
+
+  D("mcp_call_tool_stream_filter: called");
+  DF("  argc=%d, idxNum=%d", argc, idxNum);
+  for (int i = 0; i < argc; i++) {
+    DF("    argv[%d] = '%s'", i, sqlite3_value_text(argv[i]) ? (const char*)sqlite3_value_text(argv[i]) : "NULL");
+  }
+
+  if (idxNum == 0) {
+    D("mcp_call_tool_stream_filter: Invalid query plan (idxNum=0)");
+    pCur->eof = 1;
+    return SQLITE_ERROR;
+  }
+
+  const char *tool_name = NULL;
+  const char *arguments = NULL;
+
+  // Function arguments come through the constraint system
+  if (argc >= 2) {
+    tool_name = (const char*)sqlite3_value_text(argv[0]);
+    arguments = (const char*)sqlite3_value_text(argv[1]);
+  } else {
+    D("mcp_call_tool_stream_filter: Not enough arguments");
+    pCur->eof = 1;
+    return SQLITE_ERROR;
+  }
+
+  if (!tool_name || !arguments) {
+    D("mcp_call_tool_stream_filter: tool_name or arguments is NULL");
+    pCur->eof = 1;
+    return SQLITE_ERROR;
+  }
+
+  DF("mcp_call_tool_stream_filter: tool_name='%s', arguments='%s'", tool_name, arguments);
+
+  // Initialize streaming
+  pCur->stream_id = mcp_call_tool_init(tool_name, arguments);
+  DF("mcp_call_tool_stream_filter: stream_id=%d", (int)pCur->stream_id);
+  if (pCur->stream_id == 0) {
+    D("mcp_call_tool_stream_filter: stream_id is 0, failed");
+    pCur->eof = 1;
+    return SQLITE_ERROR;
+  }
+
+  pCur->rowid = 0;
+  pCur->eof = 0;
+
+  // Get first result - use longer timeout for tool execution
+  StreamResult *result = mcp_stream_wait(pCur->stream_id, 5000); // 5 seconds timeout
+  if (!result) {
+    D("mcp_call_tool_stream_filter: No initial result");
+    pCur->eof = 1;
+    return SQLITE_OK;
+  }
+
+  if (result->result_type == STREAM_TYPE_DONE) {
+    D("mcp_call_tool_stream_filter: Stream immediately done");
+    pCur->eof = 1;
+    mcp_stream_free_result(result);
+    return SQLITE_OK;
+  }
+
+  if (result->result_type == STREAM_TYPE_ERROR) {
+    // Stream error - stop iteration and set error message
+    DF("mcp_call_tool_stream_filter: STREAM_TYPE_ERROR - %s", result->data ? result->data : "unknown error");
+    if (result->data) {
+      char *error_msg = mcp_extract_error_message(result->data);
+      if (error_msg) {
+        ((mcp_call_tool_stream_vtab*)pCur->base.pVtab)->base.zErrMsg = sqlite3_mprintf("%s", error_msg);
+        mcp_free_string(error_msg);
+      } else {
+        // If not JSON error format, use the data directly
+        ((mcp_call_tool_stream_vtab*)pCur->base.pVtab)->base.zErrMsg = sqlite3_mprintf("%s", result->data);
+      }
+    }
+    pCur->eof = 1;
+    mcp_stream_free_result(result);
+    return SQLITE_ERROR;
+  }
+
+  if (result->result_type == STREAM_TYPE_CONTENT && result->data) {
+    pCur->current_content = sqlite3_mprintf("%s", result->data);
+    pCur->rowid++;
+    DF("mcp_call_tool_stream_filter: Got first content item (%d bytes)", (int)strlen(result->data));
+  }
+
+  mcp_stream_free_result(result);
+  return SQLITE_OK;
+}
+
+static int mcp_call_tool_stream_next(sqlite3_vtab_cursor *cur){
+  mcp_call_tool_stream_cursor *pCur = (mcp_call_tool_stream_cursor*)cur;
+
+  if (pCur->current_content) {
+    sqlite3_free(pCur->current_content);
+    pCur->current_content = NULL;
+  }
+
+  StreamResult *result = mcp_stream_wait(pCur->stream_id, 1000); // 1 second timeout for subsequent results
+  if (!result) {
+    pCur->eof = 1;
+    return SQLITE_OK;
+  }
+
+  if (result->result_type == STREAM_TYPE_ERROR) {
+    // Stream error - stop iteration and set error message
+    DF("mcp_call_tool_stream_next: STREAM_TYPE_ERROR - %s", result->data ? result->data : "unknown error");
+    if (result->data) {
+      char *error_msg = mcp_extract_error_message(result->data);
+      if (error_msg) {
+        ((mcp_call_tool_stream_vtab*)pCur->base.pVtab)->base.zErrMsg = sqlite3_mprintf("%s", error_msg);
+        mcp_free_string(error_msg);
+      } else {
+        // If not JSON error format, use the data directly
+        ((mcp_call_tool_stream_vtab*)pCur->base.pVtab)->base.zErrMsg = sqlite3_mprintf("%s", result->data);
+      }
+    }
+    pCur->eof = 1;
+    mcp_stream_free_result(result);
+    return SQLITE_ERROR;
+  }
+
+  if (result->result_type == STREAM_TYPE_DONE) {
+    pCur->eof = 1;
+    mcp_stream_free_result(result);
+    return SQLITE_OK;
+  }
+
+  if (result->result_type == STREAM_TYPE_CONTENT && result->data) {
+    pCur->current_content = sqlite3_mprintf("%s", result->data);
+    pCur->rowid++;
+  }
+
+  mcp_stream_free_result(result);
+  return SQLITE_OK;
+}
+
+static int mcp_call_tool_stream_eof(sqlite3_vtab_cursor *cur){
+  mcp_call_tool_stream_cursor *pCur = (mcp_call_tool_stream_cursor*)cur;
+  return pCur->eof;
+}
+
+static int mcp_call_tool_stream_column(
+  sqlite3_vtab_cursor *cur,
+  sqlite3_context *context,
+  int iCol
+){
+  mcp_call_tool_stream_cursor *pCur = (mcp_call_tool_stream_cursor*)cur;
+
+  const char *field_name = NULL;
+  switch (iCol) {
+    case 0: field_name = "type"; break;
+    case 1: field_name = "text"; break;
+    case 2: field_name = "data"; break;
+    case 3: field_name = "is_error"; break;
+    default: sqlite3_result_null(context); return SQLITE_OK;
+  }
+
+  if (!pCur->current_content) {
+    sqlite3_result_null(context);
+    return SQLITE_OK;
+  }
+
+  // Parse JSON in the Rust layer
+  char *value = mcp_get_content_field(pCur->current_content, field_name);
+  if (!value) {
+    sqlite3_result_null(context);
+  } else if (iCol == 3) {
+    sqlite3_result_int(context, strcmp(value, "true") == 0);
+  } else {
+    sqlite3_result_text(context, value, -1, SQLITE_TRANSIENT);
+  }
+  if (value) mcp_free_string(value);
+
+  return SQLITE_OK;
+}
+
+static int mcp_call_tool_stream_rowid(sqlite3_vtab_cursor *cur, sqlite_int64 *pRowid){
+  mcp_call_tool_stream_cursor *pCur = (mcp_call_tool_stream_cursor*)cur;
+  *pRowid = pCur->rowid;
+  return SQLITE_OK;
+}
+
+static int mcp_call_tool_stream_best_index(sqlite3_vtab *tab, sqlite3_index_info *pIdxInfo){
+  D("mcp_call_tool_stream_best_index: called");
+  DF("  nConstraint=%d", pIdxInfo->nConstraint);
+  
+  int has_tool_name = 0, has_arguments = 0;
+  int has_function_constraint = 0;
+  
+  for (int i = 0; i < pIdxInfo->nConstraint; i++) {
+    DF("    constraint[%d]: iColumn=%d, op=%d, usable=%d", 
+       i, pIdxInfo->aConstraint[i].iColumn, 
+       pIdxInfo->aConstraint[i].op, 
+       pIdxInfo->aConstraint[i].usable);
+    
+    if (!pIdxInfo->aConstraint[i].usable) continue;
+    
+    // Check for function-style constraints (arguments passed directly to table function)
+    if (pIdxInfo->aConstraint[i].op == SQLITE_INDEX_CONSTRAINT_FUNCTION) {
+      has_function_constraint = 1;
+      pIdxInfo->aConstraintUsage[i].argvIndex = 1; // Use first position for function args
+      pIdxInfo->aConstraintUsage[i].omit = 1;
+      continue;
+    }
+    
+    // Check for WHERE clause constraints on hidden columns
+    if (pIdxInfo->aConstraint[i].op == SQLITE_INDEX_CONSTRAINT_EQ) {
+      if (pIdxInfo->aConstraint[i].iColumn == 4) { // tool_name HIDDEN column
+        has_tool_name = 1;
+        pIdxInfo->aConstraintUsage[i].argvIndex = has_function_constraint ? 3 : 1;
+        pIdxInfo->aConstraintUsage[i].omit = 1;
+      } else if (pIdxInfo->aConstraint[i].iColumn == 5) { // arguments HIDDEN column  
+        has_arguments = 1;
+        pIdxInfo->aConstraintUsage[i].argvIndex = has_function_constraint ? 4 : 2;
+        pIdxInfo->aConstraintUsage[i].omit = 1;
+      }
+    }
+  }
+  
+  if (has_function_constraint || (has_tool_name && has_arguments)) {
+    D("mcp_call_tool_stream_best_index: Found function constraint or both tool_name and arguments constraints");
+    pIdxInfo->estimatedCost = 100.0;
+    pIdxInfo->idxNum = 1; // Mark as valid query plan
+  } else {
+    D("mcp_call_tool_stream_best_index: Missing required constraints");
+    pIdxInfo->estimatedCost = 1000000.0; // High cost for unsupported query
+    pIdxInfo->idxNum = 0;
+  }
+  
+  return SQLITE_OK;
+}
+
+static sqlite3_module mcp_call_tool_stream_module = {
+  0,                                   /* iVersion */
+  0,                                   /* xCreate */
+  mcp_call_tool_stream_connect,       /* xConnect */
+  mcp_call_tool_stream_best_index,    /* xBestIndex */
+  mcp_call_tool_stream_disconnect,    /* xDisconnect */
+  0,                                   /* xDestroy */
+  mcp_call_tool_stream_open,          /* xOpen */
+  mcp_call_tool_stream_close,         /* xClose */
+  mcp_call_tool_stream_filter,        /* xFilter */
+  mcp_call_tool_stream_next,          /* xNext */
+  mcp_call_tool_stream_eof,           /* xEof */
+  mcp_call_tool_stream_column,        /* xColumn */
+  mcp_call_tool_stream_rowid,         /* xRowid */
+  0,                                   /* xUpdate */
+  0,                                   /* xBegin */
+  0,                                   /* xSync */
+  0,                                   /* xCommit */
+  0,                                   /* xRollback */
+  0,                                   /* xFindMethod */
+  0,                                   /* xRename */
+  0,                                   /* xSavepoint */
+  0,                                   /* xRelease */
+  0,                                   /* xRollbackTo */
+  0,                                   /* xShadowName */
+  0,                                   /* xIntegrity */
+};
+
+/*
+** Virtual table for mcp_list_tools_respond (cached)
+** Returns parsed tool information as rows from cached temp table
+*/
+typedef struct mcp_tools_vtab {
+  sqlite3_vtab base;
+  sqlite3 *db;
+  char *temp_table_name;  // Name of temporary table with cached results
+  int table_created;       // Flag to track if temp table was created
+} mcp_tools_vtab;
+
+typedef struct mcp_tools_cursor {
+  sqlite3_vtab_cursor base;
+  sqlite3_stmt *stmt;
+  int eof;
+} mcp_tools_cursor;
 
 static int mcp_tools_connect(
   sqlite3 *db,
@@ -717,622 +1806,2844 @@ static int mcp_tools_connect(
   sqlite3_vtab **ppVtab,
   char **pzErr
 ){
-  mcp_tools_vtab *pNew = sqlite3_malloc(sizeof(*pNew));
-  if (pNew==0) return SQLITE_NOMEM;
+  mcp_tools_vtab *pNew = sqlite3_malloc(sizeof(*pNew));
+  if (pNew==0) return SQLITE_NOMEM;
+
+  pNew->db = db;
+  pNew->temp_table_name = sqlite3_mprintf("mcp_tools_cache_%p", (void*)pNew);
+  pNew->table_created = 0;
+  *ppVtab = (sqlite3_vtab*)pNew;
+
+  return sqlite3_declare_vtab(db,
+    "CREATE TABLE x(name TEXT, title TEXT, description TEXT, "
+    "inputSchema TEXT, outputSchema TEXT, annotations TEXT)");
+}
+
+static int mcp_tools_disconnect(sqlite3_vtab *pVtab){
+  mcp_tools_vtab *p = (mcp_tools_vtab*)pVtab;
+
+  // Drop temporary table if it was created
+  if (p->table_created && p->temp_table_name) {
+    char *drop_sql = sqlite3_mprintf("DROP TABLE IF EXISTS temp.%s", p->temp_table_name);
+    if (drop_sql) {
+      sqlite3_exec(p->db, drop_sql, NULL, NULL, NULL);
+      sqlite3_free(drop_sql);
+    }
+  }
+
+  // Free temp table name
+  if (p->temp_table_name) {
+    sqlite3_free(p->temp_table_name);
+  }
+
+  sqlite3_free(p);
+  return SQLITE_OK;
+}
+
+static int mcp_tools_open(sqlite3_vtab *pVtab, sqlite3_vtab_cursor **ppCursor){
+  mcp_tools_cursor *pCur = sqlite3_malloc(sizeof(*pCur));
+  if (pCur==0) return SQLITE_NOMEM;
+  memset(pCur, 0, sizeof(*pCur));
+  *ppCursor = (sqlite3_vtab_cursor*)pCur;
+  return SQLITE_OK;
+}
+
+static int mcp_tools_close(sqlite3_vtab_cursor *cur){
+  mcp_tools_cursor *pCur = (mcp_tools_cursor*)cur;
+  if (pCur->stmt) {
+    sqlite3_finalize(pCur->stmt);
+  }
+  sqlite3_free(pCur);
+  return SQLITE_OK;
+}
+
+static int mcp_tools_filter(
+  sqlite3_vtab_cursor *pVtabCursor,
+  int idxNum, const char *idxStr,
+  int argc, sqlite3_value **argv
+){
+  mcp_tools_cursor *pCur = (mcp_tools_cursor*)pVtabCursor;
+  mcp_tools_vtab *pVtab = (mcp_tools_vtab*)pVtabCursor->pVtab;
+
+  if (pCur->stmt) {
+    sqlite3_finalize(pCur->stmt);
+    pCur->stmt = NULL;
+  }
+
+  // Create and populate temp table if not already done
+  if (!pVtab->table_created) {
+    D("mcp_tools_filter: Creating temp table");
+    // Get tools list from MCP
+    char *result = mcp_list_tools_json(NULL, NULL);
+    if (!result) {
+      D("mcp_tools_filter: mcp_list_tools_json returned NULL");
+      pCur->eof = 1;
+      return SQLITE_OK;
+    }
+
+    DF("mcp_tools_filter: Got JSON result (%d bytes)", (int)strlen(result));
+    // Check for errors and extract error message
+    char *error_msg = mcp_extract_error_message(result);
+    if (error_msg) {
+      D("mcp_tools_filter: JSON contains error");
+      pVtab->base.zErrMsg = sqlite3_mprintf("%s", error_msg);
+      mcp_free_string(error_msg);
+      mcp_free_string(result);
+      pCur->eof = 1;
+      return SQLITE_ERROR;
+    }
+
+    // Create temporary table
+    char *create_sql = sqlite3_mprintf(
+      "CREATE TEMP TABLE IF NOT EXISTS %s("
+      "name TEXT, title TEXT, description TEXT, "
+      "inputSchema TEXT, outputSchema TEXT, annotations TEXT)",
+      pVtab->temp_table_name);
+
+    int rc = sqlite3_exec(pVtab->db, create_sql, NULL, NULL, NULL);
+    sqlite3_free(create_sql);
+
+    if (rc != SQLITE_OK) {
+      mcp_free_string(result);
+      pCur->eof = 1;
+      return rc;
+    }
+
+    // Parse JSON in the Rust layer
+    size_t tool_count = mcp_parse_tools_json(result);
+    DF("mcp_tools_filter: Parsed %d tools", (int)tool_count);
+    if (tool_count == 0) {
+      D("mcp_tools_filter: No tools found in JSON");
+      mcp_free_string(result);
+      pCur->eof = 1;
+      return SQLITE_OK;
+    }
+
+    // Insert each tool using Rust JSON parsing
+    for (size_t i = 0; i < tool_count; i++) {
+      char *name = mcp_get_tool_field(result, i, "name");
+      char *title = mcp_get_tool_field(result, i, "title");  
+      char *description = mcp_get_tool_field(result, i, "description");
+      char *inputSchema = mcp_get_tool_field(result, i, "inputSchema");
+      char *outputSchema = mcp_get_tool_field(result, i, "outputSchema");
+      char *annotations = mcp_get_tool_field(result, i, "annotations");
+
+      char *insert_sql = sqlite3_mprintf(
+        "INSERT INTO temp.%s (name, title, description, inputSchema, outputSchema, annotations) "
+        "VALUES (%Q, %Q, %Q, %Q, %Q, %Q)",
+        pVtab->temp_table_name,
+        name ? name : "",
+        title ? title : "",
+        description ? description : "",
+        inputSchema ? inputSchema : "",
+        outputSchema ? outputSchema : "",
+        annotations ? annotations : ""
+      );
+
+      rc = sqlite3_exec(pVtab->db, insert_sql, NULL, NULL, NULL);
+      sqlite3_free(insert_sql);
+
+      // Free the allocated strings
+      if (name) mcp_free_string(name);
+      if (title) mcp_free_string(title);
+      if (description) mcp_free_string(description);
+      if (inputSchema) mcp_free_string(inputSchema);
+      if (outputSchema) mcp_free_string(outputSchema);
+      if (annotations) mcp_free_string(annotations);
+
+      if (rc != SQLITE_OK) {
+        mcp_free_string(result);
+        pCur->eof = 1;
+        return rc;
+      }
+    }
+    mcp_free_string(result);
+
+    if (rc != SQLITE_OK) {
+      pCur->eof = 1;
+      return rc;
+    }
+
+    pVtab->table_created = 1;
+  }
+
+  // Query the temp table
+  char *query = sqlite3_mprintf("SELECT * FROM temp.%s", pVtab->temp_table_name);
+  int rc = sqlite3_prepare_v2(pVtab->db, query, -1, &pCur->stmt, NULL);
+  sqlite3_free(query);
+
+  if (rc != SQLITE_OK) {
+    pCur->eof = 1;
+    return rc;
+  }
+
+  // Move to first row
+  rc = sqlite3_step(pCur->stmt);
+  pCur->eof = (rc != SQLITE_ROW);
+
+  return SQLITE_OK;
+}
+
+static int mcp_tools_next(sqlite3_vtab_cursor *cur){
+  mcp_tools_cursor *pCur = (mcp_tools_cursor*)cur;
+
+  if (pCur->stmt) {
+    int rc = sqlite3_step(pCur->stmt);
+    pCur->eof = (rc != SQLITE_ROW);
+  } else {
+    pCur->eof = 1;
+  }
+
+  return SQLITE_OK;
+}
+
+static int mcp_tools_eof(sqlite3_vtab_cursor *cur){
+  mcp_tools_cursor *pCur = (mcp_tools_cursor*)cur;
+  return pCur->eof;
+}
+
+static int mcp_tools_column(
+  sqlite3_vtab_cursor *cur,
+  sqlite3_context *ctx,
+  int i
+){
+  mcp_tools_cursor *pCur = (mcp_tools_cursor*)cur;
+
+  if (!pCur->stmt || pCur->eof) {
+    sqlite3_result_null(ctx);
+    return SQLITE_OK;
+  }
+
+  const unsigned char *text = sqlite3_column_text(pCur->stmt, i);
+  if (text) {
+    sqlite3_result_text(ctx, (const char*)text, -1, SQLITE_TRANSIENT);
+  } else {
+    sqlite3_result_null(ctx);
+  }
+
+  return SQLITE_OK;
+}
+
+static int mcp_tools_rowid(sqlite3_vtab_cursor *cur, sqlite_int64 *pRowid){
+  *pRowid = 0;
+  return SQLITE_OK;
+}
+
+static int mcp_tools_best_index(sqlite3_vtab *tab, sqlite3_index_info *pIdxInfo){
+  pIdxInfo->estimatedCost = 1000.0;
+  return SQLITE_OK;
+}
+
+static sqlite3_module mcp_tools_module = {
+  0,                         /* iVersion */
+  0,                         /* xCreate */
+  mcp_tools_connect,         /* xConnect */
+  mcp_tools_best_index,      /* xBestIndex */
+  mcp_tools_disconnect,      /* xDisconnect */
+  0,                         /* xDestroy */
+  mcp_tools_open,            /* xOpen - open a cursor */
+  mcp_tools_close,           /* xClose - close a cursor */
+  mcp_tools_filter,          /* xFilter - configure scan constraints */
+  mcp_tools_next,            /* xNext - advance a cursor */
+  mcp_tools_eof,             /* xEof - check for end of scan */
+  mcp_tools_column,          /* xColumn - read data */
+  mcp_tools_rowid,           /* xRowid - read data */
+  0,                         /* xUpdate */
+  0,                         /* xBegin */
+  0,                         /* xSync */
+  0,                         /* xCommit */
+  0,                         /* xRollback */
+  0,                         /* xFindMethod */
+  0,                         /* xRename */
+  0,                         /* xSavepoint */
+  0,                         /* xRelease */
+  0,                         /* xRollbackTo */
+  0,                         /* xShadowName */
+  0,                         /* xIntegrity */
+};
+
+/*
+** Virtual table for mcp_call_tool_respond (non-streaming, no caching)
+** Returns each content item of a fresh tool call as a row, numbered by seq
+*/
+typedef struct mcp_results_vtab {
+  sqlite3_vtab base;
+  sqlite3 *db;
+  char *tool_name;    // Stored from function arguments
+  char *arguments;    // Stored from function arguments
+} mcp_results_vtab;
+
+typedef struct mcp_results_cursor {
+  sqlite3_vtab_cursor base;
+  char *json_result;     // Stored JSON result from tool call (no caching)
+  size_t content_count;  // Number of content items in result
+  size_t current_index;  // Current row index
+  char *current_row;     // Current content item as a row (see mcp_get_call_result_row)
+  int eof;
+} mcp_results_cursor;
+
+/* Load the row of the cursor's current content item */
+static void mcp_results_load_row(mcp_results_cursor *pCur){
+  if (pCur->current_row) {
+    mcp_free_string(pCur->current_row);
+    pCur->current_row = NULL;
+  }
+  if (pCur->json_result && pCur->current_index < pCur->content_count) {
+    pCur->current_row = mcp_get_call_result_row(pCur->json_result, pCur->current_index);
+  }
+}
+
+static int mcp_results_connect(
+  sqlite3 *db,
+  void *pAux,
+  int argc, const char *const*argv,
+  sqlite3_vtab **ppVtab,
+  char **pzErr
+){
+  mcp_results_vtab *pNew = sqlite3_malloc(sizeof(*pNew));
+  if (pNew==0) return SQLITE_NOMEM;
+  memset(pNew, 0, sizeof(*pNew));
+
+  pNew->db = db;
+
+  DF("mcp_results_connect: argc=%d", argc);
+  for (int i = 0; i < argc; i++) {
+    DF("  argv[%d] = '%s'", i, argv[i] ? argv[i] : "NULL");
+  }
+
+  // For table-valued functions: SELECT * FROM mcp_call_tool_respond('tool', 'args')
+  // argc will be 5: module_name, db_name, table_name, tool_name, arguments
+  if (argc >= 5) {
+    pNew->tool_name = sqlite3_mprintf("%s", argv[3]);
+    pNew->arguments = sqlite3_mprintf("%s", argv[4]);
+    DF("mcp_call_tool_respond table-valued function args: tool_name='%s', arguments='%s'", argv[3], argv[4]);
+  } else {
+    D("mcp_call_tool_respond: No function arguments, will expect constraints");
+  }
+
+  *ppVtab = (sqlite3_vtab*)pNew;
+
+  return sqlite3_declare_vtab(db,
+    "CREATE TABLE x(text TEXT, seq INTEGER, type TEXT, data TEXT, is_error INTEGER, tool_name HIDDEN, arguments HIDDEN)");
+}
+
+static int mcp_results_disconnect(sqlite3_vtab *pVtab){
+  mcp_results_vtab *p = (mcp_results_vtab*)pVtab;
+  if (p->tool_name) sqlite3_free(p->tool_name);
+  if (p->arguments) sqlite3_free(p->arguments);
+  sqlite3_free(p);
+  return SQLITE_OK;
+}
+
+static int mcp_results_open(sqlite3_vtab *pVtab, sqlite3_vtab_cursor **ppCursor){
+  mcp_results_cursor *pCur = sqlite3_malloc(sizeof(*pCur));
+  if (pCur==0) return SQLITE_NOMEM;
+  memset(pCur, 0, sizeof(*pCur));
+  pCur->eof = 1;
+  *ppCursor = (sqlite3_vtab_cursor*)pCur;
+  return SQLITE_OK;
+}
+
+static int mcp_results_close(sqlite3_vtab_cursor *cur){
+  mcp_results_cursor *pCur = (mcp_results_cursor*)cur;
+  if (pCur->json_result) {
+    mcp_free_string(pCur->json_result);
+  }
+  if (pCur->current_row) {
+    mcp_free_string(pCur->current_row);
+  }
+  sqlite3_free(pCur);
+  return SQLITE_OK;
+}
+
+static int mcp_results_filter(
+  sqlite3_vtab_cursor *pVtabCursor,
+  int idxNum, const char *idxStr,
+  int argc, sqlite3_value **argv
+){
+  mcp_results_cursor *pCur = (mcp_results_cursor*)pVtabCursor;
+  mcp_results_vtab *pVtab = (mcp_results_vtab*)pVtabCursor->pVtab;
+
+  // Free any previous result
+  if (pCur->json_result) {
+    mcp_free_string(pCur->json_result);
+    pCur->json_result = NULL;
+  }
+
+  D("mcp_results_filter: called");
+  DF("  argc=%d, idxNum=%d", argc, idxNum);
+  for (int i = 0; i < argc; i++) {
+    DF("    argv[%d] = '%s'", i, sqlite3_value_text(argv[i]) ? (const char*)sqlite3_value_text(argv[i]) : "NULL");
+  }
+
+  // For table-valued functions, arguments were stored in xConnect
+  // If not available, try to get them from filter constraints
+  const char *tool_name = pVtab->tool_name;
+  const char *arguments = pVtab->arguments;
+
+  if (!tool_name || !arguments) {
+    D("mcp_results_filter: No arguments from xConnect, checking constraints");
+    if (argc >= 2) {
+      tool_name = (const char*)sqlite3_value_text(argv[0]);
+      arguments = (const char*)sqlite3_value_text(argv[1]);
+      DF("mcp_results_filter: Using constraint args: tool_name='%s', arguments='%s'",
+         tool_name ? tool_name : "NULL", arguments ? arguments : "NULL");
+    }
+  }
+
+  if (!tool_name || !arguments) {
+    D("mcp_results_filter: Missing tool_name or arguments from both xConnect and constraints");
+    pCur->eof = 1;
+    return SQLITE_ERROR;
+  }
+
+  D("mcp_results_filter: Using arguments from xConnect");
+  DF("  tool_name='%s', arguments='%s'", tool_name, arguments);
+
+  // Call the tool - NO CACHING, fresh call every time
+  pCur->json_result = mcp_call_tool_json(NULL, tool_name, arguments, NULL);
+  if (!pCur->json_result) {
+    D("  Tool call failed");
+    pCur->eof = 1;
+    return SQLITE_OK;
+  }
+
+  // Parse JSON in the Rust layer
+  DF("  Tool result (%d bytes): %.200s%s", (int)strlen(pCur->json_result),
+     pCur->json_result, strlen(pCur->json_result) > 200 ? "..." : "");
+
+  // Check for errors first
+  char *error_msg = mcp_extract_error_message(pCur->json_result);
+  if (error_msg) {
+    D("  JSON contains error");
+    pVtab->base.zErrMsg = sqlite3_mprintf("%s", error_msg);
+    mcp_free_string(error_msg);
+    pCur->eof = 1;
+    return SQLITE_ERROR;
+  }
+
+  pCur->content_count = mcp_parse_call_result_json(pCur->json_result);
+  DF("  Parsed content count: %d", (int)pCur->content_count);
+
+  if (pCur->content_count == 0) {
+    D("  No content parsed");
+    pCur->eof = 1;
+    return SQLITE_OK;
+  }
+
+  // Start at first result - no temp table, no caching
+  pCur->current_index = 0;
+  pCur->eof = 0;
+  mcp_results_load_row(pCur);
+
+  return SQLITE_OK;
+}
+
+static int mcp_results_next(sqlite3_vtab_cursor *cur){
+  mcp_results_cursor *pCur = (mcp_results_cursor*)cur;
+
+  pCur->current_index++;
+  if (pCur->current_index >= pCur->content_count) {
+    pCur->eof = 1;
+  }
+  mcp_results_load_row(pCur);
+
+  return SQLITE_OK;
+}
+
+static int mcp_results_eof(sqlite3_vtab_cursor *cur){
+  mcp_results_cursor *pCur = (mcp_results_cursor*)cur;
+  return pCur->eof;
+}
+
+static int mcp_results_column(
+  sqlite3_vtab_cursor *cur,
+  sqlite3_context *ctx,
+  int i
+){
+  mcp_results_cursor *pCur = (mcp_results_cursor*)cur;
+
+  const char *field_name;
+  switch (i) {
+    case 0: field_name = "text"; break;
+    case 1: sqlite3_result_int64(ctx, (sqlite3_int64)pCur->current_index); return SQLITE_OK;
+    case 2: field_name = "type"; break;
+    case 3: field_name = "data"; break;
+    case 4: field_name = "is_error"; break;
+    default: sqlite3_result_null(ctx); return SQLITE_OK;
+  }
+
+  if (!pCur->current_row) {
+    sqlite3_result_null(ctx);
+    return SQLITE_OK;
+  }
+
+  // Text items with empty text are NULL, like non-text items
+  char *value = mcp_get_content_field(pCur->current_row, field_name);
+  if (!value || (i == 0 && value[0] == '\0')) {
+    sqlite3_result_null(ctx);
+  } else if (i == 4) {
+    sqlite3_result_int(ctx, strcmp(value, "true") == 0);
+  } else {
+    sqlite3_result_text(ctx, value, -1, SQLITE_TRANSIENT);
+  }
+  if (value) mcp_free_string(value);
+
+  return SQLITE_OK;
+}
+
+static int mcp_results_rowid(sqlite3_vtab_cursor *cur, sqlite_int64 *pRowid){
+  mcp_results_cursor *pCur = (mcp_results_cursor*)cur;
+  *pRowid = pCur->current_index + 1;
+  return SQLITE_OK;
+}
+
+static int mcp_results_best_index(sqlite3_vtab *tab, sqlite3_index_info *pIdxInfo){
+  D("mcp_results_best_index: called");
+  DF("  nConstraint=%d", pIdxInfo->nConstraint);
+  
+  int tool_name_idx = -1;
+  int arguments_idx = -1;
+  
+  for (int i = 0; i < pIdxInfo->nConstraint; i++) {
+    DF("    constraint[%d]: iColumn=%d, op=%d, usable=%d", 
+       i, pIdxInfo->aConstraint[i].iColumn, 
+       pIdxInfo->aConstraint[i].op, pIdxInfo->aConstraint[i].usable);
+       
+    if (pIdxInfo->aConstraint[i].usable && 
+        pIdxInfo->aConstraint[i].op == SQLITE_INDEX_CONSTRAINT_EQ) {
+      if (pIdxInfo->aConstraint[i].iColumn == 5) { // tool_name HIDDEN
+        tool_name_idx = i;
+      } else if (pIdxInfo->aConstraint[i].iColumn == 6) { // arguments HIDDEN
+        arguments_idx = i;
+      }
+    }
+  }
+  
+  if (tool_name_idx >= 0 && arguments_idx >= 0) {
+    D("mcp_results_best_index: Found both tool_name and arguments constraints");
+    // Tell SQLite to use these constraints
+    pIdxInfo->aConstraintUsage[tool_name_idx].argvIndex = 1;
+    pIdxInfo->aConstraintUsage[tool_name_idx].omit = 1;
+    pIdxInfo->aConstraintUsage[arguments_idx].argvIndex = 2;
+    pIdxInfo->aConstraintUsage[arguments_idx].omit = 1;
+    
+    pIdxInfo->idxNum = 1; // indicates we have function arguments
+    pIdxInfo->estimatedCost = 100.0;
+  } else {
+    D("mcp_results_best_index: No function constraints found");
+    pIdxInfo->idxNum = 0; // no constraints
+    pIdxInfo->estimatedCost = 1000.0;
+  }
+  
+  return SQLITE_OK;
+}
+
+static sqlite3_module mcp_results_module = {
+  0,                         /* iVersion */
+  0,                         /* xCreate */
+  mcp_results_connect,       /* xConnect */
+  mcp_results_best_index,    /* xBestIndex */
+  mcp_results_disconnect,    /* xDisconnect */
+  0,                         /* xDestroy */
+  mcp_results_open,          /* xOpen */
+  mcp_results_close,         /* xClose */
+  mcp_results_filter,        /* xFilter */
+  mcp_results_next,          /* xNext */
+  mcp_results_eof,           /* xEof */
+  mcp_results_column,        /* xColumn */
+  mcp_results_rowid,         /* xRowid */
+  0,                         /* xUpdate */
+  0,                         /* xBegin */
+  0,                         /* xSync */
+  0,                         /* xCommit */
+  0,                         /* xRollback */
+  0,                         /* xFindMethod */
+  0,                         /* xRename */
+  0,                         /* xSavepoint */
+  0,                         /* xRelease */
+  0,                         /* xRollbackTo */
+  0,                         /* xShadowName */
+  0,                         /* xIntegrity */
+};
+
+/*
+** Virtual table shared by the tables that list the items of one JSON result (non-streaming, no caching)
+** Each table is described by an mcp_list_table: the schema it declares, how the result is fetched,
+** the key of the array it lists and the item field read for each column. The result is parsed once
+** per scan in the Rust layer. The column after the listed ones is HIDDEN and passed to fetch
+*/
+#define MCP_LIST_TEXT 0      // Strings as they are, other values as JSON text
+#define MCP_LIST_JSON 1      // Every value as JSON text, strings included
+#define MCP_LIST_INTEGER 2   // Numbers as INTEGER values
+
+typedef struct mcp_list_field {
+  const char *field;         // Field of each item read for the column
+  int type;                  // MCP_LIST_TEXT, MCP_LIST_JSON or MCP_LIST_INTEGER
+} mcp_list_field;
+
+typedef struct mcp_list_table {
+  const char *name;                  // Table name, for debug output
+  const char *schema;                // Declared schema, ending with the HIDDEN column
+  const char *list_key;              // Key of the listed array in the JSON result
+  const mcp_list_field *columns;    // Columns before the HIDDEN one
+  int column_count;
+  // Fetches the JSON result, arg being the HIDDEN column constraint or NULL without one
+  // Returns NULL after setting *pzErr on failure
+  char *(*fetch)(sqlite3_value *arg, char **pzErr);
+} mcp_list_table;
+
+typedef struct mcp_list_vtab {
+  sqlite3_vtab base;
+  const mcp_list_table *table;
+} mcp_list_vtab;
+
+typedef struct mcp_list_cursor {
+  sqlite3_vtab_cursor base;
+  McpList *list;         // Items parsed from the fetched result
+  size_t count;          // Number of items in list
+  size_t current_index;  // Current row index
+  int eof;
+} mcp_list_cursor;
+
+static int mcp_list_connect(
+  sqlite3 *db,
+  void *pAux,
+  int argc, const char *const*argv,
+  sqlite3_vtab **ppVtab,
+  char **pzErr
+){
+  mcp_list_vtab *pNew = sqlite3_malloc(sizeof(*pNew));
+  if (pNew==0) return SQLITE_NOMEM;
+  memset(pNew, 0, sizeof(*pNew));
+  pNew->table = (const mcp_list_table*)pAux;
+  *ppVtab = (sqlite3_vtab*)pNew;
+
+  return sqlite3_declare_vtab(db, pNew->table->schema);
+}
+
+static int mcp_list_disconnect(sqlite3_vtab *pVtab){
+  sqlite3_free(pVtab);
+  return SQLITE_OK;
+}
+
+static int mcp_list_open(sqlite3_vtab *pVtab, sqlite3_vtab_cursor **ppCursor){
+  mcp_list_cursor *pCur = sqlite3_malloc(sizeof(*pCur));
+  if (pCur==0) return SQLITE_NOMEM;
+  memset(pCur, 0, sizeof(*pCur));
+  pCur->eof = 1;
+  *ppCursor = (sqlite3_vtab_cursor*)pCur;
+  return SQLITE_OK;
+}
+
+static int mcp_list_close(sqlite3_vtab_cursor *cur){
+  mcp_list_cursor *pCur = (mcp_list_cursor*)cur;
+  mcp_free_list(pCur->list);
+  sqlite3_free(pCur);
+  return SQLITE_OK;
+}
+
+static int mcp_list_filter(
+  sqlite3_vtab_cursor *pVtabCursor,
+  int idxNum, const char *idxStr,
+  int argc, sqlite3_value **argv
+){
+  mcp_list_cursor *pCur = (mcp_list_cursor*)pVtabCursor;
+  mcp_list_vtab *pVtab = (mcp_list_vtab*)pVtabCursor->pVtab;
+  const mcp_list_table *table = pVtab->table;
+
+  // Free any previous result
+  mcp_free_list(pCur->list);
+  pCur->list = NULL;
+  pCur->count = 0;
+  pCur->current_index = 0;
+  pCur->eof = 1;
+
+  // idxNum 1 means the HIDDEN column constraint is passed in argv[0]
+  sqlite3_value *arg = (idxNum == 1 && argc >= 1) ? argv[0] : NULL;
+
+  char *json_result = table->fetch(arg, &pVtab->base.zErrMsg);
+  if (!json_result) {
+    return SQLITE_ERROR;
+  }
+
+  // Report errors (including "Not connected") instead of returning an empty set
+  char *error_msg = mcp_extract_error_message(json_result);
+  if (error_msg) {
+    DF("mcp_list_filter: %s JSON contains error", table->name);
+    pVtab->base.zErrMsg = sqlite3_mprintf("%s", error_msg);
+    mcp_free_string(error_msg);
+    mcp_free_string(json_result);
+    return SQLITE_ERROR;
+  }
+
+  // Parse JSON once in the Rust layer
+  pCur->list = mcp_parse_list_json(json_result, table->list_key);
+  mcp_free_string(json_result);
+  pCur->count = mcp_list_len(pCur->list);
+  DF("mcp_list_filter: %s parsed %d rows", table->name, (int)pCur->count);
+
+  pCur->eof = (pCur->count == 0);
+  return SQLITE_OK;
+}
+
+static int mcp_list_next(sqlite3_vtab_cursor *cur){
+  mcp_list_cursor *pCur = (mcp_list_cursor*)cur;
+
+  pCur->current_index++;
+  if (pCur->current_index >= pCur->count) {
+    pCur->eof = 1;
+  }
+
+  return SQLITE_OK;
+}
+
+static int mcp_list_eof(sqlite3_vtab_cursor *cur){
+  mcp_list_cursor *pCur = (mcp_list_cursor*)cur;
+  return pCur->eof;
+}
+
+static int mcp_list_column(
+  sqlite3_vtab_cursor *cur,
+  sqlite3_context *ctx,
+  int i
+){
+  mcp_list_cursor *pCur = (mcp_list_cursor*)cur;
+  const mcp_list_table *table = ((mcp_list_vtab*)cur->pVtab)->table;
+
+  if (i >= table->column_count || pCur->current_index >= pCur->count) {
+    sqlite3_result_null(ctx);
+    return SQLITE_OK;
+  }
+
+  const mcp_list_field *column = &table->columns[i];
+  char *value = mcp_get_list_field(pCur->list, pCur->current_index, column->field, column->type == MCP_LIST_JSON);
+  if (value && strlen(value) > 0) {
+    if (column->type == MCP_LIST_INTEGER) {
+      sqlite3_result_int64(ctx, strtoll(value, NULL, 10));
+    } else {
+      sqlite3_result_text(ctx, value, -1, SQLITE_TRANSIENT);
+    }
+  } else {
+    sqlite3_result_null(ctx);
+  }
+  if (value) mcp_free_string(value);
+
+  return SQLITE_OK;
+}
+
+static int mcp_list_rowid(sqlite3_vtab_cursor *cur, sqlite_int64 *pRowid){
+  mcp_list_cursor *pCur = (mcp_list_cursor*)cur;
+  *pRowid = pCur->current_index + 1;
+  return SQLITE_OK;
+}
+
+static int mcp_list_best_index(sqlite3_vtab *tab, sqlite3_index_info *pIdxInfo){
+  const mcp_list_table *table = ((mcp_list_vtab*)tab)->table;
+  pIdxInfo->idxNum = 0;
+
+  for (int i = 0; i < pIdxInfo->nConstraint; i++) {
+    if (pIdxInfo->aConstraint[i].usable &&
+        pIdxInfo->aConstraint[i].op == SQLITE_INDEX_CONSTRAINT_EQ &&
+        pIdxInfo->aConstraint[i].iColumn == table->column_count) { // HIDDEN column
+      pIdxInfo->aConstraintUsage[i].argvIndex = 1;
+      pIdxInfo->aConstraintUsage[i].omit = 1;
+      pIdxInfo->idxNum = 1;
+      break;
+    }
+  }
+
+  // Every plan fetches the same single result
+  pIdxInfo->estimatedCost = 100.0;
+  return SQLITE_OK;
+}
+
+static sqlite3_module mcp_list_module = {
+  0,                            /* iVersion */
+  0,                            /* xCreate */
+  mcp_list_connect,             /* xConnect */
+  mcp_list_best_index,          /* xBestIndex */
+  mcp_list_disconnect,          /* xDisconnect */
+  0,                            /* xDestroy */
+  mcp_list_open,                /* xOpen */
+  mcp_list_close,               /* xClose */
+  mcp_list_filter,              /* xFilter */
+  mcp_list_next,                /* xNext */
+  mcp_list_eof,                 /* xEof */
+  mcp_list_column,              /* xColumn */
+  mcp_list_rowid,               /* xRowid */
+  0,                            /* xUpdate */
+  0,                            /* xBegin */
+  0,                            /* xSync */
+  0,                            /* xCommit */
+  0,                            /* xRollback */
+  0,                            /* xFindMethod */
+  0,                            /* xRename */
+  0,                            /* xSavepoint */
+  0,                            /* xRelease */
+  0,                            /* xRollbackTo */
+  0,                            /* xShadowName */
+  0,                            /* xIntegrity */
+};
+
+/*
+** List table mcp_tools
+** Lists the tools of a connection as rows: SELECT name, description FROM mcp_tools
+** Optional hidden connection_id column: SELECT * FROM mcp_tools('search')
+*/
+static char *mcp_tools_fetch(sqlite3_value *arg, char **pzErr){
+  const char *connection_id = arg ? (const char*)sqlite3_value_text(arg) : NULL;
+  DF("mcp_tools_fetch: connection_id='%s'", connection_id ? connection_id : "default");
+
+  char *result = mcp_list_tools_json(NULL, connection_id);
+  if (!result) *pzErr = sqlite3_mprintf("Failed to list tools");
+  return result;
+}
+
+static const mcp_list_field mcp_tools_columns[] = {
+  {"name", MCP_LIST_TEXT},
+  {"description", MCP_LIST_TEXT},
+  {"inputSchema", MCP_LIST_TEXT},
+};
+
+static const mcp_list_table mcp_tools_table = {
+  "mcp_tools",
+  "CREATE TABLE x(name TEXT, description TEXT, input_schema TEXT, connection_id HIDDEN)",
+  "tools",
+  mcp_tools_columns, 3,
+  mcp_tools_fetch,
+};
+
+/*
+** List table mcp_resources
+** Lists the resources of a connection as rows: SELECT uri, name, mime_type FROM mcp_resources
+** Optional hidden connection_id column: SELECT * FROM mcp_resources('docs')
+*/
+static char *mcp_resources_fetch(sqlite3_value *arg, char **pzErr){
+  const char *connection_id = arg ? (const char*)sqlite3_value_text(arg) : NULL;
+  DF("mcp_resources_fetch: connection_id='%s'", connection_id ? connection_id : "default");
+
+  // Fetches every page, following nextCursor until the server stops returning one
+  char *result = mcp_list_resources_json(NULL, connection_id);
+  if (!result) *pzErr = sqlite3_mprintf("Failed to list resources");
+  return result;
+}
+
+static const mcp_list_field mcp_resources_columns[] = {
+  {"uri", MCP_LIST_TEXT},
+  {"name", MCP_LIST_TEXT},
+  {"description", MCP_LIST_TEXT},
+  {"mimeType", MCP_LIST_TEXT},
+};
+
+static const mcp_list_table mcp_resources_table = {
+  "mcp_resources",
+  "CREATE TABLE x(uri TEXT, name TEXT, description TEXT, mime_type TEXT, connection_id HIDDEN)",
+  "resources",
+  mcp_resources_columns, 4,
+  mcp_resources_fetch,
+};
+
+/*
+** List table mcp_prompts
+** Lists the prompt templates of a connection as rows: SELECT name, description, arguments FROM mcp_prompts
+** Optional hidden connection_id column: SELECT * FROM mcp_prompts('docs')
+*/
+static char *mcp_prompts_fetch(sqlite3_value *arg, char **pzErr){
+  const char *connection_id = arg ? (const char*)sqlite3_value_text(arg) : NULL;
+  DF("mcp_prompts_fetch: connection_id='%s'", connection_id ? connection_id : "default");
+
+  char *result = mcp_list_prompts_json(NULL, connection_id);
+  if (!result) *pzErr = sqlite3_mprintf("Failed to list prompts");
+  return result;
+}
+
+static const mcp_list_field mcp_prompts_columns[] = {
+  {"name", MCP_LIST_TEXT},
+  {"description", MCP_LIST_TEXT},
+  {"arguments", MCP_LIST_TEXT},
+};
+
+static const mcp_list_table mcp_prompts_table = {
+  "mcp_prompts",
+  "CREATE TABLE x(name TEXT, description TEXT, arguments TEXT, connection_id HIDDEN)",
+  "prompts",
+  mcp_prompts_columns, 3,
+  mcp_prompts_fetch,
+};
+
+/*
+** List table mcp_logs
+** Lists the queued log messages of every connection: SELECT level, logger, data FROM mcp_logs
+** Optional hidden clear column removes the listed messages from the queue: SELECT * FROM mcp_logs(1)
+*/
+static char *mcp_logs_fetch(sqlite3_value *arg, char **pzErr){
+  int clear = arg ? sqlite3_value_int(arg) != 0 : 0;
+  DF("mcp_logs_fetch: clear=%d", clear);
+
+  char *result = mcp_server_logs_json(clear);
+  if (!result) *pzErr = sqlite3_mprintf("Failed to read server logs");
+  return result;
+}
+
+static const mcp_list_field mcp_logs_columns[] = {
+  {"level", MCP_LIST_TEXT},
+  {"logger", MCP_LIST_TEXT},
+  {"data", MCP_LIST_JSON},
+  {"connection_id", MCP_LIST_TEXT},
+};
+
+static const mcp_list_table mcp_logs_table = {
+  "mcp_logs",
+  "CREATE TABLE x(level TEXT, logger TEXT, data TEXT, connection_id TEXT, clear HIDDEN)",
+  "logs",
+  mcp_logs_columns, 4,
+  mcp_logs_fetch,
+};
+
+/*
+** List table mcp_events
+** Lists every queued notification with a sequence number: SELECT seq, kind, ts, payload FROM mcp_events
+** Optional hidden since column lists only the events after a sequence number: SELECT * FROM mcp_events(42)
+*/
+static char *mcp_events_fetch(sqlite3_value *arg, char **pzErr){
+  sqlite3_int64 since = 0;
+  if (arg && sqlite3_value_type(arg) != SQLITE_NULL) {
+    since = sqlite3_value_int64(arg);
+    if (sqlite3_value_type(arg) != SQLITE_INTEGER || since < 0) {
+      *pzErr = sqlite3_mprintf("since must be a non-negative sequence number");
+      return NULL;
+    }
+  }
+  DF("mcp_events_fetch: since=%lld", since);
+
+  char *result = mcp_events_json((uint64_t)since);
+  if (!result) *pzErr = sqlite3_mprintf("Failed to read events");
+  return result;
+}
+
+static const mcp_list_field mcp_events_columns[] = {
+  {"seq", MCP_LIST_INTEGER},
+  {"kind", MCP_LIST_TEXT},
+  {"ts", MCP_LIST_INTEGER},
+  {"connection_id", MCP_LIST_TEXT},
+  {"payload", MCP_LIST_JSON},
+};
+
+static const mcp_list_table mcp_events_table = {
+  "mcp_events",
+  "CREATE TABLE x(seq INTEGER, kind TEXT, ts INTEGER, connection_id TEXT, payload TEXT, since HIDDEN)",
+  "events",
+  mcp_events_columns, 5,
+  mcp_events_fetch,
+};
+
+/*
+** Virtual table for mcp_resource_chunks (non-streaming, no caching)
+** Splits the first content of a resource into BLOB rows of chunk_size bytes. The whole resource
+** is read and held by the cursor, so this bounds the size of each value, not the memory used:
+** SELECT seq, offset, data FROM mcp_resource_chunks('file:///big.bin', 1048576)
+** Hidden columns: uri (required), chunk_size (default 65536) and connection_id
+*/
+#define MCP_RESOURCE_CHUNK_SIZE 65536
+
+typedef struct mcp_chunks_vtab {
+  sqlite3_vtab base;
+} mcp_chunks_vtab;
+
+typedef struct mcp_chunks_cursor {
+  sqlite3_vtab_cursor base;
+  uint8_t *data;          // Content bytes from mcp_read_resource_content
+  size_t len;             // Number of content bytes
+  size_t chunk_size;      // Bytes per row
+  size_t current_index;   // Current row index
+  int eof;
+} mcp_chunks_cursor;
+
+static int mcp_chunks_connect(
+  sqlite3 *db,
+  void *pAux,
+  int argc, const char *const*argv,
+  sqlite3_vtab **ppVtab,
+  char **pzErr
+){
+  mcp_chunks_vtab *pNew = sqlite3_malloc(sizeof(*pNew));
+  if (pNew==0) return SQLITE_NOMEM;
+  memset(pNew, 0, sizeof(*pNew));
+  *ppVtab = (sqlite3_vtab*)pNew;
+
+  return sqlite3_declare_vtab(db,
+    "CREATE TABLE x(seq INTEGER, offset INTEGER, data BLOB, uri HIDDEN, chunk_size HIDDEN, connection_id HIDDEN)");
+}
+
+static int mcp_chunks_disconnect(sqlite3_vtab *pVtab){
+  sqlite3_free(pVtab);
+  return SQLITE_OK;
+}
+
+static int mcp_chunks_open(sqlite3_vtab *pVtab, sqlite3_vtab_cursor **ppCursor){
+  mcp_chunks_cursor *pCur = sqlite3_malloc(sizeof(*pCur));
+  if (pCur==0) return SQLITE_NOMEM;
+  memset(pCur, 0, sizeof(*pCur));
+  pCur->eof = 1;
+  *ppCursor = (sqlite3_vtab_cursor*)pCur;
+  return SQLITE_OK;
+}
+
+static int mcp_chunks_close(sqlite3_vtab_cursor *cur){
+  mcp_chunks_cursor *pCur = (mcp_chunks_cursor*)cur;
+  if (pCur->data) {
+    mcp_free_bytes(pCur->data, pCur->len);
+  }
+  sqlite3_free(pCur);
+  return SQLITE_OK;
+}
+
+static int mcp_chunks_filter(
+  sqlite3_vtab_cursor *pVtabCursor,
+  int idxNum, const char *idxStr,
+  int argc, sqlite3_value **argv
+){
+  mcp_chunks_cursor *pCur = (mcp_chunks_cursor*)pVtabCursor;
+  mcp_chunks_vtab *pVtab = (mcp_chunks_vtab*)pVtabCursor->pVtab;
+
+  // Free any previous content
+  if (pCur->data) {
+    mcp_free_bytes(pCur->data, pCur->len);
+    pCur->data = NULL;
+    pCur->len = 0;
+  }
+  pCur->eof = 1;
+
+  // idxNum bits 1, 2 and 4 mean uri, chunk_size and connection_id are passed in argv, in that order
+  int arg = 0;
+  const char *uri = NULL;
+  sqlite3_int64 chunk_size = MCP_RESOURCE_CHUNK_SIZE;
+  const char *connection_id = NULL;
+  if ((idxNum & 1) && arg < argc) uri = (const char*)sqlite3_value_text(argv[arg++]);
+  if ((idxNum & 2) && arg < argc) chunk_size = sqlite3_value_int64(argv[arg++]);
+  if ((idxNum & 4) && arg < argc) connection_id = (const char*)sqlite3_value_text(argv[arg++]);
+
+  if (!uri) {
+    pVtab->base.zErrMsg = sqlite3_mprintf("mcp_resource_chunks requires a resource URI: mcp_resource_chunks(uri, [chunk_size], [connection_id])");
+    return SQLITE_ERROR;
+  }
+  if (chunk_size <= 0 || chunk_size > INT_MAX) {
+    pVtab->base.zErrMsg = sqlite3_mprintf("chunk_size must be between 1 and %d", INT_MAX);
+    return SQLITE_ERROR;
+  }
+
+  DF("mcp_chunks_filter: uri='%s', chunk_size=%lld", uri, chunk_size);
+
+  // MCP has no ranged reads, so the server sends the whole resource once and rows are cut from it
+  char *error = mcp_read_resource_content(uri, connection_id, -1, &pCur->data, &pCur->len);
+  if (error) {
+    char *error_msg = mcp_extract_error_message(error);
+    pVtab->base.zErrMsg = sqlite3_mprintf("%s", error_msg ? error_msg : error);
+    if (error_msg) mcp_free_string(error_msg);
+    mcp_free_string(error);
+    pCur->data = NULL;
+    pCur->len = 0;
+    return SQLITE_ERROR;
+  }
+
+  pCur->chunk_size = (size_t)chunk_size;
+  pCur->current_index = 0;
+  pCur->eof = (pCur->len == 0);
+
+  return SQLITE_OK;
+}
+
+static int mcp_chunks_next(sqlite3_vtab_cursor *cur){
+  mcp_chunks_cursor *pCur = (mcp_chunks_cursor*)cur;
+
+  pCur->current_index++;
+  if (pCur->current_index * pCur->chunk_size >= pCur->len) {
+    pCur->eof = 1;
+  }
+
+  return SQLITE_OK;
+}
+
+static int mcp_chunks_eof(sqlite3_vtab_cursor *cur){
+  mcp_chunks_cursor *pCur = (mcp_chunks_cursor*)cur;
+  return pCur->eof;
+}
+
+static int mcp_chunks_column(
+  sqlite3_vtab_cursor *cur,
+  sqlite3_context *ctx,
+  int i
+){
+  mcp_chunks_cursor *pCur = (mcp_chunks_cursor*)cur;
+  size_t offset = pCur->current_index * pCur->chunk_size;
+
+  if (!pCur->data || offset >= pCur->len) {
+    sqlite3_result_null(ctx);
+    return SQLITE_OK;
+  }
+
+  switch (i) {
+    case 0: sqlite3_result_int64(ctx, (sqlite3_int64)pCur->current_index); break;
+    case 1: sqlite3_result_int64(ctx, (sqlite3_int64)offset); break;
+    case 2: {
+      size_t len = pCur->len - offset;
+      if (len > pCur->chunk_size) len = pCur->chunk_size;
+      sqlite3_result_blob(ctx, pCur->data + offset, (int)len, SQLITE_TRANSIENT);
+      break;
+    }
+    default: sqlite3_result_null(ctx); break;
+  }
+
+  return SQLITE_OK;
+}
+
+static int mcp_chunks_rowid(sqlite3_vtab_cursor *cur, sqlite_int64 *pRowid){
+  mcp_chunks_cursor *pCur = (mcp_chunks_cursor*)cur;
+  *pRowid = pCur->current_index + 1;
+  return SQLITE_OK;
+}
+
+static int mcp_chunks_best_index(sqlite3_vtab *tab, sqlite3_index_info *pIdxInfo){
+  int constraint[3] = {-1, -1, -1};
+  pIdxInfo->idxNum = 0;
+
+  for (int i = 0; i < pIdxInfo->nConstraint; i++) {
+    int column = pIdxInfo->aConstraint[i].iColumn;
+    if (pIdxInfo->aConstraint[i].usable &&
+        pIdxInfo->aConstraint[i].op == SQLITE_INDEX_CONSTRAINT_EQ &&
+        column >= 3 && column <= 5) { // uri, chunk_size, connection_id HIDDEN
+      constraint[column - 3] = i;
+    }
+  }
+
+  // Pass the constraints found in column order, without gaps in argvIndex
+  int argv_index = 1;
+  for (int c = 0; c < 3; c++) {
+    if (constraint[c] < 0) continue;
+    pIdxInfo->aConstraintUsage[constraint[c]].argvIndex = argv_index++;
+    pIdxInfo->aConstraintUsage[constraint[c]].omit = 1;
+    pIdxInfo->idxNum |= 1 << c;
+  }
+
+  // Plans without a uri are left possible so that xFilter can explain the missing argument
+  pIdxInfo->estimatedCost = (pIdxInfo->idxNum & 1) ? 100.0 : 1000000.0;
+  return SQLITE_OK;
+}
+
+static sqlite3_module mcp_chunks_module = {
+  0,                            /* iVersion */
+  0,                            /* xCreate */
+  mcp_chunks_connect,           /* xConnect */
+  mcp_chunks_best_index,        /* xBestIndex */
+  mcp_chunks_disconnect,        /* xDisconnect */
+  0,                            /* xDestroy */
+  mcp_chunks_open,              /* xOpen */
+  mcp_chunks_close,             /* xClose */
+  mcp_chunks_filter,            /* xFilter */
+  mcp_chunks_next,              /* xNext */
+  mcp_chunks_eof,               /* xEof */
+  mcp_chunks_column,            /* xColumn */
+  mcp_chunks_rowid,             /* xRowid */
+  0,                            /* xUpdate */
+  0,                            /* xBegin */
+  0,                            /* xSync */
+  0,                            /* xCommit */
+  0,                            /* xRollback */
+  0,                            /* xFindMethod */
+  0,                            /* xRename */
+  0,                            /* xSavepoint */
+  0,                            /* xRelease */
+  0,                            /* xRollbackTo */
+  0,                            /* xShadowName */
+  0,                            /* xIntegrity */
+};
+
+/*
+** Scalar functions for JSON output
+*/
+static void mcp_tools_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc > 1) {
+    sqlite3_result_error(context, "mcp_list_tools_json takes at most 1 argument: ([connection_id])", -1);
+    return;
+  }
+
+  char *result = mcp_list_tools_json(NULL, mcp_connection_id_arg(argc, argv, 0));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to list tools", -1);
+  }
+}
+
+static void mcp_tools_json_cached_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc > 1) {
+    sqlite3_result_error(context, "mcp_list_tools_json_cached takes at most 1 argument: ([connection_id])", -1);
+    return;
+  }
+
+  char *result = mcp_list_tools_json_cached(mcp_connection_id_arg(argc, argv, 0));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to list tools", -1);
+  }
+}
+
+static void mcp_tool_names_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc > 1) {
+    sqlite3_result_error(context, "mcp_list_tool_names_json takes at most 1 argument: ([connection_id])", -1);
+    return;
+  }
+
+  char *result = mcp_list_tool_names_json(mcp_connection_id_arg(argc, argv, 0));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to list tools", -1);
+  }
+}
+
+static void mcp_tool_schema_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 1 || argc > 2) {
+    sqlite3_result_error(context, "mcp_tool_schema_json requires 1-2 arguments: (tool_name, [connection_id])", -1);
+    return;
+  }
+
+  const char *tool_name = (const char*)sqlite3_value_text(argv[0]);
+  if (!tool_name) {
+    sqlite3_result_error(context, "mcp_tool_schema_json requires tool_name", -1);
+    return;
+  }
+
+  char *result = mcp_tool_schema_json(tool_name, mcp_connection_id_arg(argc, argv, 1));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to get tool schema", -1);
+  }
+}
+
+static void mcp_call_tool_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 2 || argc > 3) {
+    sqlite3_result_error(context, "mcp_call_tool_json requires 2-3 arguments: (tool_name, arguments_json, [connection_id])", -1);
+    return;
+  }
+
+  const char *tool_name = (const char*)sqlite3_value_text(argv[0]);
+  const char *arguments = (const char*)sqlite3_value_text(argv[1]);
+
+  if (!tool_name || !arguments) {
+    sqlite3_result_error(context, "mcp_call_tool_json requires tool_name and arguments_json", -1);
+    return;
+  }
+
+  char *result = mcp_call_tool_json(NULL, tool_name, arguments, mcp_connection_id_arg(argc, argv, 2));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to call tool", -1);
+  }
+}
+
+static void mcp_call_tool_idempotent_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 2 || argc > 3) {
+    sqlite3_result_error(context, "mcp_call_tool_idempotent_json requires 2-3 arguments: (tool_name, arguments_json, [connection_id])", -1);
+    return;
+  }
+
+  const char *tool_name = (const char*)sqlite3_value_text(argv[0]);
+  const char *arguments = (const char*)sqlite3_value_text(argv[1]);
+
+  if (!tool_name || !arguments) {
+    sqlite3_result_error(context, "mcp_call_tool_idempotent_json requires tool_name and arguments_json", -1);
+    return;
+  }
+
+  char *result = mcp_call_tool_idempotent_json(tool_name, arguments, mcp_connection_id_arg(argc, argv, 2));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to call tool", -1);
+  }
+}
+
+/*
+** SQL function: mcp_call_tool_with_blob(tool_name, arguments_json, blob_param_name, blob, [encoding], [connection_id])
+** Calls a tool with a BLOB base64 encoded into the argument named blob_param_name
+*/
+static void mcp_call_tool_with_blob_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 4 || argc > 6) {
+    sqlite3_result_error(context, "mcp_call_tool_with_blob requires 4-6 arguments: (tool_name, arguments_json, blob_param_name, blob, [encoding], [connection_id])", -1);
+    return;
+  }
+
+  const char *tool_name = (const char*)sqlite3_value_text(argv[0]);
+  const char *arguments = (const char*)sqlite3_value_text(argv[1]);
+  const char *blob_param_name = (const char*)sqlite3_value_text(argv[2]);
+
+  if (!tool_name || !blob_param_name) {
+    sqlite3_result_error(context, "mcp_call_tool_with_blob requires tool_name and blob_param_name", -1);
+    return;
+  }
+  if (sqlite3_value_type(argv[3]) == SQLITE_NULL) {
+    sqlite3_result_error(context, "mcp_call_tool_with_blob requires a blob", -1);
+    return;
+  }
+
+  const uint8_t *blob = (const uint8_t*)sqlite3_value_blob(argv[3]);
+  size_t blob_len = (size_t)sqlite3_value_bytes(argv[3]);
+  const char *encoding = NULL;
+  if (argc >= 5 && sqlite3_value_type(argv[4]) != SQLITE_NULL) {
+    encoding = (const char*)sqlite3_value_text(argv[4]);
+  }
+
+  char *result = mcp_call_tool_with_blob(tool_name, arguments, blob_param_name, blob, blob_len, encoding,
+                                         mcp_connection_id_arg(argc, argv, 5));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to call tool", -1);
+  }
+}
+
+/*
+** SQL function: mcp_call_tool_with_headers_json(tool_name, arguments_json, headers_json, [connection_id])
+** Calls a tool, sending extra HTTP headers with this call only (streamable HTTP)
+*/
+static void mcp_call_tool_with_headers_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 3 || argc > 4) {
+    sqlite3_result_error(context, "mcp_call_tool_with_headers_json requires 3-4 arguments: (tool_name, arguments_json, headers_json, [connection_id])", -1);
+    return;
+  }
+
+  const char *tool_name = (const char*)sqlite3_value_text(argv[0]);
+  const char *arguments = (const char*)sqlite3_value_text(argv[1]);
+  const char *headers = (const char*)sqlite3_value_text(argv[2]);
+
+  if (!tool_name || !arguments) {
+    sqlite3_result_error(context, "mcp_call_tool_with_headers_json requires tool_name and arguments_json", -1);
+    return;
+  }
+
+  char *result = mcp_call_tool_with_headers_json(tool_name, arguments, headers, mcp_connection_id_arg(argc, argv, 3));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to call tool", -1);
+  }
+}
+
+static void mcp_call_tools_batch_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 1 || argc > 2) {
+    sqlite3_result_error(context, "mcp_call_tools_batch_json requires 1-2 arguments: (calls_json, [connection_id])", -1);
+    return;
+  }
+
+  const char *calls = (const char*)sqlite3_value_text(argv[0]);
+  if (!calls) {
+    sqlite3_result_error(context, "mcp_call_tools_batch_json requires calls_json", -1);
+    return;
+  }
+
+  char *result = mcp_call_tools_batch_json(calls, mcp_connection_id_arg(argc, argv, 1));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to call tools", -1);
+  }
+}
+
+static void mcp_call_tools_matching_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 1 || argc > 3) {
+    sqlite3_result_error(context, "mcp_call_tools_matching_json requires 1-3 arguments: (pattern, [args_json], [connection_id])", -1);
+    return;
+  }
+
+  const char *pattern = (const char*)sqlite3_value_text(argv[0]);
+  if (!pattern) {
+    sqlite3_result_error(context, "mcp_call_tools_matching_json requires pattern", -1);
+    return;
+  }
+  const char *args = argc > 1 ? (const char*)sqlite3_value_text(argv[1]) : NULL;
+
+  char *result = mcp_call_tools_matching_json(pattern, args, mcp_connection_id_arg(argc, argv, 2));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to call tools", -1);
+  }
+}
+
+/*
+** SQL function: mcp_call_tool_stream(tool_name, arguments_json, [connection_id])
+** Starts a tool call and returns the id of the stream its chunks are polled from
+*/
+static void mcp_call_tool_stream_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 2 || argc > 3) {
+    sqlite3_result_error(context, "mcp_call_tool_stream requires 2-3 arguments: (tool_name, arguments_json, [connection_id])", -1);
+    return;
+  }
+
+  const char *tool_name = (const char*)sqlite3_value_text(argv[0]);
+  const char *arguments = (const char*)sqlite3_value_text(argv[1]);
+  uint64_t stream_id = 0;
+  if (tool_name && arguments) {
+    stream_id = mcp_call_tool_stream(tool_name, arguments, mcp_connection_id_arg(argc, argv, 2));
+  }
+  if (stream_id == 0) {
+    sqlite3_result_error(context, "mcp_call_tool_stream requires a tool name and a JSON object of arguments", -1);
+    return;
+  }
+  sqlite3_result_int64(context, (sqlite3_int64)stream_id);
+}
+
+/*
+** SQL function: mcp_poll_stream_json(stream_id, [wait_ms])
+** Returns the chunks a streamed tool call produced since the last poll
+*/
+static void mcp_poll_stream_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 1 || argc > 2) {
+    sqlite3_result_error(context, "mcp_poll_stream_json requires 1-2 arguments: (stream_id, [wait_ms])", -1);
+    return;
+  }
+
+  sqlite3_int64 stream_id = sqlite3_value_int64(argv[0]);
+  if (sqlite3_value_type(argv[0]) != SQLITE_INTEGER || stream_id <= 0) {
+    sqlite3_result_error(context, "mcp_poll_stream_json requires a stream id returned by mcp_call_tool_stream", -1);
+    return;
+  }
+  sqlite3_int64 wait_ms = 0;
+  if (argc == 2) {
+    wait_ms = sqlite3_value_int64(argv[1]);
+    if (sqlite3_value_type(argv[1]) != SQLITE_INTEGER || wait_ms < 0 || wait_ms > UINT32_MAX) {
+      sqlite3_result_error(context, "mcp_poll_stream_json requires a non-negative integer number of milliseconds to wait", -1);
+      return;
+    }
+  }
+
+  char *result = mcp_poll_stream_json((uint64_t)stream_id, (uint32_t)wait_ms);
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to poll stream", -1);
+  }
+}
+
+/*
+** SQL function: mcp_close_stream(stream_id)
+** Closes a streamed tool call before it is done and returns 1, or 0 if the stream does not exist
+*/
+static void mcp_close_stream_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  sqlite3_int64 stream_id = sqlite3_value_int64(argv[0]);
+  if (sqlite3_value_type(argv[0]) != SQLITE_INTEGER || stream_id <= 0) {
+    sqlite3_result_error(context, "mcp_close_stream requires a stream id returned by mcp_call_tool_stream", -1);
+    return;
+  }
+  sqlite3_result_int(context, mcp_close_stream((uint64_t)stream_id));
+}
+
+static void mcp_status_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc > 1) {
+    sqlite3_result_error(context, "mcp_status_json takes at most 1 argument: ([connection_id])", -1);
+    return;
+  }
+
+  char *result = mcp_status_json(NULL, mcp_connection_id_arg(argc, argv, 0));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to get connection status", -1);
+  }
+}
+
+/*
+** SQL function: mcp_transport([connection_id])
+** Returns the connection's transport: streamable-http, sse, websocket or stdio
+** Returns an empty string when not connected
+*/
+static void mcp_transport_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc > 1) {
+    sqlite3_result_error(context, "mcp_transport takes at most 1 argument: ([connection_id])", -1);
+    return;
+  }
+
+  char *result = mcp_transport(mcp_connection_id_arg(argc, argv, 0));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    mcp_result_text(context, "", 0);
+  }
+}
+
+/*
+** SQL function: mcp_server_info_json([connection_id])
+** Returns the server's name, version, instructions and capabilities from initialization
+*/
+static void mcp_server_info_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc > 1) {
+    sqlite3_result_error(context, "mcp_server_info_json takes at most 1 argument: ([connection_id])", -1);
+    return;
+  }
+
+  char *result = mcp_server_info_json(mcp_connection_id_arg(argc, argv, 0));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to get server info", -1);
+  }
+}
+
+/*
+** SQL function: mcp_initialize_result_json([connection_id])
+** Returns the InitializeResult of the connection's initialize handshake
+*/
+static void mcp_initialize_result_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc > 1) {
+    sqlite3_result_error(context, "mcp_initialize_result_json takes at most 1 argument: ([connection_id])", -1);
+    return;
+  }
+
+  char *result = mcp_initialize_result_json(mcp_connection_id_arg(argc, argv, 0));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to get initialize result", -1);
+  }
+}
+
+/*
+** SQL function: mcp_capabilities_json([connection_id])
+** Reports the transports of this build, the client settings and the server's capabilities
+*/
+static void mcp_capabilities_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc > 1) {
+    sqlite3_result_error(context, "mcp_capabilities_json takes at most 1 argument: ([connection_id])", -1);
+    return;
+  }
+
+  char *result = mcp_capabilities_json(mcp_connection_id_arg(argc, argv, 0));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to get capabilities", -1);
+  }
+}
+
+/*
+** SQL function: mcp_metrics_json()
+** Reports request counters, reconnects and queued notifications across all connections
+*/
+static void mcp_metrics_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  char *result = mcp_metrics_json();
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to get metrics", -1);
+  }
+}
+
+/*
+** SQL function: mcp_timing_json()
+** Reports min, max, average and p95 latencies per MCP method and per tool
+*/
+static void mcp_timing_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  char *result = mcp_timing_json();
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to get timing", -1);
+  }
+}
+
+/*
+** SQL function: mcp_queue_json()
+** Lists the requests waiting for a connect in progress, held by mcp_pause or waiting for the server's answer
+*/
+static void mcp_queue_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  char *result = mcp_queue_json();
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to get queue", -1);
+  }
+}
+
+/*
+** SQL function: mcp_clear_queue()
+** Cancels the requests waiting for a connect in progress or held by mcp_pause and returns how many
+*/
+static void mcp_clear_queue_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  sqlite3_result_int64(context, (sqlite3_int64)mcp_clear_queue());
+}
+
+/*
+** SQL function: mcp_pause([mode], [connection_id])
+** Holds tool calls, list and read requests until mcp_resume when mode is 'queue' (default),
+** or fails them at once when mode is 'error'; the transport and subscriptions stay open
+*/
+static void mcp_pause_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc > 2) {
+    sqlite3_result_error(context, "mcp_pause takes at most 2 arguments: ([mode], [connection_id])", -1);
+    return;
+  }
+
+  const char *mode = argc > 0 ? (const char*)sqlite3_value_text(argv[0]) : NULL;
+  int queue = 1;
+  if (mode && strcmp(mode, "error") == 0) {
+    queue = 0;
+  } else if (mode && strcmp(mode, "queue") != 0) {
+    sqlite3_result_error(context, "mode must be 'queue' or 'error'", -1);
+    return;
+  }
+
+  char *result = mcp_pause(queue, mcp_connection_id_arg(argc, argv, 1));
+  if (result) {
+    sqlite3_result_error(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_null(context);
+  }
+}
+
+/*
+** SQL function: mcp_resume([connection_id])
+** Resumes a paused connection and returns how many held requests it released
+*/
+static void mcp_resume_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc > 1) {
+    sqlite3_result_error(context, "mcp_resume takes at most 1 argument: ([connection_id])", -1);
+    return;
+  }
+
+  sqlite3_result_int64(context, (sqlite3_int64)mcp_resume(mcp_connection_id_arg(argc, argv, 0)));
+}
+
+static void mcp_poll_notifications_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  char *result = mcp_poll_notifications_json();
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to poll notifications", -1);
+  }
+}
+
+static void mcp_list_resources_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc > 1) {
+    sqlite3_result_error(context, "mcp_list_resources_json takes at most 1 argument: ([connection_id])", -1);
+    return;
+  }
+
+  char *result = mcp_list_resources_json(NULL, mcp_connection_id_arg(argc, argv, 0));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to list resources", -1);
+  }
+}
+
+static void mcp_list_resource_templates_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc > 1) {
+    sqlite3_result_error(context, "mcp_list_resource_templates_json takes at most 1 argument: ([connection_id])", -1);
+    return;
+  }
+
+  char *result = mcp_list_resource_templates_json(mcp_connection_id_arg(argc, argv, 0));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to list resource templates", -1);
+  }
+}
+
+static void mcp_read_resource_template_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 1 || argc > 3) {
+    sqlite3_result_error(context, "mcp_read_resource_template requires 1-3 arguments: (template_uri, [vars_json], [connection_id])", -1);
+    return;
+  }
+
+  const char *template_uri = (const char*)sqlite3_value_text(argv[0]);
+  if (!template_uri) {
+    sqlite3_result_error(context, "mcp_read_resource_template requires a resource template URI", -1);
+    return;
+  }
+  const char *vars_json = argc > 1 ? (const char*)sqlite3_value_text(argv[1]) : NULL;
+
+  char *result = mcp_read_resource_template(template_uri, vars_json, mcp_connection_id_arg(argc, argv, 2));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to read resource template", -1);
+  }
+}
+
+static void mcp_read_resource_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 1 || argc > 2) {
+    sqlite3_result_error(context, "mcp_read_resource_json requires 1-2 arguments: (uri, [connection_id])", -1);
+    return;
+  }
+
+  const char *uri = (const char*)sqlite3_value_text(argv[0]);
+  if (!uri) {
+    sqlite3_result_error(context, "mcp_read_resource_json requires a resource URI", -1);
+    return;
+  }
+
+  char *result = mcp_read_resource_json(NULL, uri, mcp_connection_id_arg(argc, argv, 1));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to read resource", -1);
+  }
+}
+
+static void mcp_read_resources_batch_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 1 || argc > 2) {
+    sqlite3_result_error(context, "mcp_read_resources_batch_json requires 1-2 arguments: (uris_json, [connection_id])", -1);
+    return;
+  }
+
+  const char *uris = (const char*)sqlite3_value_text(argv[0]);
+  if (!uris) {
+    sqlite3_result_error(context, "mcp_read_resources_batch_json requires uris_json", -1);
+    return;
+  }
+
+  char *result = mcp_read_resources_batch_json(uris, mcp_connection_id_arg(argc, argv, 1));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to read resources", -1);
+  }
+}
+
+/*
+** Shared by mcp_read_resource_text and mcp_read_resource_blob: read the
+** first text or blob content of a resource and return it as TEXT or BLOB.
+*/
+static void mcp_read_resource_content_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv,
+  const char *name,
+  int binary
+){
+  if (argc < 1 || argc > 2) {
+    char *msg = sqlite3_mprintf("%s requires 1-2 arguments: (uri, [connection_id])", name);
+    sqlite3_result_error(context, msg, -1);
+    sqlite3_free(msg);
+    return;
+  }
+
+  const char *uri = (const char*)sqlite3_value_text(argv[0]);
+  if (!uri) {
+    char *msg = sqlite3_mprintf("%s requires a resource URI", name);
+    sqlite3_result_error(context, msg, -1);
+    sqlite3_free(msg);
+    return;
+  }
+
+  uint8_t *data = NULL;
+  size_t len = 0;
+  char *error = mcp_read_resource_content(uri, mcp_connection_id_arg(argc, argv, 1), binary, &data, &len);
+  if (error) {
+    char *error_msg = mcp_extract_error_message(error);
+    sqlite3_result_error(context, error_msg ? error_msg : error, -1);
+    if (error_msg) mcp_free_string(error_msg);
+    mcp_free_string(error);
+    return;
+  }
+
+  if (len > INT_MAX) {
+    sqlite3_result_error_toobig(context);
+  } else if (binary) {
+    sqlite3_result_blob(context, data, (int)len, SQLITE_TRANSIENT);
+  } else {
+    sqlite3_result_text(context, (const char*)data, (int)len, SQLITE_TRANSIENT);
+  }
+  mcp_free_bytes(data, len);
+}
+
+static void mcp_read_resource_text_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  mcp_read_resource_content_func(context, argc, argv, "mcp_read_resource_text", 0);
+}
+
+static void mcp_read_resource_blob_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  mcp_read_resource_content_func(context, argc, argv, "mcp_read_resource_blob", 1);
+}
+
+static void mcp_subscribe_resource_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 1 || argc > 2) {
+    sqlite3_result_error(context, "mcp_subscribe_resource requires 1-2 arguments: (uri, [connection_id])", -1);
+    return;
+  }
+
+  const char *uri = (const char*)sqlite3_value_text(argv[0]);
+  if (!uri) {
+    sqlite3_result_error(context, "mcp_subscribe_resource requires a resource URI", -1);
+    return;
+  }
+
+  char *result = mcp_subscribe_resource(uri, mcp_connection_id_arg(argc, argv, 1));
+  if (!result) {
+    sqlite3_result_null(context);
+  } else {
+    sqlite3_result_text(context, result, -1, SQLITE_TRANSIENT);
+    mcp_free_string(result);
+  }
+}
+
+static void mcp_unsubscribe_resource_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 1 || argc > 2) {
+    sqlite3_result_error(context, "mcp_unsubscribe_resource requires 1-2 arguments: (uri, [connection_id])", -1);
+    return;
+  }
+
+  const char *uri = (const char*)sqlite3_value_text(argv[0]);
+  if (!uri) {
+    sqlite3_result_error(context, "mcp_unsubscribe_resource requires a resource URI", -1);
+    return;
+  }
+
+  char *result = mcp_unsubscribe_resource(uri, mcp_connection_id_arg(argc, argv, 1));
+  if (!result) {
+    sqlite3_result_null(context);
+  } else {
+    sqlite3_result_text(context, result, -1, SQLITE_TRANSIENT);
+    mcp_free_string(result);
+  }
+}
+
+static void mcp_list_prompts_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc > 1) {
+    sqlite3_result_error(context, "mcp_list_prompts_json takes at most 1 argument: ([connection_id])", -1);
+    return;
+  }
+
+  char *result = mcp_list_prompts_json(NULL, mcp_connection_id_arg(argc, argv, 0));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to list prompts", -1);
+  }
+}
+
+static void mcp_get_prompt_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 1 || argc > 3) {
+    sqlite3_result_error(context, "mcp_get_prompt_json requires 1-3 arguments: (name, [arguments_json], [connection_id])", -1);
+    return;
+  }
+
+  const char *name = (const char*)sqlite3_value_text(argv[0]);
+  if (!name) {
+    sqlite3_result_error(context, "mcp_get_prompt_json requires a prompt name", -1);
+    return;
+  }
+
+  const char *arguments = NULL;
+  if (argc >= 2 && sqlite3_value_type(argv[1]) != SQLITE_NULL) {
+    arguments = (const char*)sqlite3_value_text(argv[1]);
+  }
+
+  char *result = mcp_get_prompt_json(NULL, name, arguments, mcp_connection_id_arg(argc, argv, 2));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to get prompt", -1);
+  }
+}
+
+static void mcp_complete_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 2 || argc > 3) {
+    sqlite3_result_error(context, "mcp_complete_json requires 2-3 arguments: (ref_json, argument_json, [connection_id])", -1);
+    return;
+  }
+
+  const char *ref = (const char*)sqlite3_value_text(argv[0]);
+  const char *argument = (const char*)sqlite3_value_text(argv[1]);
+
+  if (!ref || !argument) {
+    sqlite3_result_error(context, "mcp_complete_json requires ref_json and argument_json", -1);
+    return;
+  }
+
+  char *result = mcp_complete_json(ref, argument, mcp_connection_id_arg(argc, argv, 2));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to complete argument", -1);
+  }
+}
+
+static void mcp_raw_request_json_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc < 1 || argc > 3) {
+    sqlite3_result_error(context, "mcp_raw_request_json requires 1-3 arguments: (method, [params_json], [connection_id])", -1);
+    return;
+  }
+
+  const char *method = (const char*)sqlite3_value_text(argv[0]);
+  const char *params = (argc > 1) ? (const char*)sqlite3_value_text(argv[1]) : NULL;
+
+  if (!method) {
+    sqlite3_result_error(context, "mcp_raw_request_json requires a method", -1);
+    return;
+  }
+
+  char *result = mcp_raw_request_json(method, params, mcp_connection_id_arg(argc, argv, 2));
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to send request", -1);
+  }
+}
+
+/*
+** Tool functions: mcp_register_tools() creates one SQL function per tool of a connection,
+** named mcp_tool_<name>, that calls the tool like mcp_call_tool_json(). Each database keeps
+** a registry of the functions it created, so a name already taken by another function is
+** reported instead of replaced, and tools added after tools/list_changed are registered on
+** the next call of a tool function. mcp_refresh_tools() registers the connection's functions
+** again; SQLite cannot drop a function while a statement runs, so the functions of tools the
+** server no longer has are kept but marked removed.
+*/
+typedef struct mcp_tool_function mcp_tool_function;
+struct mcp_tool_function {
+  char *function_name;
+  char *tool_name;
+  char *connection_id;
+  int removed;                       /* Tool was gone when mcp_refresh_tools() last ran */
+  struct mcp_tool_registry *registry;
+  mcp_tool_function *next;
+};
+
+typedef struct mcp_tool_connection mcp_tool_connection;
+struct mcp_tool_connection {
+  char *connection_id;
+  mcp_tool_connection *next;
+};
+
+typedef struct mcp_tool_registry {
+  sqlite3 *db;
+  mcp_tool_function *functions;
+  mcp_tool_connection *connections;  /* Connections whose tools were registered */
+  uint64_t list_changes;             /* mcp_tool_list_changes() at the last registration */
+} mcp_tool_registry;
+
+static void mcp_tool_registry_free(void *p){
+  mcp_tool_registry *registry = (mcp_tool_registry*)p;
+  while (registry->functions) {
+    mcp_tool_function *function = registry->functions;
+    registry->functions = function->next;
+    sqlite3_free(function->function_name);
+    sqlite3_free(function->tool_name);
+    sqlite3_free(function->connection_id);
+    sqlite3_free(function);
+  }
+  while (registry->connections) {
+    mcp_tool_connection *connection = registry->connections;
+    registry->connections = connection->next;
+    sqlite3_free(connection->connection_id);
+    sqlite3_free(connection);
+  }
+  sqlite3_free(registry);
+}
+
+/* Check whether any SQL function, built-in or not, already has the given name */
+static int mcp_function_exists(sqlite3 *db, const char *name, int *exists){
+  sqlite3_stmt *stmt;
+  int rc = sqlite3_prepare_v2(db, "SELECT 1 FROM pragma_function_list WHERE name = ?1 COLLATE NOCASE", -1, &stmt, 0);
+  if (rc != SQLITE_OK) return rc;
+  sqlite3_bind_text(stmt, 1, name, -1, SQLITE_STATIC);
+  rc = sqlite3_step(stmt);
+  *exists = (rc == SQLITE_ROW);
+  sqlite3_finalize(stmt);
+  return (rc == SQLITE_ROW || rc == SQLITE_DONE) ? SQLITE_OK : rc;
+}
+
+static void mcp_tool_function_func(sqlite3_context *context, int argc, sqlite3_value **argv);
+
+/*
+** Create a function for every tool of a connection that does not have one yet.
+** Names created are appended to registered and names already taken to collisions,
+** as JSON array items; on failure *pzErr is set to a message to free with sqlite3_free.
+*/
+static int mcp_register_tool_functions(
+  mcp_tool_registry *registry,
+  const char *connection_id,
+  sqlite3_str *registered,
+  sqlite3_str *collisions,
+  char **pzErr
+){
+  char *result = mcp_list_tools_json_cached(connection_id);
+  if (!result) {
+    *pzErr = sqlite3_mprintf("Failed to list tools");
+    return SQLITE_ERROR;
+  }
+  char *error_msg = mcp_extract_error_message(result);
+  if (error_msg) {
+    *pzErr = sqlite3_mprintf("%s", error_msg);
+    mcp_free_string(error_msg);
+    mcp_free_string(result);
+    return SQLITE_ERROR;
+  }
+
+  int rc = SQLITE_OK;
+  size_t tool_count = mcp_parse_tools_json(result);
+  for (size_t i = 0; i < tool_count && rc == SQLITE_OK; i++) {
+    char *tool_name = mcp_get_tool_field(result, i, "name");
+    if (!tool_name || !tool_name[0]) {
+      if (tool_name) mcp_free_string(tool_name);
+      continue;
+    }
+
+    /* Characters not allowed in an unquoted SQL name become underscores */
+    char *function_name = sqlite3_mprintf("mcp_tool_%s", tool_name);
+    if (!function_name) {
+      mcp_free_string(tool_name);
+      rc = SQLITE_NOMEM;
+      break;
+    }
+    for (char *c = function_name; *c; c++) {
+      if (!((*c >= 'a' && *c <= 'z') || (*c >= 'A' && *c <= 'Z') || (*c >= '0' && *c <= '9') || *c == '_')) *c = '_';
+    }
 
-  pNew->db = db;
-  pNew->temp_table_name = sqlite3_mprintf("mcp_tools_cache_%p", (void*)pNew);
-  pNew->table_created = 0;
-  *ppVtab = (sqlite3_vtab*)pNew;
+    /* Skip tools this registry already made a function for; any other owner is a collision */
+    mcp_tool_function *existing = registry->functions;
+    while (existing && sqlite3_stricmp(existing->function_name, function_name) != 0) existing = existing->next;
+    int exists = 0;
+    if (existing) {
+      exists = 1;
+    } else if (strlen(function_name) > 255) {
+      exists = 1;
+    } else {
+      rc = mcp_function_exists(registry->db, function_name, &exists);
+    }
 
-  return sqlite3_declare_vtab(db,
-    "CREATE TABLE x(name TEXT, title TEXT, description TEXT, "
-    "inputSchema TEXT, outputSchema TEXT, annotations TEXT)");
+    if (rc == SQLITE_OK && existing && strcmp(existing->tool_name, tool_name) == 0 &&
+        strcmp(existing->connection_id, connection_id) == 0) {
+      /* Already registered */
+      existing->removed = 0;
+    } else if (rc == SQLITE_OK && existing && existing->removed && strcmp(existing->connection_id, connection_id) == 0) {
+      /* Function of a removed tool whose name now belongs to another tool of the connection */
+      char *renamed = sqlite3_mprintf("%s", tool_name);
+      if (renamed) {
+        sqlite3_free(existing->tool_name);
+        existing->tool_name = renamed;
+        existing->removed = 0;
+        sqlite3_str_appendf(registered, "%s\"%s\"", sqlite3_str_length(registered) > 0 ? "," : "", function_name);
+      } else {
+        rc = SQLITE_NOMEM;
+      }
+    } else if (rc == SQLITE_OK && exists) {
+      sqlite3_str_appendf(collisions, "%s\"%s\"", sqlite3_str_length(collisions) > 0 ? "," : "", function_name);
+    } else if (rc == SQLITE_OK) {
+      mcp_tool_function *function = sqlite3_malloc(sizeof(*function));
+      if (function) {
+        function->function_name = sqlite3_mprintf("%s", function_name);
+        function->tool_name = sqlite3_mprintf("%s", tool_name);
+        function->connection_id = sqlite3_mprintf("%s", connection_id);
+        function->removed = 0;
+        function->registry = registry;
+        rc = sqlite3_create_function(registry->db, function_name, -1, SQLITE_UTF8, function, mcp_tool_function_func, 0, 0);
+        if (rc == SQLITE_OK) {
+          function->next = registry->functions;
+          registry->functions = function;
+          sqlite3_str_appendf(registered, "%s\"%s\"", sqlite3_str_length(registered) > 0 ? "," : "", function_name);
+        } else {
+          sqlite3_free(function->function_name);
+          sqlite3_free(function->tool_name);
+          sqlite3_free(function->connection_id);
+          sqlite3_free(function);
+        }
+      } else {
+        rc = SQLITE_NOMEM;
+      }
+    }
+
+    sqlite3_free(function_name);
+    mcp_free_string(tool_name);
+  }
+
+  mcp_free_string(result);
+  if (rc != SQLITE_OK && !*pzErr) {
+    *pzErr = sqlite3_mprintf("Failed to register tool functions: %s", sqlite3_errstr(rc));
+  }
+  return rc;
 }
 
-static int mcp_tools_disconnect(sqlite3_vtab *pVtab){
-  mcp_tools_vtab *p = (mcp_tools_vtab*)pVtab;
+/* Register tools added since the last registration, once any tool list has changed */
+static void mcp_refresh_tool_functions(mcp_tool_registry *registry){
+  uint64_t list_changes = mcp_tool_list_changes();
+  if (list_changes == registry->list_changes) return;
+  registry->list_changes = list_changes;
+
+  sqlite3_str *registered = sqlite3_str_new(registry->db);
+  sqlite3_str *collisions = sqlite3_str_new(registry->db);
+  for (mcp_tool_connection *connection = registry->connections; connection; connection = connection->next) {
+    char *error = NULL;
+    mcp_register_tool_functions(registry, connection->connection_id, registered, collisions, &error);
+    sqlite3_free(error);
+  }
+  sqlite3_free(sqlite3_str_finish(registered));
+  sqlite3_free(sqlite3_str_finish(collisions));
+}
 
-  // Drop temporary table if it was created
-  if (p->table_created && p->temp_table_name) {
-    char *drop_sql = sqlite3_mprintf("DROP TABLE IF EXISTS temp.%s", p->temp_table_name);
-    if (drop_sql) {
-      sqlite3_exec(p->db, drop_sql, NULL, NULL, NULL);
-      sqlite3_free(drop_sql);
-    }
+/*
+** SQL function: mcp_tool_<name>([arguments_json])
+** Calls the tool the function was registered for and returns its result as mcp_call_tool_json does
+*/
+static void mcp_tool_function_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  mcp_tool_function *function = (mcp_tool_function*)sqlite3_user_data(context);
+  if (argc > 1) {
+    char *message = sqlite3_mprintf("%s takes at most 1 argument: ([arguments_json])", function->function_name);
+    sqlite3_result_error(context, message, -1);
+    sqlite3_free(message);
+    return;
   }
 
-  // Free temp table name
-  if (p->temp_table_name) {
-    sqlite3_free(p->temp_table_name);
+  if (function->removed) {
+    char *message = sqlite3_mprintf("%s calls tool '%s', which connection '%s' no longer has",
+                                    function->function_name, function->tool_name, function->connection_id);
+    sqlite3_result_error(context, message, -1);
+    sqlite3_free(message);
+    return;
   }
 
-  sqlite3_free(p);
-  return SQLITE_OK;
+  const char *arguments = "{}";
+  if (argc == 1 && sqlite3_value_type(argv[0]) != SQLITE_NULL) {
+    arguments = (const char*)sqlite3_value_text(argv[0]);
+  }
+
+  char *result = mcp_call_tool_json(NULL, function->tool_name, arguments, function->connection_id);
+  if (result) {
+    mcp_result_text(context, result, -1);
+    mcp_free_string(result);
+  } else {
+    sqlite3_result_error(context, "Failed to call tool", -1);
+  }
+
+  mcp_refresh_tool_functions(function->registry);
 }
 
-static int mcp_tools_open(sqlite3_vtab *pVtab, sqlite3_vtab_cursor **ppCursor){
-  mcp_tools_cursor *pCur = sqlite3_malloc(sizeof(*pCur));
-  if (pCur==0) return SQLITE_NOMEM;
-  memset(pCur, 0, sizeof(*pCur));
-  *ppCursor = (sqlite3_vtab_cursor*)pCur;
-  return SQLITE_OK;
+/*
+** SQL function: mcp_register_tools([connection_id])
+** Creates an mcp_tool_<name> function for each tool of the connection and reports the names
+** created and the names skipped because another function already has them
+*/
+static void mcp_register_tools_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc > 1) {
+    sqlite3_result_error(context, "mcp_register_tools takes at most 1 argument: ([connection_id])", -1);
+    return;
+  }
+
+  mcp_tool_registry *registry = (mcp_tool_registry*)sqlite3_user_data(context);
+  const char *connection_id = mcp_connection_id_arg(argc, argv, 0);
+  if (!connection_id) connection_id = "default";
+
+  sqlite3_str *registered = sqlite3_str_new(registry->db);
+  sqlite3_str *collisions = sqlite3_str_new(registry->db);
+  char *error = NULL;
+  registry->list_changes = mcp_tool_list_changes();
+  int rc = mcp_register_tool_functions(registry, connection_id, registered, collisions, &error);
+
+  mcp_tool_connection *connection = registry->connections;
+  while (connection && strcmp(connection->connection_id, connection_id) != 0) connection = connection->next;
+  if (rc == SQLITE_OK && !connection) {
+    connection = sqlite3_malloc(sizeof(*connection));
+    if (connection) {
+      connection->connection_id = sqlite3_mprintf("%s", connection_id);
+      connection->next = registry->connections;
+      registry->connections = connection;
+    }
+  }
+
+  char *registered_json = sqlite3_str_finish(registered);
+  char *collisions_json = sqlite3_str_finish(collisions);
+  if (rc != SQLITE_OK) {
+    sqlite3_result_error(context, error ? error : "Failed to register tool functions", -1);
+  } else {
+    char *result = sqlite3_mprintf("{\"registered\":[%s],\"collisions\":[%s]}",
+                                   registered_json ? registered_json : "", collisions_json ? collisions_json : "");
+    mcp_result_text(context, result, -1);
+    sqlite3_free(result);
+  }
+  sqlite3_free(error);
+  sqlite3_free(registered_json);
+  sqlite3_free(collisions_json);
 }
 
-static int mcp_tools_close(sqlite3_vtab_cursor *cur){
-  mcp_tools_cursor *pCur = (mcp_tools_cursor*)cur;
-  if (pCur->stmt) {
-    sqlite3_finalize(pCur->stmt);
+/*
+** SQL function: mcp_refresh_tools([connection_id])
+** Drops the connection's cached tool list, fetches it again and returns it, then registers
+** the mcp_tool_<name> functions made for the connection again against the new list
+*/
+static void mcp_refresh_tools_func(
+  sqlite3_context *context,
+  int argc,
+  sqlite3_value **argv
+){
+  if (argc > 1) {
+    sqlite3_result_error(context, "mcp_refresh_tools takes at most 1 argument: ([connection_id])", -1);
+    return;
   }
-  sqlite3_free(pCur);
-  return SQLITE_OK;
+
+  mcp_tool_registry *registry = (mcp_tool_registry*)sqlite3_user_data(context);
+  const char *connection_id = mcp_connection_id_arg(argc, argv, 0);
+  char *result = mcp_refresh_tools(connection_id);
+  if (!result) {
+    sqlite3_result_error(context, "Failed to list tools", -1);
+    return;
+  }
+  if (!connection_id) connection_id = "default";
+
+  /* A failed fetch leaves the functions as they were */
+  char *error_msg = mcp_extract_error_message(result);
+  mcp_tool_connection *connection = registry->connections;
+  while (connection && strcmp(connection->connection_id, connection_id) != 0) connection = connection->next;
+  if (!error_msg && connection) {
+    for (mcp_tool_function *function = registry->functions; function; function = function->next) {
+      if (strcmp(function->connection_id, connection_id) == 0) function->removed = 1;
+    }
+
+    sqlite3_str *registered = sqlite3_str_new(registry->db);
+    sqlite3_str *collisions = sqlite3_str_new(registry->db);
+    char *error = NULL;
+    registry->list_changes = mcp_tool_list_changes();
+    mcp_register_tool_functions(registry, connection_id, registered, collisions, &error);
+    sqlite3_free(error);
+    sqlite3_free(sqlite3_str_finish(registered));
+    sqlite3_free(sqlite3_str_finish(collisions));
+  }
+  if (error_msg) mcp_free_string(error_msg);
+
+  mcp_result_text(context, result, -1);
+  mcp_free_string(result);
 }
 
-static int mcp_tools_filter(
-  sqlite3_vtab_cursor *pVtabCursor,
-  int idxNum, const char *idxStr,
-  int argc, sqlite3_value **argv
+#ifdef _WIN32
+__declspec(dllexport)
+#endif
+int sqlite3_mcp_init(
+  sqlite3 *db,
+  char **pzErrMsg,
+  const sqlite3_api_routines *pApi
 ){
-  mcp_tools_cursor *pCur = (mcp_tools_cursor*)pVtabCursor;
-  mcp_tools_vtab *pVtab = (mcp_tools_vtab*)pVtabCursor->pVtab;
+  int rc = SQLITE_OK;
+  SQLITE_EXTENSION_INIT2(pApi);
 
-  if (pCur->stmt) {
-    sqlite3_finalize(pCur->stmt);
-    pCur->stmt = NULL;
+  if (mcp_init() != 0) {
+    *pzErrMsg = sqlite3_mprintf("Failed to initialize MCP library");
+    return SQLITE_ERROR;
   }
 
-  // Create and populate temp table if not already done
-  if (!pVtab->table_created) {
-    D("mcp_tools_filter: Creating temp table");
-    // Get tools list from MCP
-    char *result = mcp_list_tools_json(NULL);
-    if (!result) {
-      D("mcp_tools_filter: mcp_list_tools_json returned NULL");
-      pCur->eof = 1;
-      return SQLITE_OK;
-    }
+  rc = sqlite3_create_function(db, "mcp_version", 0,
+                               SQLITE_UTF8 | SQLITE_DETERMINISTIC,
+                               0, mcp_version_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-    DF("mcp_tools_filter: Got JSON result (%d bytes)", (int)strlen(result));
-    // Check for errors and extract error message
-    char *error_msg = mcp_extract_error_message(result);
-    if (error_msg) {
-      D("mcp_tools_filter: JSON contains error");
-      pVtab->base.zErrMsg = sqlite3_mprintf("%s", error_msg);
-      mcp_free_string(error_msg);
-      mcp_free_string(result);
-      pCur->eof = 1;
-      return SQLITE_ERROR;
-    }
+  rc = sqlite3_create_function(db, "mcp_connect", -1,
+                               SQLITE_UTF8,
+                               0, mcp_connect_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-    // Create temporary table
-    char *create_sql = sqlite3_mprintf(
-      "CREATE TEMP TABLE IF NOT EXISTS %s("
-      "name TEXT, title TEXT, description TEXT, "
-      "inputSchema TEXT, outputSchema TEXT, annotations TEXT)",
-      pVtab->temp_table_name);
+  rc = sqlite3_create_function(db, "mcp_connect_stdio", -1,
+                               SQLITE_UTF8,
+                               0, mcp_connect_stdio_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-    int rc = sqlite3_exec(pVtab->db, create_sql, NULL, NULL, NULL);
-    sqlite3_free(create_sql);
+  rc = sqlite3_create_function(db, "mcp_connect_json", 1,
+                               SQLITE_UTF8,
+                               0, mcp_connect_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-    if (rc != SQLITE_OK) {
-      mcp_free_string(result);
-      pCur->eof = 1;
-      return rc;
-    }
+  rc = sqlite3_create_function(db, "mcp_connect_many_json", 1,
+                               SQLITE_UTF8,
+                               0, mcp_connect_many_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-    // Parse JSON in the Rust layer
-    size_t tool_count = mcp_parse_tools_json(result);
-    DF("mcp_tools_filter: Parsed %d tools", (int)tool_count);
-    if (tool_count == 0) {
-      D("mcp_tools_filter: No tools found in JSON");
-      mcp_free_string(result);
-      pCur->eof = 1;
-      return SQLITE_OK;
-    }
+  rc = sqlite3_create_function(db, "mcp_validate_config_json", 1,
+                               SQLITE_UTF8,
+                               0, mcp_validate_config_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-    // Insert each tool using Rust JSON parsing
-    for (size_t i = 0; i < tool_count; i++) {
-      char *name = mcp_get_tool_field(result, i, "name");
-      char *title = mcp_get_tool_field(result, i, "title");  
-      char *description = mcp_get_tool_field(result, i, "description");
-      char *inputSchema = mcp_get_tool_field(result, i, "inputSchema");
-      char *outputSchema = mcp_get_tool_field(result, i, "outputSchema");
-      char *annotations = mcp_get_tool_field(result, i, "annotations");
+  rc = sqlite3_create_function(db, "mcp_connection_config_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_connection_config_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-      char *insert_sql = sqlite3_mprintf(
-        "INSERT INTO temp.%s (name, title, description, inputSchema, outputSchema, annotations) "
-        "VALUES (%Q, %Q, %Q, %Q, %Q, %Q)",
-        pVtab->temp_table_name,
-        name ? name : "",
-        title ? title : "",
-        description ? description : "",
-        inputSchema ? inputSchema : "",
-        outputSchema ? outputSchema : "",
-        annotations ? annotations : ""
-      );
+  rc = sqlite3_create_function(db, "mcp_reconnect", -1,
+                               SQLITE_UTF8,
+                               0, mcp_reconnect_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-      rc = sqlite3_exec(pVtab->db, insert_sql, NULL, NULL, NULL);
-      sqlite3_free(insert_sql);
+  rc = sqlite3_create_function(db, "mcp_set_timeout_ms", -1,
+                               SQLITE_UTF8,
+                               0, mcp_set_timeout_ms_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-      // Free the allocated strings
-      if (name) mcp_free_string(name);
-      if (title) mcp_free_string(title);
-      if (description) mcp_free_string(description);
-      if (inputSchema) mcp_free_string(inputSchema);
-      if (outputSchema) mcp_free_string(outputSchema);
-      if (annotations) mcp_free_string(annotations);
+  rc = sqlite3_create_function(db, "mcp_set_connect_timeout_ms", 1,
+                               SQLITE_UTF8,
+                               0, mcp_set_connect_timeout_ms_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-      if (rc != SQLITE_OK) {
-        mcp_free_string(result);
-        pCur->eof = 1;
-        return rc;
-      }
-    }
-    mcp_free_string(result);
+  rc = sqlite3_create_function(db, "mcp_set_tools_cache_ttl_ms", 1,
+                               SQLITE_UTF8,
+                               0, mcp_set_tools_cache_ttl_ms_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-    if (rc != SQLITE_OK) {
-      pCur->eof = 1;
-      return rc;
-    }
+  rc = sqlite3_create_function(db, "mcp_set_max_list_pages", 1,
+                               SQLITE_UTF8,
+                               0, mcp_set_max_list_pages_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-    pVtab->table_created = 1;
-  }
+  rc = sqlite3_create_function(db, "mcp_set_max_response_bytes", 1,
+                               SQLITE_UTF8,
+                               0, mcp_set_max_response_bytes_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  // Query the temp table
-  char *query = sqlite3_mprintf("SELECT * FROM temp.%s", pVtab->temp_table_name);
-  int rc = sqlite3_prepare_v2(pVtab->db, query, -1, &pCur->stmt, NULL);
-  sqlite3_free(query);
+  rc = sqlite3_create_function(db, "mcp_set_lossy_utf8", 1,
+                               SQLITE_UTF8,
+                               0, mcp_set_lossy_utf8_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
+
+  rc = sqlite3_create_function(db, "mcp_set_notification_queue_size", 1,
+                               SQLITE_UTF8,
+                               0, mcp_set_notification_queue_size_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
+
+  rc = sqlite3_create_function(db, "mcp_set_auto_reconnect", -1,
+                               SQLITE_UTF8,
+                               0, mcp_set_auto_reconnect_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
+
+  rc = sqlite3_create_function(db, "mcp_set_redirect_policy", -1,
+                               SQLITE_UTF8,
+                               0, mcp_set_redirect_policy_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
+
+  rc = sqlite3_create_function(db, "mcp_set_retry", -1,
+                               SQLITE_UTF8,
+                               0, mcp_set_retry_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
+
+  rc = sqlite3_create_function(db, "mcp_set_initialize_retry", -1,
+                               SQLITE_UTF8,
+                               0, mcp_set_initialize_retry_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  if (rc != SQLITE_OK) {
-    pCur->eof = 1;
-    return rc;
-  }
+  rc = sqlite3_create_function(db, "mcp_set_keepalive", 1,
+                               SQLITE_UTF8,
+                               0, mcp_set_keepalive_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  // Move to first row
-  rc = sqlite3_step(pCur->stmt);
-  pCur->eof = (rc != SQLITE_ROW);
+  rc = sqlite3_create_function(db, "mcp_set_validate_args", -1,
+                               SQLITE_UTF8,
+                               0, mcp_set_validate_args_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  return SQLITE_OK;
-}
+  rc = sqlite3_create_function(db, "mcp_set_validate_results", -1,
+                               SQLITE_UTF8,
+                               0, mcp_set_validate_results_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-static int mcp_tools_next(sqlite3_vtab_cursor *cur){
-  mcp_tools_cursor *pCur = (mcp_tools_cursor*)cur;
+  rc = sqlite3_create_function(db, "mcp_set_resolve_resources", -1,
+                               SQLITE_UTF8,
+                               0, mcp_set_resolve_resources_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  if (pCur->stmt) {
-    int rc = sqlite3_step(pCur->stmt);
-    pCur->eof = (rc != SQLITE_ROW);
-  } else {
-    pCur->eof = 1;
-  }
+  rc = sqlite3_create_function(db, "mcp_set_pretty", -1,
+                               SQLITE_UTF8,
+                               0, mcp_set_pretty_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  return SQLITE_OK;
-}
+  rc = sqlite3_create_function(db, "mcp_set_include_request_id", -1,
+                               SQLITE_UTF8,
+                               0, mcp_set_include_request_id_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-static int mcp_tools_eof(sqlite3_vtab_cursor *cur){
-  mcp_tools_cursor *pCur = (mcp_tools_cursor*)cur;
-  return pCur->eof;
-}
+  rc = sqlite3_create_function(db, "mcp_set_lazy", -1,
+                               SQLITE_UTF8,
+                               0, mcp_set_lazy_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-static int mcp_tools_column(
-  sqlite3_vtab_cursor *cur,
-  sqlite3_context *ctx,
-  int i
-){
-  mcp_tools_cursor *pCur = (mcp_tools_cursor*)cur;
+  rc = sqlite3_create_function(db, "mcp_set_oauth", -1,
+                               SQLITE_UTF8,
+                               0, mcp_set_oauth_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  if (!pCur->stmt || pCur->eof) {
-    sqlite3_result_null(ctx);
-    return SQLITE_OK;
-  }
+  rc = sqlite3_create_function(db, "mcp_set_tls", -1,
+                               SQLITE_UTF8,
+                               0, mcp_set_tls_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  const unsigned char *text = sqlite3_column_text(pCur->stmt, i);
-  if (text) {
-    sqlite3_result_text(ctx, (const char*)text, -1, SQLITE_TRANSIENT);
-  } else {
-    sqlite3_result_null(ctx);
-  }
+  rc = sqlite3_create_function(db, "mcp_set_proxy", 1,
+                               SQLITE_UTF8,
+                               0, mcp_set_proxy_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  return SQLITE_OK;
-}
+  rc = sqlite3_create_function(db, "mcp_set_user_agent", 1,
+                               SQLITE_UTF8,
+                               0, mcp_set_user_agent_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-static int mcp_tools_rowid(sqlite3_vtab_cursor *cur, sqlite_int64 *pRowid){
-  *pRowid = 0;
-  return SQLITE_OK;
-}
+  rc = sqlite3_create_function(db, "mcp_set_local_address", 1,
+                               SQLITE_UTF8,
+                               0, mcp_set_local_address_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-static int mcp_tools_best_index(sqlite3_vtab *tab, sqlite3_index_info *pIdxInfo){
-  pIdxInfo->estimatedCost = 1000.0;
-  return SQLITE_OK;
-}
+  rc = sqlite3_create_function(db, "mcp_set_client_info", -1,
+                               SQLITE_UTF8,
+                               0, mcp_set_client_info_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-static sqlite3_module mcp_tools_module = {
-  0,                         /* iVersion */
-  0,                         /* xCreate */
-  mcp_tools_connect,         /* xConnect */
-  mcp_tools_best_index,      /* xBestIndex */
-  mcp_tools_disconnect,      /* xDisconnect */
-  0,                         /* xDestroy */
-  mcp_tools_open,            /* xOpen - open a cursor */
-  mcp_tools_close,           /* xClose - close a cursor */
-  mcp_tools_filter,          /* xFilter - configure scan constraints */
-  mcp_tools_next,            /* xNext - advance a cursor */
-  mcp_tools_eof,             /* xEof - check for end of scan */
-  mcp_tools_column,          /* xColumn - read data */
-  mcp_tools_rowid,           /* xRowid - read data */
-  0,                         /* xUpdate */
-  0,                         /* xBegin */
-  0,                         /* xSync */
-  0,                         /* xCommit */
-  0,                         /* xRollback */
-  0,                         /* xFindMethod */
-  0,                         /* xRename */
-  0,                         /* xSavepoint */
-  0,                         /* xRelease */
-  0,                         /* xRollbackTo */
-  0,                         /* xShadowName */
-  0,                         /* xIntegrity */
-};
+  rc = sqlite3_create_function(db, "mcp_set_protocol_version", 1,
+                               SQLITE_UTF8,
+                               0, mcp_set_protocol_version_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-/*
-** Virtual table for mcp_call_tool_respond (non-streaming, no caching)
-** Returns parsed text results as rows from fresh tool calls
-*/
-typedef struct mcp_results_vtab {
-  sqlite3_vtab base;
-  sqlite3 *db;
-  char *tool_name;    // Stored from function arguments
-  char *arguments;    // Stored from function arguments
-} mcp_results_vtab;
+  rc = sqlite3_create_function(db, "mcp_set_experimental_capabilities", 1,
+                               SQLITE_UTF8,
+                               0, mcp_set_experimental_capabilities_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-typedef struct mcp_results_cursor {
-  sqlite3_vtab_cursor base;
-  char *json_result;     // Stored JSON result from tool call (no caching)
-  size_t content_count;  // Number of text items in result
-  size_t current_index;  // Current row index
-  int eof;
-} mcp_results_cursor;
+  rc = sqlite3_create_function(db, "mcp_set_roots_json", 1,
+                               SQLITE_UTF8,
+                               0, mcp_set_roots_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-static int mcp_results_connect(
-  sqlite3 *db,
-  void *pAux,
-  int argc, const char *const*argv,
-  sqlite3_vtab **ppVtab,
-  char **pzErr
-){
-  mcp_results_vtab *pNew = sqlite3_malloc(sizeof(*pNew));
-  if (pNew==0) return SQLITE_NOMEM;
-  memset(pNew, 0, sizeof(*pNew));
+  rc = sqlite3_create_function(db, "mcp_set_tool_filter", 1,
+                               SQLITE_UTF8,
+                               0, mcp_set_tool_filter_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  pNew->db = db;
+  rc = sqlite3_create_function(db, "mcp_set_tool_defaults", 2,
+                               SQLITE_UTF8,
+                               0, mcp_set_tool_defaults_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  DF("mcp_results_connect: argc=%d", argc);
-  for (int i = 0; i < argc; i++) {
-    DF("  argv[%d] = '%s'", i, argv[i] ? argv[i] : "NULL");
-  }
+  rc = sqlite3_create_function(db, "mcp_disconnect", -1,
+                               SQLITE_UTF8,
+                               0, mcp_disconnect_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  // For table-valued functions: SELECT * FROM mcp_call_tool_respond('tool', 'args')
-  // argc will be 5: module_name, db_name, table_name, tool_name, arguments
-  if (argc >= 5) {
-    pNew->tool_name = sqlite3_mprintf("%s", argv[3]);
-    pNew->arguments = sqlite3_mprintf("%s", argv[4]);
-    DF("mcp_call_tool_respond table-valued function args: tool_name='%s', arguments='%s'", argv[3], argv[4]);
-  } else {
-    D("mcp_call_tool_respond: No function arguments, will expect constraints");
-  }
+  rc = sqlite3_create_function(db, "mcp_shutdown", 0,
+                               SQLITE_UTF8,
+                               0, mcp_shutdown_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  *ppVtab = (sqlite3_vtab*)pNew;
+  rc = sqlite3_create_function(db, "mcp_cancel", -1,
+                               SQLITE_UTF8,
+                               0, mcp_cancel_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  return sqlite3_declare_vtab(db, "CREATE TABLE x(text TEXT, tool_name HIDDEN, arguments HIDDEN)");
-}
+  rc = sqlite3_create_function(db, "mcp_ping", -1,
+                               SQLITE_UTF8,
+                               0, mcp_ping_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-static int mcp_results_disconnect(sqlite3_vtab *pVtab){
-  mcp_results_vtab *p = (mcp_results_vtab*)pVtab;
-  if (p->tool_name) sqlite3_free(p->tool_name);
-  if (p->arguments) sqlite3_free(p->arguments);
-  sqlite3_free(p);
-  return SQLITE_OK;
-}
+  rc = sqlite3_create_function(db, "mcp_echo_latency_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_echo_latency_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-static int mcp_results_open(sqlite3_vtab *pVtab, sqlite3_vtab_cursor **ppCursor){
-  mcp_results_cursor *pCur = sqlite3_malloc(sizeof(*pCur));
-  if (pCur==0) return SQLITE_NOMEM;
-  memset(pCur, 0, sizeof(*pCur));
-  pCur->eof = 1;
-  *ppCursor = (sqlite3_vtab_cursor*)pCur;
-  return SQLITE_OK;
-}
+  rc = sqlite3_create_function(db, "mcp_set_server_log_level", -1,
+                               SQLITE_UTF8,
+                               0, mcp_set_server_log_level_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-static int mcp_results_close(sqlite3_vtab_cursor *cur){
-  mcp_results_cursor *pCur = (mcp_results_cursor*)cur;
-  if (pCur->json_result) {
-    mcp_free_string(pCur->json_result);
-  }
-  sqlite3_free(pCur);
-  return SQLITE_OK;
-}
+  // Scalar functions that return JSON strings
+  rc = sqlite3_create_function(db, "mcp_list_tools_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_tools_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-static int mcp_results_filter(
-  sqlite3_vtab_cursor *pVtabCursor,
-  int idxNum, const char *idxStr,
-  int argc, sqlite3_value **argv
-){
-  mcp_results_cursor *pCur = (mcp_results_cursor*)pVtabCursor;
-  mcp_results_vtab *pVtab = (mcp_results_vtab*)pVtabCursor->pVtab;
+  rc = sqlite3_create_function(db, "mcp_list_tools_json_cached", -1,
+                               SQLITE_UTF8,
+                               0, mcp_tools_json_cached_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  // Free any previous result
-  if (pCur->json_result) {
-    mcp_free_string(pCur->json_result);
-    pCur->json_result = NULL;
-  }
+  rc = sqlite3_create_function(db, "mcp_list_tool_names_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_tool_names_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  D("mcp_results_filter: called");
-  DF("  argc=%d, idxNum=%d", argc, idxNum);
-  for (int i = 0; i < argc; i++) {
-    DF("    argv[%d] = '%s'", i, sqlite3_value_text(argv[i]) ? (const char*)sqlite3_value_text(argv[i]) : "NULL");
-  }
+  rc = sqlite3_create_function(db, "mcp_tool_schema_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_tool_schema_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  // For table-valued functions, arguments were stored in xConnect
-  // If not available, try to get them from filter constraints
-  const char *tool_name = pVtab->tool_name;
-  const char *arguments = pVtab->arguments;
+  mcp_tool_registry *registry = sqlite3_malloc(sizeof(*registry));
+  if (!registry) return SQLITE_NOMEM;
+  memset(registry, 0, sizeof(*registry));
+  registry->db = db;
+  rc = sqlite3_create_function_v2(db, "mcp_register_tools", -1,
+                                  SQLITE_UTF8,
+                                  registry, mcp_register_tools_func, 0, 0, mcp_tool_registry_free);
+  if (rc != SQLITE_OK) return rc;
 
-  if (!tool_name || !arguments) {
-    D("mcp_results_filter: No arguments from xConnect, checking constraints");
-    if (argc >= 2) {
-      tool_name = (const char*)sqlite3_value_text(argv[0]);
-      arguments = (const char*)sqlite3_value_text(argv[1]);
-      DF("mcp_results_filter: Using constraint args: tool_name='%s', arguments='%s'",
-         tool_name ? tool_name : "NULL", arguments ? arguments : "NULL");
-    }
-  }
+  /* Shares the registry, which mcp_register_tools frees */
+  rc = sqlite3_create_function(db, "mcp_refresh_tools", -1,
+                               SQLITE_UTF8,
+                               registry, mcp_refresh_tools_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  if (!tool_name || !arguments) {
-    D("mcp_results_filter: Missing tool_name or arguments from both xConnect and constraints");
-    pCur->eof = 1;
-    return SQLITE_ERROR;
-  }
+  rc = sqlite3_create_function(db, "mcp_call_tool_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_call_tool_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  D("mcp_results_filter: Using arguments from xConnect");
-  DF("  tool_name='%s', arguments='%s'", tool_name, arguments);
+  rc = sqlite3_create_function(db, "mcp_call_tool_idempotent_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_call_tool_idempotent_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  // Call the tool - NO CACHING, fresh call every time
-  pCur->json_result = mcp_call_tool_json(NULL, tool_name, arguments);
-  if (!pCur->json_result) {
-    D("  Tool call failed");
-    pCur->eof = 1;
-    return SQLITE_OK;
-  }
+  rc = sqlite3_create_function(db, "mcp_call_tool_with_headers_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_call_tool_with_headers_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
+
+  rc = sqlite3_create_function(db, "mcp_call_tool_with_blob", -1,
+                               SQLITE_UTF8,
+                               0, mcp_call_tool_with_blob_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  // Parse JSON in the Rust layer
-  DF("  Tool result (%d bytes): %.200s%s", (int)strlen(pCur->json_result),
-     pCur->json_result, strlen(pCur->json_result) > 200 ? "..." : "");
+  rc = sqlite3_create_function(db, "mcp_call_tools_batch_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_call_tools_batch_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  // Check for errors first
-  char *error_msg = mcp_extract_error_message(pCur->json_result);
-  if (error_msg) {
-    D("  JSON contains error");
-    pVtab->base.zErrMsg = sqlite3_mprintf("%s", error_msg);
-    mcp_free_string(error_msg);
-    pCur->eof = 1;
-    return SQLITE_ERROR;
-  }
+  rc = sqlite3_create_function(db, "mcp_call_tools_matching_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_call_tools_matching_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  pCur->content_count = mcp_parse_call_result_json(pCur->json_result);
-  DF("  Parsed content count: %d", (int)pCur->content_count);
+  rc = sqlite3_create_function(db, "mcp_call_tool_stream", -1,
+                               SQLITE_UTF8,
+                               0, mcp_call_tool_stream_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  if (pCur->content_count == 0) {
-    D("  No content parsed");
-    pCur->eof = 1;
-    return SQLITE_OK;
-  }
+  rc = sqlite3_create_function(db, "mcp_poll_stream_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_poll_stream_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  // Start at first result - no temp table, no caching
-  pCur->current_index = 0;
-  pCur->eof = 0;
+  rc = sqlite3_create_function(db, "mcp_close_stream", 1,
+                               SQLITE_UTF8,
+                               0, mcp_close_stream_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  return SQLITE_OK;
-}
+  rc = sqlite3_create_function(db, "mcp_status_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_status_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-static int mcp_results_next(sqlite3_vtab_cursor *cur){
-  mcp_results_cursor *pCur = (mcp_results_cursor*)cur;
+  rc = sqlite3_create_function(db, "mcp_transport", -1,
+                               SQLITE_UTF8,
+                               0, mcp_transport_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  pCur->current_index++;
-  if (pCur->current_index >= pCur->content_count) {
-    pCur->eof = 1;
-  }
+  rc = sqlite3_create_function(db, "mcp_server_info_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_server_info_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  return SQLITE_OK;
-}
+  rc = sqlite3_create_function(db, "mcp_initialize_result_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_initialize_result_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-static int mcp_results_eof(sqlite3_vtab_cursor *cur){
-  mcp_results_cursor *pCur = (mcp_results_cursor*)cur;
-  return pCur->eof;
-}
+  rc = sqlite3_create_function(db, "mcp_capabilities_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_capabilities_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-static int mcp_results_column(
-  sqlite3_vtab_cursor *cur,
-  sqlite3_context *ctx,
-  int i
-){
-  mcp_results_cursor *pCur = (mcp_results_cursor*)cur;
+  rc = sqlite3_create_function(db, "mcp_metrics_json", 0,
+                               SQLITE_UTF8,
+                               0, mcp_metrics_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  if (i == 0) {
-    // text column - extract directly from stored JSON (no caching)
-    if (pCur->json_result && pCur->current_index < pCur->content_count) {
-      char *text = mcp_get_call_result_text(pCur->json_result, pCur->current_index);
-      if (text && strlen(text) > 0) {
-        sqlite3_result_text(ctx, text, -1, SQLITE_TRANSIENT);
-        mcp_free_string(text);
-      } else {
-        sqlite3_result_null(ctx);
-      }
-    } else {
-      sqlite3_result_null(ctx);
-    }
-  }
+  rc = sqlite3_create_function(db, "mcp_timing_json", 0,
+                               SQLITE_UTF8,
+                               0, mcp_timing_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  return SQLITE_OK;
-}
+  rc = sqlite3_create_function(db, "mcp_queue_json", 0,
+                               SQLITE_UTF8,
+                               0, mcp_queue_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-static int mcp_results_rowid(sqlite3_vtab_cursor *cur, sqlite_int64 *pRowid){
-  mcp_results_cursor *pCur = (mcp_results_cursor*)cur;
-  *pRowid = pCur->current_index + 1;
-  return SQLITE_OK;
-}
+  rc = sqlite3_create_function(db, "mcp_clear_queue", 0,
+                               SQLITE_UTF8,
+                               0, mcp_clear_queue_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-static int mcp_results_best_index(sqlite3_vtab *tab, sqlite3_index_info *pIdxInfo){
-  D("mcp_results_best_index: called");
-  DF("  nConstraint=%d", pIdxInfo->nConstraint);
-  
-  int tool_name_idx = -1;
-  int arguments_idx = -1;
-  
-  for (int i = 0; i < pIdxInfo->nConstraint; i++) {
-    DF("    constraint[%d]: iColumn=%d, op=%d, usable=%d", 
-       i, pIdxInfo->aConstraint[i].iColumn, 
-       pIdxInfo->aConstraint[i].op, pIdxInfo->aConstraint[i].usable);
-       
-    if (pIdxInfo->aConstraint[i].usable && 
-        pIdxInfo->aConstraint[i].op == SQLITE_INDEX_CONSTRAINT_EQ) {
-      if (pIdxInfo->aConstraint[i].iColumn == 1) { // tool_name HIDDEN
-        tool_name_idx = i;
-      } else if (pIdxInfo->aConstraint[i].iColumn == 2) { // arguments HIDDEN
-        arguments_idx = i;
-      }
-    }
-  }
-  
-  if (tool_name_idx >= 0 && arguments_idx >= 0) {
-    D("mcp_results_best_index: Found both tool_name and arguments constraints");
-    // Tell SQLite to use these constraints
-    pIdxInfo->aConstraintUsage[tool_name_idx].argvIndex = 1;
-    pIdxInfo->aConstraintUsage[tool_name_idx].omit = 1;
-    pIdxInfo->aConstraintUsage[arguments_idx].argvIndex = 2;
-    pIdxInfo->aConstraintUsage[arguments_idx].omit = 1;
-    
-    pIdxInfo->idxNum = 1; // indicates we have function arguments
-    pIdxInfo->estimatedCost = 100.0;
-  } else {
-    D("mcp_results_best_index: No function constraints found");
-    pIdxInfo->idxNum = 0; // no constraints
-    pIdxInfo->estimatedCost = 1000.0;
-  }
-  
-  return SQLITE_OK;
-}
+  rc = sqlite3_create_function(db, "mcp_pause", -1,
+                               SQLITE_UTF8,
+                               0, mcp_pause_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-static sqlite3_module mcp_results_module = {
-  0,                         /* iVersion */
-  0,                         /* xCreate */
-  mcp_results_connect,       /* xConnect */
-  mcp_results_best_index,    /* xBestIndex */
-  mcp_results_disconnect,    /* xDisconnect */
-  0,                         /* xDestroy */
-  mcp_results_open,          /* xOpen */
-  mcp_results_close,         /* xClose */
-  mcp_results_filter,        /* xFilter */
-  mcp_results_next,          /* xNext */
-  mcp_results_eof,           /* xEof */
-  mcp_results_column,        /* xColumn */
-  mcp_results_rowid,         /* xRowid */
-  0,                         /* xUpdate */
-  0,                         /* xBegin */
-  0,                         /* xSync */
-  0,                         /* xCommit */
-  0,                         /* xRollback */
-  0,                         /* xFindMethod */
-  0,                         /* xRename */
-  0,                         /* xSavepoint */
-  0,                         /* xRelease */
-  0,                         /* xRollbackTo */
-  0,                         /* xShadowName */
-  0,                         /* xIntegrity */
-};
+  rc = sqlite3_create_function(db, "mcp_resume", -1,
+                               SQLITE_UTF8,
+                               0, mcp_resume_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-/*
-** Scalar functions for JSON output
-*/
-static void mcp_tools_json_func(
-  sqlite3_context *context,
-  int argc,
-  sqlite3_value **argv
-){
-  char *result = mcp_list_tools_json(NULL);
-  if (result) {
-    mcp_result_text(context, result, -1);
-    mcp_free_string(result);
-  } else {
-    sqlite3_result_error(context, "Failed to list tools", -1);
-  }
-}
+  rc = sqlite3_create_function(db, "mcp_poll_notifications_json", 0,
+                               SQLITE_UTF8,
+                               0, mcp_poll_notifications_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-static void mcp_call_tool_json_func(
-  sqlite3_context *context,
-  int argc,
-  sqlite3_value **argv
-){
-  if (argc != 2) {
-    sqlite3_result_error(context, "mcp_call_tool_json requires 2 arguments", -1);
-    return;
-  }
+  rc = sqlite3_create_function(db, "mcp_list_resources_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_list_resources_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  const char *tool_name = (const char*)sqlite3_value_text(argv[0]);
-  const char *arguments = (const char*)sqlite3_value_text(argv[1]);
+  rc = sqlite3_create_function(db, "mcp_list_resource_templates_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_list_resource_templates_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  if (!tool_name || !arguments) {
-    sqlite3_result_error(context, "mcp_call_tool_json requires tool_name and arguments_json", -1);
-    return;
-  }
+  rc = sqlite3_create_function(db, "mcp_read_resource_template", -1,
+                               SQLITE_UTF8,
+                               0, mcp_read_resource_template_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  char *result = mcp_call_tool_json(NULL, tool_name, arguments);
-  if (result) {
-    mcp_result_text(context, result, -1);
-    mcp_free_string(result);
-  } else {
-    sqlite3_result_error(context, "Failed to call tool", -1);
-  }
-}
+  rc = sqlite3_create_function(db, "mcp_read_resource_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_read_resource_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-#ifdef _WIN32
-__declspec(dllexport)
-#endif
-int sqlite3_mcp_init(
-  sqlite3 *db,
-  char **pzErrMsg,
-  const sqlite3_api_routines *pApi
-){
-  int rc = SQLITE_OK;
-  SQLITE_EXTENSION_INIT2(pApi);
+  rc = sqlite3_create_function(db, "mcp_read_resources_batch_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_read_resources_batch_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  if (mcp_init() != 0) {
-    *pzErrMsg = sqlite3_mprintf("Failed to initialize MCP library");
-    return SQLITE_ERROR;
-  }
+  rc = sqlite3_create_function(db, "mcp_read_resource_text", -1,
+                               SQLITE_UTF8,
+                               0, mcp_read_resource_text_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
 
-  rc = sqlite3_create_function(db, "mcp_version", 0,
-                               SQLITE_UTF8 | SQLITE_DETERMINISTIC,
-                               0, mcp_version_func, 0, 0);
+  rc = sqlite3_create_function(db, "mcp_read_resource_blob", -1,
+                               SQLITE_UTF8,
+                               0, mcp_read_resource_blob_func, 0, 0);
   if (rc != SQLITE_OK) return rc;
 
-  rc = sqlite3_create_function(db, "mcp_connect", -1,
+  rc = sqlite3_create_function(db, "mcp_subscribe_resource", -1,
                                SQLITE_UTF8,
-                               0, mcp_connect_func, 0, 0);
+                               0, mcp_subscribe_resource_func, 0, 0);
   if (rc != SQLITE_OK) return rc;
 
-  rc = sqlite3_create_function(db, "mcp_disconnect", 0,
+  rc = sqlite3_create_function(db, "mcp_unsubscribe_resource", -1,
                                SQLITE_UTF8,
-                               0, mcp_disconnect_func, 0, 0);
+                               0, mcp_unsubscribe_resource_func, 0, 0);
   if (rc != SQLITE_OK) return rc;
 
-  // Scalar functions that return JSON strings
-  rc = sqlite3_create_function(db, "mcp_list_tools_json", 0,
+  rc = sqlite3_create_function(db, "mcp_list_prompts_json", -1,
                                SQLITE_UTF8,
-                               0, mcp_tools_json_func, 0, 0);
+                               0, mcp_list_prompts_json_func, 0, 0);
   if (rc != SQLITE_OK) return rc;
 
-  rc = sqlite3_create_function(db, "mcp_call_tool_json", 2,
+  rc = sqlite3_create_function(db, "mcp_get_prompt_json", -1,
                                SQLITE_UTF8,
-                               0, mcp_call_tool_json_func, 0, 0);
+                               0, mcp_get_prompt_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
+
+  rc = sqlite3_create_function(db, "mcp_complete_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_complete_json_func, 0, 0);
+  if (rc != SQLITE_OK) return rc;
+
+  rc = sqlite3_create_function(db, "mcp_raw_request_json", -1,
+                               SQLITE_UTF8,
+                               0, mcp_raw_request_json_func, 0, 0);
   if (rc != SQLITE_OK) return rc;
 
   // Virtual tables that return structured rows
@@ -1342,6 +4653,24 @@ int sqlite3_mcp_init(
   rc = sqlite3_create_module(db, "mcp_call_tool_respond", &mcp_results_module, 0);
   if (rc != SQLITE_OK) return rc;
 
+  rc = sqlite3_create_module(db, "mcp_tools", &mcp_list_module, (void*)&mcp_tools_table);
+  if (rc != SQLITE_OK) return rc;
+
+  rc = sqlite3_create_module(db, "mcp_resources", &mcp_list_module, (void*)&mcp_resources_table);
+  if (rc != SQLITE_OK) return rc;
+
+  rc = sqlite3_create_module(db, "mcp_prompts", &mcp_list_module, (void*)&mcp_prompts_table);
+  if (rc != SQLITE_OK) return rc;
+
+  rc = sqlite3_create_module(db, "mcp_logs", &mcp_list_module, (void*)&mcp_logs_table);
+  if (rc != SQLITE_OK) return rc;
+
+  rc = sqlite3_create_module(db, "mcp_events", &mcp_list_module, (void*)&mcp_events_table);
+  if (rc != SQLITE_OK) return rc;
+
+  rc = sqlite3_create_module(db, "mcp_resource_chunks", &mcp_chunks_module, 0);
+  if (rc != SQLITE_OK) return rc;
+
   // Streaming virtual tables
   rc = sqlite3_create_module(db, "mcp_list_tools", &mcp_stream_module, 0);
   if (rc != SQLITE_OK) return rc;
@@ -1351,3 +4680,21 @@ int sqlite3_mcp_init(
 
   return rc;
 }
+
+/*
+** Generic entry point, tried by sqlite3_load_extension() when the library was renamed
+** and no sqlite3_<name>_init matches its file name (e.g. sqlite-mcp.so). Left out of
+** builds linked into SQLite itself, where every extension would define this symbol.
+*/
+#ifndef SQLITE_CORE
+#ifdef _WIN32
+__declspec(dllexport)
+#endif
+int sqlite3_extension_init(
+  sqlite3 *db,
+  char **pzErrMsg,
+  const sqlite3_api_routines *pApi
+){
+  return sqlite3_mcp_init(db, pzErrMsg, pApi);
+}
+#endif